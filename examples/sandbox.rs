@@ -4,8 +4,8 @@ fn main() {
   if true {
     let curr_sec = now_sec();
     let mut time_cache = TimeCache::new();
-    println!("now {}", String::from_utf8_lossy(time_cache.refresh_dt(curr_sec)));
-    println!("now {}", String::from_utf8_lossy(time_cache.refresh_dt(curr_sec + 10)));
+    println!("now {}", String::from_utf8_lossy(time_cache.refresh_dt_bytes(curr_sec)));
+    println!("now {}", String::from_utf8_lossy(time_cache.refresh_dt_bytes(curr_sec + 10)));
     // return;
   }
 
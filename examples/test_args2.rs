@@ -2,7 +2,7 @@ use std::fmt;
 use std::fmt::Display;
 use std::hint::black_box;
 use bytemuck::{Pod, Zeroable};
-use hft_log_demo::args2::{args2, decode_fmt_args2, UserPod};
+use hft_log_demo::args2::{args2, decode_fmt_args2, encode_to_vec, UserPod};
 // use hft_log_demo::args::Args2;
 
 fn main() {
@@ -17,9 +17,9 @@ fn main() {
   println!("t1={} t2={}", args.tag1, args.tag2);
 
   println!("decode");
-  let bytes = bytemuck::bytes_of(&args);
+  let bytes = encode_to_vec(&args);
   println!("data: {:?}", bytes);
-  decode_fmt_args2(bytes);
+  decode_fmt_args2(&bytes);
 
   let timer = minstant::Instant::now();
   for id in 0..1_000_000u32 {
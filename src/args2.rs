@@ -1,10 +1,38 @@
 use std::fmt;
 use std::fmt::{Display, Formatter};
-use std::mem::transmute;
+use std::any::TypeId;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ptr;
+use std::sync::RwLock;
 use bytemuck::{Pod, Zeroable};
+use crate::format::EpochNs;
+use crate::log::MAX_PAYLOAD_LEN;
+use crate::tscns;
 
-pub trait Arg: Display + Copy + Clone {
+/// Tags `0..=8` are reserved for the builtin arg types,
+/// `UserPodSnap<T>` uses its own size (at least 16, `T` cannot be zero-sized) so the two never collide.
+pub trait Arg: Display + Sized {
   const ARG_TAG: u8;
+  /// Fewest bytes the arg takes in the payload, what the compile-time size check counts.
+  const MIN_ENCODED_LEN: usize = size_of::<Self>();
+
+  /// Bytes `encode` writes, a multiple of 8. Fixed-size args are their own encoding.
+  #[inline(always)]
+  fn encoded_len(&self) -> usize {
+    size_of::<Self>()
+  }
+
+  /// Write the arg to the front of `out` and return how many bytes that took.
+  #[inline(always)]
+  fn encode(&self, out: &mut [u8]) -> usize {
+    let sz = size_of::<Self>();
+    unsafe {
+      ptr::copy_nonoverlapping(self as *const Self as *const u8, out[..sz].as_mut_ptr(), sz);
+    }
+    sz
+  }
 }
 
 #[derive(Copy, Clone, Pod, Zeroable)]
@@ -50,11 +78,247 @@ impl Arg for ArgI64 {
   const ARG_TAG: u8 = 2;
 }
 
+/// `bool` in one byte, padded to the 8-byte arg slot.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct ArgBool {
+  v: u8,
+  _pad: [u8; 7],
+}
+
+impl Display for ArgBool {
+  #[inline]
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    bool::fmt(&(self.v != 0), f)
+  }
+}
+impl Arg for ArgBool {
+  const ARG_TAG: u8 = 4;
+}
+
+/// `char` as its 4-byte scalar value, padded to the 8-byte arg slot.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct ArgChar {
+  v: u32,
+  _pad: u32,
+}
+
+impl Display for ArgChar {
+  #[inline]
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    char::fmt(&char::from_u32(self.v).unwrap_or(char::REPLACEMENT_CHARACTER), f)
+  }
+}
+impl Arg for ArgChar {
+  const ARG_TAG: u8 = 5;
+}
+
+/// Raw tsc (`tscns::read_tsc` clock) in the 8-byte slot, rendered as the calendar time `tscns::tsc2ns` maps it to.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(transparent)]
+pub struct ArgTsc(i64);
+
+impl Display for ArgTsc {
+  #[inline]
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    EpochNs(tscns::tsc2ns(self.0)).fmt(f)
+  }
+}
+impl Arg for ArgTsc {
+  const ARG_TAG: u8 = 7;
+}
+
+/// A tsc captured elsewhere (e.g. at packet receive), logged as `MM-DD HH:MM:SS.mmm.uuu.nnn` (UTC):
+/// `hft_info!(l, "recv_ts={}", Tsc(nic_tsc))`. Always converted on the default TSC clock (`tscns::tsc2ns`),
+/// even when a sink was given its own `Clock`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tsc(pub i64);
+
+impl IntoArg for Tsc {
+  type D = ArgTsc;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgTsc(self.0)
+  }
+}
+
+/// Address of a logged reference plus a hash of what it pointed to when logged.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct ArgPtr {
+  addr: u64,
+  hash: u64,
+}
+
+impl Display for ArgPtr {
+  #[inline]
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "obj@{:#x}#{:016x}", self.addr, self.hash)
+  }
+}
+impl Arg for ArgPtr {
+  const ARG_TAG: u8 = 8;
+}
+
+/// Log any `Hash` object by identity instead of by value: `hft_info!(l, "book {}", ptr(&book))`
+/// prints `obj@0x7f..#<hash>`. Works for types that are not `Pod`; the hash is computed on the
+/// calling thread, so keep it to objects whose `Hash` is cheap enough for the hot path.
+pub struct Ptr<'a, T: ?Sized>(pub &'a T);
+
+#[inline(always)]
+pub fn ptr<T: ?Sized + Hash>(r: &T) -> Ptr<'_, T> {
+  Ptr(r)
+}
+
+impl<T: ?Sized + Hash> IntoArg for Ptr<'_, T> {
+  type D = ArgPtr;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    let mut hasher = DefaultHasher::new();
+    self.0.hash(&mut hasher);
+    ArgPtr {
+      addr: self.0 as *const T as *const () as u64,
+      hash: hasher.finish(),
+    }
+  }
+}
+
+/// Max bytes of a `&str` arg, so a lone one always fits a record; longer strings are truncated.
+pub const STR_MAX_LEN: usize = MAX_PAYLOAD_LEN - 16;
+
+/// `&str` or `String` arg, encoded as a 2-byte length and the bytes, zero-padded to a multiple of 8.
+/// An owned `String` is moved in, not copied; either way the bytes land in the payload at the call site.
+#[derive(Clone)]
+pub struct ArgStr<'a> {
+  s: Cow<'a, str>,
+}
+
+impl<'a> ArgStr<'a> {
+  #[inline(always)]
+  pub fn new(s: &'a str) -> Self {
+    ArgStr { s: Cow::Borrowed(&s[..floor_char_boundary(s, STR_MAX_LEN)]) }
+  }
+
+  #[inline(always)]
+  pub fn owned(mut s: String) -> Self {
+    s.truncate(floor_char_boundary(&s, STR_MAX_LEN));
+    ArgStr { s: Cow::Owned(s) }
+  }
+
+  #[inline(always)]
+  pub fn as_str(&self) -> &str {
+    &self.s
+  }
+}
+
+/// Payload bytes of a `len`-byte string: length prefix plus bytes, rounded up to 8.
+#[inline(always)]
+const fn str_encoded_len(len: usize) -> usize {
+  (2 + len + 7) & !7
+}
+
+/// Largest index <= `max` that does not split a UTF-8 code point.
+#[inline(always)]
+fn floor_char_boundary(s: &str, max: usize) -> usize {
+  if s.len() <= max {
+    return s.len();
+  }
+  let mut idx = max;
+  while !s.is_char_boundary(idx) {
+    idx -= 1;
+  }
+  idx
+}
+
+impl Display for ArgStr<'_> {
+  #[inline]
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    str::fmt(self.as_str(), f)
+  }
+}
+impl Arg for ArgStr<'_> {
+  const ARG_TAG: u8 = 3;
+  const MIN_ENCODED_LEN: usize = str_encoded_len(0);
+
+  #[inline(always)]
+  fn encoded_len(&self) -> usize {
+    str_encoded_len(self.s.len())
+  }
+
+  #[inline(always)]
+  fn encode(&self, out: &mut [u8]) -> usize {
+    let len = self.s.len();
+    let end = str_encoded_len(len);
+    out[..2].copy_from_slice(&(len as u16).to_ne_bytes());
+    out[2..2 + len].copy_from_slice(self.s.as_bytes());
+    out[2 + len..end].fill(0);
+    end
+  }
+}
+
 pub trait IntoArg {
   type D: Arg;
   fn into_arg(self) -> Self::D;
 }
 
+impl IntoArg for f64 {
+  type D = ArgF64;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgF64(self)
+  }
+}
+
+/// widened to f64, so it prints the f64 value of the f32 (exact for values like 3.5, not for 0.1)
+impl IntoArg for f32 {
+  type D = ArgF64;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgF64(self as _)
+  }
+}
+
+impl IntoArg for bool {
+  type D = ArgBool;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgBool { v: self as u8, _pad: [0; 7] }
+  }
+}
+
+impl IntoArg for char {
+  type D = ArgChar;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgChar { v: self as u32, _pad: 0 }
+  }
+}
+
+impl IntoArg for u8 {
+  type D = ArgU64;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgU64(self as _)
+  }
+}
+
+impl IntoArg for u16 {
+  type D = ArgU64;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgU64(self as _)
+  }
+}
+
 impl IntoArg for u32 {
   type D = ArgU64;
 
@@ -73,6 +337,148 @@ impl IntoArg for u64 {
   }
 }
 
+impl IntoArg for i8 {
+  type D = ArgI64;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgI64(self as _)
+  }
+}
+
+impl IntoArg for i16 {
+  type D = ArgI64;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgI64(self as _)
+  }
+}
+
+impl IntoArg for i32 {
+  type D = ArgI64;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgI64(self as _)
+  }
+}
+
+impl IntoArg for i64 {
+  type D = ArgI64;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgI64(self)
+  }
+}
+
+impl<'a> IntoArg for &'a str {
+  type D = ArgStr<'a>;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgStr::new(self)
+  }
+}
+
+/// Owned strings are moved into the args and copied into the payload at the call site,
+/// so nothing refers to them once the macro returns.
+impl IntoArg for String {
+  type D = ArgStr<'static>;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgStr::owned(self)
+  }
+}
+
+impl<'a> IntoArg for &'a String {
+  type D = ArgStr<'a>;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgStr::new(self)
+  }
+}
+
+/// Max bytes of a `hex(..)` slice copied into the payload.
+pub const HEX_INLINE_CAP: usize = 56;
+
+/// Byte slice copied into the payload at call time, rendered as `de ad be ef`;
+/// bytes past the cap are only counted and shown as `…(M more)`. 64 bytes total.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ArgHex {
+  len: u16,
+  _pad: u16,
+  more: u32,
+  bytes: [u8; HEX_INLINE_CAP],
+}
+
+unsafe impl Zeroable for ArgHex {}
+unsafe impl Pod for ArgHex {}
+
+impl ArgHex {
+  #[inline(always)]
+  pub fn new(bytes: &[u8], cap: usize) -> Self {
+    let len = bytes.len().min(cap).min(HEX_INLINE_CAP);
+    let mut arg = ArgHex {
+      len: len as u16,
+      _pad: 0,
+      more: (bytes.len() - len).min(u32::MAX as usize) as u32,
+      bytes: [0u8; HEX_INLINE_CAP],
+    };
+    arg.bytes[..len].copy_from_slice(&bytes[..len]);
+    arg
+  }
+}
+
+impl Display for ArgHex {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    for (i, b) in self.bytes[..self.len as usize].iter().enumerate() {
+      if i > 0 {
+        f.write_str(" ")?;
+      }
+      write!(f, "{:02x}", b)?;
+    }
+    if self.more > 0 {
+      write!(f, "…({} more)", self.more)?;
+    }
+    Ok(())
+  }
+}
+impl Arg for ArgHex {
+  const ARG_TAG: u8 = 6;
+}
+
+/// Log a byte slice as hex: `hft_info!(logger, "pkt {}", hex(&buf))`.
+pub struct Hex<'a> {
+  bytes: &'a [u8],
+  cap: usize,
+}
+
+/// First `HEX_INLINE_CAP` bytes of `bytes`.
+#[inline(always)]
+pub fn hex(bytes: &[u8]) -> Hex<'_> {
+  Hex { bytes, cap: HEX_INLINE_CAP }
+}
+
+/// First `cap` bytes of `bytes` (at most `HEX_INLINE_CAP`).
+#[inline(always)]
+pub fn hex_n(bytes: &[u8], cap: usize) -> Hex<'_> {
+  Hex { bytes, cap }
+}
+
+impl IntoArg for Hex<'_> {
+  type D = ArgHex;
+
+  #[inline(always)]
+  fn into_arg(self) -> Self::D {
+    ArgHex::new(self.bytes, self.cap)
+  }
+}
+
 #[inline(always)]
 pub(crate) fn repr_as<T>(slice: &[u8]) -> &T {
   unsafe {
@@ -94,10 +500,14 @@ pub trait UserPod: Display + Copy + Pod + Zeroable {
   }
 }
 
+/// `data` travels with the id `T` is registered under, never with the address of `T::decode`:
+/// the snapshot stays decodable by another process (see `binary_sink::decode`) that registers `T` too.
+/// `T` must be a non-empty multiple of 8 bytes with an alignment of at most 8 (pad it by hand
+/// otherwise), so the snapshot has no padding bytes and the args after it stay 8-aligned.
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct UserPodSnap<T: UserPod> {
-  decode_fn: u64,
+  pod_id: u64,
   data: T,
 }
 
@@ -112,7 +522,15 @@ impl <T: UserPod> Display for UserPodSnap<T> {
 }
 
 impl <T: UserPod> Arg for UserPodSnap<T> {
-  const ARG_TAG: u8 = size_of::<T>() as u8 + 8;
+  // 8 tag bytes + 8 pod_id + T must fit one record, which also keeps the tag below 256
+  const ARG_TAG: u8 = {
+    assert!(size_of::<T>() + 16 <= MAX_PAYLOAD_LEN, "UserPod type too large: size_of::<T>() must be at most MAX_PAYLOAD_LEN - 16");
+    // 否则 repr(C) 会补 padding（Pod 不成立），而且 0 字节的 T 会和 ArgPtr 的 tag 8 撞上
+    assert!(size_of::<T>() > 0 && size_of::<T>().is_multiple_of(8), "UserPod type must be a non-empty multiple of 8 bytes");
+    assert!(align_of::<T>() <= 8, "UserPod type must be at most 8-aligned");
+    // the tag is the snapshot's own size, what the decoder skips
+    size_of::<Self>() as u8
+  };
 }
 
 impl <T: UserPod> IntoArg for T {
@@ -120,15 +538,150 @@ impl <T: UserPod> IntoArg for T {
 
   fn into_arg(self) -> Self::D {
     UserPodSnap {
-      decode_fn: T::decode as u64,
+      pod_id: user_pod_id::<T>() as u64,
       data: self,
     }
   }
 }
 
+// (id, type name, decoder)
+static USER_PODS: RwLock<Vec<(u32, &'static str, DecodeFn)>> = RwLock::new(Vec::new());
+
+thread_local! {
+  // 生产者线程上 TypeId -> id，免得每条日志都去拿全局锁
+  static POD_IDS: RefCell<Vec<(TypeId, u32)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Register `T` as decodable and return its id: FNV-1a of `type_name::<T>()`, the same in every process
+/// built with `T`. Logging a `T` registers it on first use; an offline reader calls this for the types
+/// it expects to find. Panics if two different types hash to the same id.
+pub fn register_user_pod<T: UserPod>() -> u32 {
+  let name = std::any::type_name::<T>();
+  let id = fnv1a32(name.as_bytes());
+  let mut pods = USER_PODS.write().unwrap();
+  match pods.iter().find(|(pid, _, _)| *pid == id) {
+    Some((_, other, _)) => assert!(*other == name, "UserPod id collision: {} and {}", other, name),
+    None => pods.push((id, name, T::decode)),
+  }
+  id
+}
+
+#[inline(always)]
+fn user_pod_id<T: UserPod>() -> u32 {
+  POD_IDS.with(|ids| {
+    let tid = TypeId::of::<T>();
+    if let Some((_, id)) = ids.borrow().iter().find(|(t, _)| *t == tid) {
+      return *id;
+    }
+    let id = register_user_pod::<T>();
+    ids.borrow_mut().push((tid, id));
+    id
+  })
+}
+
+fn user_pod_decoder(id: u32) -> Option<DecodeFn> {
+  USER_PODS.read().unwrap().iter().find(|(pid, _, _)| *pid == id).map(|(_, _, f)| *f)
+}
+
+const fn fnv1a32(bytes: &[u8]) -> u32 {
+  let mut h = 0x811c9dc5u32;
+  let mut i = 0;
+  while i < bytes.len() {
+    h = (h ^ bytes[i] as u32).wrapping_mul(0x01000193);
+    i += 1;
+  }
+  h
+}
+
+/// What gets written to the queue for one record. An `ArgsN` encodes as its N tag bytes,
+/// zero-padded to a multiple of 8, then each arg's own encoding back to back (see `decode_args`).
+pub trait Payload {
+  /// Fewest bytes the payload can take, checked against `MAX_PAYLOAD_LEN` at compile time.
+  const MIN_ENCODED_LEN: usize;
+
+  /// Bytes `encode` writes.
+  fn encoded_len(&self) -> usize;
+
+  /// Write the payload to the front of `out`, which holds at least `encoded_len()` bytes;
+  /// returns how many bytes that took.
+  fn encode(&self, out: &mut [u8]) -> usize;
+}
+
+/// Any other plain-old-data value is copied as it is.
+impl<T: Pod> Payload for T {
+  const MIN_ENCODED_LEN: usize = size_of::<T>();
+
+  #[inline(always)]
+  fn encoded_len(&self) -> usize {
+    size_of::<T>()
+  }
+
+  #[inline(always)]
+  fn encode(&self, out: &mut [u8]) -> usize {
+    out[..size_of::<T>()].copy_from_slice(bytemuck::bytes_of(self));
+    size_of::<T>()
+  }
+}
+
+/// Bytes that are already encoded, e.g. from `encode_to_vec`.
+impl Payload for [u8] {
+  const MIN_ENCODED_LEN: usize = 0;
+
+  #[inline(always)]
+  fn encoded_len(&self) -> usize {
+    self.len()
+  }
+
+  #[inline(always)]
+  fn encode(&self, out: &mut [u8]) -> usize {
+    out[..self.len()].copy_from_slice(self);
+    self.len()
+  }
+}
+
+/// The encoded payload as an owned buffer, for `LoggerHandle::log` and tests.
+pub fn encode_to_vec<P: Payload>(payload: &P) -> Vec<u8> {
+  let mut out = vec![0u8; payload.encoded_len()];
+  payload.encode(&mut out);
+  out
+}
+
+/// Payload of a message without args: empty, nothing to decode.
 #[derive(Copy, Clone, Pod, Zeroable)]
-#[repr(C, packed)]
-pub struct Args2<T1: Arg, T2: Arg> {
+#[repr(C)]
+pub struct Args0;
+
+#[inline]
+pub fn args0() -> Args0 {
+  Args0
+}
+
+#[derive(Copy, Clone)]
+pub struct Args1<T1>
+where
+  T1: Arg,
+{
+  pub tag1: u8,
+  _pad: [u8; 7],
+  pub arg1: T1,
+}
+
+#[inline]
+pub fn args1<T1: IntoArg>(arg1: T1) -> Args1::<T1::D> {
+  const { assert!(<Args1::<T1::D> as Payload>::MIN_ENCODED_LEN <= MAX_PAYLOAD_LEN, "log args exceed MAX_PAYLOAD_LEN") };
+  let arg1 = arg1.into_arg();
+  Args1 {
+    tag1: T1::D::ARG_TAG,
+    _pad: [0; 7],
+    arg1,
+  }
+}
+
+#[derive(Copy, Clone)]
+pub struct Args2<T1, T2>
+where
+  T1: Arg, T2: Arg,
+{
   pub tag1: u8,
   pub tag2: u8,
   _pad: [u8; 6],
@@ -138,6 +691,7 @@ pub struct Args2<T1: Arg, T2: Arg> {
 
 #[inline]
 pub fn args2<T1: IntoArg, T2: IntoArg>(arg1: T1, arg2: T2) -> Args2::<T1::D, T2::D> {
+  const { assert!(<Args2::<T1::D, T2::D> as Payload>::MIN_ENCODED_LEN <= MAX_PAYLOAD_LEN, "log args exceed MAX_PAYLOAD_LEN") };
   let arg1 = arg1.into_arg();
   let arg2 = arg2.into_arg();
   Args2 {
@@ -149,11 +703,576 @@ pub fn args2<T1: IntoArg, T2: IntoArg>(arg1: T1, arg2: T2) -> Args2::<T1::D, T2:
   }
 }
 
+#[derive(Copy, Clone)]
+pub struct Args3<T1, T2, T3>
+where
+  T1: Arg, T2: Arg, T3: Arg,
+{
+  pub tag1: u8,
+  pub tag2: u8,
+  pub tag3: u8,
+  _pad: [u8; 5],
+  pub arg1: T1,
+  pub arg2: T2,
+  pub arg3: T3,
+}
+
+#[inline]
+pub fn args3<T1: IntoArg, T2: IntoArg, T3: IntoArg>(arg1: T1, arg2: T2, arg3: T3) -> Args3::<T1::D, T2::D, T3::D> {
+  const { assert!(<Args3::<T1::D, T2::D, T3::D> as Payload>::MIN_ENCODED_LEN <= MAX_PAYLOAD_LEN, "log args exceed MAX_PAYLOAD_LEN") };
+  let arg1 = arg1.into_arg();
+  let arg2 = arg2.into_arg();
+  let arg3 = arg3.into_arg();
+  Args3 {
+    tag1: T1::D::ARG_TAG,
+    tag2: T2::D::ARG_TAG,
+    tag3: T3::D::ARG_TAG,
+    _pad: [0; 5],
+    arg1,
+    arg2,
+    arg3,
+  }
+}
+
+#[derive(Copy, Clone)]
+pub struct Args4<T1, T2, T3, T4>
+where
+  T1: Arg, T2: Arg, T3: Arg, T4: Arg,
+{
+  pub tag1: u8,
+  pub tag2: u8,
+  pub tag3: u8,
+  pub tag4: u8,
+  _pad: [u8; 4],
+  pub arg1: T1,
+  pub arg2: T2,
+  pub arg3: T3,
+  pub arg4: T4,
+}
+
+#[inline]
+pub fn args4<T1: IntoArg, T2: IntoArg, T3: IntoArg, T4: IntoArg>(arg1: T1, arg2: T2, arg3: T3, arg4: T4) -> Args4::<T1::D, T2::D, T3::D, T4::D> {
+  const { assert!(<Args4::<T1::D, T2::D, T3::D, T4::D> as Payload>::MIN_ENCODED_LEN <= MAX_PAYLOAD_LEN, "log args exceed MAX_PAYLOAD_LEN") };
+  let arg1 = arg1.into_arg();
+  let arg2 = arg2.into_arg();
+  let arg3 = arg3.into_arg();
+  let arg4 = arg4.into_arg();
+  Args4 {
+    tag1: T1::D::ARG_TAG,
+    tag2: T2::D::ARG_TAG,
+    tag3: T3::D::ARG_TAG,
+    tag4: T4::D::ARG_TAG,
+    _pad: [0; 4],
+    arg1,
+    arg2,
+    arg3,
+    arg4,
+  }
+}
+
+#[derive(Copy, Clone)]
+pub struct Args5<T1: Arg, T2: Arg, T3: Arg, T4: Arg, T5: Arg> {
+  pub tag1: u8,
+  pub tag2: u8,
+  pub tag3: u8,
+  pub tag4: u8,
+  pub tag5: u8,
+  _pad: [u8; 3],
+  pub arg1: T1,
+  pub arg2: T2,
+  pub arg3: T3,
+  pub arg4: T4,
+  pub arg5: T5,
+}
+
+// argsN takes one param per macro arg and returns ArgsN of their `IntoArg::D`s, that is the whole
+// point of the arity; a type alias or a params struct would only hide it.
+#[inline]
+#[allow(clippy::type_complexity)]
+pub fn args5<T1: IntoArg, T2: IntoArg, T3: IntoArg, T4: IntoArg, T5: IntoArg>(arg1: T1, arg2: T2, arg3: T3, arg4: T4, arg5: T5) -> Args5::<T1::D, T2::D, T3::D, T4::D, T5::D> {
+  const { assert!(<Args5::<T1::D, T2::D, T3::D, T4::D, T5::D> as Payload>::MIN_ENCODED_LEN <= MAX_PAYLOAD_LEN, "log args exceed MAX_PAYLOAD_LEN") };
+  let arg1 = arg1.into_arg();
+  let arg2 = arg2.into_arg();
+  let arg3 = arg3.into_arg();
+  let arg4 = arg4.into_arg();
+  let arg5 = arg5.into_arg();
+  Args5 {
+    tag1: T1::D::ARG_TAG,
+    tag2: T2::D::ARG_TAG,
+    tag3: T3::D::ARG_TAG,
+    tag4: T4::D::ARG_TAG,
+    tag5: T5::D::ARG_TAG,
+    _pad: [0; 3],
+    arg1,
+    arg2,
+    arg3,
+    arg4,
+    arg5,
+  }
+}
+
+#[derive(Copy, Clone)]
+pub struct Args6<T1: Arg, T2: Arg, T3: Arg, T4: Arg, T5: Arg, T6: Arg> {
+  pub tag1: u8,
+  pub tag2: u8,
+  pub tag3: u8,
+  pub tag4: u8,
+  pub tag5: u8,
+  pub tag6: u8,
+  _pad: [u8; 2],
+  pub arg1: T1,
+  pub arg2: T2,
+  pub arg3: T3,
+  pub arg4: T4,
+  pub arg5: T5,
+  pub arg6: T6,
+}
+
+#[inline]
+#[allow(clippy::type_complexity)]
+pub fn args6<T1: IntoArg, T2: IntoArg, T3: IntoArg, T4: IntoArg, T5: IntoArg, T6: IntoArg>(arg1: T1, arg2: T2, arg3: T3, arg4: T4, arg5: T5, arg6: T6) -> Args6::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D> {
+  const { assert!(<Args6::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D> as Payload>::MIN_ENCODED_LEN <= MAX_PAYLOAD_LEN, "log args exceed MAX_PAYLOAD_LEN") };
+  let arg1 = arg1.into_arg();
+  let arg2 = arg2.into_arg();
+  let arg3 = arg3.into_arg();
+  let arg4 = arg4.into_arg();
+  let arg5 = arg5.into_arg();
+  let arg6 = arg6.into_arg();
+  Args6 {
+    tag1: T1::D::ARG_TAG,
+    tag2: T2::D::ARG_TAG,
+    tag3: T3::D::ARG_TAG,
+    tag4: T4::D::ARG_TAG,
+    tag5: T5::D::ARG_TAG,
+    tag6: T6::D::ARG_TAG,
+    _pad: [0; 2],
+    arg1,
+    arg2,
+    arg3,
+    arg4,
+    arg5,
+    arg6,
+  }
+}
+
+// every ArgsN is checked at compile time against MAX_PAYLOAD_LEN, a `&str` counting as 8 bytes there;
+// past 8 args the tag header takes 16 bytes.
+#[derive(Copy, Clone)]
+pub struct Args7<T1: Arg, T2: Arg, T3: Arg, T4: Arg, T5: Arg, T6: Arg, T7: Arg> {
+  pub tag1: u8,
+  pub tag2: u8,
+  pub tag3: u8,
+  pub tag4: u8,
+  pub tag5: u8,
+  pub tag6: u8,
+  pub tag7: u8,
+  _pad: [u8; 1],
+  pub arg1: T1,
+  pub arg2: T2,
+  pub arg3: T3,
+  pub arg4: T4,
+  pub arg5: T5,
+  pub arg6: T6,
+  pub arg7: T7,
+}
+
+#[inline]
+#[allow(clippy::type_complexity)]
+pub fn args7<T1: IntoArg, T2: IntoArg, T3: IntoArg, T4: IntoArg, T5: IntoArg, T6: IntoArg, T7: IntoArg>(arg1: T1, arg2: T2, arg3: T3, arg4: T4, arg5: T5, arg6: T6, arg7: T7) -> Args7::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D, T7::D> {
+  const { assert!(<Args7::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D, T7::D> as Payload>::MIN_ENCODED_LEN <= MAX_PAYLOAD_LEN, "log args exceed MAX_PAYLOAD_LEN") };
+  let arg1 = arg1.into_arg();
+  let arg2 = arg2.into_arg();
+  let arg3 = arg3.into_arg();
+  let arg4 = arg4.into_arg();
+  let arg5 = arg5.into_arg();
+  let arg6 = arg6.into_arg();
+  let arg7 = arg7.into_arg();
+  Args7 {
+    tag1: T1::D::ARG_TAG,
+    tag2: T2::D::ARG_TAG,
+    tag3: T3::D::ARG_TAG,
+    tag4: T4::D::ARG_TAG,
+    tag5: T5::D::ARG_TAG,
+    tag6: T6::D::ARG_TAG,
+    tag7: T7::D::ARG_TAG,
+    _pad: [0; 1],
+    arg1,
+    arg2,
+    arg3,
+    arg4,
+    arg5,
+    arg6,
+    arg7,
+  }
+}
+
+#[derive(Copy, Clone)]
+pub struct Args8<T1: Arg, T2: Arg, T3: Arg, T4: Arg, T5: Arg, T6: Arg, T7: Arg, T8: Arg> {
+  pub tag1: u8,
+  pub tag2: u8,
+  pub tag3: u8,
+  pub tag4: u8,
+  pub tag5: u8,
+  pub tag6: u8,
+  pub tag7: u8,
+  pub tag8: u8,
+  pub arg1: T1,
+  pub arg2: T2,
+  pub arg3: T3,
+  pub arg4: T4,
+  pub arg5: T5,
+  pub arg6: T6,
+  pub arg7: T7,
+  pub arg8: T8,
+}
+
+#[inline]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn args8<T1: IntoArg, T2: IntoArg, T3: IntoArg, T4: IntoArg, T5: IntoArg, T6: IntoArg, T7: IntoArg, T8: IntoArg>(arg1: T1, arg2: T2, arg3: T3, arg4: T4, arg5: T5, arg6: T6, arg7: T7, arg8: T8) -> Args8::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D, T7::D, T8::D> {
+  const { assert!(<Args8::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D, T7::D, T8::D> as Payload>::MIN_ENCODED_LEN <= MAX_PAYLOAD_LEN, "log args exceed MAX_PAYLOAD_LEN") };
+  let arg1 = arg1.into_arg();
+  let arg2 = arg2.into_arg();
+  let arg3 = arg3.into_arg();
+  let arg4 = arg4.into_arg();
+  let arg5 = arg5.into_arg();
+  let arg6 = arg6.into_arg();
+  let arg7 = arg7.into_arg();
+  let arg8 = arg8.into_arg();
+  Args8 {
+    tag1: T1::D::ARG_TAG,
+    tag2: T2::D::ARG_TAG,
+    tag3: T3::D::ARG_TAG,
+    tag4: T4::D::ARG_TAG,
+    tag5: T5::D::ARG_TAG,
+    tag6: T6::D::ARG_TAG,
+    tag7: T7::D::ARG_TAG,
+    tag8: T8::D::ARG_TAG,
+    arg1,
+    arg2,
+    arg3,
+    arg4,
+    arg5,
+    arg6,
+    arg7,
+    arg8,
+  }
+}
+
+#[derive(Copy, Clone)]
+pub struct Args9<T1: Arg, T2: Arg, T3: Arg, T4: Arg, T5: Arg, T6: Arg, T7: Arg, T8: Arg, T9: Arg> {
+  pub tag1: u8,
+  pub tag2: u8,
+  pub tag3: u8,
+  pub tag4: u8,
+  pub tag5: u8,
+  pub tag6: u8,
+  pub tag7: u8,
+  pub tag8: u8,
+  pub tag9: u8,
+  _pad: [u8; 7],
+  pub arg1: T1,
+  pub arg2: T2,
+  pub arg3: T3,
+  pub arg4: T4,
+  pub arg5: T5,
+  pub arg6: T6,
+  pub arg7: T7,
+  pub arg8: T8,
+  pub arg9: T9,
+}
+
+#[inline]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn args9<T1: IntoArg, T2: IntoArg, T3: IntoArg, T4: IntoArg, T5: IntoArg, T6: IntoArg, T7: IntoArg, T8: IntoArg, T9: IntoArg>(arg1: T1, arg2: T2, arg3: T3, arg4: T4, arg5: T5, arg6: T6, arg7: T7, arg8: T8, arg9: T9) -> Args9::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D, T7::D, T8::D, T9::D> {
+  const { assert!(<Args9::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D, T7::D, T8::D, T9::D> as Payload>::MIN_ENCODED_LEN <= MAX_PAYLOAD_LEN, "log args exceed MAX_PAYLOAD_LEN") };
+  let arg1 = arg1.into_arg();
+  let arg2 = arg2.into_arg();
+  let arg3 = arg3.into_arg();
+  let arg4 = arg4.into_arg();
+  let arg5 = arg5.into_arg();
+  let arg6 = arg6.into_arg();
+  let arg7 = arg7.into_arg();
+  let arg8 = arg8.into_arg();
+  let arg9 = arg9.into_arg();
+  Args9 {
+    tag1: T1::D::ARG_TAG,
+    tag2: T2::D::ARG_TAG,
+    tag3: T3::D::ARG_TAG,
+    tag4: T4::D::ARG_TAG,
+    tag5: T5::D::ARG_TAG,
+    tag6: T6::D::ARG_TAG,
+    tag7: T7::D::ARG_TAG,
+    tag8: T8::D::ARG_TAG,
+    tag9: T9::D::ARG_TAG,
+    _pad: [0; 7],
+    arg1,
+    arg2,
+    arg3,
+    arg4,
+    arg5,
+    arg6,
+    arg7,
+    arg8,
+    arg9,
+  }
+}
+
+#[derive(Copy, Clone)]
+pub struct Args10<T1: Arg, T2: Arg, T3: Arg, T4: Arg, T5: Arg, T6: Arg, T7: Arg, T8: Arg, T9: Arg, T10: Arg> {
+  pub tag1: u8,
+  pub tag2: u8,
+  pub tag3: u8,
+  pub tag4: u8,
+  pub tag5: u8,
+  pub tag6: u8,
+  pub tag7: u8,
+  pub tag8: u8,
+  pub tag9: u8,
+  pub tag10: u8,
+  _pad: [u8; 6],
+  pub arg1: T1,
+  pub arg2: T2,
+  pub arg3: T3,
+  pub arg4: T4,
+  pub arg5: T5,
+  pub arg6: T6,
+  pub arg7: T7,
+  pub arg8: T8,
+  pub arg9: T9,
+  pub arg10: T10,
+}
+
+#[inline]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn args10<T1: IntoArg, T2: IntoArg, T3: IntoArg, T4: IntoArg, T5: IntoArg, T6: IntoArg, T7: IntoArg, T8: IntoArg, T9: IntoArg, T10: IntoArg>(arg1: T1, arg2: T2, arg3: T3, arg4: T4, arg5: T5, arg6: T6, arg7: T7, arg8: T8, arg9: T9, arg10: T10) -> Args10::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D, T7::D, T8::D, T9::D, T10::D> {
+  const { assert!(<Args10::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D, T7::D, T8::D, T9::D, T10::D> as Payload>::MIN_ENCODED_LEN <= MAX_PAYLOAD_LEN, "log args exceed MAX_PAYLOAD_LEN") };
+  let arg1 = arg1.into_arg();
+  let arg2 = arg2.into_arg();
+  let arg3 = arg3.into_arg();
+  let arg4 = arg4.into_arg();
+  let arg5 = arg5.into_arg();
+  let arg6 = arg6.into_arg();
+  let arg7 = arg7.into_arg();
+  let arg8 = arg8.into_arg();
+  let arg9 = arg9.into_arg();
+  let arg10 = arg10.into_arg();
+  Args10 {
+    tag1: T1::D::ARG_TAG,
+    tag2: T2::D::ARG_TAG,
+    tag3: T3::D::ARG_TAG,
+    tag4: T4::D::ARG_TAG,
+    tag5: T5::D::ARG_TAG,
+    tag6: T6::D::ARG_TAG,
+    tag7: T7::D::ARG_TAG,
+    tag8: T8::D::ARG_TAG,
+    tag9: T9::D::ARG_TAG,
+    tag10: T10::D::ARG_TAG,
+    _pad: [0; 6],
+    arg1,
+    arg2,
+    arg3,
+    arg4,
+    arg5,
+    arg6,
+    arg7,
+    arg8,
+    arg9,
+    arg10,
+  }
+}
+
+#[derive(Copy, Clone)]
+pub struct Args11<T1: Arg, T2: Arg, T3: Arg, T4: Arg, T5: Arg, T6: Arg, T7: Arg, T8: Arg, T9: Arg, T10: Arg, T11: Arg> {
+  pub tag1: u8,
+  pub tag2: u8,
+  pub tag3: u8,
+  pub tag4: u8,
+  pub tag5: u8,
+  pub tag6: u8,
+  pub tag7: u8,
+  pub tag8: u8,
+  pub tag9: u8,
+  pub tag10: u8,
+  pub tag11: u8,
+  _pad: [u8; 5],
+  pub arg1: T1,
+  pub arg2: T2,
+  pub arg3: T3,
+  pub arg4: T4,
+  pub arg5: T5,
+  pub arg6: T6,
+  pub arg7: T7,
+  pub arg8: T8,
+  pub arg9: T9,
+  pub arg10: T10,
+  pub arg11: T11,
+}
+
+#[inline]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn args11<T1: IntoArg, T2: IntoArg, T3: IntoArg, T4: IntoArg, T5: IntoArg, T6: IntoArg, T7: IntoArg, T8: IntoArg, T9: IntoArg, T10: IntoArg, T11: IntoArg>(arg1: T1, arg2: T2, arg3: T3, arg4: T4, arg5: T5, arg6: T6, arg7: T7, arg8: T8, arg9: T9, arg10: T10, arg11: T11) -> Args11::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D, T7::D, T8::D, T9::D, T10::D, T11::D> {
+  const { assert!(<Args11::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D, T7::D, T8::D, T9::D, T10::D, T11::D> as Payload>::MIN_ENCODED_LEN <= MAX_PAYLOAD_LEN, "log args exceed MAX_PAYLOAD_LEN") };
+  let arg1 = arg1.into_arg();
+  let arg2 = arg2.into_arg();
+  let arg3 = arg3.into_arg();
+  let arg4 = arg4.into_arg();
+  let arg5 = arg5.into_arg();
+  let arg6 = arg6.into_arg();
+  let arg7 = arg7.into_arg();
+  let arg8 = arg8.into_arg();
+  let arg9 = arg9.into_arg();
+  let arg10 = arg10.into_arg();
+  let arg11 = arg11.into_arg();
+  Args11 {
+    tag1: T1::D::ARG_TAG,
+    tag2: T2::D::ARG_TAG,
+    tag3: T3::D::ARG_TAG,
+    tag4: T4::D::ARG_TAG,
+    tag5: T5::D::ARG_TAG,
+    tag6: T6::D::ARG_TAG,
+    tag7: T7::D::ARG_TAG,
+    tag8: T8::D::ARG_TAG,
+    tag9: T9::D::ARG_TAG,
+    tag10: T10::D::ARG_TAG,
+    tag11: T11::D::ARG_TAG,
+    _pad: [0; 5],
+    arg1,
+    arg2,
+    arg3,
+    arg4,
+    arg5,
+    arg6,
+    arg7,
+    arg8,
+    arg9,
+    arg10,
+    arg11,
+  }
+}
+
+#[derive(Copy, Clone)]
+pub struct Args12<T1: Arg, T2: Arg, T3: Arg, T4: Arg, T5: Arg, T6: Arg, T7: Arg, T8: Arg, T9: Arg, T10: Arg, T11: Arg, T12: Arg> {
+  pub tag1: u8,
+  pub tag2: u8,
+  pub tag3: u8,
+  pub tag4: u8,
+  pub tag5: u8,
+  pub tag6: u8,
+  pub tag7: u8,
+  pub tag8: u8,
+  pub tag9: u8,
+  pub tag10: u8,
+  pub tag11: u8,
+  pub tag12: u8,
+  _pad: [u8; 4],
+  pub arg1: T1,
+  pub arg2: T2,
+  pub arg3: T3,
+  pub arg4: T4,
+  pub arg5: T5,
+  pub arg6: T6,
+  pub arg7: T7,
+  pub arg8: T8,
+  pub arg9: T9,
+  pub arg10: T10,
+  pub arg11: T11,
+  pub arg12: T12,
+}
+
+#[inline]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn args12<T1: IntoArg, T2: IntoArg, T3: IntoArg, T4: IntoArg, T5: IntoArg, T6: IntoArg, T7: IntoArg, T8: IntoArg, T9: IntoArg, T10: IntoArg, T11: IntoArg, T12: IntoArg>(arg1: T1, arg2: T2, arg3: T3, arg4: T4, arg5: T5, arg6: T6, arg7: T7, arg8: T8, arg9: T9, arg10: T10, arg11: T11, arg12: T12) -> Args12::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D, T7::D, T8::D, T9::D, T10::D, T11::D, T12::D> {
+  const { assert!(<Args12::<T1::D, T2::D, T3::D, T4::D, T5::D, T6::D, T7::D, T8::D, T9::D, T10::D, T11::D, T12::D> as Payload>::MIN_ENCODED_LEN <= MAX_PAYLOAD_LEN, "log args exceed MAX_PAYLOAD_LEN") };
+  let arg1 = arg1.into_arg();
+  let arg2 = arg2.into_arg();
+  let arg3 = arg3.into_arg();
+  let arg4 = arg4.into_arg();
+  let arg5 = arg5.into_arg();
+  let arg6 = arg6.into_arg();
+  let arg7 = arg7.into_arg();
+  let arg8 = arg8.into_arg();
+  let arg9 = arg9.into_arg();
+  let arg10 = arg10.into_arg();
+  let arg11 = arg11.into_arg();
+  let arg12 = arg12.into_arg();
+  Args12 {
+    tag1: T1::D::ARG_TAG,
+    tag2: T2::D::ARG_TAG,
+    tag3: T3::D::ARG_TAG,
+    tag4: T4::D::ARG_TAG,
+    tag5: T5::D::ARG_TAG,
+    tag6: T6::D::ARG_TAG,
+    tag7: T7::D::ARG_TAG,
+    tag8: T8::D::ARG_TAG,
+    tag9: T9::D::ARG_TAG,
+    tag10: T10::D::ARG_TAG,
+    tag11: T11::D::ARG_TAG,
+    tag12: T12::D::ARG_TAG,
+    _pad: [0; 4],
+    arg1,
+    arg2,
+    arg3,
+    arg4,
+    arg5,
+    arg6,
+    arg7,
+    arg8,
+    arg9,
+    arg10,
+    arg11,
+    arg12,
+  }
+}
+
+macro_rules! args_payload {
+  ($args:ident, $n:literal, $($t:ident $tag:ident $arg:ident),+) => {
+    impl<$($t: Arg),+> Payload for $args<$($t),+> {
+      const MIN_ENCODED_LEN: usize = (($n + 7) & !7) $(+ $t::MIN_ENCODED_LEN)+;
+
+      #[inline(always)]
+      fn encoded_len(&self) -> usize {
+        (($n + 7) & !7) $(+ self.$arg.encoded_len())+
+      }
+
+      #[inline(always)]
+      fn encode(&self, out: &mut [u8]) -> usize {
+        let mut offset = ($n + 7) & !7;
+        out[..offset].fill(0);
+        out[..$n].copy_from_slice(&[$(self.$tag),+]);
+        $(offset += self.$arg.encode(&mut out[offset..]);)+
+        offset
+      }
+    }
+  };
+}
+
+args_payload!(Args1, 1, T1 tag1 arg1);
+args_payload!(Args2, 2, T1 tag1 arg1, T2 tag2 arg2);
+args_payload!(Args3, 3, T1 tag1 arg1, T2 tag2 arg2, T3 tag3 arg3);
+args_payload!(Args4, 4, T1 tag1 arg1, T2 tag2 arg2, T3 tag3 arg3, T4 tag4 arg4);
+args_payload!(Args5, 5, T1 tag1 arg1, T2 tag2 arg2, T3 tag3 arg3, T4 tag4 arg4, T5 tag5 arg5);
+args_payload!(Args6, 6, T1 tag1 arg1, T2 tag2 arg2, T3 tag3 arg3, T4 tag4 arg4, T5 tag5 arg5, T6 tag6 arg6);
+args_payload!(Args7, 7, T1 tag1 arg1, T2 tag2 arg2, T3 tag3 arg3, T4 tag4 arg4, T5 tag5 arg5, T6 tag6 arg6, T7 tag7 arg7);
+args_payload!(Args8, 8, T1 tag1 arg1, T2 tag2 arg2, T3 tag3 arg3, T4 tag4 arg4, T5 tag5 arg5, T6 tag6 arg6, T7 tag7 arg7, T8 tag8 arg8);
+args_payload!(Args9, 9, T1 tag1 arg1, T2 tag2 arg2, T3 tag3 arg3, T4 tag4 arg4, T5 tag5 arg5, T6 tag6 arg6, T7 tag7 arg7, T8 tag8 arg8, T9 tag9 arg9);
+args_payload!(Args10, 10, T1 tag1 arg1, T2 tag2 arg2, T3 tag3 arg3, T4 tag4 arg4, T5 tag5 arg5, T6 tag6 arg6, T7 tag7 arg7, T8 tag8 arg8, T9 tag9 arg9, T10 tag10 arg10);
+args_payload!(Args11, 11, T1 tag1 arg1, T2 tag2 arg2, T3 tag3 arg3, T4 tag4 arg4, T5 tag5 arg5, T6 tag6 arg6, T7 tag7 arg7, T8 tag8 arg8, T9 tag9 arg9, T10 tag10 arg10, T11 tag11 arg11);
+args_payload!(Args12, 12, T1 tag1 arg1, T2 tag2 arg2, T3 tag3 arg3, T4 tag4 arg4, T5 tag5 arg5, T6 tag6 arg6, T7 tag7 arg7, T8 tag8 arg8, T9 tag9 arg9, T10 tag10 arg10, T11 tag11 arg11, T12 tag12 arg12);
+
 pub enum DecodeResult<'a> {
   F64(f64),
   U64(u64),
   I64(i64),
+  Str(&'a str),
+  Bool(bool),
+  Char(char),
+  Hex(&'a ArgHex),
+  /// raw tsc, see `Tsc`; displayed via `tscns::tsc2ns`, not a custom clock
+  Tsc(i64),
+  Ptr(&'a ArgPtr),
   Snap(SnapBytes<'a>),
+  /// the arg does not fit the rest of the payload (truncated/corrupt record), shown as `<decode error>`
+  Error,
 }
 
 impl <'a> Display for DecodeResult<'a> {
@@ -163,12 +1282,73 @@ impl <'a> Display for DecodeResult<'a> {
       DecodeResult::F64(v) => v.fmt(f),
       DecodeResult::U64(v) => v.fmt(f),
       DecodeResult::I64(v) => v.fmt(f),
+      DecodeResult::Str(v) if ESCAPE_STRS.with(Cell::get) => fmt_escaped(v, f),
+      DecodeResult::Str(v) => v.fmt(f),
+      DecodeResult::Bool(v) => v.fmt(f),
+      DecodeResult::Char(v) => v.fmt(f),
+      DecodeResult::Hex(v) => v.fmt(f),
+      DecodeResult::Tsc(v) => EpochNs(tscns::tsc2ns(*v)).fmt(f),
+      DecodeResult::Ptr(v) => v.fmt(f),
       DecodeResult::Snap(s) => s.fmt(f),
+      DecodeResult::Error => f.write_str("<decode error>"),
     }
   }
 }
 
-pub fn decode(tag: u8, bytes: &[u8], offset: usize) -> (DecodeResult, usize) {
+thread_local! {
+  // set by the formatter before each record, from its sink's `with_escape_strings`
+  static ESCAPE_STRS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Render `&str` args of the records formatted next on this thread with control characters escaped.
+#[inline(always)]
+pub(crate) fn set_escape_strs(on: bool) {
+  ESCAPE_STRS.with(|e| e.set(on));
+}
+
+/// `\n`, `\r`, `\t` and `\x1b` spelled out, so untrusted input cannot start a fake line or
+/// recolor the terminal. Width/alignment specs still apply.
+fn fmt_escaped(s: &str, f: &mut Formatter<'_>) -> fmt::Result {
+  if !s.bytes().any(|b| matches!(b, b'\n' | b'\r' | b'\t' | 0x1b)) {
+    return s.fmt(f);
+  }
+  let mut out = String::with_capacity(s.len() + 8);
+  for c in s.chars() {
+    match c {
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      '\x1b' => out.push_str("\\x1b"),
+      c => out.push(c),
+    }
+  }
+  f.pad(&out)
+}
+
+/// Bytes the arg with `tag` at `offset` takes in the payload; `None` if it is a `&str` whose
+/// length prefix is cut off.
+#[inline(always)]
+pub(crate) fn arg_size(tag: u8, bytes: &[u8], offset: usize) -> Option<usize> {
+  match tag {
+    0 | 1 | 2 | 4 | 5 | 7 => Some(8),
+    3 => {
+      let len = bytes.get(offset..offset.checked_add(2)?)?;
+      Some(str_encoded_len(u16::from_ne_bytes([len[0], len[1]]) as usize))
+    },
+    6 => Some(size_of::<ArgHex>()),
+    8 => Some(size_of::<ArgPtr>()),
+    len => Some(len as usize),
+  }
+}
+
+/// Decode the arg at `offset`. Never reads past `bytes`: an arg that does not fit, or whose inline
+/// length is out of range, decodes as `DecodeResult::Error` and moves `offset` to the end,
+/// so every arg after it is an error too.
+pub fn decode(tag: u8, bytes: &[u8], offset: usize) -> (DecodeResult<'_>, usize) {
+  let end = arg_size(tag, bytes, offset).and_then(|size| offset.checked_add(size));
+  if end.is_none_or(|end| end > bytes.len()) {
+    return (DecodeResult::Error, bytes.len());
+  }
   match tag {
     0 => {
       let v = repr_off_as::<f64>(bytes, offset);
@@ -182,12 +1362,42 @@ pub fn decode(tag: u8, bytes: &[u8], offset: usize) -> (DecodeResult, usize) {
       let v = repr_off_as::<i64>(bytes, offset);
       (DecodeResult::I64(*v), offset + 8)
     },
+    3 => {
+      let len = *repr_off_as::<u16>(bytes, offset) as usize;
+      let Ok(v) = std::str::from_utf8(&bytes[offset + 2..offset + 2 + len]) else {
+        return (DecodeResult::Error, bytes.len());
+      };
+      (DecodeResult::Str(v), offset + str_encoded_len(len))
+    },
+    4 => {
+      let v = repr_off_as::<u8>(bytes, offset);
+      (DecodeResult::Bool(*v != 0), offset + 8)
+    },
+    5 => {
+      let v = repr_off_as::<u32>(bytes, offset);
+      (DecodeResult::Char(char::from_u32(*v).unwrap_or(char::REPLACEMENT_CHARACTER)), offset + 8)
+    },
+    6 => {
+      let v = repr_off_as::<ArgHex>(bytes, offset);
+      if v.len as usize > HEX_INLINE_CAP {
+        return (DecodeResult::Error, bytes.len());
+      }
+      (DecodeResult::Hex(v), offset + size_of::<ArgHex>())
+    },
+    7 => {
+      let v = repr_off_as::<i64>(bytes, offset);
+      (DecodeResult::Tsc(*v), offset + 8)
+    },
+    8 => {
+      let v = repr_off_as::<ArgPtr>(bytes, offset);
+      (DecodeResult::Ptr(v), offset + size_of::<ArgPtr>())
+    },
     len => {
-      let decode_fn = *repr_off_as::<u64>(bytes, offset);
+      let pod_id = *repr_off_as::<u64>(bytes, offset) as u32;
       let start = offset + 8;
       let new_offset = offset + len as usize;
       let snap_bytes = SnapBytes {
-        decode_fn,
+        pod_id,
         bytes: &bytes[start..new_offset],
       };
       (DecodeResult::Snap(snap_bytes), new_offset)
@@ -195,25 +1405,51 @@ pub fn decode(tag: u8, bytes: &[u8], offset: usize) -> (DecodeResult, usize) {
   }
 }
 
+/// Walk the payload of an `ArgsN`: N tag bytes (padded to a multiple of 8), then the args back to back.
+#[inline(always)]
+pub fn decode_args<const N: usize>(bytes: &[u8]) -> [DecodeResult<'_>; N] {
+  let mut offset = (N + 7) & !7;
+  std::array::from_fn(|i| {
+    let Some(&tag) = bytes.get(i) else {
+      return DecodeResult::Error;
+    };
+    let (arg, next_offset) = decode(tag, bytes, offset);
+    offset = next_offset;
+    arg
+  })
+}
+
 pub fn decode_fmt_args2(bytes: &[u8]) {
-  let tag1 = bytes[0];
-  let tag2 = bytes[1];
-  let (arg1, offset) = decode(tag1, bytes, 8);
-  let (arg2, _) = decode(tag2, bytes, offset);
+  let [arg1, arg2] = decode_args::<2>(bytes);
   println!("arg1 {} arg2 {}", arg1, arg2);
 }
 
 type DecodeFn = fn(&[u8], &mut fmt::Formatter<'_>) -> fmt::Result;
 
 pub(crate) struct SnapBytes<'a> {
-  decode_fn: u64,
+  pod_id: u32,
   bytes: &'a [u8],
 }
 
+impl <'a> SnapBytes<'a> {
+  /// Raw `T` bytes, for readers that have not registered `T`.
+  #[inline]
+  pub(crate) fn bytes(&self) -> &'a [u8] {
+    self.bytes
+  }
+
+  /// Whether this process knows how to decode it (`register_user_pod`).
+  pub(crate) fn registered(&self) -> bool {
+    user_pod_decoder(self.pod_id).is_some()
+  }
+}
+
 impl <'a> Display for SnapBytes<'a> {
   #[inline]
   fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-    let decode_fn = unsafe { transmute::<_, DecodeFn>(self.decode_fn) };
-    decode_fn(self.bytes, f)
+    match user_pod_decoder(self.pod_id) {
+      Some(decode_fn) => decode_fn(self.bytes, f),
+      None => write!(f, "pod#{:08x}[{} bytes]", self.pod_id, self.bytes.len()),
+    }
   }
 }
\ No newline at end of file
@@ -0,0 +1,18 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use hft_log_demo::binary_sink::BinaryDecoder;
+
+/// decode <file>: render a file written by `BinarySink` as text lines on stdout.
+fn main() -> io::Result<()> {
+  let Some(path) = std::env::args().nth(1) else {
+    eprintln!("usage: decode <binary-log-file>");
+    std::process::exit(2);
+  };
+  let mut decoder = BinaryDecoder::new(BufReader::new(File::open(path)?))?;
+
+  let mut out = BufWriter::new(io::stdout().lock());
+  while let Some(line) = decoder.next_line()? {
+    out.write_all(line)?;
+  }
+  out.flush()
+}
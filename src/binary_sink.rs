@@ -0,0 +1,576 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::args2::{arg_size, decode, DecodeResult};
+use crate::format::{EpochNs, LineFormatter};
+use crate::log::{SourceLocation, MAX_PAYLOAD_LEN};
+use crate::metadata::{Metadata, MetadataCache};
+use crate::my_bytes_mut::MyBytesMut;
+use crate::sink::Sink;
+use crate::spsc_var_queue_opt::MsgHeader;
+use crate::tscns::{self, TscClock};
+
+// File layout: MAGIC, then records `u32 len | u8 kind | body` (len counts kind + body),
+// all integers little-endian. Records only ever refer to metadata/clock records written before them.
+pub const MAGIC: &[u8; 8] = b"NLOGBIN1";
+
+/// `u32 id | u8 level | u8 num_args | u32 line | u32 column | str fmt | str module | str file | u8 n | str name * n
+/// | u8 m | str field * m`
+/// (`str` is `u32 len | bytes`). A later record with the same id replaces the earlier one,
+/// e.g. when a new process appends to the file.
+const REC_META: u8 = 1;
+/// `i64 tsc | i64 ns | f64 ns_per_tsc`: tsc -> ns mapping for the records that follow.
+const REC_CLOCK: u8 = 2;
+/// `u32 tid | u32 meta_id | u8 level | i64 tsc | payload`
+const REC_LOG: u8 = 3;
+/// `u32 tid | str name`: from here on `tid` prints as `name`.
+const REC_THREAD: u8 = 4;
+
+/// -------- Binary sink --------
+/// Writes each record unformatted (tsc, metadata id, raw args); all `Display` work is
+/// deferred to [`BinaryDecoder`] / the `decode` binary. Batching is the same as `FileBatchSink`.
+pub struct BinarySink {
+  path: PathBuf,
+  file: File,
+
+  batch: Vec<u8>,
+  metas: MetadataCache,
+  // ids 1..=metas_written are already in the file
+  metas_written: u32,
+  clock_written: bool,
+
+  flush_bytes: usize,
+  flush_interval_cycles: i64,
+  last_flush_cycles: i64,
+}
+
+impl BinarySink {
+  pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+    Self::with_config(path, 256 * 1024, Duration::from_micros(500))
+  }
+
+  pub fn with_config(path: impl Into<PathBuf>, flush_bytes: usize, flush_interval: Duration) -> io::Result<Self> {
+    let path = path.into();
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    if file.metadata()?.len() == 0 {
+      file.write_all(MAGIC)?;
+    }
+
+    Ok(Self {
+      path,
+      file,
+
+      batch: Vec::with_capacity(flush_bytes),
+      metas: MetadataCache::new(),
+      metas_written: 0,
+      clock_written: false,
+
+      flush_bytes,
+      flush_interval_cycles: tscns::ns2cycles(flush_interval.as_nanos() as i64),
+      last_flush_cycles: tscns::read_tsc(),
+    })
+  }
+
+  pub fn path(&self) -> &PathBuf {
+    &self.path
+  }
+
+  fn write_meta(&mut self, id: u32, meta: &Metadata) {
+    let at = begin_record(&mut self.batch, REC_META);
+    self.batch.extend_from_slice(&id.to_le_bytes());
+    self.batch.push(meta.level as u8);
+    self.batch.push(meta.num_args);
+    self.batch.extend_from_slice(&meta.loc.line.to_le_bytes());
+    self.batch.extend_from_slice(&meta.loc.column.to_le_bytes());
+    put_str(&mut self.batch, meta.fmt);
+    put_str(&mut self.batch, meta.loc.module_path);
+    put_str(&mut self.batch, meta.loc.file);
+    self.batch.push(meta.arg_names.len() as u8);
+    for name in meta.arg_names {
+      put_str(&mut self.batch, name);
+    }
+    self.batch.push(meta.field_names.len() as u8);
+    for key in meta.field_names {
+      put_str(&mut self.batch, key);
+    }
+    end_record(&mut self.batch, at);
+  }
+
+  fn write_clock(&mut self) {
+    let tsc = tscns::read_tsc();
+    let at = begin_record(&mut self.batch, REC_CLOCK);
+    self.batch.extend_from_slice(&tsc.to_le_bytes());
+    self.batch.extend_from_slice(&tscns::tsc2ns(tsc).to_le_bytes());
+    self.batch.extend_from_slice(&tscns::get_ns_per_tsc().to_le_bytes());
+    end_record(&mut self.batch, at);
+    self.clock_written = true;
+  }
+
+  #[inline(always)]
+  fn should_flush(&self, now_cycles: i64) -> bool {
+    self.batch.len() >= self.flush_bytes || now_cycles.wrapping_sub(self.last_flush_cycles) >= self.flush_interval_cycles
+  }
+
+  #[inline(always)]
+  fn flush_now(&mut self) -> io::Result<()> {
+    if self.batch.is_empty() {
+      self.last_flush_cycles = tscns::read_tsc();
+      return Ok(());
+    }
+
+    self.file.write_all(&self.batch)?;
+    self.batch.clear();
+    // calibration moves on, every batch starts with a fresh mapping
+    self.clock_written = false;
+    self.last_flush_cycles = tscns::read_tsc();
+    Ok(())
+  }
+}
+
+impl Sink for BinarySink {
+  #[inline(always)]
+  fn on_record(&mut self, tid: usize, log_meta: &MsgHeader, log_payload: &[u8]) -> io::Result<()> {
+    let meta_id = log_meta.meta_id as u32;
+    while self.metas_written < meta_id {
+      let id = self.metas_written + 1;
+      let meta = self.metas.get(id)?;
+      self.write_meta(id, meta);
+      self.metas_written = id;
+    }
+    if !self.clock_written {
+      self.write_clock();
+    }
+
+    let at = begin_record(&mut self.batch, REC_LOG);
+    self.batch.extend_from_slice(&(tid as u32).to_le_bytes());
+    self.batch.extend_from_slice(&meta_id.to_le_bytes());
+    self.batch.push(log_meta.level as u8);
+    self.batch.extend_from_slice(&log_meta.tsc.to_le_bytes());
+    self.batch.extend_from_slice(log_payload);
+    end_record(&mut self.batch, at);
+
+    if self.should_flush(log_meta.tsc) {
+      self.flush_now()?;
+    }
+    Ok(())
+  }
+
+  fn on_thread_name(&mut self, tid: usize, name: &str) -> io::Result<()> {
+    let at = begin_record(&mut self.batch, REC_THREAD);
+    self.batch.extend_from_slice(&(tid as u32).to_le_bytes());
+    put_str(&mut self.batch, name);
+    end_record(&mut self.batch, at);
+    Ok(())
+  }
+
+  #[inline(always)]
+  fn on_idle(&mut self, now_cycles: i64) -> io::Result<()> {
+    if !self.batch.is_empty()
+      && now_cycles.wrapping_sub(self.last_flush_cycles) >= self.flush_interval_cycles
+    {
+      self.flush_now()?;
+    }
+    Ok(())
+  }
+
+  #[inline]
+  fn flush(&mut self) -> io::Result<()> {
+    self.flush_now()
+  }
+}
+
+impl Drop for BinarySink {
+  fn drop(&mut self) {
+    let _ = self.flush_now();
+  }
+}
+
+/// Reserve the length prefix, returns where it is.
+#[inline(always)]
+fn begin_record(buf: &mut Vec<u8>, kind: u8) -> usize {
+  let at = buf.len();
+  buf.extend_from_slice(&[0u8; 4]);
+  buf.push(kind);
+  at
+}
+
+#[inline(always)]
+fn end_record(buf: &mut [u8], at: usize) {
+  let len = (buf.len() - at - 4) as u32;
+  buf[at..at + 4].copy_from_slice(&len.to_le_bytes());
+}
+
+#[inline(always)]
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+  buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+  buf.extend_from_slice(s.as_bytes());
+}
+
+/// -------- Offline decoder --------
+/// Reads a file written by [`BinarySink`] and renders the same text lines `ConsoleBatchSink`
+/// prints (without colors). The args are formatted from the stored format string, so this
+/// works in another process. Holes take the same `Display` options the shims accept
+/// (fill/align, `+`, `0`, width, precision); `UserPod` args print through their `Display` if the
+/// reader called `args2::register_user_pod` for them, as their raw bytes otherwise.
+pub struct BinaryDecoder<R: Read> {
+  reader: R,
+  record: Vec<u8>,
+  // the payload sits unaligned in `record`, args are read from an 8-aligned copy
+  payload: Vec<u64>,
+  metas: Vec<Option<DecodedMeta>>,
+  clock: Option<TscClock>,
+  line: LineFormatter,
+}
+
+struct DecodedMeta {
+  fmt: String,
+  num_args: usize,
+  arg_names: Vec<String>,
+  field_names: Vec<String>,
+  // leaked: SourceLocation wants 'static, and there is one per call site
+  loc: SourceLocation,
+}
+
+impl<R: Read> BinaryDecoder<R> {
+  pub fn new(mut reader: R) -> io::Result<Self> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+      return Err(invalid("not a binary log file"));
+    }
+    let mut line = LineFormatter::new();
+    line.set_color(false);
+    Ok(Self {
+      reader,
+      record: Vec::with_capacity(512),
+      payload: Vec::with_capacity(MAX_PAYLOAD_LEN / 8),
+      metas: Vec::new(),
+      clock: None,
+      line,
+    })
+  }
+
+  /// Next rendered line (with the trailing `\n`), `None` at the end of the file.
+  pub fn next_line(&mut self) -> io::Result<Option<&[u8]>> {
+    loop {
+      let mut len = [0u8; 4];
+      match self.reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+      }
+      let len = u32::from_le_bytes(len) as usize;
+      if len == 0 {
+        return Err(invalid("empty record"));
+      }
+      self.record.resize(len, 0);
+      self.reader.read_exact(&mut self.record)?;
+
+      let mut rd = RecordReader { buf: &self.record[1..] };
+      match self.record[0] {
+        REC_META => {
+          let id = rd.u32()? as usize;
+          let _level = rd.u8()?;
+          let num_args = rd.u8()? as usize;
+          let line = rd.u32()?;
+          let column = rd.u32()?;
+          let fmt = rd.str()?.to_string();
+          let module_path: &'static str = Box::leak(rd.str()?.into());
+          let file: &'static str = Box::leak(rd.str()?.into());
+          let n = rd.u8()? as usize;
+          let mut arg_names = Vec::with_capacity(n);
+          for _ in 0..n {
+            arg_names.push(rd.str()?.to_string());
+          }
+          let m = rd.u8()? as usize;
+          let mut field_names = Vec::with_capacity(m);
+          for _ in 0..m {
+            field_names.push(rd.str()?.to_string());
+          }
+          if m > num_args {
+            return Err(invalid("metadata has more fields than args"));
+          }
+          if self.metas.len() <= id {
+            self.metas.resize_with(id + 1, || None);
+          }
+          self.metas[id] = Some(DecodedMeta {
+            fmt,
+            num_args,
+            arg_names,
+            field_names,
+            loc: SourceLocation::__new(module_path, file, line, column),
+          });
+        }
+        REC_CLOCK => {
+          let tsc = rd.i64()?;
+          let ns = rd.i64()?;
+          let ns_per_tsc = f64::from_bits(rd.i64()? as u64);
+          self.clock = Some(TscClock::with_params(tsc, ns, ns_per_tsc, tscns::CALIBRATE_INTERVAL_NANOS));
+        }
+        REC_LOG => {
+          let tid = rd.u32()? as usize;
+          let meta_id = rd.u32()? as usize;
+          let level = rd.u8()? as usize;
+          let tsc = rd.i64()?;
+          self.payload.clear();
+          self.payload.resize(rd.buf.len().div_ceil(8), 0);
+          let payload = &mut bytemuck::cast_slice_mut::<u64, u8>(&mut self.payload)[..rd.buf.len()];
+          payload.copy_from_slice(rd.buf);
+          let payload = &*payload;
+
+          let Some(clock) = self.clock.as_ref() else {
+            return Err(invalid("log record before any clock record"));
+          };
+          let Some(meta) = self.metas.get(meta_id).and_then(|m| m.as_ref()) else {
+            return Err(invalid("log record refers to unknown metadata"));
+          };
+          let curr_ns = clock.tsc2ns(tsc);
+          let line = self.line.format_body(tid, level, curr_ns, |out| {
+            meta.loc.write_to(out);
+            let args = decode_payload(meta.num_args, payload)?;
+            let (args, fields) = args.split_at(meta.num_args - meta.field_names.len());
+            render(out, &meta.fmt, &meta.arg_names, args, clock);
+            for (key, value) in meta.field_names.iter().zip(fields) {
+              out.push(b' ');
+              out.extend_from_slice(key.as_bytes());
+              out.push(b'=');
+              write_arg(out, value, &parse_spec(""), clock);
+            }
+            Ok(())
+          })?;
+          return Ok(Some(line));
+        }
+        REC_THREAD => {
+          let tid = rd.u32()? as usize;
+          let name = rd.str()?;
+          self.line.set_thread_name(tid, name);
+        }
+        // unknown kinds are skipped, the length prefix makes that possible
+        _ => {}
+      }
+    }
+  }
+}
+
+struct RecordReader<'a> {
+  buf: &'a [u8],
+}
+
+impl<'a> RecordReader<'a> {
+  fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+    if self.buf.len() < n {
+      return Err(invalid("truncated record"));
+    }
+    let (head, rest) = self.buf.split_at(n);
+    self.buf = rest;
+    Ok(head)
+  }
+
+  fn u8(&mut self) -> io::Result<u8> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u32(&mut self) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn i64(&mut self) -> io::Result<i64> {
+    Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  fn str(&mut self) -> io::Result<&'a str> {
+    let len = self.u32()? as usize;
+    std::str::from_utf8(self.take(len)?).map_err(|_| invalid("metadata string is not utf-8"))
+  }
+}
+
+fn invalid(msg: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Same walk as `decode_args`, with the arg count from the metadata and bounds checked,
+/// the file may be truncated or corrupt.
+fn decode_payload(num_args: usize, payload: &[u8]) -> io::Result<Vec<DecodeResult<'_>>> {
+  let mut offset = (num_args + 7) & !7;
+  if payload.len() < offset {
+    return Err(invalid("truncated payload"));
+  }
+  let mut args = Vec::with_capacity(num_args);
+  for i in 0..num_args {
+    let tag = payload[i];
+    let size = arg_size(tag, payload, offset).unwrap_or(0);
+    if size < 8 || offset + size > payload.len() {
+      return Err(invalid("truncated payload"));
+    }
+    let (arg, next_offset) = decode(tag, payload, offset);
+    args.push(arg);
+    offset = next_offset;
+  }
+  Ok(args)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Align {
+  Left,
+  Center,
+  Right,
+}
+
+struct Spec {
+  fill: char,
+  align: Option<Align>,
+  plus: bool,
+  zero: bool,
+  width: usize,
+  precision: Option<usize>,
+}
+
+fn parse_spec(spec: &str) -> Spec {
+  let mut out = Spec { fill: ' ', align: None, plus: false, zero: false, width: 0, precision: None };
+  let chars: Vec<char> = spec.chars().collect();
+  let align_of = |c: char| match c {
+    '<' => Some(Align::Left),
+    '^' => Some(Align::Center),
+    '>' => Some(Align::Right),
+    _ => None,
+  };
+  let mut i = 0;
+  if chars.len() >= 2 && align_of(chars[1]).is_some() {
+    out.fill = chars[0];
+    out.align = align_of(chars[1]);
+    i = 2;
+  } else if !chars.is_empty() && align_of(chars[0]).is_some() {
+    out.align = align_of(chars[0]);
+    i = 1;
+  }
+  if i < chars.len() && (chars[i] == '+' || chars[i] == '-') {
+    out.plus = chars[i] == '+';
+    i += 1;
+  }
+  if i < chars.len() && chars[i] == '0' {
+    out.zero = true;
+    i += 1;
+  }
+  while i < chars.len() && chars[i].is_ascii_digit() {
+    out.width = out.width * 10 + chars[i].to_digit(10).unwrap() as usize;
+    i += 1;
+  }
+  if i < chars.len() && chars[i] == '.' {
+    i += 1;
+    let mut p = 0;
+    while i < chars.len() && chars[i].is_ascii_digit() {
+      p = p * 10 + chars[i].to_digit(10).unwrap() as usize;
+      i += 1;
+    }
+    out.precision = Some(p);
+  }
+  out
+}
+
+/// Expand `fmt` with `args`, like `write!` would have in the shim.
+/// `Tsc` args map through `clock`, the writer's calibration, not this process's.
+fn render(out: &mut MyBytesMut, fmt: &str, names: &[String], args: &[DecodeResult<'_>], clock: &TscClock) {
+  let mut next = 0;
+  let mut rest = fmt;
+  while let Some(pos) = rest.find(['{', '}']) {
+    out.extend_from_slice(&rest.as_bytes()[..pos]);
+    let tail = &rest[pos..];
+    if tail.starts_with("{{") || tail.starts_with("}}") {
+      out.push(tail.as_bytes()[0]);
+      rest = &tail[2..];
+      continue;
+    }
+    if let Some(after) = tail.strip_prefix('}') {
+      out.push(b'}');
+      rest = after;
+      continue;
+    }
+    let Some(end) = tail.find('}') else {
+      out.extend_from_slice(tail.as_bytes());
+      return;
+    };
+    let hole = &tail[1..end];
+    rest = &tail[end + 1..];
+
+    let (arg_ref, spec) = match hole.find(':') {
+      Some(i) => (&hole[..i], &hole[i + 1..]),
+      None => (hole, ""),
+    };
+    let idx = if arg_ref.is_empty() {
+      next += 1;
+      Some(next - 1)
+    } else if let Ok(i) = arg_ref.parse::<usize>() {
+      Some(i)
+    } else {
+      names.iter().position(|n| n == arg_ref)
+    };
+    match idx.and_then(|i| args.get(i)) {
+      Some(arg) => write_arg(out, arg, &parse_spec(spec), clock),
+      None => out.extend_from_slice(b"{?}"),
+    }
+  }
+  out.extend_from_slice(rest.as_bytes());
+}
+
+fn write_arg(out: &mut MyBytesMut, arg: &DecodeResult<'_>, spec: &Spec, clock: &TscClock) {
+  let numeric = matches!(arg, DecodeResult::F64(_) | DecodeResult::U64(_) | DecodeResult::I64(_));
+  let mut body = match arg {
+    DecodeResult::F64(v) => match spec.precision {
+      Some(p) => format!("{:.*}", p, v),
+      None => v.to_string(),
+    },
+    DecodeResult::U64(v) => v.to_string(),
+    DecodeResult::I64(v) => v.to_string(),
+    DecodeResult::Str(s) => match spec.precision {
+      Some(p) => s.char_indices().nth(p).map_or(*s, |(i, _)| &s[..i]).to_string(),
+      None => s.to_string(),
+    },
+    DecodeResult::Bool(v) => v.to_string(),
+    DecodeResult::Char(c) => c.to_string(),
+    DecodeResult::Hex(h) => h.to_string(),
+    DecodeResult::Tsc(t) => EpochNs(clock.tsc2ns(*t)).to_string(),
+    DecodeResult::Ptr(p) => p.to_string(),
+    DecodeResult::Error => "<decode error>".to_string(),
+    DecodeResult::Snap(snap) if snap.registered() => snap.to_string(),
+    DecodeResult::Snap(snap) => {
+      let hex: Vec<String> = snap.bytes().iter().map(|b| format!("{:02x}", b)).collect();
+      format!("pod[{}]", hex.join(" "))
+    }
+  };
+  if spec.plus && numeric && !body.starts_with('-') {
+    body.insert(0, '+');
+  }
+
+  let len = body.chars().count();
+  if len >= spec.width {
+    out.extend_from_slice(body.as_bytes());
+    return;
+  }
+  let pad = spec.width - len;
+  if spec.zero && numeric {
+    // zeros go after the sign
+    let split = if body.starts_with(['+', '-']) { 1 } else { 0 };
+    out.extend_from_slice(&body.as_bytes()[..split]);
+    for _ in 0..pad {
+      out.push(b'0');
+    }
+    out.extend_from_slice(&body.as_bytes()[split..]);
+    return;
+  }
+  let align = spec.align.unwrap_or(if numeric { Align::Right } else { Align::Left });
+  let (before, after) = match align {
+    Align::Left => (0, pad),
+    Align::Right => (pad, 0),
+    Align::Center => (pad / 2, pad - pad / 2),
+  };
+  let mut fill = [0u8; 4];
+  let fill = spec.fill.encode_utf8(&mut fill).as_bytes();
+  for _ in 0..before {
+    out.extend_from_slice(fill);
+  }
+  out.extend_from_slice(body.as_bytes());
+  for _ in 0..after {
+    out.extend_from_slice(fill);
+  }
+}
@@ -0,0 +1,118 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use crate::format::{HeaderConfig, LineFormat, LineFormatter, Precision, TimeFormat};
+use crate::sink::Sink;
+use crate::spsc_var_queue_opt::MsgHeader;
+use crate::tscns::Clock;
+
+/// -------- In-memory sink --------
+/// Keeps every rendered line (without the trailing `\n`) in a shared `Vec<String>`, for tests that
+/// assert on what was logged. Behind `LoggerHandle::in_memory`, also usable in a `TeeSink`.
+pub struct CaptureSink {
+  lines: Arc<Mutex<Vec<String>>>,
+  line: LineFormatter,
+}
+
+impl Default for CaptureSink {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl CaptureSink {
+  /// Plain (no ANSI colors) `LineFormat::Text` lines with microsecond timestamps.
+  pub fn new() -> Self {
+    let mut line = LineFormatter::new();
+    line.set_color(false);
+    Self {
+      lines: Arc::new(Mutex::new(Vec::new())),
+      line,
+    }
+  }
+
+  /// The captured lines, shared with this sink; clone it before handing the sink to a logger.
+  pub fn lines(&self) -> Arc<Mutex<Vec<String>>> {
+    self.lines.clone()
+  }
+
+  pub fn with_precision(mut self, precision: Precision) -> Self {
+    self.line.set_precision(precision);
+    self
+  }
+
+  pub fn with_format(mut self, line_format: LineFormat) -> Self {
+    self.line.set_line_format(line_format);
+    self
+  }
+
+  pub fn with_header(mut self, header: HeaderConfig) -> Self {
+    self.line.set_header(header);
+    self
+  }
+
+  pub fn with_time_format(mut self, time_format: TimeFormat) -> Self {
+    self.line.set_time_format(time_format);
+    self
+  }
+
+  /// Stamp lines from `clock`, e.g. a `tscns::ManualClock` pinned to a known time for exact asserts.
+  pub fn with_clock(mut self, clock: Arc<dyn Clock + Send + Sync>) -> Self {
+    self.line.set_clock(clock);
+    self
+  }
+
+  pub fn with_strip_module_prefix(mut self, prefix: &str) -> Self {
+    self.line.set_module_prefix(prefix);
+    self
+  }
+
+  pub fn with_level_names(mut self, names: &[&str]) -> Self {
+    self.line.set_level_names(names);
+    self
+  }
+
+  /// Escape control characters in `&str` args, as a file sink would.
+  pub fn with_escape_strings(mut self, on: bool) -> Self {
+    self.line.set_escape_strings(on);
+    self
+  }
+}
+
+fn push(lines: &Mutex<Vec<String>>, line: &[u8]) {
+  let line = line.strip_suffix(b"\n").unwrap_or(line);
+  lines.lock().unwrap().push(String::from_utf8_lossy(line).into_owned());
+}
+
+impl Sink for CaptureSink {
+  fn on_record(&mut self, tid: usize, log_meta: &MsgHeader, log_payload: &[u8]) -> io::Result<()> {
+    let line = self.line.format(tid, log_meta, log_payload)?;
+    push(&self.lines, line);
+    Ok(())
+  }
+
+  fn accepts_lines(&self) -> bool {
+    true
+  }
+
+  fn on_line(&mut self, _hdr: &MsgHeader, line: &[u8]) -> io::Result<()> {
+    push(&self.lines, line);
+    Ok(())
+  }
+
+  fn on_thread_name(&mut self, tid: usize, name: &str) -> io::Result<()> {
+    self.line.set_thread_name(tid, name);
+    Ok(())
+  }
+
+  fn set_clock(&mut self, clock: Arc<dyn Clock + Send + Sync>) {
+    self.line.set_clock(clock);
+  }
+
+  fn on_idle(&mut self, _now_cycles: i64) -> io::Result<()> {
+    Ok(())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
@@ -1,51 +1,137 @@
-use std::io::{self, Write};
-use std::mem::transmute;
-use crate::format::{lut_msus, TidCache, TimeCache, LEVEL_STRS};
-use crate::log::LogFn;
-use crate::my_bytes_mut::MyBytesMut;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::format::{self, HeaderConfig, LineFormat, LineFormatter, Precision, TimeFormat};
+use crate::sink::Sink;
 use crate::spsc_var_queue_opt::MsgHeader;
-use crate::tscns;
+use crate::tscns::{self, Clock};
 
 /// -------- Console batch sink --------
 pub struct ConsoleBatchSink {
   // 批量 buffer
   batch: Vec<u8>,
-  // 每条 log 拼接的 scratch（可选，用于减少 batch 里反复 extend）
-  // 你也可以直接 batch.extend(prefix); batch.extend(payload)...
-  // 这里保留一个 scratch 是为了你后续加 timestamp/level 时更顺手。
-  scratch: MyBytesMut,
+  // 每条 log 的 timestamp/tid/level 前缀 + 正文在这里拼好再 extend 进 batch
+  line: LineFormatter,
+
+  // 重复行折叠：上一条输出的行 + 之后被吞掉的相同行数
+  dedup: bool,
+  last_line: Vec<u8>,
+  repeated: u64,
 
   // flush 策略
   flush_bytes: usize,
   flush_interval_cycles: i64,
   last_flush_cycles: i64,
+}
 
-  time_cache: TimeCache, // like 01-16 09:33:36 T00
-  tid_cache: TidCache, // like T=00
+impl Default for ConsoleBatchSink {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl ConsoleBatchSink {
+  /// 256KB batch, flushed at least every 500us.
   pub fn new() -> Self {
+    Self::with_config(256 * 1024, Duration::from_micros(500))
+  }
+
+  /// `flush_interval` is converted to tsc cycles with the current calibration,
+  /// so it must be called after `tscns::init` (the backend thread always is).
+  pub fn with_config(flush_bytes: usize, flush_interval: Duration) -> Self {
     // 注意：StdoutLock 生命周期问题：最简单的做法是在 consumer 线程里构造 sink，
     // 并用 Box::leak 把 stdout 变成 'static（仅骨架用；生产里你可以把 lock 放到 run() 里）。
     // let stdout = Box::leak(Box::new(io::stdout()));
     // let out = stdout.lock();
 
-    // let flush_interval_cycles = (500_000.0 / tscns::get_ns_per_tsc()) as i64;
-    // let flush_interval_cycles = us_to_cycles(500, tsc_hz);
+    let mut line = LineFormatter::new();
+    line.set_color(io::stdout().is_terminal());
 
     Self {
-      batch: Vec::with_capacity(256 * 1024),
-      scratch: MyBytesMut::with_capacity(512),
+      batch: Vec::with_capacity(flush_bytes),
+      line,
+
+      dedup: false,
+      last_line: Vec::new(),
+      repeated: 0,
 
-      flush_bytes: 256 * 1024,
-      flush_interval_cycles: 1_500_000,
+      flush_bytes,
+      flush_interval_cycles: tscns::ns2cycles(flush_interval.as_nanos() as i64),
       last_flush_cycles: tscns::read_tsc(),
 
       // prefix: TidCache::new(32),
       // out,
-      time_cache: TimeCache::new(),
-      tid_cache: TidCache::new(32),
+    }
+  }
+
+  /// Sub-second digits in the header, `Precision::Micros` by default.
+  pub fn with_precision(mut self, precision: Precision) -> Self {
+    self.line.set_precision(precision);
+    self
+  }
+
+  /// Force ANSI colors on/off; by default they are on only when stdout is a tty.
+  pub fn with_color(mut self, color: bool) -> Self {
+    self.line.set_color(color);
+    self
+  }
+
+  /// `LineFormat::Json` switches to one JSON object per line (no colors).
+  pub fn with_format(mut self, line_format: LineFormat) -> Self {
+    self.line.set_line_format(line_format);
+    self
+  }
+
+  /// Header fields and their order for `LineFormat::Text`, see `HeaderConfig`.
+  pub fn with_header(mut self, header: HeaderConfig) -> Self {
+    self.line.set_header(header);
+    self
+  }
+
+  /// `TimeFormat::EpochNanos` writes the time as a raw epoch-ns integer instead of the calendar time.
+  pub fn with_time_format(mut self, time_format: TimeFormat) -> Self {
+    self.line.set_time_format(time_format);
+    self
+  }
+
+  /// Drop `prefix` from the front of module paths, e.g. `"mytradingsystem::"` prints
+  /// `marketdata::binance` for `mytradingsystem::marketdata::binance`. Modules without it are left alone.
+  pub fn with_strip_module_prefix(mut self, prefix: &str) -> Self {
+    self.line.set_module_prefix(prefix);
+    self
+  }
+
+  /// Replace the level column, one name per `Level` (trace..fatal); colors stay per level.
+  pub fn with_level_names(mut self, names: &[&str]) -> Self {
+    self.line.set_level_names(names);
+    self
+  }
+
+  /// Escape `\n`, `\r`, `\t` and ESC inside `&str` args, e.g. when they carry untrusted input.
+  pub fn with_escape_strings(mut self, on: bool) -> Self {
+    self.line.set_escape_strings(on);
+    self
+  }
+
+  /// Timestamps from `clock` instead of the global tscns one, e.g. a `tscns::ManualClock` in tests.
+  pub fn with_clock(mut self, clock: Arc<dyn Clock + Send + Sync>) -> Self {
+    self.line.set_clock(clock);
+    self
+  }
+
+  /// Collapse runs of lines that only differ in the timestamp: the first one is written,
+  /// the rest become one `<last line> (repeated K times)` once a different line arrives or the flush interval passes.
+  pub fn with_dedup(mut self, dedup: bool) -> Self {
+    self.dedup = dedup;
+    self
+  }
+
+  /// Write the summary of the suppressed repeats, if any.
+  #[cold]
+  fn flush_repeated(&mut self) {
+    if self.repeated > 0 {
+      format::write_repeated(&mut self.batch, &self.last_line, self.repeated);
+      self.repeated = 0;
     }
   }
 
@@ -71,63 +157,92 @@ impl ConsoleBatchSink {
     self.last_flush_cycles = tscns::read_tsc();
     Ok(())
   }
+}
 
+impl Sink for ConsoleBatchSink {
   /// 处理一条日志（payload 已经是 bytes；你也可以传入结构化参数）
   #[inline(always)]
-  pub fn on_record(&mut self, tid: usize, log_meta: &MsgHeader, log_payload: &[u8]) -> io::Result<()> {
-    let level = log_meta.level as usize;
-    let tsc = log_meta.tsc;
-    let log_fn = unsafe { transmute::<_, LogFn>(log_meta.log_func) };
-
-    let curr_ns = tscns::tsc2ns(tsc);
-
-    let curr_sec = curr_ns / 1_000_000_000;
-    let sub_ns = curr_ns % 1_000_000_000;
-
-    let sub_us = sub_ns / 1_000;        // 0..999_999
-    let curr_ms = (sub_us / 1_000) as usize;   // 0..999
-    let curr_us = (sub_us % 1_000) as usize;   // 0..999
-
-    self.scratch.clear();
-    self.scratch.push(b'[');
-    self.time_cache.refresh_dt(curr_sec, self.scratch.unfilled());
-    self.scratch.advance(TimeCache::TIME_LEN);
-    lut_msus(self.scratch.unfilled(), curr_ms, curr_us);
-    self.scratch.advance(8);
-    self.scratch.push(b' ');
-
-    self.tid_cache.write(tid, self.scratch.unfilled());
-    self.scratch.advance(TidCache::TID_LEN);
-    self.scratch.push(b' ');
-
-    unsafe {
-      self.scratch.extend_from_slice(LEVEL_STRS.get_unchecked(level).as_bytes());
+  fn on_record(&mut self, tid: usize, log_meta: &MsgHeader, log_payload: &[u8]) -> io::Result<()> {
+    let line = self.line.format(tid, log_meta, log_payload)?;
+    if !self.dedup {
+      self.batch.extend_from_slice(line);
+    } else {
+      push_deduped(&mut self.batch, &mut self.last_line, &mut self.repeated, line);
     }
 
-    (log_fn)(&mut self.scratch, log_payload)?;
+    // 2) flush 条件
+    if self.should_flush(log_meta.tsc) {
+      self.flush_now()?;
+    }
+    Ok(())
+  }
 
-    // self.scratch.extend_from_slice(payload);
-    self.scratch.push(b'\n');
+  #[inline(always)]
+  fn accepts_lines(&self) -> bool {
+    true
+  }
 
-    self.batch.extend_from_slice(self.scratch.result());
+  #[inline(always)]
+  fn on_line(&mut self, log_meta: &MsgHeader, line: &[u8]) -> io::Result<()> {
+    if !self.dedup {
+      self.batch.extend_from_slice(line);
+    } else {
+      push_deduped(&mut self.batch, &mut self.last_line, &mut self.repeated, line);
+    }
 
-    // 2) flush 条件
-    if self.should_flush(tsc) {
+    if self.should_flush(log_meta.tsc) {
       self.flush_now()?;
     }
     Ok(())
   }
 
+  #[inline]
+  fn on_thread_name(&mut self, tid: usize, name: &str) -> io::Result<()> {
+    self.line.set_thread_name(tid, name);
+    Ok(())
+  }
+
+  #[inline]
+  fn set_clock(&mut self, clock: Arc<dyn Clock + Send + Sync>) {
+    self.line.set_clock(clock);
+  }
+
   /// 在空闲时也调用一下：如果 500us 到了，强制 flush（即使没有新日志）
   #[inline(always)]
-  pub fn on_idle(&mut self, now_cycles: i64) -> io::Result<()> {
-    if !self.batch.is_empty()
+  fn on_idle(&mut self, now_cycles: i64) -> io::Result<()> {
+    if (!self.batch.is_empty() || self.repeated > 0)
       && now_cycles.wrapping_sub(self.last_flush_cycles) >= self.flush_interval_cycles
     {
+      self.flush_repeated();
       self.flush_now()?;
     }
     Ok(())
   }
+
+  #[inline]
+  fn flush(&mut self) -> io::Result<()> {
+    self.flush_repeated();
+    self.flush_now()
+  }
+}
+
+/// Append `line` to `batch`, or count it as a repeat of `last_line` if only the timestamp differs.
+#[inline(always)]
+fn push_deduped(batch: &mut Vec<u8>, last_line: &mut Vec<u8>, repeated: &mut u64, line: &[u8]) {
+  if !last_line.is_empty() && format::same_but_timestamp(line, last_line) {
+    // keep the newest timestamp for the summary
+    last_line.clear();
+    last_line.extend_from_slice(line);
+    *repeated += 1;
+  } else {
+    if *repeated > 0 {
+      format::write_repeated(batch, last_line, *repeated);
+      *repeated = 0;
+    }
+    batch.extend_from_slice(line);
+    last_line.clear();
+    last_line.extend_from_slice(line);
+  }
 }
 
 // for test
@@ -144,4 +259,60 @@ impl ConsoleBatchSink {
 //   let (arg2, _) = crate::args2::decode(tag2, bytes, offset);
 //
 //   write!(out, "x={} y={}", arg1, arg2)
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::args2::{args1, decode_args, encode_to_vec};
+  use crate::log::{Level, SourceLocation};
+  use crate::metadata::{CallSite, Metadata};
+  use crate::my_bytes_mut::MyBytesMut;
+
+  fn seq_shim(out: &mut MyBytesMut, bytes: &[u8], _loc: Option<&mut SourceLocation>) -> io::Result<()> {
+    let [seq] = decode_args::<1>(bytes);
+    write!(out, "] seq={}", seq)
+  }
+
+  static SEQ_SITE: CallSite = CallSite::new(Metadata {
+    fmt: "seq={}",
+    level: Level::Info,
+    loc: SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+    num_args: 1,
+    arg_names: &[],
+    field_names: &[],
+    log_fn: seq_shim,
+  });
+
+  fn log(sink: &mut ConsoleBatchSink, seq: u64) {
+    let hdr = MsgHeader {
+      size: 0,
+      level: Level::Info as u32,
+      tsc: tscns::read_tsc(),
+      meta_id: SEQ_SITE.id() as u64,
+    };
+    sink.on_record(1, &hdr, &encode_to_vec(&args1(seq))).unwrap();
+  }
+
+  #[test]
+  fn five_repeats_collapse_into_one_summary() {
+    // 不会自己 flush，batch 里就是全部输出
+    let mut sink = ConsoleBatchSink::with_config(1 << 20, Duration::from_secs(3600))
+      .with_color(false)
+      .with_dedup(true);
+    for _ in 0..5 {
+      log(&mut sink, 7);
+    }
+    log(&mut sink, 8);
+
+    let text = String::from_utf8(sink.batch.clone()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 3, "{text}");
+    assert!(lines[0].ends_with("] seq=7"), "{text}");
+    assert!(lines[1].ends_with("] seq=7 (repeated 4 times)"), "{text}");
+    assert!(lines[2].ends_with("] seq=8"), "{text}");
+
+    // 没有重复时不多写 summary
+    sink.flush_repeated();
+    assert_eq!(sink.batch.len(), text.len());
+  }
+}
@@ -0,0 +1,492 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::format::{self, HeaderConfig, LineFormat, LineFormatter, Precision, TimeFormat};
+use crate::sink::Sink;
+use crate::spsc_var_queue_opt::MsgHeader;
+use crate::tscns::{self, Clock};
+
+/// Wall-clock (UTC) boundary at which `FileBatchSink` starts a new file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RotateEvery {
+  Never,
+  Hour,
+  Day,
+}
+
+impl RotateEvery {
+  #[inline(always)]
+  const fn period_ns(self) -> i64 {
+    match self {
+      RotateEvery::Never => 0,
+      RotateEvery::Hour => 3_600 * 1_000_000_000,
+      RotateEvery::Day => 86_400 * 1_000_000_000,
+    }
+  }
+}
+
+/// -------- File batch sink --------
+/// Same batching policy as `ConsoleBatchSink`, but the batch goes to an append-only file.
+/// Optionally rotates: see `with_max_bytes` / `with_rotate_every` / `with_max_files`.
+pub struct FileBatchSink {
+  path: PathBuf,
+  file: File,
+
+  // rotation
+  written: u64,
+  max_bytes: u64, // 0 = no size limit
+  rotate_every: RotateEvery,
+  opened_ns: i64,
+  period_end_ns: i64,
+  max_files: usize, // 0 = keep all
+
+  // 批量 buffer
+  batch: Vec<u8>,
+  line: LineFormatter,
+
+  // flush 策略
+  flush_bytes: usize,
+  flush_interval_cycles: i64,
+  last_flush_cycles: i64,
+}
+
+impl FileBatchSink {
+  /// Open (or create) `path` in append mode (`O_APPEND`), 256KB batch flushed at least every 500us.
+  pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+    Self::with_config(path, 256 * 1024, Duration::from_micros(500))
+  }
+
+  /// Same as `ConsoleBatchSink::with_config`, `flush_interval` is converted with the current tsc calibration.
+  pub fn with_config(path: impl Into<PathBuf>, flush_bytes: usize, flush_interval: Duration) -> io::Result<Self> {
+    let path = path.into();
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    // plain text unless the "file" is actually a terminal (e.g. /dev/tty)
+    let mut line = LineFormatter::new();
+    line.set_escape_strings(true);
+    line.set_color(file.is_terminal());
+
+    let written = file.metadata()?.len();
+
+    Ok(Self {
+      path,
+      file,
+
+      written,
+      max_bytes: 0,
+      rotate_every: RotateEvery::Never,
+      opened_ns: tscns::read_nanos(),
+      period_end_ns: i64::MAX,
+      max_files: 0,
+
+      batch: Vec::with_capacity(flush_bytes),
+      line,
+
+      flush_bytes,
+      flush_interval_cycles: tscns::ns2cycles(flush_interval.as_nanos() as i64),
+      last_flush_cycles: tscns::read_tsc(),
+    })
+  }
+
+  pub fn path(&self) -> &PathBuf {
+    &self.path
+  }
+
+  /// Sub-second digits in the header, `Precision::Micros` by default.
+  pub fn with_precision(mut self, precision: Precision) -> Self {
+    self.line.set_precision(precision);
+    self
+  }
+
+  pub fn with_color(mut self, color: bool) -> Self {
+    self.line.set_color(color);
+    self
+  }
+
+  /// `LineFormat::Json` switches to one JSON object per line (no colors).
+  pub fn with_format(mut self, line_format: LineFormat) -> Self {
+    self.line.set_line_format(line_format);
+    self
+  }
+
+  /// Header fields and their order for `LineFormat::Text`, see `HeaderConfig`.
+  pub fn with_header(mut self, header: HeaderConfig) -> Self {
+    self.line.set_header(header);
+    self
+  }
+
+  /// `TimeFormat::EpochNanos` writes the time as a raw epoch-ns integer instead of the calendar time.
+  pub fn with_time_format(mut self, time_format: TimeFormat) -> Self {
+    self.line.set_time_format(time_format);
+    self
+  }
+
+  /// Drop `prefix` from the front of module paths, e.g. `"mytradingsystem::"` prints
+  /// `marketdata::binance` for `mytradingsystem::marketdata::binance`. Modules without it are left alone.
+  pub fn with_strip_module_prefix(mut self, prefix: &str) -> Self {
+    self.line.set_module_prefix(prefix);
+    self
+  }
+
+  /// Own level names, e.g. `&["TRC", "DBG", "INF", "WRN", "ERR", "FTL"]`; panics unless there are `Level::COUNT`.
+  pub fn with_level_names(mut self, names: &[&str]) -> Self {
+    self.line.set_level_names(names);
+    self
+  }
+
+  /// Escape `\n`, `\r`, `\t` and ESC inside `&str` args, on by default so one record stays one line.
+  pub fn with_escape_strings(mut self, on: bool) -> Self {
+    self.line.set_escape_strings(on);
+    self
+  }
+
+  /// Rotate once the current file reaches `max_bytes` (checked at each flush, so a file can overshoot
+  /// by up to one batch). 0 disables the size limit.
+  pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+    self.max_bytes = max_bytes;
+    self
+  }
+
+  /// Rotate on the first flush after an hour / day boundary (UTC, same clock as the line headers).
+  pub fn with_rotate_every(mut self, rotate_every: RotateEvery) -> Self {
+    self.rotate_every = rotate_every;
+    self.period_end_ns = period_end(rotate_every, self.opened_ns);
+    self
+  }
+
+  /// Keep at most `max_files` rotated files next to `path`, deleting the oldest. 0 keeps all.
+  pub fn with_max_files(mut self, max_files: usize) -> Self {
+    self.max_files = max_files;
+    self
+  }
+
+  #[inline(always)]
+  fn should_flush(&self, now_cycles: i64) -> bool {
+    self.batch.len() >= self.flush_bytes || now_cycles.wrapping_sub(self.last_flush_cycles) >= self.flush_interval_cycles
+  }
+
+  #[inline(always)]
+  fn flush_now(&mut self) -> io::Result<()> {
+    if self.batch.is_empty() {
+      self.last_flush_cycles = tscns::read_tsc();
+      return Ok(());
+    }
+
+    self.file.write_all(&self.batch)?;
+    self.written += self.batch.len() as u64;
+    self.batch.clear();
+    self.last_flush_cycles = tscns::read_tsc();
+
+    if self.max_bytes > 0 && self.written >= self.max_bytes {
+      self.rotate(tscns::read_nanos())?;
+    } else if self.rotate_every != RotateEvery::Never {
+      let now_ns = tscns::read_nanos();
+      if now_ns >= self.period_end_ns {
+        self.rotate(now_ns)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Move the current file to `path.YYYY-MM-DD-HH` (the hour it was opened in, `.1`, `.2`.. appended
+  /// after the newest file of that hour), reopen `path` and prune old files.
+  #[cold]
+  fn rotate(&mut self, now_ns: i64) -> io::Result<()> {
+    let stamp = format::utc_hour_stamp(self.opened_ns.div_euclid(1_000_000_000));
+    let mut old = self.rotated_files()?;
+    let n = old.iter().filter(|((s, _), _)| *s == stamp).map(|((_, n), _)| n + 1).max().unwrap_or(0);
+    let target = if n == 0 {
+      suffixed(&self.path, &stamp)
+    } else {
+      suffixed(&self.path, &format!("{}.{}", stamp, n))
+    };
+    fs::rename(&self.path, &target)?;
+    self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+    self.written = 0;
+    self.opened_ns = now_ns;
+    self.period_end_ns = period_end(self.rotate_every, now_ns);
+
+    old.push(((stamp, n), target));
+    if self.max_files > 0 && old.len() > self.max_files {
+      old.sort_unstable();
+      for (_, path) in &old[..old.len() - self.max_files] {
+        fs::remove_file(path)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Rotated files of `path` in its directory, keyed by (stamp, collision counter) so they sort oldest first.
+  fn rotated_files(&self) -> io::Result<Vec<((String, u32), PathBuf)>> {
+    let dir = match self.path.parent() {
+      Some(p) if !p.as_os_str().is_empty() => p,
+      _ => Path::new("."),
+    };
+    let prefix = match self.path.file_name().and_then(|n| n.to_str()) {
+      Some(n) => format!("{}.", n),
+      None => return Ok(Vec::new()),
+    };
+    let mut out = Vec::new();
+    for entry in fs::read_dir(dir)? {
+      let entry = entry?;
+      let name = entry.file_name();
+      let Some(suffix) = name.to_str().and_then(|n| n.strip_prefix(prefix.as_str())) else {
+        continue;
+      };
+      // YYYY-MM-DD-HH[.n]
+      let (stamp, n) = match suffix.split_once('.') {
+        Some((stamp, n)) => match n.parse::<u32>() {
+          Ok(n) => (stamp, n),
+          Err(_) => continue,
+        },
+        None => (suffix, 0),
+      };
+      if stamp.len() != 13 || !stamp.bytes().all(|b| b.is_ascii_digit() || b == b'-') {
+        continue;
+      }
+      out.push(((stamp.to_string(), n), entry.path()));
+    }
+    Ok(out)
+  }
+}
+
+#[inline]
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+  let mut name = path.as_os_str().to_owned();
+  name.push(".");
+  name.push(suffix);
+  PathBuf::from(name)
+}
+
+/// Start of the next `every` period after `now_ns`, `i64::MAX` for `Never`.
+#[inline]
+fn period_end(every: RotateEvery, now_ns: i64) -> i64 {
+  let period = every.period_ns();
+  if period == 0 {
+    return i64::MAX;
+  }
+  (now_ns.div_euclid(period) + 1) * period
+}
+
+impl Sink for FileBatchSink {
+  #[inline(always)]
+  fn on_record(&mut self, tid: usize, log_meta: &MsgHeader, log_payload: &[u8]) -> io::Result<()> {
+    let line = self.line.format(tid, log_meta, log_payload)?;
+    self.batch.extend_from_slice(line);
+
+    if self.should_flush(log_meta.tsc) {
+      self.flush_now()?;
+    }
+    Ok(())
+  }
+
+  #[inline(always)]
+  fn accepts_lines(&self) -> bool {
+    true
+  }
+
+  #[inline(always)]
+  fn on_line(&mut self, log_meta: &MsgHeader, line: &[u8]) -> io::Result<()> {
+    self.batch.extend_from_slice(line);
+
+    if self.should_flush(log_meta.tsc) {
+      self.flush_now()?;
+    }
+    Ok(())
+  }
+
+  #[inline]
+  fn on_thread_name(&mut self, tid: usize, name: &str) -> io::Result<()> {
+    self.line.set_thread_name(tid, name);
+    Ok(())
+  }
+
+  #[inline]
+  fn set_clock(&mut self, clock: Arc<dyn Clock + Send + Sync>) {
+    self.line.set_clock(clock);
+  }
+
+  /// 空闲时调用：flush 间隔到了就把 batch 写进文件
+  #[inline(always)]
+  fn on_idle(&mut self, now_cycles: i64) -> io::Result<()> {
+    if !self.batch.is_empty()
+      && now_cycles.wrapping_sub(self.last_flush_cycles) >= self.flush_interval_cycles
+    {
+      self.flush_now()?;
+    }
+    Ok(())
+  }
+  #[inline]
+  fn flush(&mut self) -> io::Result<()> {
+    self.flush_now()
+  }
+
+}
+
+impl Drop for FileBatchSink {
+  fn drop(&mut self) {
+    let _ = self.flush_now();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::args2::{args1, decode_args, encode_to_vec};
+  use crate::log::{Level, SourceLocation};
+  use crate::metadata::{CallSite, Metadata};
+  use crate::my_bytes_mut::MyBytesMut;
+
+  fn seq_shim(out: &mut MyBytesMut, bytes: &[u8], _loc: Option<&mut SourceLocation>) -> io::Result<()> {
+    let [seq] = decode_args::<1>(bytes);
+    write!(out, "seq={}", seq)
+  }
+
+  static SEQ_SITE: CallSite = CallSite::new(Metadata {
+    fmt: "seq={}",
+    level: Level::Info,
+    loc: SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+    num_args: 1,
+    arg_names: &[],
+    field_names: &[],
+    log_fn: seq_shim,
+  });
+
+  fn record(seq: u64) -> (MsgHeader, Vec<u8>) {
+    let hdr = MsgHeader {
+      size: 0,
+      level: Level::Info as u32,
+      tsc: tscns::read_tsc(),
+      meta_id: SEQ_SITE.id() as u64,
+    };
+    (hdr, encode_to_vec(&args1(seq)))
+  }
+
+  fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("hft_{}_{}.log", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+  }
+
+  #[test]
+  fn logs_n_records_and_reads_them_back() {
+    let path = temp_path("file_sink");
+
+    const N: u64 = 100;
+    {
+      let mut sink = FileBatchSink::new(&path).unwrap();
+      for seq in 0..N {
+        let (hdr, payload) = record(seq);
+        sink.on_record(3, &hdr, &payload).unwrap();
+      }
+      // drop 时把剩下的 batch 写出去
+    }
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), N as usize);
+    for (seq, line) in lines.iter().enumerate() {
+      assert!(line.starts_with('['), "{line}");
+      // "[MM-DD HH:MM:SS.mmm.uuu T=03 "
+      assert_eq!(&line[24..29], "T=03 ", "{line}");
+      assert!(line.contains("info"), "{line}");
+      assert!(line.ends_with(&format!("seq={}", seq)), "{line}");
+    }
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn small_flush_bytes_writes_after_expected_records() {
+    let path = temp_path("flush_bytes");
+    let file_len = || std::fs::metadata(&path).unwrap().len() as usize;
+    // 时间间隔足够长，只有字节阈值能触发 flush
+    let mut sink = FileBatchSink::with_config(&path, 1 << 20, Duration::from_secs(3600)).unwrap();
+
+    let (hdr, payload) = record(1);
+    sink.on_record(0, &hdr, &payload).unwrap();
+    let line_len = sink.batch.len();
+    assert_eq!(file_len(), 0);
+
+    sink.flush_bytes = 3 * line_len;
+    for seq in 2..=6 {
+      let (hdr, payload) = record(seq);
+      sink.on_record(0, &hdr, &payload).unwrap();
+      let expected = if seq < 3 { 0 } else if seq < 6 { 3 } else { 6 };
+      assert_eq!(file_len(), expected * line_len, "after record {seq}");
+    }
+    drop(sink);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn plain_levels_in_a_regular_file() {
+    let path = temp_path("color");
+    {
+      let mut sink = FileBatchSink::new(&path).unwrap();
+      for level in [Level::Trace, Level::Debug, Level::Info, Level::Warn, Level::Error] {
+        let (mut hdr, payload) = record(level as u64);
+        hdr.level = level as u32;
+        sink.on_record(0, &hdr, &payload).unwrap();
+      }
+    }
+    let bytes = std::fs::read(&path).unwrap();
+    assert!(!bytes.contains(&0x1b));
+    let text = String::from_utf8(bytes).unwrap();
+    assert!(text.lines().any(|l| l.contains(" warn  ") && l.ends_with("] seq=3")), "{text}");
+
+    {
+      let (hdr, payload) = record(9);
+      let mut sink = FileBatchSink::new(&path).unwrap().with_color(true);
+      sink.on_record(0, &hdr, &payload).unwrap();
+    }
+    assert!(std::fs::read(&path).unwrap().contains(&0x1b));
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn size_rotation_keeps_the_newest_files() {
+    let dir = std::env::temp_dir().join(format!("hft_rotate_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    let path = dir.join("app.log");
+
+    // 每条都 flush；单条约 40 字节，12 条左右转一次
+    let mut sink = FileBatchSink::with_config(&path, 1, Duration::from_secs(3600)).unwrap()
+      .with_max_bytes(500)
+      .with_max_files(2);
+    for seq in 0..40 {
+      let (hdr, payload) = record(seq);
+      sink.on_record(0, &hdr, &payload).unwrap();
+    }
+    drop(sink);
+
+    let mut rotated: Vec<String> = std::fs::read_dir(&dir).unwrap()
+      .map(|e| e.unwrap().file_name().into_string().unwrap())
+      .filter(|n| n != "app.log")
+      .collect();
+    rotated.sort();
+    // 转了三次，最老的一个被删掉
+    assert_eq!(rotated.len(), 2, "{rotated:?}");
+    for name in &rotated {
+      // app.log.YYYY-MM-DD-HH[.n]
+      let suffix = name.strip_prefix("app.log.").unwrap();
+      let (stamp, n) = suffix.split_at(13);
+      assert!(stamp.bytes().all(|b| b.is_ascii_digit() || b == b'-'), "{name}");
+      assert!(n.is_empty() || n[1..].parse::<u32>().is_ok(), "{name}");
+    }
+
+    // 剩下的是后两个文件 + 当前文件，seq 连续到 39
+    let first_seq = |name: &str| {
+      let text = std::fs::read_to_string(dir.join(name)).unwrap();
+      let line = text.lines().next().unwrap().to_string();
+      line.rsplit_once("seq=").unwrap().1.parse::<u64>().unwrap()
+    };
+    let starts: Vec<u64> = rotated.iter().map(|n| first_seq(n)).collect();
+    assert!(starts[0] > 0 && starts[0] < starts[1], "{starts:?}");
+    let current = std::fs::read_to_string(&path).unwrap();
+    assert!(current.lines().last().unwrap().ends_with("seq=39"));
+    assert!(first_seq("app.log") > starts[1]);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -1,7 +1,17 @@
-use std::ptr;
+use std::{io, ptr};
+use std::io::Write;
+use std::sync::Arc;
+use crate::log::{Level, SourceLocation};
+use crate::args2::set_escape_strs;
+use crate::metadata::{Metadata, MetadataCache};
+use crate::my_bytes_mut::MyBytesMut;
+use crate::spsc_var_queue_opt::MsgHeader;
+use crate::tscns::{self, Clock};
 
 pub(crate) struct TidCache {
   tid_lut: Vec<u8>,
+  // set_thread_name 注册的名字；tid 可能是内核 tid，不能直接当下标
+  names: Vec<(usize, Box<str>)>,
 }
 
 impl TidCache {
@@ -16,13 +26,35 @@ impl TidCache {
 
     TidCache {
       tid_lut,
+      names: Vec::new(),
     }
   }
 
-  pub fn write(&self, tid: usize, buff: &mut [u8]) {
-    let offset = (tid << 2);
-    unsafe {
-      ptr::copy_nonoverlapping(self.tid_lut.as_ptr().add(offset), buff.as_mut_ptr(), Self::TID_LEN);
+  pub fn set_name(&mut self, tid: usize, name: &str) {
+    match self.names.iter_mut().find(|(t, _)| *t == tid) {
+      Some((_, n)) => *n = name.into(),
+      None => self.names.push((tid, name.into())),
+    }
+  }
+
+  #[inline]
+  pub fn name(&self, tid: usize) -> Option<&str> {
+    self.names.iter().find(|(t, _)| *t == tid).map(|(_, n)| &**n)
+  }
+
+  /// The thread's name if it has one, else `T=NN` (from the LUT for tids below 32, `lut_u64` otherwise).
+  #[inline(always)]
+  pub fn write_to(&self, tid: usize, out: &mut MyBytesMut) {
+    if let Some(name) = self.name(tid) {
+      out.extend_from_slice(name.as_bytes());
+    } else if tid < self.tid_lut.len() / Self::TID_LEN {
+      let offset = tid << 2;
+      out.extend_from_slice(&self.tid_lut[offset..offset + Self::TID_LEN]);
+    } else {
+      // tid >= 32 here, always at least 2 digits
+      out.extend_from_slice(b"T=");
+      let len = lut_u64(out.unfilled(), tid as u64);
+      out.advance(len);
     }
   }
 }
@@ -46,42 +78,544 @@ impl TimeCache {
     time_cache
   }
 
+  /// Write `MM-DD HH:MM:SS` (UTC) of `curr_sec` into `buff`, re-rendering only when the second changed.
   pub fn refresh_dt(&mut self, curr_sec: i64, buff: &mut [u8]) {
+    self.update(curr_sec);
+    unsafe {
+      ptr::copy_nonoverlapping(self.buf.as_ptr(), buff.as_mut_ptr(), Self::TIME_LEN);
+    }
+  }
+
+  /// [`TimeCache::refresh_dt`] without a destination: the cached `MM-DD HH:MM:SS` itself.
+  pub fn refresh_dt_bytes(&mut self, curr_sec: i64) -> &[u8] {
+    self.update(curr_sec);
+    &self.buf[..Self::TIME_LEN]
+  }
+
+  #[inline(always)]
+  fn update(&mut self, curr_sec: i64) {
     if curr_sec == self.sec {
-      unsafe {
-        ptr::copy_nonoverlapping(self.buf.as_ptr(), buff.as_mut_ptr(), Self::TIME_LEN);
-      }
-    } else {
-      self.sec = curr_sec;
-      let (month, day, hour, minute, second) = split_utc(curr_sec);
-      unsafe {
-        let month_off = (month << 1) as usize;
-        ptr::copy_nonoverlapping(DEC_2DIGITS_LUT.as_ptr().add(month_off), self.buf.as_mut_ptr(), 2);
-        let day_off = (day << 1) as usize;
-        ptr::copy_nonoverlapping(DEC_2DIGITS_LUT.as_ptr().add(day_off), self.buf.as_mut_ptr().add(3), 2);
-        let hour_off = (hour << 1) as usize;
-        ptr::copy_nonoverlapping(DEC_2DIGITS_LUT.as_ptr().add(hour_off), self.buf.as_mut_ptr().add(6), 2);
-        let minute_off = (minute << 1) as usize;
-        ptr::copy_nonoverlapping(DEC_2DIGITS_LUT.as_ptr().add(minute_off), self.buf.as_mut_ptr().add(9), 2);
-        let second_off = (second << 1) as usize;
-        ptr::copy_nonoverlapping(DEC_2DIGITS_LUT.as_ptr().add(second_off), self.buf.as_mut_ptr().add(12), 2);
-      }
-      unsafe {
-        ptr::copy_nonoverlapping(self.buf.as_ptr(), buff.as_mut_ptr(), Self::TIME_LEN);
+      return;
+    }
+    self.sec = curr_sec;
+    let (month, day, hour, minute, second) = split_utc(curr_sec);
+    unsafe {
+      let month_off = (month << 1) as usize;
+      ptr::copy_nonoverlapping(DEC_2DIGITS_LUT.as_ptr().add(month_off), self.buf.as_mut_ptr(), 2);
+      let day_off = (day << 1) as usize;
+      ptr::copy_nonoverlapping(DEC_2DIGITS_LUT.as_ptr().add(day_off), self.buf.as_mut_ptr().add(3), 2);
+      let hour_off = (hour << 1) as usize;
+      ptr::copy_nonoverlapping(DEC_2DIGITS_LUT.as_ptr().add(hour_off), self.buf.as_mut_ptr().add(6), 2);
+      let minute_off = (minute << 1) as usize;
+      ptr::copy_nonoverlapping(DEC_2DIGITS_LUT.as_ptr().add(minute_off), self.buf.as_mut_ptr().add(9), 2);
+      let second_off = (second << 1) as usize;
+      ptr::copy_nonoverlapping(DEC_2DIGITS_LUT.as_ptr().add(second_off), self.buf.as_mut_ptr().add(12), 2);
+    }
+  }
+}
+
+/// How many sub-second digit groups the header carries: `.mmm`, `.mmm.uuu` or `.mmm.uuu.nnn`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Precision {
+  Millis,
+  Micros,
+  Nanos,
+  /// micros as a single group, `.uuuuuu`
+  MicrosCompact,
+}
+
+impl Precision {
+  #[inline(always)]
+  pub(crate) const fn len(self) -> usize {
+    match self {
+      Precision::Millis => 4,
+      Precision::Micros => 8,
+      Precision::Nanos => 12,
+      Precision::MicrosCompact => 7,
+    }
+  }
+}
+
+/// How the header's `Time` field is written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimeFormat {
+  /// `MM-DD HH:MM:SS.mmm.uuu` (UTC), digits per `Precision`
+  Calendar,
+  /// nanoseconds since the unix epoch as a plain integer, for machine parsing
+  EpochNanos,
+}
+
+/// Layout of each rendered line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineFormat {
+  /// `[MM-DD HH:MM:SS.mmm.uuu T=00 level module::file#line] msg`
+  Text,
+  /// one JSON object per line: `ts` (epoch ns), `tid`, `thread` (if named), `level`, `module`, `file`, `line`, `msg`
+  Json,
+}
+
+/// One item of the text header.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HeaderField {
+  /// `MM-DD HH:MM:SS.mmm.uuu` (UTC, digits per `Precision`)
+  Time,
+  /// thread name or `T=NN`
+  Tid,
+  /// level name padded to 5 chars
+  Level,
+  /// `module::file#line:col`
+  Location,
+}
+
+/// Which header fields a text line carries and in what order, e.g. `[info  10-16 09:33:36.123.456] msg`
+/// for `HeaderConfig::new(&[HeaderField::Level, HeaderField::Time])`. No fields means no `[..]` at all.
+/// `LineFormat::Json` always has every field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderConfig {
+  fields: Vec<HeaderField>,
+}
+
+impl HeaderConfig {
+  pub fn new(fields: &[HeaderField]) -> Self {
+    Self {
+      fields: fields.to_vec(),
+    }
+  }
+
+  pub fn fields(&self) -> &[HeaderField] {
+    &self.fields
+  }
+}
+
+impl Default for HeaderConfig {
+  /// `[time tid level location]`, the built-in layout.
+  fn default() -> Self {
+    Self::new(&[HeaderField::Time, HeaderField::Tid, HeaderField::Level, HeaderField::Location])
+  }
+}
+
+/// Builds one rendered line `[MM-DD HH:MM:SS.mmm.uuu T=00 level module::file#line] msg\n`,
+/// shared by every batch sink.
+pub(crate) struct LineFormatter {
+  // 每条 log 拼接的 scratch
+  scratch: MyBytesMut,
+  time_cache: TimeCache, // like 01-16 09:33:36 T00
+  tid_cache: TidCache, // like T=00
+  precision: Precision,
+  time_format: TimeFormat,
+  level_strs: &'static [&'static str],
+  // (plain, colored, json names)，默认就是上面三张常量表
+  level_tables: (&'static [&'static str], &'static [&'static str], &'static [&'static str]),
+  color: bool,
+  line_format: LineFormat,
+  // None = 默认 header，走快路径
+  header: Option<HeaderConfig>,
+  // 从 module path 开头去掉的前缀，"" = 不去
+  module_prefix: Box<str>,
+  // json 模式下 message 先渲染到这里，再 escape 进 scratch
+  msg: MyBytesMut,
+  metas: MetadataCache,
+  // None = 全局 tscns 时钟，不走虚调用
+  clock: Option<Arc<dyn Clock + Send + Sync>>,
+  // text 模式下 &str 参数里的 \n \r \t ESC 转义；json 整条都会 escape
+  escape_strs: bool,
+}
+
+impl LineFormatter {
+  pub(crate) fn new() -> Self {
+    Self {
+      scratch: MyBytesMut::with_capacity(512),
+      time_cache: TimeCache::new(),
+      tid_cache: TidCache::new(32),
+      precision: Precision::Micros,
+      time_format: TimeFormat::Calendar,
+      level_strs: LEVEL_STRS,
+      level_tables: (LEVEL_STRS_PLAIN, LEVEL_STRS, LEVEL_NAMES),
+      color: true,
+      line_format: LineFormat::Text,
+      header: None,
+      module_prefix: "".into(),
+      msg: MyBytesMut::with_capacity(512),
+      metas: MetadataCache::new(),
+      clock: None,
+      escape_strs: false,
+    }
+  }
+
+  #[inline]
+  pub(crate) fn set_line_format(&mut self, line_format: LineFormat) {
+    self.line_format = line_format;
+  }
+
+  /// `false` writes plain level names, without ANSI escape codes.
+  #[inline]
+  pub(crate) fn set_color(&mut self, color: bool) {
+    self.color = color;
+    self.level_strs = if color { self.level_tables.1 } else { self.level_tables.0 };
+  }
+
+  /// Escape control characters in `&str` args of text lines, see `args2::set_escape_strs`.
+  #[inline]
+  pub(crate) fn set_escape_strings(&mut self, on: bool) {
+    self.escape_strs = on;
+  }
+
+  /// One name per `Level` (trace..fatal), padded to the longest; panics on a wrong count.
+  pub(crate) fn set_level_names(&mut self, names: &[&str]) {
+    self.level_tables = level_tables(names);
+    self.set_color(self.color);
+  }
+
+  /// Print `name` in place of `T=NN` for `tid`.
+  #[inline]
+  pub(crate) fn set_thread_name(&mut self, tid: usize, name: &str) {
+    self.tid_cache.set_name(tid, name);
+  }
+
+  #[inline]
+  pub(crate) fn set_header(&mut self, header: HeaderConfig) {
+    self.header = if header == HeaderConfig::default() { None } else { Some(header) };
+  }
+
+  #[inline]
+  pub(crate) fn set_time_format(&mut self, time_format: TimeFormat) {
+    self.time_format = time_format;
+  }
+
+  #[inline]
+  pub(crate) fn set_module_prefix(&mut self, prefix: &str) {
+    self.module_prefix = prefix.into();
+  }
+
+  #[inline]
+  pub(crate) fn set_precision(&mut self, precision: Precision) {
+    self.precision = precision;
+  }
+
+  #[inline]
+  pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock + Send + Sync>) {
+    self.clock = Some(clock);
+  }
+
+  /// Epoch ns of the record's tsc on this formatter's clock.
+  #[inline(always)]
+  fn record_ns(&self, tsc: i64) -> i64 {
+    match &self.clock {
+      None => tscns::tsc2ns(tsc),
+      Some(clock) => clock.tsc_to_ns(tsc),
+    }
+  }
+
+  /// Render one record into the scratch and return the finished line.
+  #[inline(always)]
+  pub(crate) fn format(&mut self, tid: usize, log_meta: &MsgHeader, log_payload: &[u8]) -> io::Result<&[u8]> {
+    if self.line_format == LineFormat::Json {
+      return self.format_json(tid, log_meta, log_payload);
+    }
+    if self.header.is_some() {
+      return self.format_custom(tid, log_meta, log_payload);
+    }
+    let meta = self.metas.get(log_meta.meta_id as u32)?;
+    let curr_ns = self.record_ns(log_meta.tsc);
+
+    self.write_prefix(tid, log_meta.level as usize, curr_ns);
+    // location from the metadata, so runtime sites (`metadata::dynamic_site`) sharing one log_fn print their own
+    meta.loc.write_site(&mut self.scratch, &self.module_prefix);
+    self.scratch.extend_from_slice(b"] ");
+    let mut loc = SourceLocation::__new("", "", 0, 0);
+    set_escape_strs(self.escape_strs);
+    (meta.log_fn)(&mut self.scratch, log_payload, Some(&mut loc))?;
+    mark_decode_error(&mut self.scratch, meta, log_payload);
+
+    self.scratch.push(b'\n');
+    Ok(self.scratch.result())
+  }
+
+  /// Text line with a caller-rendered body (`module::file#line:col] msg`), e.g. from the binary decoder.
+  pub(crate) fn format_body<F>(&mut self, tid: usize, level: usize, curr_ns: i64, body: F) -> io::Result<&[u8]>
+  where
+    F: FnOnce(&mut MyBytesMut) -> io::Result<()>,
+  {
+    self.write_prefix(tid, level, curr_ns);
+    body(&mut self.scratch)?;
+    self.scratch.push(b'\n');
+    Ok(self.scratch.result())
+  }
+
+  /// Clear the scratch and write `[MM-DD HH:MM:SS.mmm.uuu T=00 level `.
+  /// An out-of-range `level` (corrupted header) prints as `unk`.
+  #[inline(always)]
+  fn write_prefix(&mut self, tid: usize, level: usize, curr_ns: i64) {
+    // 最后一项是 "unk"，越界的 level 落到这里，get_unchecked 才安全
+    let level = level.min(self.level_strs.len() - 1);
+
+    self.scratch.clear();
+    self.scratch.push(b'[');
+    self.write_time(curr_ns);
+    self.scratch.push(b' ');
+
+    self.tid_cache.write_to(tid, &mut self.scratch);
+    self.scratch.push(b' ');
+
+    unsafe {
+      self.scratch.extend_from_slice(self.level_strs.get_unchecked(level).as_bytes());
+    }
+  }
+
+  /// `MM-DD HH:MM:SS` plus the sub-second digits of `precision`, or the raw epoch ns.
+  #[inline(always)]
+  fn write_time(&mut self, curr_ns: i64) {
+    if self.time_format == TimeFormat::EpochNanos {
+      let len = lut_u64(self.scratch.unfilled(), curr_ns.max(0) as u64);
+      self.scratch.advance(len);
+      return;
+    }
+    let curr_sec = curr_ns / 1_000_000_000;
+    let sub_ns = curr_ns % 1_000_000_000;
+
+    let sub_us = sub_ns / 1_000;        // 0..999_999
+    let curr_ms = (sub_us / 1_000) as usize;   // 0..999
+    let curr_us = (sub_us % 1_000) as usize;   // 0..999
+
+    self.time_cache.refresh_dt(curr_sec, self.scratch.unfilled());
+    self.scratch.advance(TimeCache::TIME_LEN);
+    match self.precision {
+      Precision::Millis => lut_ms(self.scratch.unfilled(), curr_ms),
+      Precision::Micros => lut_msus(self.scratch.unfilled(), curr_ms, curr_us),
+      Precision::Nanos => lut_msusns(self.scratch.unfilled(), curr_ms, curr_us, (sub_ns % 1_000) as usize),
+      Precision::MicrosCompact => lut_us6(self.scratch.unfilled(), sub_us as usize),
+    }
+    self.scratch.advance(self.precision.len());
+  }
+
+  /// Text line with the fields / order of a non-default `HeaderConfig`.
+  #[inline(never)]
+  fn format_custom(&mut self, tid: usize, log_meta: &MsgHeader, log_payload: &[u8]) -> io::Result<&[u8]> {
+    let meta = self.metas.get(log_meta.meta_id as u32)?;
+    let curr_ns = self.record_ns(log_meta.tsc);
+    let level = (log_meta.level as usize).min(self.level_strs.len() - 1);
+    // 拿出来再放回去，避免边遍历 fields 边写 scratch 的借用冲突
+    let header = self.header.take().unwrap_or_default();
+
+    self.scratch.clear();
+    if !header.fields.is_empty() {
+      self.scratch.push(b'[');
+      for (i, field) in header.fields.iter().enumerate() {
+        if i > 0 {
+          self.scratch.push(b' ');
+        }
+        match field {
+          HeaderField::Time => self.write_time(curr_ns),
+          HeaderField::Tid => self.tid_cache.write_to(tid, &mut self.scratch),
+          HeaderField::Level => {
+            // 去掉末尾的分隔空格
+            let s = self.level_strs[level].as_bytes();
+            self.scratch.extend_from_slice(&s[..s.len() - 1]);
+          }
+          HeaderField::Location => meta.loc.write_site(&mut self.scratch, &self.module_prefix),
+        }
       }
+      self.scratch.extend_from_slice(b"] ");
+    }
+    self.header = Some(header);
+
+    let mut captured = SourceLocation::__new("", "", 0, 0);
+    set_escape_strs(self.escape_strs);
+    (meta.log_fn)(&mut self.scratch, log_payload, Some(&mut captured))?;
+    mark_decode_error(&mut self.scratch, meta, log_payload);
+    self.scratch.push(b'\n');
+    Ok(self.scratch.result())
+  }
+
+  #[inline(never)]
+  fn format_json(&mut self, tid: usize, log_meta: &MsgHeader, log_payload: &[u8]) -> io::Result<&[u8]> {
+    let level_names = self.level_tables.2;
+    let level = (log_meta.level as usize).min(level_names.len() - 1);
+    let meta = self.metas.get(log_meta.meta_id as u32)?;
+    let curr_ns = self.record_ns(log_meta.tsc);
+
+    let mut captured = SourceLocation::__new("", "", 0, 0);
+    self.msg.clear();
+    set_escape_strs(false);
+    (meta.log_fn)(&mut self.msg, log_payload, Some(&mut captured))?;
+    mark_decode_error(&mut self.msg, meta, log_payload);
+    let loc = &meta.loc;
+
+    self.scratch.clear();
+    write!(self.scratch, "{{\"ts\":{},\"tid\":{},", curr_ns, tid)?;
+    if let Some(name) = self.tid_cache.name(tid) {
+      self.scratch.extend_from_slice(b"\"thread\":");
+      json_escape(&mut self.scratch, name.as_bytes());
+      self.scratch.push(b',');
+    }
+    write!(self.scratch, "\"level\":\"{}\",\"module\":", level_names[level])?;
+    json_escape(&mut self.scratch, loc.module(&self.module_prefix).as_bytes());
+    self.scratch.extend_from_slice(b",\"file\":");
+    json_escape(&mut self.scratch, loc.file.as_bytes());
+    write!(self.scratch, ",\"line\":{},\"column\":{},\"msg\":", loc.line, loc.column)?;
+    json_escape(&mut self.scratch, self.msg.result());
+    self.scratch.extend_from_slice(b"}\n");
+    Ok(self.scratch.result())
+  }
+}
+
+/// Flag a record whose tags do not add up to its payload length, its args may be garbage.
+#[inline(always)]
+fn mark_decode_error(out: &mut MyBytesMut, meta: &Metadata, payload: &[u8]) {
+  if meta.decoded_len(payload) != Some(payload.len()) {
+    out.extend_from_slice(b" <decode error>");
+  }
+}
+
+/// `a` and `b` are the same line apart from the timestamp (text `MM-DD HH:MM:SS.fff` wherever the
+/// header puts it, JSON `"ts"`), i.e. a repeated message.
+pub(crate) fn same_but_timestamp(a: &[u8], b: &[u8]) -> bool {
+  let (a0, a1) = split_timestamp(a);
+  let (b0, b1) = split_timestamp(b);
+  a0 == b0 && a1 == b1
+}
+
+/// (before, after) the timestamp of a rendered line, `(line, [])` if it has none.
+fn split_timestamp(line: &[u8]) -> (&[u8], &[u8]) {
+  if line.first() == Some(&b'{') {
+    return match line.windows(7).position(|w| w == b",\"tid\":") {
+      Some(i) => (&[], &line[i..]),
+      None => (line, &[]),
+    };
+  }
+  // MM-DD HH:MM:SS, then .ddd groups
+  const SHAPE: &[u8; TimeCache::TIME_LEN] = b"00-00 00:00:00";
+  let Some(start) = line.windows(SHAPE.len()).position(|w| {
+    w.iter().zip(SHAPE).all(|(&c, &s)| if s == b'0' { c.is_ascii_digit() } else { c == s })
+  }) else {
+    return split_epoch_nanos(line);
+  };
+  let mut end = start + SHAPE.len();
+  while end < line.len() && (line[end] == b'.' || line[end].is_ascii_digit()) {
+    end += 1;
+  }
+  (&line[..start], &line[end..])
+}
+
+/// `TimeFormat::EpochNanos`: the first run of 16+ digits.
+fn split_epoch_nanos(line: &[u8]) -> (&[u8], &[u8]) {
+  let mut i = 0;
+  while i < line.len() {
+    if !line[i].is_ascii_digit() {
+      i += 1;
+      continue;
     }
+    let start = i;
+    while i < line.len() && line[i].is_ascii_digit() {
+      i += 1;
+    }
+    if i - start >= 16 {
+      return (&line[..start], &line[i..]);
+    }
+  }
+  (line, &[])
+}
+
+/// `line` with the number of identical lines collapsed after it: ` (repeated K times)` for text,
+/// a `"repeated":K` field for JSON.
+pub(crate) fn write_repeated(out: &mut Vec<u8>, line: &[u8], count: u64) {
+  if line.first() == Some(&b'{') && line.ends_with(b"}\n") {
+    out.extend_from_slice(&line[..line.len() - 2]);
+    let _ = writeln!(out, ",\"repeated\":{}}}", count);
+  } else {
+    out.extend_from_slice(line.strip_suffix(b"\n").unwrap_or(line));
+    let _ = writeln!(out, " (repeated {} times)", count);
+  }
+}
+
+/// Write `s` as a quoted JSON string.
+fn json_escape(out: &mut MyBytesMut, s: &[u8]) {
+  const HEX: &[u8; 16] = b"0123456789abcdef";
+  out.push(b'"');
+  let mut start = 0;
+  for (i, &b) in s.iter().enumerate() {
+    let esc: &[u8] = match b {
+      b'"' => b"\\\"",
+      b'\\' => b"\\\\",
+      b'\n' => b"\\n",
+      b'\r' => b"\\r",
+      b'\t' => b"\\t",
+      0..=0x1f => {
+        out.extend_from_slice(&s[start..i]);
+        out.extend_from_slice(&[b'\\', b'u', b'0', b'0', HEX[(b >> 4) as usize], HEX[(b & 0xf) as usize]]);
+        start = i + 1;
+        continue;
+      }
+      _ => continue,
+    };
+    out.extend_from_slice(&s[start..i]);
+    out.extend_from_slice(esc);
+    start = i + 1;
   }
+  out.extend_from_slice(&s[start..]);
+  out.push(b'"');
 }
 
+// every level is padded to 5 visible chars + 1 separator
 pub(crate) const LEVEL_STRS: &'static [&'static str] = &[
+  "trace ",
+  "debug ",
+  "\x1b[32minfo\x1b[m  ",
+  "\x1b[31mwarn\x1b[m  ",
+  "\x1b[31merror\x1b[m ",
+  "\x1b[1;31mfatal\x1b[m ",
+  "unk   ",
+];
+
+pub(crate) const LEVEL_NAMES: &[&str] = &[
   "trace",
   "debug",
-  "\x1b[32minfo\x1b[m ",
-  "\x1b[31mwarn\x1b[m ",
-  "\x1b[31merror\x1b[m",
-  "unk  ",
+  "info",
+  "warn",
+  "error",
+  "fatal",
+  "unk",
+];
+
+pub(crate) const LEVEL_STRS_PLAIN: &[&str] = &[
+  "trace ",
+  "debug ",
+  "info  ",
+  "warn  ",
+  "error ",
+  "fatal ",
+  "unk   ",
 ];
 
+// LEVEL_STRS 的颜色，自定义名字沿用
+const LEVEL_COLORS: [&str; Level::COUNT] = ["", "", "\x1b[32m", "\x1b[31m", "\x1b[31m", "\x1b[1;31m"];
+
+/// The three tables a `LineFormatter` looks levels up in, built from user names.
+/// Leaked: built once per sink at setup, like `metadata::dynamic_site` strings.
+fn level_tables(names: &[&str]) -> (&'static [&'static str], &'static [&'static str], &'static [&'static str]) {
+  assert!(
+    names.len() == Level::COUNT,
+    "level names must cover all {} levels (trace..fatal), got {}",
+    Level::COUNT,
+    names.len()
+  );
+  let leak = |v: Vec<String>| -> &'static [&'static str] {
+    Box::leak(v.into_iter().map(|s| &*Box::leak(s.into_boxed_str())).collect::<Vec<_>>().into_boxed_slice())
+  };
+  let names: Vec<&str> = names.iter().copied().chain(["unk"]).collect();
+  let width = names.iter().map(|n| n.chars().count()).max().unwrap_or(0);
+  let plain = names.iter().map(|n| format!("{:<width$} ", n)).collect();
+  let colored = names
+    .iter()
+    .enumerate()
+    .map(|(i, n)| match LEVEL_COLORS.get(i) {
+      Some(c) if !c.is_empty() => format!("{}{}\x1b[m{:pad$} ", c, n, "", pad = width - n.chars().count()),
+      _ => format!("{:<width$} ", n),
+    })
+    .collect();
+  (leak(plain), leak(colored), leak(names.iter().map(|n| n.to_string()).collect()))
+}
+
 pub fn lut_msus(buf: &mut [u8], ms: usize, us: usize) {
   let rms = ms << 2;
   let rus = us << 2;
@@ -94,33 +628,106 @@ pub fn lut_msus(buf: &mut [u8], ms: usize, us: usize) {
   }
 }
 
+/// Decimal `v` at the start of `buf` (at most 20 bytes), returns the number of digits.
 #[inline(always)]
-fn civil_from_days(days: i64) -> (u32, u32) {
+pub fn lut_u64(buf: &mut [u8], mut v: u64) -> usize {
+  let mut tmp = [0u8; 20];
+  let mut i = tmp.len();
+  while v >= 100 {
+    let off = ((v % 100) << 1) as usize;
+    v /= 100;
+    i -= 2;
+    tmp[i..i + 2].copy_from_slice(&DEC_2DIGITS_LUT[off..off + 2]);
+  }
+  if v >= 10 {
+    let off = (v << 1) as usize;
+    i -= 2;
+    tmp[i..i + 2].copy_from_slice(&DEC_2DIGITS_LUT[off..off + 2]);
+  } else {
+    i -= 1;
+    tmp[i] = b'0' + v as u8;
+  }
+  let len = tmp.len() - i;
+  buf[..len].copy_from_slice(&tmp[i..]);
+  len
+}
+
+pub fn lut_ms(buf: &mut [u8], ms: usize) {
+  let rms = ms << 2;
+  debug_assert!(rms < DEC_4DIGITS_LUT.len());
+  unsafe {
+    ptr::copy_nonoverlapping(DEC_4DIGITS_LUT.as_ptr().add(rms), buf.as_mut_ptr(), 4);
+  }
+}
+
+/// `.uuuuuu` for `us` micros within the second (0..999_999): 7 bytes, one dot.
+#[inline(always)]
+pub fn lut_us6(buf: &mut [u8], us: usize) {
+  let rhi = (us / 1_000) << 2;
+  let rlo = (us % 1_000) << 2;
+  debug_assert!(rhi < DEC_4DIGITS_LUT.len());
+  unsafe {
+    let dest = buf.as_mut_ptr();
+    ptr::copy_nonoverlapping(DEC_4DIGITS_LUT.as_ptr().add(rhi), dest, 4);
+    // 低三位不要前面的 '.'
+    ptr::copy_nonoverlapping(DEC_4DIGITS_LUT.as_ptr().add(rlo + 1), dest.add(4), 3);
+  }
+}
+
+pub fn lut_msusns(buf: &mut [u8], ms: usize, us: usize, ns: usize) {
+  let rns = ns << 2;
+  debug_assert!(rns < DEC_4DIGITS_LUT.len());
+  lut_msus(buf, ms, us);
+  unsafe {
+    ptr::copy_nonoverlapping(DEC_4DIGITS_LUT.as_ptr().add(rns), buf.as_mut_ptr().add(8), 4);
+  }
+}
+
+#[inline(always)]
+fn civil_from_days(days: i64) -> (i32, u32, u32) {
   // Howard Hinnant: days since 1970-01-01 -> (y,m,d)
   let z = days + 719_468;
   let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
   let doe = z - era * 146_097;                          // [0, 146096]
   let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365; // [0, 399]
-  // let y = (yoe + era * 400) as i32;
+  let y = (yoe + era * 400) as i32;
   let doy = doe - (365*yoe + yoe/4 - yoe/100);          // [0, 365]
   let mp = (5*doy + 2) / 153;                           // [0, 11]
   let d = (doy - (153*mp + 2)/5 + 1) as u32;            // [1, 31]
   let m = (mp + if mp < 10 { 3 } else { -9 }) as i32;   // [1, 12]
-  // let year = y + if m <= 2 { 1 } else { 0 };
-  (m as u32, d)
+  let year = y + if m <= 2 { 1 } else { 0 };
+  (year, m as u32, d)
 }
 
 #[inline(always)]
 fn split_utc(secs: i64) -> (u32,u32,u32,u32,u32) {
   let days = secs.div_euclid(86_400);
   let sod  = secs.rem_euclid(86_400);
-  let (month, day) = civil_from_days(days);
+  let (_, month, day) = civil_from_days(days);
   let hh = (sod / 3600) as u32;
   let mm = ((sod % 3600) / 60) as u32;
   let ss = (sod % 60) as u32;
   (month, day, hh, mm, ss)
 }
 
+/// Epoch ns shown as `MM-DD HH:MM:SS.mmm.uuu.nnn` (UTC), the header's layout at `Precision::Nanos`.
+pub(crate) struct EpochNs(pub(crate) i64);
+
+impl std::fmt::Display for EpochNs {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let (month, day, hh, mm, ss) = split_utc(self.0.div_euclid(1_000_000_000));
+    let sub = self.0.rem_euclid(1_000_000_000);
+    write!(f, "{:02}-{:02} {:02}:{:02}:{:02}.{:03}.{:03}.{:03}", month, day, hh, mm, ss, sub / 1_000_000, sub / 1_000 % 1_000, sub % 1_000)
+  }
+}
+
+/// `YYYY-MM-DD-HH` (UTC) of the epoch second `secs`, used for rotated file names.
+pub(crate) fn utc_hour_stamp(secs: i64) -> String {
+  let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+  let hh = secs.rem_euclid(86_400) / 3600;
+  format!("{:04}-{:02}-{:02}-{:02}", year, month, day, hh)
+}
+
 pub(crate) const DEC_2DIGITS_LUT: [u8; 100 * 2] = *b"\
       0001020304050607080910111213141516171819\
       2021222324252627282930313233343536373839\
@@ -144,4 +751,296 @@ const fn build_4digit_table() -> [u8; 4_000] {
     i += 1;
   }
   table
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::log::Level;
+  use crate::metadata::{CallSite, Metadata};
+
+  fn msg_shim(out: &mut MyBytesMut, _bytes: &[u8], _loc: Option<&mut SourceLocation>) -> io::Result<()> {
+    out.extend_from_slice(b"msg");
+    Ok(())
+  }
+
+  static MSG_SITE: CallSite = CallSite::new(Metadata {
+    fmt: "msg",
+    level: Level::Info,
+    loc: SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+    num_args: 0,
+    arg_names: &[],
+    field_names: &[],
+    log_fn: msg_shim,
+  });
+
+  fn header(tsc: i64) -> MsgHeader {
+    MsgHeader {
+      size: 0,
+      level: Level::Info as u32,
+      tsc,
+      meta_id: MSG_SITE.id() as u64,
+    }
+  }
+
+  #[test]
+  fn sub_second_digit_groups() {
+    let mut buf = [0u8; 12];
+    lut_ms(&mut buf, 123);
+    assert_eq!(&buf[..4], b".123");
+    lut_msus(&mut buf, 123, 45);
+    assert_eq!(&buf[..8], b".123.045");
+    lut_msusns(&mut buf, 7, 456, 789);
+    assert_eq!(&buf, b".007.456.789");
+  }
+
+  #[test]
+  fn compact_micros_vs_dotted() {
+    let mut dotted = [0u8; 8];
+    lut_msus(&mut dotted, 123, 456);
+    let mut compact = [0u8; 7];
+    lut_us6(&mut compact, 123_456);
+    assert_eq!(&dotted, b".123.456");
+    assert_eq!(&compact, b".123456");
+    lut_us6(&mut compact, 7);
+    assert_eq!(&compact, b".000007");
+    lut_us6(&mut compact, 999_999);
+    assert_eq!(&compact, b".999999");
+
+    let mut line = LineFormatter::new();
+    line.set_precision(Precision::MicrosCompact);
+    let out = line.format(1, &header(0), &[]).unwrap().to_vec();
+    let out = std::str::from_utf8(&out).unwrap();
+    // "[MM-DD HH:MM:SS.uuuuuu T=01 "
+    assert_eq!(out.as_bytes()[15], b'.', "{out}");
+    assert!(out[16..22].bytes().all(|b| b.is_ascii_digit()), "{out}");
+    assert!(out[22..].starts_with(" T=01 "), "{out}");
+  }
+
+  #[test]
+  fn precision_sets_header_width() {
+    for (precision, digits) in [(Precision::Millis, 1), (Precision::Micros, 2), (Precision::Nanos, 3)] {
+      let mut line = LineFormatter::new();
+      line.set_precision(precision);
+      let out = line.format(1, &header(0), &[]).unwrap().to_vec();
+      let out = std::str::from_utf8(&out).unwrap();
+
+      // "[MM-DD HH:MM:SS" + digits * ".ddd" + " T=01 "
+      let frac = &out[15..15 + precision.len()];
+      assert_eq!(precision.len(), digits * 4);
+      for group in frac.as_bytes().chunks(4) {
+        assert_eq!(group[0], b'.', "{out}");
+        assert!(group[1..].iter().all(u8::is_ascii_digit), "{out}");
+      }
+      assert!(out[15 + precision.len()..].starts_with(" T=01 "), "{out}");
+      assert!(out.ends_with("] msg\n"), "{out}");
+    }
+  }
+
+  fn long_shim(out: &mut MyBytesMut, _bytes: &[u8], _loc: Option<&mut SourceLocation>) -> io::Result<()> {
+    out.extend_from_slice(b"] ");
+    out.extend_from_slice(&[b'z'; 2048]);
+    Ok(())
+  }
+
+  static LONG_SITE: CallSite = CallSite::new(Metadata {
+    fmt: "zzz",
+    level: Level::Info,
+    loc: SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+    num_args: 0,
+    arg_names: &[],
+    field_names: &[],
+    log_fn: long_shim,
+  });
+
+  #[test]
+  fn two_kb_message_grows_the_scratch() {
+    let mut line = LineFormatter::new();
+    let mut hdr = header(0);
+    hdr.meta_id = LONG_SITE.id() as u64;
+    // 第二次复用已经长大的 scratch
+    for _ in 0..2 {
+      let out = line.format(1, &hdr, &[]).unwrap();
+      let (head, body) = out.split_at(out.len() - 2049);
+      assert!(head.starts_with(b"[") && head.ends_with(b"] "));
+      assert_eq!(&body[..2048], &[b'z'; 2048][..]);
+      assert_eq!(body[2048], b'\n');
+    }
+  }
+
+  #[test]
+  fn out_of_range_level_prints_unk() {
+    let mut line = LineFormatter::new();
+    for color in [true, false] {
+      line.set_color(color);
+      let mut hdr = header(0);
+      hdr.level = 99;
+      let out = line.format(1, &hdr, &[]).unwrap().to_vec();
+      let out = std::str::from_utf8(&out).unwrap();
+      assert!(out.contains(" T=01 unk   "), "{out}");
+    }
+  }
+
+  #[test]
+  fn same_record_under_two_header_configs() {
+    let mut line = LineFormatter::new();
+    line.set_color(false);
+    line.set_header(HeaderConfig::new(&[HeaderField::Level, HeaderField::Tid]));
+    assert_eq!(line.format(1, &header(0), &[]).unwrap(), b"[info  T=01] msg\n");
+
+    line.set_header(HeaderConfig::new(&[HeaderField::Location, HeaderField::Level, HeaderField::Time]));
+    let out = String::from_utf8(line.format(1, &header(0), &[]).unwrap().to_vec()).unwrap();
+    let site = format!("[{}::format#", module_path!());
+    assert!(out.starts_with(&site), "{out}");
+    // 时间的具体值取决于 tscns 有没有被别的测试初始化过，只看形状
+    let time = &out[out.find(" info  ").unwrap() + 7..out.len() - 6];
+    assert!(out.ends_with("] msg\n"), "{out}");
+    assert_eq!(split_timestamp(time.as_bytes()), (&b""[..], &b""[..]), "{out}");
+
+    line.set_header(HeaderConfig::new(&[]));
+    assert_eq!(line.format(1, &header(0), &[]).unwrap(), b"msg\n");
+
+    // 回到默认布局走快路径，结果和没设过一样
+    line.set_header(HeaderConfig::default());
+    let default = LineFormatter::new().format(1, &header(0), &[]).unwrap().to_vec();
+    line.set_color(true);
+    assert_eq!(line.format(1, &header(0), &[]).unwrap(), &default[..]);
+  }
+
+  #[test]
+  fn epoch_nanos_matches_tsc2ns() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| tscns::init(10_000_000, tscns::CALIBRATE_INTERVAL_NANOS));
+
+    let mut line = LineFormatter::new();
+    line.set_time_format(TimeFormat::EpochNanos);
+    let tsc = crate::log::rdtsc() as i64;
+    let before = tscns::tsc2ns(tsc);
+    let out = String::from_utf8(line.format(1, &header(tsc), &[]).unwrap().to_vec()).unwrap();
+    // 别的测试可能同时在重新校准
+    let after = tscns::tsc2ns(tsc);
+    let ns: i64 = out[1..out.find(' ').unwrap()].parse().unwrap();
+    assert!(ns == before || ns == after, "{out} vs {before}");
+    assert!(out[1..].starts_with(&format!("{ns} T=01 ")), "{out}");
+
+    let mut buf = [0u8; 20];
+    for v in [0, 7, 10, 99, 100, 12_345, u64::MAX] {
+      let len = lut_u64(&mut buf, v);
+      assert_eq!(&buf[..len], v.to_string().as_bytes());
+    }
+  }
+
+  #[test]
+  fn location_digits_match_fmt() {
+    let mut out = MyBytesMut::with_capacity(16);
+    for (line, column) in [(0, 0), (9, 1), (10, 99), (4_321, 100), (999_999, 65_535), (u32::MAX, u32::MAX)] {
+      let loc = SourceLocation::__new("app::oms", "src/oms.rs", line, column);
+      out.clear();
+      loc.write_to(&mut out);
+      assert_eq!(out.result(), format!("app::oms::oms#{line}:{column}] ").as_bytes());
+    }
+
+    // 粗略对比一下旧的 to_string 写法，只打印不断言（debug 构建下数字没有意义）
+    let loc = SourceLocation::__new("app::oms", "src/oms.rs", 123_456, 17);
+    let rounds = 100_000;
+    let start = std::time::Instant::now();
+    for _ in 0..rounds {
+      out.clear();
+      loc.write_to(&mut out);
+    }
+    let lut = start.elapsed();
+    let start = std::time::Instant::now();
+    for _ in 0..rounds {
+      out.clear();
+      out.extend_from_slice(b"app::oms::oms#");
+      out.extend_from_slice(123_456u32.to_string().as_bytes());
+      out.push(b':');
+      out.extend_from_slice(17u32.to_string().as_bytes());
+      out.extend_from_slice(b"] ");
+    }
+    let fmt = start.elapsed();
+    eprintln!("location: lut {:?}/record, fmt {:?}/record", lut / rounds, fmt / rounds);
+  }
+
+  #[test]
+  fn module_prefix_is_stripped_when_configured() {
+    let render = |line: &mut LineFormatter| String::from_utf8(line.format(1, &header(0), &[]).unwrap().to_vec()).unwrap();
+    let mut line = LineFormatter::new();
+    assert!(render(&mut line).contains(" hft_log_demo::format::tests::format#"));
+
+    line.set_module_prefix("hft_log_demo::");
+    let out = render(&mut line);
+    assert!(out.contains(" format::tests::format#"), "{out}");
+    assert!(!out.contains("hft_log_demo"), "{out}");
+
+    line.set_line_format(LineFormat::Json);
+    let out = render(&mut line);
+    assert!(out.contains("\"module\":\"format::tests\""), "{out}");
+
+    // 不匹配的前缀原样保留
+    line.set_line_format(LineFormat::Text);
+    line.set_module_prefix("mytradingsystem::");
+    assert!(render(&mut line).contains(" hft_log_demo::format::tests::format#"));
+  }
+
+  #[test]
+  fn refresh_dt_bytes_returns_the_cached_time() {
+    let mut cache = TimeCache::new();
+    // 2026-01-16 09:33:36 UTC
+    assert_eq!(cache.refresh_dt_bytes(1_768_556_016), b"01-16 09:33:36");
+    assert_eq!(cache.refresh_dt_bytes(1_768_556_016), b"01-16 09:33:36");
+    assert_eq!(cache.refresh_dt_bytes(1_768_556_026), b"01-16 09:33:46");
+
+    let mut buf = [0u8; TimeCache::TIME_LEN];
+    cache.refresh_dt(1_768_556_016 + 86_400, &mut buf);
+    assert_eq!(&buf, b"01-17 09:33:36");
+  }
+
+  #[test]
+  fn tids_past_the_lut_and_names() {
+    let mut tids = TidCache::new(32);
+    let mut out = MyBytesMut::with_capacity(64);
+    for tid in [3, 31, 32, 4_194_303] {
+      out.clear();
+      tids.write_to(tid, &mut out);
+      assert_eq!(out.result(), format!("T={:02}", tid).as_bytes());
+    }
+
+    tids.set_name(4_194_303, "market-data");
+    tids.set_name(3, "oms");
+    tids.set_name(3, "oms-2");
+    out.clear();
+    tids.write_to(4_194_303, &mut out);
+    assert_eq!(out.result(), b"market-data");
+    out.clear();
+    tids.write_to(3, &mut out);
+    assert_eq!(out.result(), b"oms-2");
+  }
+
+  static PX_SITE: CallSite = CallSite::new(Metadata {
+    fmt: "px={}",
+    level: Level::Info,
+    loc: SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+    num_args: 1,
+    arg_names: &[],
+    field_names: &[],
+    log_fn: msg_shim,
+  });
+
+  #[test]
+  fn decoded_length_mismatch_is_marked() {
+    let meta = &PX_SITE.meta;
+    let good = crate::args2::encode_to_vec(&crate::args2::args1(7u64));
+    assert_eq!(meta.decoded_len(&good), Some(good.len()));
+    let mut out = MyBytesMut::with_capacity(64);
+    mark_decode_error(&mut out, meta, &good);
+    assert!(out.result().is_empty());
+
+    // 编码端多写了 8 字节：tag 说只有一个 u64
+    let mut long = good.clone();
+    long.extend_from_slice(&[0; 8]);
+    assert_eq!(meta.decoded_len(&long), Some(good.len()));
+    assert_eq!(meta.decoded_len(&[]), None);
+    mark_decode_error(&mut out, meta, &long);
+    assert_eq!(out.result(), b" <decode error>");
+  }
+}
@@ -1,14 +1,26 @@
-use crate::spsc_var_queue_opt::SpscVarQueueOpt;
+use crate::spsc_var_queue_opt::BLOCK_SIZE;
+pub use crate::spsc_var_queue_opt::{blocks_for, SpscVarQueueOpt};
 
 pub mod args;
 pub mod args2;
 pub mod log;
+pub mod metadata;
 pub mod run_log;
 pub(crate) mod spsc;
 pub(crate) mod spsc_var_queue_opt;
 pub mod run_log2;
 pub mod tscns;
-pub(crate) mod console_sink;
+pub mod console_sink;
+pub mod file_sink;
+pub mod binary_sink;
+pub mod capture_sink;
+pub mod tee_sink;
+pub mod udp_sink;
+#[cfg(unix)]
+pub mod mmap_sink;
+#[cfg(all(unix, feature = "signal_flush"))]
+pub(crate) mod signal_flush;
+pub mod sink;
 pub mod format;
 pub mod my_bytes_mut;
 
@@ -21,4 +33,5 @@ pub mod spsc_queue {
   }
 }
 
-pub type StagingBuffer = SpscVarQueueOpt<1024>;
\ No newline at end of file
+/// One producer thread's queue: 64KB unless the logger was given another capacity (`SpscVarQueueOpt::with_capacity`).
+pub type StagingBuffer = SpscVarQueueOpt<{ blocks_for(64 * 1024, BLOCK_SIZE) }>;
\ No newline at end of file
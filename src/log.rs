@@ -1,80 +1,296 @@
 use std::io::Write;
-use std::{io, mem, ptr};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{io, mem};
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 use crate::my_bytes_mut::MyBytesMut;
+use crate::format::lut_u64;
+use crate::args2::Payload;
 
-const MAX_PAYLOAD_LEN: usize = 256;
+pub const MAX_PAYLOAD_LEN: usize = 256;
 
-#[cfg(target_arch = "x86_64")]
+/// The cycle counter every clock in this crate runs on: `rdtsc` on x86, `cntvct_el0` on aarch64,
+/// see `tscns::read_tsc` (which falls back to the system clock elsewhere).
 #[inline(always)]
 pub fn rdtsc() -> u64 {
-  unsafe { core::arch::x86_64::_rdtsc() as u64 }
+  crate::tscns::read_tsc() as u64
 }
 
-#[cfg(not(target_arch = "x86_64"))]
-#[inline(always)]
-pub fn rdtsc() -> u64 {
-  // fallback，仅用于非 x86_64
-  SystemTime::now()
-    .duration_since(UNIX_EPOCH)
-    .unwrap()
-    .as_nanos() as u64
+/// Kernel id of the calling thread, as shown by `top -H` / `perf` / `strace`.
+#[cfg(target_os = "linux")]
+#[inline]
+pub fn get_tid() -> u32 {
+  unsafe { libc::syscall(libc::SYS_gettid) as u32 }
+}
+
+#[cfg(target_os = "macos")]
+#[inline]
+pub fn get_tid() -> u32 {
+  let mut tid = 0u64;
+  unsafe { libc::pthread_threadid_np(0, &mut tid) };
+  tid as u32
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+#[inline]
+pub fn get_tid() -> u32 {
+  // 没有 gettid：退回到进程内递增编号
+  static NEXT: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(1);
+  thread_local!(static TID: u32 = NEXT.fetch_add(1, Ordering::Relaxed));
+  TID.with(|t| *t)
 }
 
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Level {
   Trace = 0,
   Debug = 1,
   Info = 2,
   Warn = 3,
   Error = 4,
+  /// `hft_fatal!` flushes the logger right after logging it
+  Fatal = 5,
+}
+
+impl Level {
+  /// Number of levels; a custom level-name table (`with_level_names`) needs exactly this many entries.
+  pub const COUNT: usize = 6;
+
+  #[inline(always)]
+  pub(crate) fn from_u8(v: u8) -> Level {
+    match v {
+      0 => Level::Trace,
+      1 => Level::Debug,
+      2 => Level::Info,
+      3 => Level::Warn,
+      4 => Level::Error,
+      _ => Level::Fatal,
+    }
+  }
+}
+
+/// Compile-time floor under `MAX_LEVEL`, picked by the `max_level_*` cargo features (the most
+/// restrictive one wins). Calls below it are constant-false and never evaluate their arguments.
+pub const STATIC_MAX_LEVEL: Level = if cfg!(feature = "max_level_error") {
+  Level::Error
+} else if cfg!(feature = "max_level_warn") {
+  Level::Warn
+} else if cfg!(feature = "max_level_info") {
+  Level::Info
+} else if cfg!(feature = "max_level_debug") {
+  Level::Debug
+} else {
+  Level::Trace
+};
+
+#[inline(always)]
+pub const fn static_enabled(lvl: Level) -> bool {
+  lvl as u8 >= STATIC_MAX_LEVEL as u8
+}
+
+/// minimum level that reaches the queue, everything below it is filtered at the call site
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Trace as u8);
+
+/// Set the minimum active level at runtime, e.g. `set_max_level(Level::Info)` silences trace/debug.
+#[inline]
+pub fn set_max_level(lvl: Level) {
+  MAX_LEVEL.store(lvl as u8, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn max_level() -> Level {
+  Level::from_u8(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+#[inline(always)]
+pub fn enabled(lvl: Level) -> bool {
+  lvl as u8 >= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+static ABORT_ON_FATAL: AtomicBool = AtomicBool::new(false);
+
+/// Make every `hft_fatal!` abort the process once its line is flushed. Off by default.
+pub fn set_abort_on_fatal(abort: bool) {
+  ABORT_ON_FATAL.store(abort, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn abort_on_fatal() -> bool {
+  ABORT_ON_FATAL.load(Ordering::Relaxed)
+}
+
+/// per-module overrides, sorted by prefix length (longest first) so the first hit is the most specific rule
+static MODULE_LEVELS: RwLock<Vec<(&'static str, u8)>> = RwLock::new(Vec::new());
+static HAS_MODULE_LEVELS: AtomicBool = AtomicBool::new(false);
+/// bumped on every change to `MODULE_LEVELS`; a [`ModuleFilter`] cached under an older value re-resolves.
+/// Starts at 1 so a fresh filter (generation 0) never matches.
+static MODULE_GEN: AtomicU64 = AtomicU64::new(1);
+
+/// `module_rule` result when no override covers the module: fall back to the global level
+const NO_RULE: u8 = u8::MAX;
+
+/// Override the minimum level for `module` and every module nested under it,
+/// e.g. `set_module_level("my_crate::hotloop", Level::Info)` also covers `my_crate::hotloop::inner`.
+pub fn set_module_level(module: &'static str, lvl: Level) {
+  let mut rules = MODULE_LEVELS.write().unwrap();
+  match rules.iter_mut().find(|(m, _)| *m == module) {
+    Some(rule) => rule.1 = lvl as u8,
+    None => {
+      rules.push((module, lvl as u8));
+      rules.sort_by_key(|r| std::cmp::Reverse(r.0.len()));
+    }
+  }
+  HAS_MODULE_LEVELS.store(true, Ordering::Release);
+  MODULE_GEN.fetch_add(1, Ordering::Release);
+}
+
+/// Drop the override for exactly `module` (not the ones nested under it). Returns whether there was one.
+pub fn clear_module_level(module: &str) -> bool {
+  let mut rules = MODULE_LEVELS.write().unwrap();
+  let before = rules.len();
+  rules.retain(|(m, _)| *m != module);
+  let removed = rules.len() != before;
+  HAS_MODULE_LEVELS.store(!rules.is_empty(), Ordering::Release);
+  MODULE_GEN.fetch_add(1, Ordering::Release);
+  removed
+}
+
+/// Drop every module override; filtering goes back to the single global level.
+pub fn clear_module_levels() {
+  let mut rules = MODULE_LEVELS.write().unwrap();
+  rules.clear();
+  HAS_MODULE_LEVELS.store(false, Ordering::Release);
+  MODULE_GEN.fetch_add(1, Ordering::Release);
 }
 
 #[inline(always)]
-pub fn enabled(_lvl: Level) -> bool {
-  true
+fn module_matches(module_path: &str, prefix: &str) -> bool {
+  match module_path.strip_prefix(prefix) {
+    Some(rest) => rest.is_empty() || rest.starts_with("::"),
+    None => false,
+  }
 }
 
-pub(crate) type LogFn = fn(&mut MyBytesMut, bytes: &[u8]) -> io::Result<()>;
+/// Like [`enabled`], but honors the overrides installed by [`set_module_level`].
+/// Without any override this is still a single relaxed load. The macros go through a per-site
+/// [`ModuleFilter`] instead, so they only take the lock when the overrides change.
+#[inline(always)]
+pub fn enabled_for(lvl: Level, module_path: &str) -> bool {
+  if !HAS_MODULE_LEVELS.load(Ordering::Relaxed) {
+    return enabled(lvl);
+  }
+  rule_enabled(lvl, module_rule(module_path))
+}
+
+#[inline(always)]
+fn rule_enabled(lvl: Level, rule: u8) -> bool {
+  if rule == NO_RULE {
+    enabled(lvl)
+  } else {
+    lvl as u8 >= rule
+  }
+}
+
+#[inline(never)]
+fn module_rule(module_path: &str) -> u8 {
+  let rules = MODULE_LEVELS.read().unwrap();
+  match rules.iter().find(|(m, _)| module_matches(module_path, m)) {
+    Some((_, min)) => *min,
+    None => NO_RULE,
+  }
+}
+
+/// Per-call-site cache of the module override, one `static` per macro invocation.
+/// Holds `generation << 8 | rule`; while the generation is current the check is two relaxed loads.
+pub struct ModuleFilter {
+  cached: AtomicU64,
+}
+
+impl ModuleFilter {
+  pub const fn new() -> Self {
+    Self { cached: AtomicU64::new(0) }
+  }
+
+  #[inline(always)]
+  pub fn enabled(&self, lvl: Level, module_path: &str) -> bool {
+    if !HAS_MODULE_LEVELS.load(Ordering::Relaxed) {
+      return enabled(lvl);
+    }
+    let gen = MODULE_GEN.load(Ordering::Acquire);
+    let cached = self.cached.load(Ordering::Relaxed);
+    let rule = if cached >> 8 == gen {
+      cached as u8
+    } else {
+      // 读 generation 在查表之前：查表期间规则又变了的话，存下的是旧 generation，下次还会重查
+      let rule = module_rule(module_path);
+      self.cached.store(gen << 8 | rule as u64, Ordering::Relaxed);
+      rule
+    };
+    rule_enabled(lvl, rule)
+  }
+}
+
+impl Default for ModuleFilter {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// The level check the macros run: a per-site [`ModuleFilter`] over `module_path!()`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __site_enabled {
+    ($lvl:expr) => {{
+        static __FILTER: $crate::log::ModuleFilter = $crate::log::ModuleFilter::new();
+        __FILTER.enabled($lvl, module_path!())
+    }};
+}
+
+/// Per-call-site formatter generated by the emit macros.
+/// `loc == None`: write the `module::file#line] ` prefix, then the message;
+/// `loc == Some(slot)`: store the call site in `slot` and write the message only (structured output).
+pub(crate) type LogFn = fn(&mut MyBytesMut, bytes: &[u8], loc: Option<&mut SourceLocation>) -> io::Result<()>;
 
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct LogEntry {
   pub tsc: u64,
   pub level: u64,
-  // pub len: u16,
-  // pub _pad: [u8; 7],
+  /// bytes of `data` written by the producer (`encoded_len` of the args)
+  pub len: u16,
+  pub _pad: [u8; 6],
   pub func: LogFn,
   pub data: [u8; MAX_PAYLOAD_LEN],
 }
 
 impl LogEntry {
   #[inline(always)]
-  pub fn from_args<A: Copy>(level: Level, func: LogFn, args: &A) -> Self {
-    let sz = size_of::<A>();
-    debug_assert!(sz <= MAX_PAYLOAD_LEN);
+  pub fn from_args<A: Payload>(level: Level, func: LogFn, args: &A) -> Self {
+    debug_assert!(args.encoded_len() <= MAX_PAYLOAD_LEN);
     let mut log_entry = LogEntry {
-      tsc: 0, //rdtsc(),
+      // same clock as run_log2's MsgHeader::tsc, so entries from every queue merge by it
+      tsc: crate::tscns::read_tsc() as u64,
       level: level as u8 as u64,
-      // len: sz as u16,
-      // _pad: [0; 7],
+      len: 0,
+      _pad: [0; 6],
       func,
       data: [0u8; MAX_PAYLOAD_LEN],
     };
-
-    unsafe {
-      ptr::copy_nonoverlapping(args as *const A as *const u8, log_entry.data.as_mut_ptr(), sz);
-    }
+    log_entry.len = args.encode(&mut log_entry.data) as u16;
     log_entry
   }
 
   #[inline(always)]
-  pub fn mut_from_args<A: Copy>(&mut self, level: Level, func: LogFn, args: &A) {
-    let sz = size_of::<A>();
-    debug_assert!(sz <= MAX_PAYLOAD_LEN);
-    self.tsc = 0; // rdtsc();
+  pub fn mut_from_args<A: Payload>(&mut self, level: Level, func: LogFn, args: &A) {
+    self.mut_from_args_at(level, func, args, crate::tscns::read_tsc());
+  }
+
+  /// `mut_from_args` with a `tsc` the caller read, before converting the args.
+  #[inline(always)]
+  pub fn mut_from_args_at<A: Payload>(&mut self, level: Level, func: LogFn, args: &A, tsc: i64) {
+    debug_assert!(args.encoded_len() <= MAX_PAYLOAD_LEN);
+    self.tsc = tsc as u64;
     self.level = level as u8 as u64;
+    self.len = args.encode(&mut self.data) as u16;
     self.func = func;
     // let mut log_entry = LogEntry {
     //   tsc: 0, //rdtsc(),
@@ -84,37 +300,153 @@ impl LogEntry {
     //   func,
     //   data: [0u8; MAX_PAYLOAD_LEN],
     // };
-
-    unsafe {
-      ptr::copy_nonoverlapping(args as *const A as *const u8, self.data.as_mut_ptr(), sz);
-    }
     // log_entry
   }
+
+  /// The encoded args, without the unused tail of `data`.
+  #[inline(always)]
+  pub fn payload(&self) -> &[u8] {
+    &self.data[..self.len as usize]
+  }
 }
 
+/// Log at `$lvl`: `hft_log!(logger, Level::Warn, "px {}", px)`. The level checks come first, so when
+/// the call is filtered out the arguments are never evaluated; a level excluded by a `max_level_*`
+/// feature is a constant `false` and the whole call is compiled away.
 #[macro_export]
-macro_rules! hft_info {
-    ($logger:expr, $fmt:literal $(,)?) => {{
-        if crate::log::enabled(Level::Info) { __emit0!($logger, Level::Info, $fmt); }
+macro_rules! hft_log {
+    ($logger:expr, $lvl:expr, $fmt:literal $(, $a:expr)* ; $($key:ident = $val:expr),+ $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit_fields!($logger, $lvl, $fmt, [$($a),*], [$($key = $val),+]) } else { false }
+    }};
+    ($logger:expr, $lvl:expr, $fmt:literal, $($key:ident = $val:expr),+ $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit_named!($logger, $lvl, $fmt, $($key = $val),+) } else { false }
+    }};
+    ($logger:expr, $lvl:expr, $fmt:literal $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit0!($logger, $lvl, $fmt) } else { false }
     }};
-    ($logger:expr, $fmt:literal, $a0:expr $(,)?) => {{
-        if enabled(Level::Info) { __emit1!($logger, Level::Info, $fmt, $a0); }
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit1!($logger, $lvl, $fmt, $a0) } else { false }
     }};
-    ($logger:expr, $fmt:literal, $a0:expr, $a1:expr $(,)?) => {{
-        //if $crate::log::enabled($crate::log::Level::Info) { $crate::__emit2!($logger, $crate::log::Level::Info, $fmt, $a0, $a1); }
-        $crate::__emit2!($logger, $crate::log::Level::Info, $fmt, $a0, $a1)
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit2!($logger, $lvl, $fmt, $a0, $a1) } else { false }
     }};
-    ($logger:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr $(,)?) => {{
-        if enabled(Level::Info) { __emit3!($logger, Level::Info, $fmt, $a0, $a1, $a2); }
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit3!($logger, $lvl, $fmt, $a0, $a1, $a2) } else { false }
     }};
-    ($logger:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr $(,)?) => {{
-        if enabled(Level::Info) { __emit4!($logger, Level::Info, $fmt, $a0, $a1, $a2, $a3); }
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit4!($logger, $lvl, $fmt, $a0, $a1, $a2, $a3) } else { false }
     }};
-    ($logger:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr $(,)?) => {{
-        if enabled(Level::Info) { __emit5!($logger, Level::Info, $fmt, $a0, $a1, $a2, $a3, $a4); }
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit5!($logger, $lvl, $fmt, $a0, $a1, $a2, $a3, $a4) } else { false }
     }};
-    ($logger:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr $(,)?) => {{
-        if enabled(Level::Info) { __emit6!($logger, Level::Info, $fmt, $a0, $a1, $a2, $a3, $a4, $a5); }
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit6!($logger, $lvl, $fmt, $a0, $a1, $a2, $a3, $a4, $a5) } else { false }
+    }};
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit7!($logger, $lvl, $fmt, $a0, $a1, $a2, $a3, $a4, $a5, $a6) } else { false }
+    }};
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit8!($logger, $lvl, $fmt, $a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7) } else { false }
+    }};
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit9!($logger, $lvl, $fmt, $a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8) } else { false }
+    }};
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr, $a9:expr $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit10!($logger, $lvl, $fmt, $a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8, $a9) } else { false }
+    }};
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr, $a9:expr, $a10:expr $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit11!($logger, $lvl, $fmt, $a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8, $a9, $a10) } else { false }
+    }};
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr, $a9:expr, $a10:expr, $a11:expr $(,)?) => {{
+        if $crate::log::static_enabled($lvl) && $crate::__site_enabled!($lvl) { $crate::__emit12!($logger, $lvl, $fmt, $a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8, $a9, $a10, $a11) } else { false }
+    }};
+}
+
+/// `hft_log!` at `Level::Trace`.
+#[macro_export]
+macro_rules! hft_trace {
+    ($logger:expr, $($rest:tt)+) => { $crate::hft_log!($logger, $crate::log::Level::Trace, $($rest)+) };
+}
+
+/// `hft_log!` at `Level::Debug`.
+#[macro_export]
+macro_rules! hft_debug {
+    ($logger:expr, $($rest:tt)+) => { $crate::hft_log!($logger, $crate::log::Level::Debug, $($rest)+) };
+}
+
+/// `hft_log!` at `Level::Info`.
+#[macro_export]
+macro_rules! hft_info {
+    ($logger:expr, $($rest:tt)+) => { $crate::hft_log!($logger, $crate::log::Level::Info, $($rest)+) };
+}
+
+/// `hft_log!` at `Level::Warn`.
+#[macro_export]
+macro_rules! hft_warn {
+    ($logger:expr, $($rest:tt)+) => { $crate::hft_log!($logger, $crate::log::Level::Warn, $($rest)+) };
+}
+
+/// `hft_log!` at `Level::Error`.
+#[macro_export]
+macro_rules! hft_error {
+    ($logger:expr, $($rest:tt)+) => { $crate::hft_log!($logger, $crate::log::Level::Error, $($rest)+) };
+}
+
+/// `hft_log!` at `Level::Fatal`, then `$logger.flush()`: the line has left the sink when it returns,
+/// so it survives an `abort`/`exit` right after. A full queue makes it wait (up to 1s) rather than drop.
+/// With [`set_abort_on_fatal`]`(true)` it then calls `std::process::abort()`.
+#[macro_export]
+macro_rules! hft_fatal {
+    ($logger:expr, $($rest:tt)+) => {{
+        let __hft_logger = &$logger;
+        let __hft_ok = $crate::hft_log!(__hft_logger, $crate::log::Level::Fatal, $($rest)+);
+        __hft_logger.flush();
+        if $crate::log::abort_on_fatal() {
+            ::std::process::abort();
+        }
+        __hft_ok
+    }};
+}
+
+/// `hft_info!` that only fires on the 1st, N+1th, 2N+1th.. hit of this call site (counted across threads);
+/// `n == 0` is treated as 1. Every line after the first gets ` skipped=N-1` appended, so at most 11 args.
+/// Skipped hits cost one relaxed `fetch_add`.
+#[macro_export]
+macro_rules! hft_info_every {
+    ($logger:expr, $n:expr, $fmt:literal $(, $a:expr)* $(,)?) => {{
+        static __HFT_HITS: ::std::sync::atomic::AtomicU64 = ::std::sync::atomic::AtomicU64::new(0);
+        let __hft_n = ($n as u64).max(1);
+        let __hft_hit = __HFT_HITS.fetch_add(1, ::std::sync::atomic::Ordering::Relaxed);
+        if __hft_hit % __hft_n != 0 {
+            false
+        } else if __hft_hit == 0 || __hft_n == 1 {
+            $crate::hft_info!($logger, $fmt $(, $a)*)
+        } else {
+            $crate::hft_info!($logger, $fmt $(, $a)*; skipped = __hft_n - 1)
+        }
+    }};
+}
+
+/// `hft_info!` that fires at most once per `ms` milliseconds for this call site, timed with `tscns::read_nanos`.
+/// When several threads hit the window at once only the one that wins the CAS logs. The hits dropped since
+/// the last line are appended as ` skipped=N` (so at most 11 args).
+#[macro_export]
+macro_rules! hft_info_every_ms {
+    ($logger:expr, $ms:expr, $fmt:literal $(, $a:expr)* $(,)?) => {{
+        static __HFT_LAST_NS: ::std::sync::atomic::AtomicI64 = ::std::sync::atomic::AtomicI64::new(i64::MIN);
+        static __HFT_SKIPPED: ::std::sync::atomic::AtomicU64 = ::std::sync::atomic::AtomicU64::new(0);
+        let now = $crate::tscns::read_nanos();
+        let last = __HFT_LAST_NS.load(::std::sync::atomic::Ordering::Relaxed);
+        if (last == i64::MIN || now.wrapping_sub(last) >= ($ms as i64) * 1_000_000)
+          && __HFT_LAST_NS.compare_exchange(last, now, ::std::sync::atomic::Ordering::Relaxed, ::std::sync::atomic::Ordering::Relaxed).is_ok() {
+            match __HFT_SKIPPED.swap(0, ::std::sync::atomic::Ordering::Relaxed) {
+                0 => $crate::hft_info!($logger, $fmt $(, $a)*),
+                __hft_skipped => $crate::hft_info!($logger, $fmt $(, $a)*; skipped = __hft_skipped),
+            }
+        } else {
+            __HFT_SKIPPED.fetch_add(1, ::std::sync::atomic::Ordering::Relaxed);
+            false
+        }
     }};
 }
 
@@ -122,62 +454,159 @@ macro_rules! hft_info {
 #[derive(Copy, Clone)]
 pub struct SourceLocation {
   pub(crate) module_path: &'static str,
-  file: &'static str,
+  pub(crate) file: &'static str,
   pub(crate) line: u32,
+  // 同一行多次调用时用来区分
+  pub(crate) column: u32,
+  // `file` without directories and `.rs`, worked out once in `__new` (const for macro call sites)
+  file_name: &'static str,
 }
 
 impl SourceLocation {
-  pub fn __new(module_path: &'static str, file: &'static str, line: u32) -> Self {
+  pub const fn __new(module_path: &'static str, file: &'static str, line: u32, column: u32) -> Self {
     Self {
       module_path,
       file,
       line,
+      column,
+      file_name: file_stem(file),
     }
   }
 
+  #[inline(always)]
+  pub(crate) fn module(&self, strip_prefix: &str) -> &'static str {
+    self.module_path.strip_prefix(strip_prefix).unwrap_or(self.module_path)
+  }
+
   #[inline(always)]
   pub(crate) fn file_name(&self) -> &'static str {
-    let file = if self.file.ends_with(".rs") {
-      &self.file[..self.file.len()-3]
-    } else {
-      self.file
-    };
-    if let Some(index) = file.rfind(std::path::MAIN_SEPARATOR) {
-      &file[index + 1..]
-    } else {
-      file
+    self.file_name
+  }
+
+  #[inline(always)]
+  pub fn write_or_capture(self, out: &mut MyBytesMut, loc: Option<&mut SourceLocation>) {
+    match loc {
+      Some(slot) => *slot = self,
+      None => self.write_to(out),
     }
   }
 
+  /// `module::file#line:col] `, the tail of the default header.
   #[inline(always)]
   pub fn write_to(&self, out: &mut MyBytesMut) {
-    out.extend_from_slice(self.module_path.as_bytes());
+    self.write_site(out, "");
+    out.extend_from_slice(b"] ");
+  }
+
+  /// `module::file#line:col`, with `strip_prefix` cut off the front of the module path if it starts with it.
+  #[inline(always)]
+  pub(crate) fn write_site(&self, out: &mut MyBytesMut, strip_prefix: &str) {
+    out.extend_from_slice(self.module(strip_prefix).as_bytes());
     out.extend_from_slice(b"::");
     out.extend_from_slice(self.file_name().as_bytes());
     out.push(b'#');
-    out.extend_from_slice(self.line.to_string().as_bytes());
-    out.extend_from_slice(b"] ");
+    let len = lut_u64(out.unfilled(), self.line as u64);
+    out.advance(len);
+    out.push(b':');
+    let len = lut_u64(out.unfilled(), self.column as u64);
+    out.advance(len);
+  }
+}
+
+/// `src/foo/bar.rs` -> `bar`: the part after the last `MAIN_SEPARATOR`, minus a `.rs` suffix.
+const fn file_stem(file: &'static str) -> &'static str {
+  let bytes = file.as_bytes();
+  let mut end = bytes.len();
+  if end >= 3 && bytes[end - 3] == b'.' && bytes[end - 2] == b'r' && bytes[end - 1] == b's' {
+    end -= 3;
+  }
+  let mut start = end;
+  while start > 0 && bytes[start - 1] != std::path::MAIN_SEPARATOR as u8 {
+    start -= 1;
+  }
+  let (_, tail) = bytes.split_at(start);
+  let (stem, _) = tail.split_at(end - start);
+  match std::str::from_utf8(stem) {
+    Ok(stem) => stem,
+    Err(_) => file,
   }
 }
 
+#[macro_export]
+macro_rules! __emit0 {
+    ($logger:expr, $lvl:expr, $fmt:literal) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, _bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+
+        write!(out, $fmt)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args0 = $crate::args2::args0();
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 0,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args0, __hft_tsc).is_ok()
+    }};
+}
+
+#[macro_export]
+macro_rules! __emit1 {
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let [arg1] = $crate::args2::decode_args::<1>(bytes);
+
+        write!(out, $fmt, arg1)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args1 = $crate::args2::args1($a0);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 1,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args1, __hft_tsc).is_ok()
+    }};
+}
+
 #[macro_export]
 macro_rules! __emit2 {
     ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr) => {{
       #[inline(never)]
-      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8]) -> std::io::Result<()> {
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
         use std::io::Write;
-        let src_loc = $crate::log::SourceLocation::__new(module_path!(), file!(), line!());
-        src_loc.write_to(out);
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
         // out.extend_from_slice(b"] ");
-        let tag1 = bytes[0];
-        let tag2 = bytes[1];
-        let (arg1, offset) = $crate::args2::decode(tag1, bytes, 8);
-        let (arg2, _) = $crate::args2::decode(tag2, bytes, offset);
+        let [arg1, arg2] = $crate::args2::decode_args::<2>(bytes);
 
         write!(out, $fmt, arg1, arg2)
       }
+      let __hft_tsc = $crate::tscns::read_tsc();
       let args2 = $crate::args2::args2($a0, $a1);
-      $logger.publish_args($lvl, __hft_shim, &args2)
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 2,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args2, __hft_tsc).is_ok()
       // $logger.push_write(|log_entry| log_entry.mut_from_args($lvl, __hft_shim, &args2))
       //let e = $crate::log::LogEntry::from_args($lvl, __hft_shim, &args2);
       //std::hint::black_box(e);
@@ -185,6 +614,346 @@ macro_rules! __emit2 {
     }};
 }
 
+#[macro_export]
+macro_rules! __emit3 {
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let [arg1, arg2, arg3] = $crate::args2::decode_args::<3>(bytes);
+
+        write!(out, $fmt, arg1, arg2, arg3)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args3 = $crate::args2::args3($a0, $a1, $a2);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 3,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args3, __hft_tsc).is_ok()
+    }};
+}
+
+#[macro_export]
+macro_rules! __emit4 {
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let [arg1, arg2, arg3, arg4] = $crate::args2::decode_args::<4>(bytes);
+
+        write!(out, $fmt, arg1, arg2, arg3, arg4)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args4 = $crate::args2::args4($a0, $a1, $a2, $a3);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 4,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args4, __hft_tsc).is_ok()
+    }};
+}
+
+#[macro_export]
+macro_rules! __emit5 {
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let [arg1, arg2, arg3, arg4, arg5] = $crate::args2::decode_args::<5>(bytes);
+
+        write!(out, $fmt, arg1, arg2, arg3, arg4, arg5)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args5 = $crate::args2::args5($a0, $a1, $a2, $a3, $a4);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 5,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args5, __hft_tsc).is_ok()
+    }};
+}
+
+#[macro_export]
+macro_rules! __emit6 {
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let [arg1, arg2, arg3, arg4, arg5, arg6] = $crate::args2::decode_args::<6>(bytes);
+
+        write!(out, $fmt, arg1, arg2, arg3, arg4, arg5, arg6)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args6 = $crate::args2::args6($a0, $a1, $a2, $a3, $a4, $a5);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 6,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args6, __hft_tsc).is_ok()
+    }};
+}
+
+#[macro_export]
+macro_rules! __emit7 {
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let [arg1, arg2, arg3, arg4, arg5, arg6, arg7] = $crate::args2::decode_args::<7>(bytes);
+
+        write!(out, $fmt, arg1, arg2, arg3, arg4, arg5, arg6, arg7)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args7 = $crate::args2::args7($a0, $a1, $a2, $a3, $a4, $a5, $a6);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 7,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args7, __hft_tsc).is_ok()
+    }};
+}
+
+#[macro_export]
+macro_rules! __emit8 {
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let [arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8] = $crate::args2::decode_args::<8>(bytes);
+
+        write!(out, $fmt, arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args8 = $crate::args2::args8($a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 8,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args8, __hft_tsc).is_ok()
+    }};
+}
+
+#[macro_export]
+macro_rules! __emit9 {
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let [arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8, arg9] = $crate::args2::decode_args::<9>(bytes);
+
+        write!(out, $fmt, arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8, arg9)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args9 = $crate::args2::args9($a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 9,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args9, __hft_tsc).is_ok()
+    }};
+}
+
+#[macro_export]
+macro_rules! __emit10 {
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr, $a9:expr) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let [arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8, arg9, arg10] = $crate::args2::decode_args::<10>(bytes);
+
+        write!(out, $fmt, arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8, arg9, arg10)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args10 = $crate::args2::args10($a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8, $a9);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 10,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args10, __hft_tsc).is_ok()
+    }};
+}
+
+#[macro_export]
+macro_rules! __emit11 {
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr, $a9:expr, $a10:expr) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let [arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8, arg9, arg10, arg11] = $crate::args2::decode_args::<11>(bytes);
+
+        write!(out, $fmt, arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8, arg9, arg10, arg11)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args11 = $crate::args2::args11($a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8, $a9, $a10);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 11,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args11, __hft_tsc).is_ok()
+    }};
+}
+
+#[macro_export]
+macro_rules! __emit12 {
+    ($logger:expr, $lvl:expr, $fmt:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr, $a9:expr, $a10:expr, $a11:expr) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let [arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8, arg9, arg10, arg11, arg12] = $crate::args2::decode_args::<12>(bytes);
+
+        write!(out, $fmt, arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8, arg9, arg10, arg11, arg12)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args12 = $crate::args2::args12($a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8, $a9, $a10, $a11);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: 12,
+        arg_names: &[],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args12, __hft_tsc).is_ok()
+    }};
+}
+
+/// `hft_info!(logger, "px={px} qty={qty:>8}", px = p, qty = q)`: the decoded args are bound to the
+/// given names, so `write!` resolves the named holes (and any width/precision flags) by inline capture.
+#[macro_export]
+macro_rules! __emit_named {
+    ($logger:expr, $lvl:expr, $fmt:literal, $($key:ident = $val:expr),+) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let [$($key),+] = $crate::args2::decode_args::<{ [$(stringify!($key)),+].len() }>(bytes);
+
+        write!(out, $fmt)
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args = $crate::__args!($($val),+);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: [$(stringify!($key)),+].len() as u8,
+        arg_names: &[$(stringify!($key)),+],
+        field_names: &[],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args, __hft_tsc).is_ok()
+    }};
+}
+
+/// `hft_info!(logger, "sent {}", px; order_id = id, venue = "NYSE")` renders `sent 101.5 order_id=5 venue=NYSE`:
+/// the field values are encoded after the positional args, the keys only live in the shim.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __emit_fields {
+    ($logger:expr, $lvl:expr, $fmt:literal, [$($a:expr),*], [$($key:ident = $val:expr),+]) => {{
+      #[inline(never)]
+      fn __hft_shim(out: &mut $crate::my_bytes_mut::MyBytesMut, bytes: &[u8], loc: Option<&mut $crate::log::SourceLocation>) -> std::io::Result<()> {
+        use std::io::Write;
+        __HFT_SITE.meta.loc.write_or_capture(out, loc);
+        let mut args = $crate::args2::decode_args::<{ [$(stringify!($a),)* $(stringify!($key)),+].len() }>(bytes).into_iter();
+
+        write!(out, $fmt $(, { let _ = stringify!($a); args.next().unwrap() })*)?;
+        $(
+          out.extend_from_slice(concat!(" ", stringify!($key), "=").as_bytes());
+          write!(out, "{}", args.next().unwrap())?;
+        )+
+        Ok(())
+      }
+      let __hft_tsc = $crate::tscns::read_tsc();
+      let args = $crate::__args!($($a,)* $($val),+);
+      static __HFT_SITE: $crate::metadata::CallSite = $crate::metadata::CallSite::new($crate::metadata::Metadata {
+        fmt: $fmt,
+        level: $lvl,
+        loc: $crate::log::SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+        num_args: [$(stringify!($a),)* $(stringify!($key)),+].len() as u8,
+        arg_names: &[],
+        field_names: &[$(stringify!($key)),+],
+        log_fn: __hft_shim,
+      });
+      $logger.publish_args_at(&__HFT_SITE, &args, __hft_tsc).is_ok()
+    }};
+}
+
+/// `argsN(..)` for N = number of expressions.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __args {
+    ($a0:expr) => { $crate::args2::args1($a0) };
+    ($a0:expr, $a1:expr) => { $crate::args2::args2($a0, $a1) };
+    ($a0:expr, $a1:expr, $a2:expr) => { $crate::args2::args3($a0, $a1, $a2) };
+    ($a0:expr, $a1:expr, $a2:expr, $a3:expr) => { $crate::args2::args4($a0, $a1, $a2, $a3) };
+    ($a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr) => { $crate::args2::args5($a0, $a1, $a2, $a3, $a4) };
+    ($a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr) => { $crate::args2::args6($a0, $a1, $a2, $a3, $a4, $a5) };
+    ($a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr) => { $crate::args2::args7($a0, $a1, $a2, $a3, $a4, $a5, $a6) };
+    ($a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr) => { $crate::args2::args8($a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7) };
+    ($a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr) => { $crate::args2::args9($a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8) };
+    ($a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr, $a9:expr) => { $crate::args2::args10($a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8, $a9) };
+    ($a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr, $a9:expr, $a10:expr) => { $crate::args2::args11($a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8, $a9, $a10) };
+    ($a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr, $a9:expr, $a10:expr, $a11:expr) => { $crate::args2::args12($a0, $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8, $a9, $a10, $a11) };
+}
+
 // #[inline(always)]
 // pub fn write_loc_tid(out: &mut dyn std::io::Write, src_loc: SourceLocation, tid: u32) -> io::Result<()> {
 //   out.write_all(src_loc.module_path.as_bytes())?;
@@ -194,3 +963,44 @@ macro_rules! __emit2 {
 //   out.write_all(b"] ")?;
 //   Ok(())
 // }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::metadata::CallSite;
+  use std::cell::Cell;
+  use std::ptr;
+
+  const STEM: &str = file_stem("src/app/oms.rs");
+
+  // 只记下 hft_info! 展开出来的 call site
+  struct SiteLogger(Cell<Option<&'static CallSite>>);
+
+  impl SiteLogger {
+    fn publish_args_at<A: ?Sized>(&self, site: &'static CallSite, _args: &A, _tsc: i64) -> Result<(), ()> {
+      self.0.set(Some(site));
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn file_name_is_worked_out_once() {
+    assert_eq!(STEM, "oms");
+    assert_eq!(file_stem("oms.rs"), "oms");
+    assert_eq!(file_stem("build/gen"), "gen");
+    assert_eq!(file_stem(".rs"), "");
+
+    let logger = SiteLogger(Cell::new(None));
+    assert!(crate::hft_info!(logger, "px={}", 7u64));
+    let site = logger.0.get().unwrap();
+
+    // shim 交出来的就是 site 里那份 location，file_name 是 file 的子切片，没有重新切分
+    let mut loc = SourceLocation::__new("", "", 0, 0);
+    let mut out = MyBytesMut::with_capacity(64);
+    (site.meta.log_fn)(&mut out, &crate::args2::encode_to_vec(&crate::args2::args1(7u64)), Some(&mut loc)).unwrap();
+    assert!(ptr::eq(loc.file, site.meta.loc.file));
+    assert!(ptr::eq(loc.file_name(), site.meta.loc.file_name()));
+    assert_eq!(loc.file_name(), "log");
+    assert!(ptr::eq(loc.file_name().as_ptr(), loc.file[loc.file.len() - "log.rs".len()..].as_ptr()));
+  }
+}
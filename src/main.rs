@@ -69,14 +69,14 @@ fn percentile_ns(mut v: Vec<u64>, p: f64) -> u64 {
   v[idx.min(n - 1)]
 }
 
-#[cfg(target_os = "linux")]
+#[cfg(target_arch = "x86_64")]
 #[inline(always)]
 pub fn tsc_start() -> u64 {
   unsafe { core::arch::x86_64::_mm_lfence(); }
   unsafe { core::arch::x86_64::_rdtsc() }
 }
 
-#[cfg(target_os = "linux")]
+#[cfg(target_arch = "x86_64")]
 #[inline(always)]
 pub fn tsc_end() -> u64 {
   let t = unsafe { core::arch::x86_64::_rdtsc() };
@@ -84,13 +84,13 @@ pub fn tsc_end() -> u64 {
   t
 }
 
-#[cfg(not(target_os = "linux"))]
+#[cfg(not(target_arch = "x86_64"))]
 #[inline(always)]
 pub fn tsc_start() -> u64 {
   rdtsc()
 }
 
-#[cfg(not(target_os = "linux"))]
+#[cfg(not(target_arch = "x86_64"))]
 #[inline(always)]
 pub fn tsc_end() -> u64 {
   rdtsc()
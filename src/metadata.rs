@@ -0,0 +1,261 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::args2::arg_size;
+use crate::log::{Level, LogFn, SourceLocation};
+
+/// Everything about a log call site that is known at compile time.
+/// The queue only carries the interned id of this, plus the raw args.
+pub struct Metadata {
+  pub fmt: &'static str,
+  pub level: Level,
+  pub loc: SourceLocation,
+  /// number of encoded args; the payload alone does not say where its tag header ends
+  pub num_args: u8,
+  /// names of `key = value` args in payload order, empty for positional args
+  pub arg_names: &'static [&'static str],
+  /// keys of the `; key = value` fields, their values are the last `field_names.len()` args
+  pub field_names: &'static [&'static str],
+  #[doc(hidden)]
+  pub log_fn: LogFn,
+}
+
+impl Metadata {
+  #[inline]
+  fn same_site(&self, other: &Metadata) -> bool {
+    self.fmt == other.fmt
+      && self.level == other.level
+      && self.loc.module_path == other.loc.module_path
+      && self.loc.file == other.loc.file
+      && self.loc.line == other.loc.line
+      && self.loc.column == other.loc.column
+      // one macro can expand to several sites at the same location (e.g. with and without trailing fields)
+      && self.num_args == other.num_args
+      && self.arg_names == other.arg_names
+      && self.field_names == other.field_names
+  }
+
+  /// Schema check of one record, for offline readers: the fmt takes as many positional args as the
+  /// site declares and the payload is exactly as long as its tags say. Catches a hand-built site
+  /// (`dynamic_site`, `LoggerHandle::log`) whose `num_args` or payload does not match its fmt.
+  /// The tags themselves are not pinned: a site in a generic fn is shared by every instantiation.
+  pub fn check_payload(&self, payload: &[u8]) -> io::Result<()> {
+    let num_args = self.num_args as usize;
+    if self.arg_names.is_empty() {
+      let takes = fmt_arg_count(self.fmt);
+      let positional = num_args.saturating_sub(self.field_names.len());
+      if takes != positional {
+        return Err(mismatch(format!("fmt takes {} args, site declares {}", takes, positional)));
+      }
+    }
+    if payload.len() < num_args {
+      return Err(mismatch(format!("{} byte payload cannot hold {} arg tags", payload.len(), num_args)));
+    }
+    let len = self.decoded_len(payload).unwrap_or(0);
+    if len != payload.len() {
+      return Err(mismatch(format!("{} byte payload, its {} arg tags take {} bytes", payload.len(), num_args, len)));
+    }
+    Ok(())
+  }
+
+  /// Bytes the tag header plus the args it declares take, what decoding `payload` consumes;
+  /// `None` if `payload` cannot even hold the tags or a `&str` length. Anything but `payload.len()`
+  /// means encoder/decoder drift.
+  #[inline]
+  pub fn decoded_len(&self, payload: &[u8]) -> Option<usize> {
+    let num_args = self.num_args as usize;
+    let tags = payload.get(..num_args)?;
+    tags.iter().try_fold((num_args + 7) & !7, |len, &tag| Some(len + arg_size(tag, payload, len)?))
+  }
+}
+
+fn mismatch(msg: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Positional args `fmt` consumes: one per `{}`, the highest `{N}` / `N$` index + 1 if larger, and
+/// an extra one for each `.*` precision. `{{` and `}}` are literal braces, `{name}` captures nothing.
+fn fmt_arg_count(fmt: &str) -> usize {
+  // next implicit arg, and how many args the holes so far reach
+  let (mut next, mut count) = (0, 0);
+  let mut rest = fmt;
+  while let Some(i) = rest.find(['{', '}']) {
+    let brace = rest.as_bytes()[i];
+    rest = &rest[i + 1..];
+    if brace == b'}' {
+      rest = rest.strip_prefix('}').unwrap_or(rest);
+      continue;
+    }
+    if let Some(after) = rest.strip_prefix('{') {
+      rest = after;
+      continue;
+    }
+    let end = rest.find('}').unwrap_or(rest.len());
+    let (arg, spec) = rest[..end].split_once(':').unwrap_or((&rest[..end], ""));
+    rest = rest.get(end + 1..).unwrap_or("");
+
+    // `.*` 先吃一个参数当精度，再轮到值本身
+    if spec.contains(".*") {
+      next += 1;
+      count = count.max(next);
+    }
+    match arg.trim() {
+      "" => {
+        next += 1;
+        count = count.max(next);
+      }
+      arg => {
+        if let Ok(idx) = arg.parse::<usize>() {
+          count = count.max(idx + 1);
+        }
+      }
+    }
+    // `{:1$}` / `{:.2$}`: width / precision taken from a positional arg
+    for (at, _) in spec.match_indices('$') {
+      let head = &spec[..at];
+      let digits = head.len() - head.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+      let named = head[..head.len() - digits].ends_with(|c: char| c.is_alphanumeric() || c == '_');
+      if digits > 0 && !named {
+        count = count.max(head[head.len() - digits..].parse::<usize>().unwrap_or(0) + 1);
+      }
+    }
+  }
+  count
+}
+
+/// One `static` per `hft_*!` call site; the id is assigned on first use (0 = not yet).
+pub struct CallSite {
+  pub meta: Metadata,
+  id: AtomicU32,
+}
+
+// 只追加：id = index + 1
+static REGISTRY: Mutex<Vec<&'static Metadata>> = Mutex::new(Vec::new());
+
+// 运行时传进来的 fmt / 路径字符串，内容相同的只留一份
+static INTERNED: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+/// The one `&'static str` with `s`'s content, a copy leaked on first use. Identical literals in one
+/// crate already share storage (rustc merges them), this does the same for strings only known at
+/// runtime, so `fmt.as_ptr()` can key per-format tables.
+pub fn intern(s: &str) -> &'static str {
+  let mut interned = INTERNED.lock().unwrap();
+  if let Some(&found) = interned.get(s) {
+    return found;
+  }
+  let s: &'static str = Box::leak(s.into());
+  interned.insert(s);
+  s
+}
+
+impl CallSite {
+  pub const fn new(meta: Metadata) -> Self {
+    Self {
+      meta,
+      id: AtomicU32::new(0),
+    }
+  }
+
+  #[inline(always)]
+  pub fn id(&'static self) -> u32 {
+    let id = self.id.load(Ordering::Relaxed);
+    if id != 0 {
+      return id;
+    }
+    self.register()
+  }
+
+  #[cold]
+  #[inline(never)]
+  fn register(&'static self) -> u32 {
+    let mut registry = REGISTRY.lock().unwrap();
+    // another thread may have won the race for this site
+    let id = self.id.load(Ordering::Relaxed);
+    if id != 0 {
+      return id;
+    }
+    let id = match registry.iter().position(|m| m.same_site(&self.meta)) {
+      Some(idx) => idx as u32 + 1,
+      None => {
+        registry.push(&self.meta);
+        registry.len() as u32
+      }
+    };
+    self.id.store(id, Ordering::Relaxed);
+    id
+  }
+}
+
+// 运行时创建的 call site（桥接其他日志框架用），只追加
+static DYN_SITES: Mutex<Vec<&'static CallSite>> = Mutex::new(Vec::new());
+
+/// `CallSite` for a call site only known at runtime, e.g. forwarded from another logging framework.
+/// The strings are interned (see [`intern`]) and the site is leaked on first use; later calls with the same
+/// level/location/fmt get the same site back. Takes a lock, so bridges should cache the result.
+/// `log_fn` renders the payloads published for it with `LoggerHandle::log`; it only has to write the
+/// message, the sinks take the location from the site.
+pub fn dynamic_site(level: Level, module_path: &str, file: &str, line: u32, fmt: &str, num_args: u8, log_fn: LogFn) -> &'static CallSite {
+  let mut sites = DYN_SITES.lock().unwrap();
+  let found = sites.iter().find(|site| {
+    let m = &site.meta;
+    m.level == level && m.loc.line == line && m.fmt == fmt && m.loc.module_path == module_path && m.loc.file == file
+  });
+  if let Some(site) = found {
+    return site;
+  }
+  let site: &'static CallSite = Box::leak(Box::new(CallSite::new(Metadata {
+    fmt: intern(fmt),
+    level,
+    loc: SourceLocation::__new(intern(module_path), intern(file), line, 0),
+    num_args,
+    arg_names: &[],
+    field_names: &[],
+    log_fn,
+  })));
+  sites.push(site);
+  site
+}
+
+/// Metadata registered under `id`, `None` if no call site has that id (yet).
+pub fn get(id: u32) -> Option<&'static Metadata> {
+  let registry = REGISTRY.lock().unwrap();
+  registry.get((id as usize).wrapping_sub(1)).copied()
+}
+
+/// Every call site registered so far, index `i` has id `i + 1`.
+pub fn snapshot() -> Vec<&'static Metadata> {
+  REGISTRY.lock().unwrap().clone()
+}
+
+/// Backend-side copy of the registry, so a lookup only takes the lock for ids it has not seen yet.
+pub(crate) struct MetadataCache {
+  metas: Vec<&'static Metadata>,
+}
+
+impl MetadataCache {
+  pub(crate) fn new() -> Self {
+    Self {
+      metas: Vec::new(),
+    }
+  }
+
+  #[inline(always)]
+  pub(crate) fn get(&mut self, id: u32) -> io::Result<&'static Metadata> {
+    let idx = (id as usize).wrapping_sub(1);
+    if idx >= self.metas.len() {
+      self.refresh();
+    }
+    match self.metas.get(idx) {
+      Some(meta) => Ok(*meta),
+      None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown log metadata id {}", id))),
+    }
+  }
+
+  #[cold]
+  #[inline(never)]
+  fn refresh(&mut self) {
+    let registry = REGISTRY.lock().unwrap();
+    self.metas.extend_from_slice(&registry[self.metas.len()..]);
+  }
+}
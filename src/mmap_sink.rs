@@ -0,0 +1,334 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::format::{HeaderConfig, LineFormat, LineFormatter, Precision, TimeFormat};
+use crate::sink::Sink;
+use crate::spsc_var_queue_opt::MsgHeader;
+use crate::tscns::Clock;
+
+// File layout: 64 byte header `MAGIC | u64 capacity | u64 cursor | pad`, then `capacity` bytes of ring.
+// `cursor` counts every byte ever written, the next write goes to `cursor % capacity`.
+pub const MAGIC: &[u8; 8] = b"NLOGRING";
+const HEADER_LEN: usize = 64;
+const CAPACITY_OFF: usize = 8;
+const CURSOR_OFF: usize = 16;
+
+/// -------- mmap ring sink --------
+/// Each rendered line is copied straight into a shared file mapping, wrapping at the end, and the
+/// cursor in the file header is bumped after the copy. Nothing waits for a flush: if the process
+/// dies the kernel still owns the dirty pages, so [`MmapSink::recover`] can read back the most
+/// recent `capacity` bytes. (A machine crash needs `flush`, which `msync`s.)
+pub struct MmapSink {
+  path: PathBuf,
+  _file: File,
+  map: *mut u8,
+  capacity: usize,
+  cursor: u64,
+  line: LineFormatter,
+}
+
+// map 只在 backend 线程里用
+unsafe impl Send for MmapSink {}
+
+impl MmapSink {
+  /// Map `path` with a `capacity` byte ring. An existing ring of the same capacity is continued,
+  /// anything else is truncated and re-initialized.
+  pub fn new(path: impl Into<PathBuf>, capacity: usize) -> io::Result<Self> {
+    if capacity == 0 {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "mmap ring capacity must be > 0"));
+    }
+    let path = path.into();
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+    let file_len = (HEADER_LEN + capacity) as u64;
+    let mut head = [0u8; HEADER_LEN];
+    let reuse = file.metadata()?.len() == file_len && {
+      read_exact_at(&file, &mut head)?;
+      &head[..8] == MAGIC && u64::from_le_bytes(head[CAPACITY_OFF..CAPACITY_OFF + 8].try_into().unwrap()) == capacity as u64
+    };
+    if !reuse {
+      file.set_len(0)?;
+      file.set_len(file_len)?;
+    }
+
+    let map = unsafe {
+      libc::mmap(
+        ptr::null_mut(),
+        file_len as usize,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+        file.as_raw_fd(),
+        0,
+      )
+    };
+    if map == libc::MAP_FAILED {
+      return Err(io::Error::last_os_error());
+    }
+    let map = map as *mut u8;
+
+    let cursor = if reuse {
+      u64::from_le_bytes(head[CURSOR_OFF..CURSOR_OFF + 8].try_into().unwrap())
+    } else {
+      unsafe {
+        ptr::copy_nonoverlapping(MAGIC.as_ptr(), map, MAGIC.len());
+        ptr::copy_nonoverlapping((capacity as u64).to_le_bytes().as_ptr(), map.add(CAPACITY_OFF), 8);
+      }
+      0
+    };
+
+    let mut line = LineFormatter::new();
+    line.set_escape_strings(true);
+    line.set_color(false);
+
+    let mut sink = Self {
+      path,
+      _file: file,
+      map,
+      capacity,
+      cursor,
+      line,
+    };
+    sink.store_cursor();
+    Ok(sink)
+  }
+
+  pub fn path(&self) -> &PathBuf {
+    &self.path
+  }
+
+  /// Sub-second digits in the header, `Precision::Micros` by default.
+  pub fn with_precision(mut self, precision: Precision) -> Self {
+    self.line.set_precision(precision);
+    self
+  }
+
+  pub fn with_format(mut self, line_format: LineFormat) -> Self {
+    self.line.set_line_format(line_format);
+    self
+  }
+
+  pub fn with_header(mut self, header: HeaderConfig) -> Self {
+    self.line.set_header(header);
+    self
+  }
+
+  pub fn with_time_format(mut self, time_format: TimeFormat) -> Self {
+    self.line.set_time_format(time_format);
+    self
+  }
+
+  pub fn with_strip_module_prefix(mut self, prefix: &str) -> Self {
+    self.line.set_module_prefix(prefix);
+    self
+  }
+
+  pub fn with_level_names(mut self, names: &[&str]) -> Self {
+    self.line.set_level_names(names);
+    self
+  }
+
+  /// Escape `\n`, `\r`, `\t` and ESC inside `&str` args (on by default).
+  pub fn with_escape_strings(mut self, on: bool) -> Self {
+    self.line.set_escape_strings(on);
+    self
+  }
+
+  /// The surviving tail of the ring at `path`, oldest first. When the ring has wrapped, the
+  /// partially overwritten first line is dropped so the result starts at a line boundary.
+  pub fn recover(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let data = std::fs::read(path)?;
+    if data.len() < HEADER_LEN || &data[..8] != MAGIC {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "not an mmap log ring"));
+    }
+    let capacity = u64::from_le_bytes(data[CAPACITY_OFF..CAPACITY_OFF + 8].try_into().unwrap()) as usize;
+    let cursor = u64::from_le_bytes(data[CURSOR_OFF..CURSOR_OFF + 8].try_into().unwrap());
+    if capacity == 0 {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "mmap log ring has zero capacity"));
+    }
+    if data.len() != HEADER_LEN + capacity {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "mmap log ring truncated"));
+    }
+    let ring = &data[HEADER_LEN..];
+    if cursor <= capacity as u64 {
+      return Ok(ring[..cursor as usize].to_vec());
+    }
+    let pos = (cursor % capacity as u64) as usize;
+    let mut out = Vec::with_capacity(capacity);
+    out.extend_from_slice(&ring[pos..]);
+    out.extend_from_slice(&ring[..pos]);
+    match out.iter().position(|&b| b == b'\n') {
+      Some(nl) => Ok(out.split_off(nl + 1)),
+      None => Ok(out),
+    }
+  }
+
+  #[inline(always)]
+  fn store_cursor(&mut self) {
+    // 先写数据再发布 cursor
+    unsafe { (*(self.map.add(CURSOR_OFF) as *const AtomicU64)).store(self.cursor.to_le(), Ordering::Release) };
+  }
+
+  fn sync(&self) -> io::Result<()> {
+    if unsafe { libc::msync(self.map as *mut libc::c_void, HEADER_LEN + self.capacity, libc::MS_SYNC) } != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+}
+
+/// Copy `bytes` into the ring at `*cursor`, wrapping at the end, and advance `*cursor`.
+#[inline(always)]
+fn write_ring(map: *mut u8, capacity: usize, cursor: &mut u64, mut bytes: &[u8]) {
+  if bytes.len() > capacity {
+    // 只留最后 capacity 字节
+    *cursor += (bytes.len() - capacity) as u64;
+    bytes = &bytes[bytes.len() - capacity..];
+  }
+  let pos = (*cursor % capacity as u64) as usize;
+  let first = bytes.len().min(capacity - pos);
+  unsafe {
+    let ring = map.add(HEADER_LEN);
+    ptr::copy_nonoverlapping(bytes.as_ptr(), ring.add(pos), first);
+    ptr::copy_nonoverlapping(bytes.as_ptr().add(first), ring, bytes.len() - first);
+  }
+  *cursor += bytes.len() as u64;
+}
+
+fn read_exact_at(file: &File, buf: &mut [u8]) -> io::Result<()> {
+  use std::os::unix::fs::FileExt;
+  file.read_exact_at(buf, 0)
+}
+
+impl Sink for MmapSink {
+  #[inline(always)]
+  fn on_record(&mut self, tid: usize, log_meta: &MsgHeader, log_payload: &[u8]) -> io::Result<()> {
+    let line = self.line.format(tid, log_meta, log_payload)?;
+    write_ring(self.map, self.capacity, &mut self.cursor, line);
+    self.store_cursor();
+    Ok(())
+  }
+
+  #[inline(always)]
+  fn accepts_lines(&self) -> bool {
+    true
+  }
+
+  #[inline(always)]
+  fn on_line(&mut self, _log_meta: &MsgHeader, line: &[u8]) -> io::Result<()> {
+    write_ring(self.map, self.capacity, &mut self.cursor, line);
+    self.store_cursor();
+    Ok(())
+  }
+
+  #[inline]
+  fn on_thread_name(&mut self, tid: usize, name: &str) -> io::Result<()> {
+    self.line.set_thread_name(tid, name);
+    Ok(())
+  }
+
+  #[inline]
+  fn set_clock(&mut self, clock: Arc<dyn Clock + Send + Sync>) {
+    self.line.set_clock(clock);
+  }
+
+  #[inline(always)]
+  fn on_idle(&mut self, _now_cycles: i64) -> io::Result<()> {
+    Ok(())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.sync()
+  }
+}
+
+impl Drop for MmapSink {
+  fn drop(&mut self) {
+    unsafe { libc::munmap(self.map as *mut libc::c_void, HEADER_LEN + self.capacity) };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::args2::{args1, decode_args, encode_to_vec};
+  use std::io::Write;
+  use crate::log::{Level, SourceLocation};
+  use crate::metadata::{CallSite, Metadata};
+  use crate::my_bytes_mut::MyBytesMut;
+  use crate::tscns;
+
+  fn seq_shim(out: &mut MyBytesMut, bytes: &[u8], _loc: Option<&mut SourceLocation>) -> io::Result<()> {
+    let [seq] = decode_args::<1>(bytes);
+    write!(out, "seq={}", seq)
+  }
+
+  static SEQ_SITE: CallSite = CallSite::new(Metadata {
+    fmt: "seq={}",
+    level: Level::Info,
+    loc: SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+    num_args: 1,
+    arg_names: &[],
+    field_names: &[],
+    log_fn: seq_shim,
+  });
+
+  fn log(sink: &mut MmapSink, seq: u64) {
+    let hdr = MsgHeader {
+      size: 0,
+      level: Level::Info as u32,
+      tsc: tscns::read_tsc(),
+      meta_id: SEQ_SITE.id() as u64,
+    };
+    sink.on_record(1, &hdr, &encode_to_vec(&args1(seq))).unwrap();
+  }
+
+  fn seqs(text: &[u8]) -> Vec<u64> {
+    std::str::from_utf8(text).unwrap().lines()
+      .map(|l| l.rsplit_once("seq=").unwrap().1.parse().unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn tail_survives_a_drop_without_flush() {
+    let path = std::env::temp_dir().join(format!("hft_ring_{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // 1KB 的环，100 条一定会绕回好几圈
+    let mut sink = MmapSink::new(&path, 1024).unwrap();
+    for seq in 0..100 {
+      log(&mut sink, seq);
+    }
+    // 模拟崩溃：不 flush 直接丢掉
+    drop(sink);
+
+    let tail = seqs(&MmapSink::recover(&path).unwrap());
+    assert!(tail.len() > 5 && tail.len() < 100, "{tail:?}");
+    assert_eq!(*tail.last().unwrap(), 99);
+    assert!(tail.windows(2).all(|w| w[1] == w[0] + 1), "{tail:?}");
+
+    // 同样容量重新打开接着写
+    let mut sink = MmapSink::new(&path, 1024).unwrap();
+    log(&mut sink, 100);
+    drop(sink);
+    let tail = seqs(&MmapSink::recover(&path).unwrap());
+    assert_eq!(&tail[tail.len() - 2..], [99, 100]);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn zero_capacity_header_is_rejected() {
+    let path = std::env::temp_dir().join(format!("hft_ring_zero_{}.bin", std::process::id()));
+    // capacity 为 0 但 cursor 不为 0 的坏头
+    let mut data = vec![0u8; HEADER_LEN];
+    data[..8].copy_from_slice(MAGIC);
+    data[CURSOR_OFF..CURSOR_OFF + 8].copy_from_slice(&5u64.to_le_bytes());
+    std::fs::write(&path, &data).unwrap();
+
+    let err = MmapSink::recover(&path).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    std::fs::remove_file(&path).unwrap();
+  }
+}
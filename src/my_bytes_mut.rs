@@ -1,4 +1,7 @@
 
+/// `unfilled()` always hands out at least this many bytes, enough for any fixed-width header field.
+pub const UNFILLED_MIN: usize = 64;
+
 pub struct MyBytesMut {
   inner: Vec<u8>,
   pos: usize,
@@ -21,6 +24,9 @@ impl MyBytesMut {
 
   #[inline(always)]
   pub fn push(&mut self, b: u8) {
+    if self.pos == self.inner.len() {
+      self.grow(self.pos + 1);
+    }
     unsafe {
       // self.inner[self.pos] = b;
       *self.inner.get_unchecked_mut(self.pos) = b;
@@ -40,21 +46,32 @@ impl MyBytesMut {
     self.pos = new_len;
   }
 
+  /// Filling the buffer up to the last byte is fine; anything longer grows the buffer
+  /// instead of panicking the consumer thread.
   #[inline(always)]
   pub fn extend_from_slice(&mut self, src: &[u8]) {
     let new_len = self.pos + src.len();
-    assert!(self.inner.len() > new_len, "buff too small pos={} len={} #src={}", self.pos, self.inner.len(), src.len());
-    // if new_len > self.inner.len() {
-    //   self.inner.resize(self.inner.len() * 2, 0);
-    // }
+    if new_len > self.inner.len() {
+      self.grow(new_len);
+    }
+    debug_assert!(self.inner.len() >= new_len, "buff too small pos={} len={} #src={}", self.pos, self.inner.len(), src.len());
     unsafe {
       std::ptr::copy_nonoverlapping(src.as_ptr(), self.inner[self.pos..].as_mut_ptr(), src.len());
     }
     self.pos = new_len;
   }
 
+  // 慢路径：至少翻倍，避免长消息反复 realloc
+  #[cold]
+  #[inline(never)]
+  fn grow(&mut self, min_len: usize) {
+    let new_len = min_len.max(self.inner.len() * 2);
+    self.inner.resize(new_len, 0);
+  }
+
   #[inline(always)]
   pub fn advance(&mut self, len: usize) {
+    debug_assert!(self.pos + len <= self.inner.len());
     self.pos += len;
   }
 
@@ -86,8 +103,13 @@ impl MyBytesMut {
     &self.inner[from..to]
   }
 
+  /// Remaining space after `pos`, grown first if less than `UNFILLED_MIN` bytes are left,
+  /// so a following `advance()` stays in bounds.
   #[inline(always)]
   pub fn unfilled(&mut self) -> &mut [u8] {
+    if self.inner.len() - self.pos < UNFILLED_MIN {
+      self.grow(self.pos + UNFILLED_MIN);
+    }
     &mut self.inner[self.pos..]
   }
 }
@@ -104,3 +126,31 @@ impl std::io::Write for MyBytesMut {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn extend_fills_to_the_last_byte() {
+    let mut buf = MyBytesMut::with_capacity(16);
+    buf.extend_from_slice(b"0123456789");
+    buf.extend_from_slice(b"abcdef");
+    assert_eq!(buf.result(), b"0123456789abcdef");
+    assert_eq!(buf.inner.len(), 16);
+  }
+
+  #[test]
+  fn extend_past_the_end_grows() {
+    let mut buf = MyBytesMut::with_capacity(16);
+    buf.extend_from_slice(b"0123456789abcdef");
+    buf.extend_from_slice(b"!");
+    assert_eq!(buf.result(), b"0123456789abcdef!");
+    assert!(buf.inner.len() >= 17);
+
+    let big = vec![b'x'; 100];
+    buf.extend_from_slice(&big);
+    assert_eq!(buf.curr_pos(), 117);
+    assert_eq!(&buf.result()[17..], &big[..]);
+  }
+}
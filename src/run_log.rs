@@ -5,11 +5,16 @@ use std::io;
 use std::io::Write;
 use std::mem::MaybeUninit;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{fence, AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
 use crossbeam_channel::{Receiver, Sender};
-use crate::log::{rdtsc, LogEntry};
-use crate::{spsc_queue, StagingBuffer};
+use crate::args2::Payload;
+use crate::log::{LogEntry, MAX_PAYLOAD_LEN};
+use crate::metadata::CallSite;
+use crate::my_bytes_mut::MyBytesMut;
+use crate::run_log2::PublishResult;
+use crate::{spsc_queue, tscns, StagingBuffer};
 
 struct RegMsg {
   cons: spsc_queue::Consumer<LogEntry>,
@@ -20,6 +25,10 @@ pub struct LoggerHandle {
   prod: spsc_queue::Producer<LogEntry>,
   reg_tx: Sender<RegMsg>,
   capacity: usize,
+  stop: Arc<AtomicBool>,
+  // qs.len() of the logger thread
+  queue_count: Arc<AtomicUsize>,
+  backend: JoinHandle<()>,
 }
 
 // impl Clone for LoggerHandle {
@@ -101,6 +110,109 @@ impl LoggerHandle {
   pub fn push_write<F: FnOnce(&mut LogEntry)>(&mut self, f: F) -> bool {
     self.prod.push_write(f).is_ok() // 满了就丢；你可以加 dropped 计数
   }
+
+  /// Fills the next slot in place, stamped now; fails on a full queue or args past `MAX_PAYLOAD_LEN`.
+  #[inline(always)]
+  pub fn publish_args<A: Payload>(&mut self, site: &'static CallSite, args: &A) -> PublishResult {
+    if !fits(args) {
+      return PublishResult::PayloadTooLarge;
+    }
+    publish_result(self.push_write(|log_entry| log_entry.mut_from_args(site.meta.level, site.meta.log_fn, args)))
+  }
+
+  /// `hft_info!` entry point, `tsc` read before the args were converted.
+  #[inline(always)]
+  pub fn publish_args_at<A: Payload>(&mut self, site: &'static CallSite, args: &A, tsc: i64) -> PublishResult {
+    if !fits(args) {
+      return PublishResult::PayloadTooLarge;
+    }
+    publish_result(self.push_write(|log_entry| log_entry.mut_from_args_at(site.meta.level, site.meta.log_fn, args, tsc)))
+  }
+
+  /// A new queue registered with the logger thread, for logging from another thread.
+  /// Entries from all queues are merged by tsc.
+  pub fn new_producer(&self) -> ThreadProducer {
+    self.new_producer_with_capacity(self.capacity)
+  }
+
+  /// [`new_producer`](Self::new_producer) with its own queue size instead of the one given to `init_logger`:
+  /// deep for a bursty market-data thread, small for a quiet control-plane one. Rounded up to a power of two.
+  pub fn new_producer_with_capacity(&self, capacity: usize) -> ThreadProducer {
+    let (prod, cons) = spsc_queue::spsc_queue::<LogEntry>(capacity);
+    let tid = NEXT_TID.fetch_add(1, Ordering::Relaxed);
+    let _ = self.reg_tx.send(RegMsg { cons, tid });
+    ThreadProducer { prod }
+  }
+
+  /// Entries this handle's own queue holds.
+  #[inline]
+  pub fn capacity(&self) -> usize {
+    self.prod.capacity()
+  }
+
+  /// Queues the logger thread still scans: this handle's, live `ThreadProducer`s,
+  /// and dropped ones it has not finished draining yet.
+  pub fn queue_count(&self) -> usize {
+    self.queue_count.load(Ordering::Relaxed)
+  }
+
+  /// Stop the logger thread after it drained every registered queue, then join it.
+  pub fn shutdown(self) {
+    self.stop.store(true, Ordering::Release);
+    self.backend.thread().unpark();
+    if self.backend.join().is_err() {
+      println!("log-backend panicked before shutdown");
+    }
+  }
+}
+
+/// Per-thread producer from [`LoggerHandle::new_producer`].
+pub struct ThreadProducer {
+  prod: spsc_queue::Producer<LogEntry>,
+}
+
+impl ThreadProducer {
+  /// Entries this producer's queue holds.
+  #[inline]
+  pub fn capacity(&self) -> usize {
+    self.prod.capacity()
+  }
+
+  #[inline(always)]
+  pub fn push_write<F: FnOnce(&mut LogEntry)>(&mut self, f: F) -> bool {
+    self.prod.push_write(f).is_ok()
+  }
+
+  #[inline(always)]
+  pub fn publish_args<A: Payload>(&mut self, site: &'static CallSite, args: &A) -> PublishResult {
+    if !fits(args) {
+      return PublishResult::PayloadTooLarge;
+    }
+    publish_result(self.push_write(|log_entry| log_entry.mut_from_args(site.meta.level, site.meta.log_fn, args)))
+  }
+
+  #[inline(always)]
+  pub fn publish_args_at<A: Payload>(&mut self, site: &'static CallSite, args: &A, tsc: i64) -> PublishResult {
+    if !fits(args) {
+      return PublishResult::PayloadTooLarge;
+    }
+    publish_result(self.push_write(|log_entry| log_entry.mut_from_args_at(site.meta.level, site.meta.log_fn, args, tsc)))
+  }
+}
+
+/// A `LogEntry` holds `MAX_PAYLOAD_LEN` bytes of args, a record past that is never pushed.
+#[inline(always)]
+fn fits<A: Payload>(args: &A) -> bool {
+  args.encoded_len() <= MAX_PAYLOAD_LEN
+}
+
+#[inline(always)]
+fn publish_result(pushed: bool) -> PublishResult {
+  if pushed {
+    PublishResult::Ok
+  } else {
+    PublishResult::QueueFull
+  }
 }
 
 #[inline(always)]
@@ -111,6 +223,7 @@ fn level_str(l: u64) -> &'static str {
     2 => "\x1b[32minfo\x1b[m ",
     3 => "\x1b[31mwarn\x1b[m ",
     4 => "\x1b[31merror\x1b[m",
+    5 => "\x1b[1;31mfatal\x1b[m",
     _ => "unk  ",
   }
 }
@@ -120,182 +233,219 @@ fn level_str(l: u64) -> &'static str {
 // =============================
 struct QState {
   cons: spsc_queue::Consumer<LogEntry>,
-  head: Option<LogEntry>,
+  // tsc of the entry at cons.peek() once it is in the heap; the entry itself stays in the ring
+  head: Option<u64>,
   tid: u32,
 }
 
 
 struct LoggerThread {
   reg_rx: Receiver<RegMsg>,
+  stop: Arc<AtomicBool>,
+  queue_count: Arc<AtomicUsize>,
   qs: Vec<QState>,
   heap: BinaryHeap<Reverse<(u64, usize)>>, // (tsc, qid)
   empty: Vec<usize>,
   empty_cursor: usize,
   clock: TscClock,
   prefix: PrefixCache,
+  scratch: MyBytesMut,
 }
 
 impl LoggerThread {
-  fn new(reg_rx: Receiver<RegMsg>) -> Self {
+  fn new(reg_rx: Receiver<RegMsg>, stop: Arc<AtomicBool>, queue_count: Arc<AtomicUsize>) -> Self {
     Self {
       reg_rx,
+      stop,
+      queue_count,
       qs: Vec::new(),
       heap: BinaryHeap::new(),
       empty: Vec::new(),
       empty_cursor: 0,
-      clock: TscClock::calibrate(),
+      clock: {
+        let clock = TscClock::calibrate();
+        println!("log-backend: {} at {:.4} GHz ({}/5 calibration intervals kept)", tscns::COUNTER, clock.ghz(), clock.intervals_kept());
+        clock
+      },
       prefix: PrefixCache::new(),
+      scratch: MyBytesMut::with_capacity(512),
     }
   }
 
-  // fn add_consumer(&mut self, msg: RegMsg) {
-  //   let mut cons = msg.cons;
-  //   let mut st = QState {
-  //     cons,
-  //     head: None,
-  //     tid: msg.tid,
-  //   };
-  //   self.qs.push(st);
-  //   // let qid = self.qs.len();
-  //   // if let Some(e) = st.cons.pop() {
-  //   //   let t = e.tsc;
-  //   //   st.head = Some(e);
-  //   //   self.qs.push(st);
-  //   //   self.heap.push(Reverse((t, qid)));
-  //   // } else {
-  //   //   self.qs.push(st);
-  //   //   self.empty.push(qid);
-  //   // }
-  // }
+  fn add_consumer(&mut self, msg: RegMsg) {
+    let qid = self.qs.len();
+    self.qs.push(QState {
+      cons: msg.cons,
+      head: None,
+      tid: msg.tid,
+    });
+    self.queue_count.store(self.qs.len(), Ordering::Relaxed);
+    self.refill_head(qid);
+  }
 
-  // #[inline(always)]
-  // fn refill_head(&mut self, qid: usize) {
-  //   if self.qs[qid].head.is_none() {
-  //     if let Some(e) = self.qs[qid].cons.pop() {
-  //       let t = e.tsc;
-  //       self.qs[qid].head = Some(e);
-  //       self.heap.push(Reverse((t, qid)));
-  //     } else {
-  //       self.empty.push(qid);
-  //     }
-  //   }
-  // }
-  //
-  // #[inline(always)]
-  // fn scan_empty_budget(&mut self, budget: usize) {
-  //   let mut b = 0;
-  //   while b < budget && !self.empty.is_empty() {
-  //     let len = self.empty.len();
-  //     let idx = self.empty_cursor % len;
-  //     let qid = self.empty[idx];
-  //
-  //     if self.qs[qid].head.is_none() {
-  //       if let Some(e) = self.qs[qid].cons.pop() {
-  //         let t = e.tsc;
-  //         self.qs[qid].head = Some(e);
-  //         self.heap.push(Reverse((t, qid)));
-  //         self.empty.swap_remove(idx);
-  //       } else {
-  //         self.empty_cursor = self.empty_cursor.wrapping_add(1);
-  //       }
-  //     } else {
-  //       self.empty.swap_remove(idx);
-  //     }
-  //
-  //     b += 1;
-  //   }
-  // }
+  #[inline(always)]
+  fn refill_head(&mut self, qid: usize) {
+    if self.qs[qid].head.is_none() {
+      if let Some(e) = self.qs[qid].cons.peek() {
+        let t = e.tsc;
+        self.qs[qid].head = Some(t);
+        self.heap.push(Reverse((t, qid)));
+      } else {
+        self.empty.push(qid);
+      }
+    }
+  }
 
-  // #[inline(always)]
-  // fn write_header(&mut self, out: &mut dyn Write, e: &LogEntry) -> io::Result<()> {
-  //   // tsc -> epoch_ns
-  //   let epoch_ns = self.clock.tsc_to_epoch_ns(e.tsc);
-  //   let sec = epoch_ns / 1_000_000_000;
-  //   let sub = (epoch_ns % 1_000_000_000) as u32;
-  //   let ms = sub / 1_000_000;
-  //   let us = (sub / 1_000) % 1000;
-  //
-  //   // per-second prefix cache: "MM-DD HH:MM:SS"
-  //   if self.prefix.sec != sec {
-  //     self.prefix.refresh(sec);
-  //   }
-  //
-  //   // [MM-DD HH:MM:SS.mmm.uuu level site tid]
-  //   out.write_all(b"[")?;
-  //   out.write_all(&self.prefix.buf[..self.prefix.len])?;
-  //   out.write_all(b".")?;
-  //   let mut tmp = [0u8; 3];
-  //   three_digits(&mut tmp, ms);
-  //   out.write_all(&tmp)?;
-  //   out.write_all(b".")?;
-  //   three_digits(&mut tmp, us);
-  //   out.write_all(&tmp)?;
-  //   out.write_all(b" ")?;
-  //   out.write_all(level_str(e.level).as_bytes())?;
-  //   out.write_all(b" ")?;
-  //   Ok(())
-  // }
+  #[inline(always)]
+  fn scan_empty_budget(&mut self, budget: usize) {
+    let mut b = 0;
+    while b < budget && !self.empty.is_empty() {
+      let len = self.empty.len();
+      let idx = self.empty_cursor % len;
+      let qid = self.empty[idx];
+
+      if self.qs[qid].head.is_none() {
+        // read before peeking: once the producer is gone, an empty peek means nothing is left
+        let closed = self.qs[qid].cons.is_disconnected();
+        if closed {
+          // pairs with the Release decrement of the producer's Arc
+          fence(Ordering::Acquire);
+        }
+        if let Some(e) = self.qs[qid].cons.peek() {
+          let t = e.tsc;
+          self.qs[qid].head = Some(t);
+          self.heap.push(Reverse((t, qid)));
+          self.empty.swap_remove(idx);
+        } else if closed {
+          self.empty.swap_remove(idx);
+          self.remove_queue(qid);
+        } else {
+          self.empty_cursor = self.empty_cursor.wrapping_add(1);
+        }
+      } else {
+        self.empty.swap_remove(idx);
+      }
+
+      b += 1;
+    }
+  }
+
+  /// Forget a drained queue whose producer has been dropped. The last queue moves into `qid`,
+  /// so its entry in `heap` / `empty` is renamed.
+  #[cold]
+  #[inline(never)]
+  fn remove_queue(&mut self, qid: usize) {
+    let last = self.qs.len() - 1;
+    self.qs.swap_remove(qid);
+    self.queue_count.store(self.qs.len(), Ordering::Relaxed);
+    if qid == last {
+      return;
+    }
+    if self.qs[qid].head.is_some() {
+      let heap = std::mem::take(&mut self.heap);
+      self.heap = heap.into_iter()
+        .map(|Reverse((t, q))| Reverse((t, if q == last { qid } else { q })))
+        .collect();
+    } else if let Some(e) = self.empty.iter_mut().find(|q| **q == last) {
+      *e = qid;
+    }
+  }
+
+  #[inline(always)]
+  fn write_header(clock: &TscClock, prefix: &mut PrefixCache, out: &mut dyn Write, e: &LogEntry, tid: u32) -> io::Result<()> {
+    // tsc -> epoch_ns
+    let epoch_ns = clock.tsc_to_epoch_ns(e.tsc);
+    let sec = epoch_ns / 1_000_000_000;
+    let sub = (epoch_ns % 1_000_000_000) as u32;
+    let ms = sub / 1_000_000;
+    let us = (sub / 1_000) % 1000;
+
+    // per-second prefix cache: "MM-DD HH:MM:SS"
+    if prefix.sec != sec {
+      prefix.refresh(sec);
+    }
+
+    // [MM-DD HH:MM:SS.mmm.uuu T=tid level site]
+    out.write_all(b"[")?;
+    out.write_all(&prefix.buf[..prefix.len])?;
+    out.write_all(b".")?;
+    let mut tmp = [0u8; 3];
+    three_digits(&mut tmp, ms);
+    out.write_all(&tmp)?;
+    out.write_all(b".")?;
+    three_digits(&mut tmp, us);
+    out.write_all(&tmp)?;
+    write!(out, " T={:02} ", tid)?;
+    out.write_all(level_str(e.level).as_bytes())?;
+    out.write_all(b" ")?;
+    Ok(())
+  }
+
+  /// Write the queue head with the smallest tsc straight from its slot, then refill that queue's head.
+  #[inline(always)]
+  fn write_next(&mut self, out: &mut dyn Write) -> io::Result<bool> {
+    let Some(Reverse((_t, qid))) = self.heap.pop() else {
+      return Ok(false);
+    };
+    let st = &mut self.qs[qid];
+    st.head = None;
+    let tid = st.tid;
+    let (clock, prefix, scratch) = (&self.clock, &mut self.prefix, &mut self.scratch);
+    let written = st.cons.pop_with(|e| -> io::Result<()> {
+      Self::write_header(clock, prefix, out, e, tid)?;
+      scratch.clear();
+      (e.func)(scratch, e.payload(), None)?;
+      out.write_all(scratch.result())?;
+      out.write_all(b"\n")
+    });
+
+    self.refill_head(qid);
+    // the heap only holds queues with a head
+    written.unwrap()?;
+    Ok(true)
+  }
 
   fn run(mut self) -> io::Result<()> {
-    let mut qs = Vec::with_capacity(64);
+    let mut out = io::BufWriter::new(io::stdout().lock());
+
     loop {
+      // checked before draining so the final pass sees everything pushed before shutdown()
+      let stopping = self.stop.load(Ordering::Acquire);
       while let Ok(msg) = self.reg_rx.try_recv() {
-        // self.add_consumer(msg);
-        let mut cons = msg.cons;
-        let mut st = QState {
-          cons,
-          head: None,
-          tid: msg.tid,
-        };
-        qs.push(st);
+        self.add_consumer(msg);
       }
 
-      // let mut out = io::stdout();
-      for qs in qs.iter_mut() {
-        let tid = qs.tid;
-        while let Some(log_entry) = qs.cons.pop() {
-          // self.write_header(&mut out, &log_entry)?;
-          // // let len = e.len as usize;
-          // (log_entry.func)(&mut out, tid, &log_entry.data)?;
-          // out.write_all(b"\n")?;
+      if stopping {
+        // 最后一轮：每个队列都要再看一次
+        loop {
+          self.empty.clear();
+          for qid in 0..self.qs.len() {
+            self.refill_head(qid);
+          }
+          if self.heap.is_empty() {
+            break;
+          }
+          while self.write_next(&mut out)? {}
         }
+        break;
+      }
+
+      // 小预算扫描空队列（兜底）
+      self.scan_empty_budget(4);
+
+      let mut budget = 256;
+      while budget > 0 && self.write_next(&mut out)? {
+        budget -= 1;
+      }
+
+      if self.heap.is_empty() {
+        out.flush()?;
+        std::thread::park_timeout(Duration::from_micros(100));
       }
-      // out.flush()?;
-      // drop(out);
-      // drop(stdout);
-
-      // println!("park");
-      std::thread::park_timeout(Duration::from_micros(100));
-      // println!("unpark");
-      // break;
     }
+    out.flush()?;
     println!("Done");
-
-    // let stdout = io::stdout();
-    // let mut out = io::BufWriter::new(stdout.lock());
-    //
-    // loop {
-    //   while let Ok(msg) = self.reg_rx.try_recv() {
-    //     self.add_consumer(msg);
-    //   }
-    //
-    //   // 小预算扫描空队列（兜底）
-    //   self.scan_empty_budget(4);
-    //
-    //   if let Some(Reverse((_t, qid))) = self.heap.pop() {
-    //     let e = self.qs[qid].head.take().unwrap();
-    //     let tid = self.qs[qid].tid;
-    //
-    //     self.write_header(&mut out, &e)?;
-    //     // let len = e.len as usize;
-    //     (e.func)(&mut out, tid, &e.data);
-    //     out.write_all(b"\n")?;
-    //
-    //     self.refill_head(qid);
-    //   } else {
-    //     std::thread::yield_now();
-    //   }
-    // }
+    Ok(())
   }
 }
 
@@ -303,61 +453,105 @@ impl LoggerThread {
 // init_logger
 // =============================
 pub fn init_logger(capacity: usize) -> LoggerHandle {
-  let (reg_tx, reg_rx) = crossbeam_channel::unbounded();
+  init_logger_on(capacity, None)
+}
 
-  std::thread::spawn(move || {
-    let res = core_affinity::set_for_current( core_affinity::CoreId { id: 7 });
-    let lt = LoggerThread::new(reg_rx);
-    if let Err(e) = lt.run() {
-      println!("Run log-backend error: {:?}", e);
-    }
-  });
+/// Same as [`init_logger`], with the logger thread pinned to `core` when that core exists.
+pub fn init_logger_on(capacity: usize, core: Option<usize>) -> LoggerHandle {
+  let (reg_tx, reg_rx) = crossbeam_channel::unbounded();
+  let stop = Arc::new(AtomicBool::new(false));
+  let queue_count = Arc::new(AtomicUsize::new(0));
+
+  let backend = {
+    let stop = stop.clone();
+    let queue_count = queue_count.clone();
+    std::thread::spawn(move || {
+      crate::run_log2::pin_backend(core);
+      let lt = LoggerThread::new(reg_rx, stop, queue_count);
+      if let Err(e) = lt.run() {
+        println!("Run log-backend error: {:?}", e);
+      }
+    })
+  };
 
   // let queue = Arc::new(StagingBuffer::new());
   let (prod, cons) = spsc_queue::spsc_queue::<LogEntry>(capacity);
   let tid = NEXT_TID.fetch_add(1, Ordering::Relaxed); //get_tid();
   let _ = reg_tx.send(RegMsg { cons, tid });
 
-  LoggerHandle { prod, reg_tx, capacity }
+  LoggerHandle { prod, reg_tx, capacity, stop, queue_count, backend }
 }
 
 // =============================
 // TSC -> epoch_ns mapping + prefix cache (优化#1)
 // =============================
+/// Maps `tscns::read_tsc` values (the arch's counter: `rdtsc` / `cntvct_el0`) to epoch ns.
 pub struct TscClock {
   base_tsc: u64,
   base_epoch_ns: u64,
   hz: f64,
+  // calibration intervals within 0.1% of the median, out of 5
+  kept: usize,
 }
 
 impl TscClock {
+  /// `base_epoch_ns` is read once here, so a later NTP step never moves the timestamps;
+  /// they follow the tsc from this point on.
+  ///
+  /// Blocks for about 50ms: the rate is measured over 5 × 10ms intervals between `tscns::sync_time`
+  /// samples, intervals more than 0.1% off the median (preemption, a clock step) are dropped and the rest averaged.
   pub fn calibrate() -> Self {
-    // base
-    let base_epoch_ns = SystemTime::now()
-      .duration_since(UNIX_EPOCH)
-      .unwrap()
-      .as_nanos() as u64;
-
-    // hz
-    let t0 = rdtsc();
-    let s0 = std::time::Instant::now();
-    std::thread::sleep(Duration::from_millis(10));
-    let t1 = rdtsc();
-    let dt_ns = s0.elapsed().as_nanos() as f64;
-    let hz = (t1 - t0) as f64 * 1e9 / dt_ns;
+    const INTERVALS: usize = 5;
+    let mut samples = [(0i64, 0i64); INTERVALS + 1];
+    samples[0] = tscns::sync_time();
+    for sample in &mut samples[1..] {
+      std::thread::sleep(Duration::from_millis(10));
+      *sample = tscns::sync_time();
+    }
 
+    // cycles per ns of each interval
+    let mut rates = [0f64; INTERVALS];
+    for i in 0..INTERVALS {
+      let (t0, ns0) = samples[i];
+      let (t1, ns1) = samples[i + 1];
+      rates[i] = t1.wrapping_sub(t0) as f64 / ns1.wrapping_sub(ns0).max(1) as f64;
+    }
+    let mut sorted = rates;
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[INTERVALS / 2];
+    let (sum, cnt) = rates.iter()
+      .filter(|&&r| (r - median).abs() <= median * 1e-3)
+      .fold((0.0, 0), |(sum, cnt), &r| (sum + r, cnt + 1));
+    let hz = sum / cnt as f64 * 1e9;
+
+    let (base_tsc, base_ns) = samples[INTERVALS];
     Self {
-      base_tsc: t1,
-      base_epoch_ns,
+      base_tsc: base_tsc as u64,
+      base_epoch_ns: base_ns.max(0) as u64,
       hz,
+      kept: cnt,
     }
   }
 
+  /// Measured counter frequency; the cpu's (nominal) GHz only when `tscns::COUNTER` is `rdtsc`.
+  #[inline]
+  pub fn ghz(&self) -> f64 {
+    self.hz / 1e9
+  }
+
+  /// How many of the 5 calibration intervals agreed with the median and went into `ghz`, at least 1.
+  #[inline]
+  pub fn intervals_kept(&self) -> usize {
+    self.kept
+  }
+
   #[inline(always)]
   pub fn tsc_to_epoch_ns(&self, tsc: u64) -> u64 {
-    let d_cycles = tsc.wrapping_sub(self.base_tsc) as f64;
+    // tsc < base_tsc (stamped before calibrate, or read on a core slightly behind) maps to the base,
+    // a wrapped delta would land centuries in the future
+    let d_cycles = tsc.saturating_sub(self.base_tsc) as f64;
     let d_ns = d_cycles * 1e9 / self.hz;
-    self.base_epoch_ns.wrapping_add(d_ns.max(0.0) as u64)
+    self.base_epoch_ns + d_ns as u64
   }
 }
 
@@ -427,3 +621,201 @@ fn three_digits(dst: &mut [u8], x: u32) {
   dst[1] = b'0' + (((x / 10) % 10) as u8);
   dst[2] = b'0' + ((x % 10) as u8);
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::log::{Level, SourceLocation};
+
+  fn seq_shim(out: &mut MyBytesMut, bytes: &[u8], _loc: Option<&mut SourceLocation>) -> io::Result<()> {
+    write!(out, "seq={}", u64::from_le_bytes(bytes[..8].try_into().unwrap()))
+  }
+
+  #[test]
+  fn two_producers_come_out_in_tsc_order() {
+    const N: u64 = 500;
+    let (_reg_tx, reg_rx) = crossbeam_channel::unbounded();
+    let mut lt = LoggerThread::new(reg_rx, Arc::new(AtomicBool::new(false)), Arc::new(AtomicUsize::new(0)));
+
+    let workers: Vec<_> = (0..2u64).map(|t| {
+      let (mut prod, cons) = spsc_queue::spsc_queue::<LogEntry>(1024);
+      let handle = std::thread::spawn(move || {
+        let mut stamps = Vec::new();
+        for i in 0..N {
+          let seq = t * 1000 + i;
+          assert!(prod.push_write(|e| {
+            e.mut_from_args(Level::Info, seq_shim, &seq);
+            stamps.push((seq, e.tsc));
+          }).is_ok());
+          if i % 50 == 0 {
+            std::thread::yield_now();
+          }
+        }
+        (prod, stamps)
+      });
+      (cons, t as u32, handle)
+    }).collect();
+
+    let mut tsc_of = std::collections::HashMap::new();
+    for (cons, tid, handle) in workers {
+      let (_prod, stamps) = handle.join().unwrap();
+      tsc_of.extend(stamps);
+      lt.add_consumer(RegMsg { cons, tid });
+    }
+
+    let mut out = Vec::new();
+    while lt.write_next(&mut out).unwrap() {}
+    let text = String::from_utf8(out).unwrap();
+    let tscs: Vec<u64> = text.lines().map(|l| tsc_of[&l.rsplit_once("seq=").unwrap().1.parse::<u64>().unwrap()]).collect();
+
+    assert_eq!(tscs.len(), 2 * N as usize);
+    assert!(tscs.windows(2).all(|w| w[0] <= w[1]));
+  }
+
+  #[test]
+  fn sequential_entries_get_increasing_tsc() {
+    let (mut prod, mut cons) = spsc_queue::spsc_queue::<LogEntry>(256);
+    for i in 0..100u64 {
+      assert!(prod.push_write(|e| e.mut_from_args(Level::Info, seq_shim, &i)).is_ok());
+      assert!(prod.push_write(|e| *e = LogEntry::from_args(Level::Info, seq_shim, &i)).is_ok());
+    }
+    let mut last = 0;
+    while let Some(e) = cons.pop() {
+      assert!(e.tsc > last, "{} after {}", e.tsc, last);
+      last = e.tsc;
+    }
+    assert_ne!(last, 0);
+  }
+
+  #[test]
+  fn str_args_are_encoded_into_the_entry() {
+    use crate::args2::{args2, encode_to_vec};
+
+    let short = args2(7u64, "BTCUSDT");
+    let e = LogEntry::from_args(Level::Info, seq_shim, &short);
+    assert_eq!(e.len, 8 + 8 + 16);
+    assert_eq!(encode_to_vec(&short), e.data[..e.len as usize]);
+
+    // 单个长字符串放得下，两个加起来超过 MAX_PAYLOAD_LEN 就整条拒掉
+    let mut logger = init_logger(64);
+    let site = crate::metadata::dynamic_site(Level::Info, module_path!(), file!(), line!(), "{} {}", 2, seq_shim);
+    let long = "x".repeat(200);
+    assert!(logger.publish_args(site, &args2(long.as_str(), 1u64)).is_ok());
+    assert_eq!(logger.publish_args(site, &args2(long.as_str(), long.as_str())), PublishResult::PayloadTooLarge);
+    logger.shutdown();
+  }
+
+  #[test]
+  fn tsc_before_the_base_does_not_leap_forward() {
+    let clock = TscClock::calibrate();
+    let at_base = clock.tsc_to_epoch_ns(clock.base_tsc);
+    assert_eq!(at_base, clock.base_epoch_ns);
+
+    for behind in [1, 1_000, clock.base_tsc] {
+      assert_eq!(clock.tsc_to_epoch_ns(clock.base_tsc - behind), at_base);
+    }
+    // 正常往前走的 tsc 仍然按频率换算
+    let one_sec_later = clock.tsc_to_epoch_ns(clock.base_tsc + clock.hz as u64);
+    assert!(one_sec_later.abs_diff(at_base + 1_000_000_000) < 1_000);
+  }
+
+  #[test]
+  fn calibrated_clock_tracks_system_time_for_a_second() {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let clock = TscClock::calibrate();
+    std::thread::sleep(Duration::from_secs(1));
+    let ns = clock.tsc_to_epoch_ns(tscns::read_tsc() as u64);
+    let sys = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+    assert!(ns.abs_diff(sys) < 1_000_000, "off by {}ns after 1s", ns as i64 - sys as i64);
+  }
+
+  #[cfg(target_arch = "aarch64")]
+  #[test]
+  fn aarch64_counter_tracks_wall_time() {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let clock = TscClock::calibrate();
+    // cntvct_el0 跑在固定频率上（一般几十 MHz 到 1GHz），不是 SystemTime 的纳秒
+    let t0 = crate::log::rdtsc();
+    std::thread::sleep(Duration::from_millis(200));
+    let t1 = crate::log::rdtsc();
+    assert!(t1 > t0);
+    assert!((t1 - t0) as f64 / clock.hz > 0.19);
+
+    let ns = clock.tsc_to_epoch_ns(t1);
+    let sys = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+    assert!(ns.abs_diff(sys) < 1_000_000, "off by {}ns", ns as i64 - sys as i64);
+  }
+
+  #[test]
+  fn producers_pick_their_own_capacity() {
+    let logger = init_logger(64);
+    let md = logger.new_producer_with_capacity(4096);
+    let ctl = logger.new_producer_with_capacity(16);
+    let odd = logger.new_producer_with_capacity(100);
+    let default = logger.new_producer();
+    assert_eq!(logger.capacity(), 64);
+    assert_eq!(md.capacity(), 4096);
+    assert_eq!(ctl.capacity(), 16);
+    assert_eq!(odd.capacity(), 128);
+    assert_eq!(default.capacity(), 64);
+    drop((md, ctl, odd, default));
+    logger.shutdown();
+  }
+
+  #[test]
+  fn reported_ghz_is_plausible() {
+    let clock = TscClock::calibrate();
+    assert!((1..=5).contains(&clock.intervals_kept()));
+    // 只有 rdtsc 跟 cpu 主频有关；别的架构的计数器频率没有这个范围
+    if tscns::COUNTER != "rdtsc" {
+      return;
+    }
+    assert!((0.5..=6.0).contains(&clock.ghz()), "{} GHz", clock.ghz());
+    tscns::init(tscns::INIT_CALIBRATE_NANOS, tscns::CALIBRATE_INTERVAL_NANOS);
+    assert!((0.5..=6.0).contains(&tscns::get_tsc_ghz()), "{} GHz", tscns::get_tsc_ghz());
+  }
+
+  #[test]
+  fn dropped_producer_is_drained_then_forgotten() {
+    let (_reg_tx, reg_rx) = crossbeam_channel::unbounded();
+    let count = Arc::new(AtomicUsize::new(0));
+    let mut lt = LoggerThread::new(reg_rx, Arc::new(AtomicBool::new(false)), count.clone());
+
+    let (_live, live_cons) = spsc_queue::spsc_queue::<LogEntry>(16);
+    let (mut prod, cons) = spsc_queue::spsc_queue::<LogEntry>(16);
+    lt.add_consumer(RegMsg { cons: live_cons, tid: 0 });
+    lt.add_consumer(RegMsg { cons, tid: 1 });
+    assert_eq!(count.load(Ordering::Relaxed), 2);
+
+    for i in 0..5u64 {
+      assert!(prod.push_write(|e| e.mut_from_args(Level::Info, seq_shim, &i)).is_ok());
+    }
+    drop(prod);
+
+    let mut out = Vec::new();
+    for _ in 0..10 {
+      lt.scan_empty_budget(4);
+      while lt.write_next(&mut out).unwrap() {}
+    }
+    // 先写完剩下的 5 条，再把队列去掉；活着的那个不受影响
+    assert_eq!(String::from_utf8(out).unwrap().lines().count(), 5);
+    assert_eq!(lt.qs.len(), 1);
+    assert_eq!(lt.qs[0].tid, 0);
+    assert_eq!(count.load(Ordering::Relaxed), 1);
+  }
+
+  #[test]
+  fn entry_len_is_the_size_of_the_args() {
+    let args = [0xabu8; 16];
+    let e = LogEntry::from_args(Level::Info, seq_shim, &args);
+    assert_eq!(e.len, 16);
+    assert_eq!(e.payload(), &args[..]);
+
+    let (mut prod, cons) = spsc_queue::spsc_queue::<LogEntry>(4);
+    assert!(prod.push_write(|e| e.mut_from_args(Level::Info, seq_shim, &7u64)).is_ok());
+    let e = cons.peek().unwrap();
+    assert_eq!(e.payload(), &7u64.to_ne_bytes()[..]);
+  }
+}
@@ -1,92 +1,962 @@
-use std::{io, ptr};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr::slice_from_raw_parts;
-use std::sync::Arc;
-use std::time::Duration;
-use crate::log::{rdtsc, Level, LogFn};
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex, Once, Weak};
+use std::sync::atomic::{fence, AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use crossbeam_channel::{Receiver, Sender};
+use crate::args2::{args1, decode_args, encode_to_vec, Payload};
+use crate::log::{get_tid, Level, SourceLocation, MAX_PAYLOAD_LEN};
+use crate::metadata::{CallSite, Metadata};
+use crate::my_bytes_mut::MyBytesMut;
 use crate::{tscns, StagingBuffer};
+use crate::tscns::TimeSource;
+use crate::capture_sink::CaptureSink;
 use crate::console_sink::ConsoleBatchSink;
-use crate::spsc_var_queue_opt::{Consumer, Producer};
+use crate::sink::Sink;
+use crate::spsc_var_queue_opt::{blocks_for, AllocError, Consumer, MsgHeader, Producer, BLOCK_SIZE, MSG_HEADER_SIZE};
 
+/// A staging queue owned by one producer thread, handed to the backend on first use.
+/// Sent again with `name` set when the thread is named after its first log.
+struct RegMsg {
+  queue: Arc<StagingBuffer>,
+  tid: u32,
+  name: Option<Box<str>>,
+}
+
+static NEXT_LOGGER_ID: AtomicUsize = AtomicUsize::new(1);
+static NEXT_TID: AtomicU32 = AtomicU32::new(1);
+
+thread_local! {
+  // (logger id, tid, this thread's queue for that logger), one per logger the thread has logged to,
+  // the one used last in front
+  static TLS_QUEUES: RefCell<Vec<(usize, u32, Arc<StagingBuffer>)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Cheap to clone and `Send`: every thread that logs through it gets its own `StagingBuffer`,
+/// so `SpscVarQueueOpt` keeps exactly one producer per ring.
+#[derive(Clone)]
 pub struct LoggerHandle {
-  pub queue: Arc<StagingBuffer>,
+  id: usize,
+  reg_tx: Sender<RegMsg>,
+  // weak: once a thread exits the backend holds the last Arc and retires the queue
+  queues: Arc<Mutex<Vec<Weak<StagingBuffer>>>>,
+  counters: Arc<Counters>,
+  stop: Arc<AtomicBool>,
+  flush: Arc<FlushReq>,
+  backend: Arc<Mutex<Option<JoinHandle<()>>>>,
+  // `in_memory` loggers: no backend thread, `captured_lines` drains on the caller's thread
+  capture: Option<Arc<Mutex<Backend<CaptureSink>>>>,
+  tid_source: TidSource,
+  // bytes of the queue a thread gets on its first log through this handle
+  queue_capacity: usize,
+}
+
+/// Totals the backend keeps for `LoggerHandle`; per-queue counters die with their queue.
+#[derive(Default)]
+struct Counters {
+  // drops counted on queues the backend has already retired
+  retired_dropped: AtomicU64,
+  // records pushed to queues the backend has already retired
+  retired_pushed: AtomicU64,
+  // largest high-water mark among retired queues, blocks
+  retired_peak: AtomicU32,
+  // records the backend skipped because formatting/writing them failed or panicked
+  record_errors: AtomicU64,
+  // publish -> backend pickup, per record
+  drain_latency: LatencyHistogram,
+}
+
+/// Power-of-two ns buckets: bucket `i` counts latencies in `[2^(i-1), 2^i)`, bucket 0 counts 0.
+/// Written by the backend only.
+struct LatencyHistogram {
+  buckets: [AtomicU64; 64],
+}
+
+impl Default for LatencyHistogram {
+  fn default() -> Self {
+    Self { buckets: [const { AtomicU64::new(0) }; 64] }
+  }
+}
+
+impl LatencyHistogram {
+  #[inline(always)]
+  fn record(&self, ns: u64) {
+    let b = &self.buckets[(64 - ns.leading_zeros() as usize).min(63)];
+    // single writer: no locked RMW
+    b.store(b.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+  }
+
+  fn percentiles(&self) -> DrainLatency {
+    let counts: Vec<u64> = self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+    let count: u64 = counts.iter().sum();
+    let upper = |i: usize| if i == 0 { 0 } else { 1u64 << i.min(63) };
+    let at = |q: f64| {
+      // rank of the q-quantile, 1-based
+      let rank = ((count as f64 * q).ceil() as u64).max(1);
+      let mut seen = 0;
+      for (i, c) in counts.iter().enumerate() {
+        seen += c;
+        if seen >= rank {
+          return upper(i);
+        }
+      }
+      0
+    };
+    if count == 0 {
+      return DrainLatency::default();
+    }
+    DrainLatency {
+      count,
+      p50: at(0.5),
+      p90: at(0.9),
+      p99: at(0.99),
+      p999: at(0.999),
+      max: counts.iter().rposition(|c| *c != 0).map_or(0, upper),
+    }
+  }
+}
+
+/// How long records sat in the staging queues before the backend picked them up, in ns, since init.
+/// Values are bucket upper bounds (powers of two), so within 2x of the true latency; all 0 before the first record.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DrainLatency {
+  /// records measured
+  pub count: u64,
+  pub p50: u64,
+  pub p90: u64,
+  pub p99: u64,
+  pub p999: u64,
+  pub max: u64,
+}
+
+/// Snapshot for dashboards, see [`LoggerHandle::stats`]. Sizes are bytes, summed over the
+/// per-thread staging queues unless noted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoggerStats {
+  /// capacity of one thread's queue
+  pub queue_capacity: usize,
+  /// live queues, see [`LoggerHandle::queue_count`]
+  pub queues: usize,
+  /// bytes published or reserved and not yet drained, right now
+  pub occupancy: usize,
+  /// highest occupancy any single queue has reached
+  pub peak_occupancy: usize,
+  /// records that made it into a queue, since init
+  pub pushed: u64,
+  /// records rejected because a queue was full (or the record too large), since init
+  pub dropped: u64,
+}
+
+/// `LoggerHandle::flush` bumps `req`, the backend drains, flushes the sink and stores it into `ack`.
+#[derive(Default)]
+struct FlushReq {
+  req: AtomicU64,
+  ack: AtomicU64,
+}
+
+/// Longest `LoggerHandle::flush` waits on a backend that is parked or busy, and a fatal record for room.
+const FLUSH_WAIT: Duration = Duration::from_secs(1);
+
+/// What the `tid` in the header is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TidSource {
+  /// kernel thread id (`gettid`), matches `top -H` / `perf` / `strace`
+  Os,
+  /// 1, 2, 3.. in the order threads first log
+  Counter,
+}
+
+/// Outcome of [`LoggerHandle::publish_args`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PublishResult {
+  Ok,
+  /// the thread's staging queue has no room right now (backpressure), counted in `dropped_count`
+  QueueFull,
+  /// header + args are bigger than the whole staging queue, never publishable (a bug at the call site);
+  /// also counted in `dropped_count`
+  PayloadTooLarge,
+}
+
+impl PublishResult {
+  #[inline(always)]
+  pub fn is_ok(self) -> bool {
+    self == PublishResult::Ok
+  }
 }
 
 impl LoggerHandle {
-  pub fn publish_args<A: Copy>(&self, level: Level, func: LogFn, args: &A) -> bool {
-    let prod = Producer {
-      q: self.queue.as_ref(),
+  /// Publish `args` for `site`, stamped now.
+  #[inline(always)]
+  pub fn publish_args<A: Payload + ?Sized>(&self, site: &'static CallSite, args: &A) -> PublishResult {
+    self.publish_args_at(site, args, tscns::read_tsc())
+  }
+
+  /// What the `hft_*` macros call, they return `is_ok()` of it. `tsc` is read before the args are
+  /// converted, so a slow `into_arg` does not shift the record's time.
+  pub fn publish_args_at<A: Payload + ?Sized>(&self, site: &'static CallSite, args: &A, tsc: i64) -> PublishResult {
+    if site.meta.level == Level::Fatal {
+      return self.publish_fatal(site, args, tsc);
+    }
+    self.with_queue(|queue| publish_payload(queue, site, args, tsc))
+  }
+
+  /// `publish_args` that waits for room instead of dropping: retries (spin, then yield) while the
+  /// queue is full, for at most `timeout`; then one last try that counts as dropped if it fails.
+  /// For messages that must not be lost (errors before a trading halt); it stalls the caller while it waits.
+  pub fn publish_args_blocking<A: Payload + ?Sized>(&self, site: &'static CallSite, args: &A, timeout: Duration) -> PublishResult {
+    self.publish_args_blocking_at(site, args, timeout, tscns::read_tsc())
+  }
+
+  fn publish_args_blocking_at<A: Payload + ?Sized>(&self, site: &'static CallSite, args: &A, timeout: Duration, tsc: i64) -> PublishResult {
+    self.with_queue(|queue| publish_payload_blocking(queue, site, args, timeout, tsc))
+  }
+
+  /// Fatal records wait for room instead of being dropped on a full queue.
+  #[cold]
+  fn publish_fatal<A: Payload + ?Sized>(&self, site: &'static CallSite, args: &A, tsc: i64) -> PublishResult {
+    // a parked backend only frees room after its park timeout, an in-memory one never on its own
+    match &self.capture {
+      Some(capture) => {
+        let mut backend = capture.lock().unwrap();
+        while let Ok(true) = backend.poll(&self.counters) {}
+      }
+      None => {
+        if let Some(backend) = self.backend.lock().unwrap().as_ref() {
+          backend.thread().unpark();
+        }
+      }
+    }
+    self.publish_args_blocking_at(site, args, FLUSH_WAIT, tsc)
+  }
+
+  /// Non-macro entry point: publish a pre-encoded `payload` for `site`, typically the
+  /// `args2::encode_to_vec` of an `args2::argsN(..)` and a site from `metadata::dynamic_site`.
+  /// `false` if the queue is full or `payload` is longer than `MAX_PAYLOAD_LEN`.
+  pub fn log(&self, site: &'static CallSite, payload: &[u8]) -> bool {
+    if payload.len() > MAX_PAYLOAD_LEN {
+      return false;
+    }
+    let tsc = tscns::read_tsc();
+    self.with_queue(|queue| publish_payload(queue, site, payload, tsc).is_ok())
+  }
+
+  /// `tsc` (from `tscns::read_tsc`, e.g. stamped at packet receive) as epoch ns. Always the default TSC
+  /// clock (`tscns::tsc2ns`): a custom `Clock` set on the sink is not consulted.
+  #[inline]
+  pub fn tsc_to_ns(&self, tsc: i64) -> i64 {
+    tscns::tsc2ns(tsc)
+  }
+
+  /// Threads that have not logged yet get their tid from `source`; `TidSource::Os` by default.
+  /// Set it before handing out clones, each clone keeps the source it was cloned with.
+  pub fn with_tid_source(mut self, source: TidSource) -> Self {
+    self.tid_source = source;
+    self
+  }
+
+  /// Threads that have not logged to this logger yet get a staging queue of `bytes` (rounded up to a
+  /// power of two number of blocks) instead of the `init_logger` capacity: a deep one for a bursty
+  /// market-data thread, a small one for a quiet control-plane thread. A thread keeps the queue it got first.
+  pub fn with_capacity(mut self, bytes: usize) -> Self {
+    self.queue_capacity = bytes;
+    self
+  }
+
+  /// Bytes of the calling thread's staging queue for this logger, `None` before its first log.
+  pub fn thread_queue_capacity(&self) -> Option<usize> {
+    TLS_QUEUES.with(|slots| {
+      slots.borrow().iter().find(|(id, _, _)| *id == self.id).map(|(_, _, queue)| queue.capacity_bytes())
+    })
+  }
+
+  /// Lines logged from the calling thread show `name` instead of `T=NN`.
+  /// Takes effect for everything the backend formats after it gets the name, lines already queued included.
+  pub fn set_thread_name(&self, name: &str) {
+    TLS_QUEUES.with(|slots| {
+      let mut slots = slots.borrow_mut();
+      match slots.iter().find(|(id, _, _)| *id == self.id) {
+        Some((_, tid, queue)) => {
+          let _ = self.reg_tx.send(RegMsg { queue: queue.clone(), tid: *tid, name: Some(name.into()) });
+        }
+        None => {
+          slots.retain(|(_, _, queue)| Arc::strong_count(queue) > 1);
+          let slot = self.register_current_thread(Some(name));
+          slots.insert(0, slot);
+        }
+      }
+    })
+  }
+
+  /// Run `f` on this thread's queue for this logger, registering one on first use.
+  /// A thread alternating between loggers keeps one queue per logger instead of swapping them out.
+  #[inline(always)]
+  fn with_queue<R>(&self, f: impl FnOnce(&StagingBuffer) -> R) -> R {
+    TLS_QUEUES.with(|slots| {
+      let mut slots = slots.borrow_mut();
+      match slots.iter().position(|(id, _, _)| *id == self.id) {
+        Some(0) => {}
+        Some(idx) => slots[..=idx].rotate_right(1),
+        None => {
+          // queues of loggers whose backend is gone: nothing drains them any more
+          slots.retain(|(_, _, queue)| Arc::strong_count(queue) > 1);
+          let slot = self.register_current_thread(None);
+          slots.insert(0, slot);
+        }
+      }
+      f(&slots[0].2)
+    })
+  }
+
+  /// First log from this thread: new queue, registered with the backend.
+  #[cold]
+  #[inline(never)]
+  fn register_current_thread(&self, name: Option<&str>) -> (usize, u32, Arc<StagingBuffer>) {
+    let queue = Arc::new(StagingBuffer::with_capacity(self.queue_capacity));
+    let tid = match self.tid_source {
+      TidSource::Os => get_tid(),
+      TidSource::Counter => NEXT_TID.fetch_add(1, Ordering::Relaxed),
     };
+    let mut queues = self.queues.lock().unwrap();
+    queues.retain(|q| q.strong_count() > 0);
+    queues.push(Arc::downgrade(&queue));
+    drop(queues);
+    let _ = self.reg_tx.send(RegMsg { queue: queue.clone(), tid, name: name.map(Into::into) });
+    (self.id, tid, queue)
+  }
 
-    let len = size_of::<A>();
-    if let Some((hdr, payload, payload_cap, total, _blk_sz)) = prod.try_alloc(len) {
-      unsafe {
-        let hdr = &mut (*hdr);
-        hdr.level = level as u8 as u32;
-        hdr.tsc = tscns::read_tsc();
-        hdr.log_func = func as u64;
+  /// Stop the backend: it drains whatever is still queued, flushes the sink and exits.
+  /// Blocks until the backend thread has been joined; a no-op if another clone already did.
+  /// Shutting down the last running logger also stops the tsc calibration thread.
+  pub fn shutdown(self) {
+    self.stop.store(true, Ordering::Release);
+    let Some(backend) = self.backend.lock().unwrap().take() else {
+      return;
+    };
+    backend.thread().unpark();
+    if backend.join().is_err() {
+      println!("log-backend panicked before shutdown");
+    }
+    release_calibration();
+  }
 
-        ptr::copy_nonoverlapping(args as *const A as *const u8, payload, len);
-        prod.commit(hdr, total);
+  /// Blocks until everything this thread logged so far has been drained and written out with `Sink::flush`.
+  /// Returns false if the backend is gone or did not get to it within a second; `hft_fatal!` calls it.
+  pub fn flush(&self) -> bool {
+    if let Some(capture) = &self.capture {
+      let mut backend = capture.lock().unwrap();
+      while let Ok(true) = backend.poll(&self.counters) {}
+      return backend.sink.flush().is_ok();
+    }
+    let req = self.flush.req.fetch_add(1, Ordering::AcqRel) + 1;
+    match self.backend.lock().unwrap().as_ref() {
+      Some(backend) => backend.thread().unpark(),
+      None => return false,
+    }
+    let deadline = Instant::now() + FLUSH_WAIT;
+    while self.flush.ack.load(Ordering::Acquire) < req {
+      if Instant::now() >= deadline {
+        return false;
       }
-      true
-    } else {
-      false
+      std::thread::yield_now();
     }
+    true
+  }
+
+  /// Drains every queue into the `CaptureSink` on the calling thread and returns all lines captured so far.
+  /// Empty for a logger that is not `in_memory`.
+  pub fn captured_lines(&self) -> Vec<String> {
+    let Some(capture) = &self.capture else {
+      return Vec::new();
+    };
+    let mut backend = capture.lock().unwrap();
+    while let Ok(true) = backend.poll(&self.counters) {}
+    let _ = backend.report_pending_dropped();
+    backend.sink.lines().lock().unwrap().clone()
+  }
+
+  /// Number of log messages dropped because a staging queue was full, summed over all threads.
+  #[inline]
+  pub fn dropped_count(&self) -> u64 {
+    let live: u64 = self.queues.lock().unwrap().iter().filter_map(|q| q.upgrade()).map(|q| q.dropped_count()).sum();
+    live + self.counters.retired_dropped.load(Ordering::Relaxed)
+  }
+
+  /// Records the backend skipped instead of dying: bad metadata id, a sink error or a panicking `LogFn`.
+  /// A failed `Sink::on_idle` counts here too.
+  #[inline]
+  pub fn record_errors(&self) -> u64 {
+    self.counters.record_errors.load(Ordering::Relaxed)
+  }
+
+  /// Percentiles of the time from a record's stamp to the backend taking it off its queue:
+  /// growing tails mean the backend falls behind and the queues need to be bigger (or the sink faster).
+  pub fn drain_latency_percentiles(&self) -> DrainLatency {
+    self.counters.drain_latency.percentiles()
+  }
+
+  /// Capacity, occupancy and lifetime push/drop counts over all staging queues. Reads relaxed
+  /// counters, so the fields are not one consistent instant under load.
+  pub fn stats(&self) -> LoggerStats {
+    let c = &self.counters;
+    let mut stats = LoggerStats {
+      queue_capacity: blocks_for(self.queue_capacity, BLOCK_SIZE) * BLOCK_SIZE,
+      pushed: c.retired_pushed.load(Ordering::Relaxed),
+      dropped: c.retired_dropped.load(Ordering::Relaxed),
+      ..LoggerStats::default()
+    };
+    let mut peak = c.retired_peak.load(Ordering::Relaxed);
+    for q in self.queues.lock().unwrap().iter().filter_map(|q| q.upgrade()) {
+      stats.queues += 1;
+      stats.occupancy += q.used_blocks() as usize * BLOCK_SIZE;
+      stats.pushed += q.pushed_count();
+      stats.dropped += q.dropped_count();
+      peak = peak.max(q.high_water_mark());
+    }
+    stats.peak_occupancy = peak as usize * BLOCK_SIZE;
+    stats
+  }
+
+  /// Staging queues still alive: one per thread that has logged and not exited yet,
+  /// plus those of exited threads the backend has not finished draining.
+  pub fn queue_count(&self) -> usize {
+    self.queues.lock().unwrap().iter().filter(|q| q.strong_count() > 0).count()
   }
 }
 
+#[inline(always)]
+fn publish_payload<P: Payload + ?Sized>(queue: &StagingBuffer, site: &'static CallSite, payload: &P, tsc: i64) -> PublishResult {
+  let prod = Producer {
+    q: queue,
+  };
+
+  match prod.try_alloc(payload.encoded_len()) {
+    Ok((hdr, dst, _payload_cap, total, _blk_sz)) => {
+      unsafe { write_record(&prod, hdr, dst, total, site, payload, tsc) };
+      PublishResult::Ok
+    }
+    Err(AllocError::Full) => PublishResult::QueueFull,
+    Err(AllocError::TooLarge) => {
+      report_too_large(site, payload.encoded_len(), queue.capacity_bytes());
+      PublishResult::PayloadTooLarge
+    }
+  }
+}
+
+static TOO_LARGE_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Once per process: which call site can never fit, so the fix (bigger queue, shorter args) is obvious.
+#[cold]
+#[inline(never)]
+fn report_too_large(site: &'static CallSite, payload_len: usize, capacity: usize) {
+  if TOO_LARGE_REPORTED.swap(true, Ordering::Relaxed) {
+    return;
+  }
+  let loc = &site.meta.loc;
+  println!(
+    "log: {}:{} \"{}\": {} byte payload + {} byte header exceeds the {} byte staging queue, never logged; raise the logger's capacity or shorten the args",
+    loc.file, loc.line, site.meta.fmt, payload_len, MSG_HEADER_SIZE, capacity,
+  );
+}
+
+#[inline(never)]
+fn publish_payload_blocking<P: Payload + ?Sized>(queue: &StagingBuffer, site: &'static CallSite, payload: &P, timeout: Duration, tsc: i64) -> PublishResult {
+  let prod = Producer {
+    q: queue,
+  };
+  let mut deadline = None;
+  let mut rounds = 0u32;
+  loop {
+    match prod.try_reserve(payload.encoded_len()) {
+      Ok((hdr, dst, _payload_cap, total, _blk_sz)) => {
+        unsafe { write_record(&prod, hdr, dst, total, site, payload, tsc) };
+        return PublishResult::Ok;
+      }
+      Err(AllocError::Full) => {
+        // clock only read once we actually have to wait
+        let deadline = *deadline.get_or_insert_with(|| tscns::read_nanos().saturating_add(timeout.as_nanos() as i64));
+        if tscns::read_nanos() >= deadline {
+          return publish_payload(queue, site, payload, tsc);
+        }
+        if rounds < 64 {
+          std::hint::spin_loop();
+        } else {
+          std::thread::yield_now();
+        }
+        rounds = rounds.saturating_add(1);
+      }
+      // counted and reported like any other drop
+      Err(AllocError::TooLarge) => return publish_payload(queue, site, payload, tsc),
+    }
+  }
+}
+
+#[inline(always)]
+unsafe fn write_record<P: Payload + ?Sized, const N: usize>(prod: &Producer<'_, N>, hdr: *mut MsgHeader, dst: *mut u8, total: u32, site: &'static CallSite, payload: &P, tsc: i64) {
+  let hdr = &mut (*hdr);
+  hdr.level = site.meta.level as u8 as u32;
+  hdr.tsc = tsc;
+
+  let out = std::slice::from_raw_parts_mut(dst, payload.encoded_len());
+  payload.encode(out);
+  hdr.meta_id = site.id() as u64;
+  prod.commit(hdr, total);
+}
+
+/// LogFn of the summary line the backend emits when it goes idle after drops; payload is `args1` of the u64 count.
+fn dropped_shim(out: &mut MyBytesMut, bytes: &[u8], loc: Option<&mut SourceLocation>) -> io::Result<()> {
+  use std::io::Write;
+  DROPPED_SITE.meta.loc.write_or_capture(out, loc);
+  let [num_dropped] = decode_args::<1>(bytes);
+  write!(out, "dropped {} log messages in last interval", num_dropped)
+}
+
+static DROPPED_SITE: CallSite = CallSite::new(Metadata {
+  fmt: "dropped {} log messages in last interval",
+  level: Level::Warn,
+  loc: SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+  num_args: 1,
+  arg_names: &[],
+  field_names: &[],
+  log_fn: dropped_shim,
+});
+
+/// Goes straight to the sink, never through a staging queue, so it cannot be dropped itself.
+/// `tid` 0: the backend, the drops may come from several threads.
+fn report_dropped<S: Sink>(sink: &mut S, num_dropped: u64) -> io::Result<()> {
+  let payload = encode_to_vec(&args1(num_dropped));
+  let hdr = MsgHeader {
+    size: (MSG_HEADER_SIZE + payload.len()) as u32,
+    level: Level::Warn as u8 as u32,
+    tsc: tscns::read_tsc(),
+    meta_id: DROPPED_SITE.id() as u64,
+  };
+  sink.on_record(0, &hdr, &payload)
+}
+
+/// Console logger, backend thread not pinned. Each thread that logs gets a staging queue of
+/// `capacity` bytes, rounded up to a power of two number of blocks (see [`LoggerHandle::with_capacity`]).
+/// With the `signal_flush` feature (unix), every `init_logger*` also installs a SIGTERM/SIGINT handler:
+/// the backends drain and flush (waiting at most 1s) before the signal's default action kills the process.
 pub fn init_logger(capacity: usize) -> LoggerHandle {
-  tscns::init(tscns::INIT_CALIBRATE_NANOS, tscns::CALIBRATE_INTERVAL_NANOS);
+  init_logger_on(capacity, None)
+}
+
+/// Console logger with the backend pinned to `core` (see [`init_logger_with`]).
+pub fn init_logger_on(capacity: usize, core: Option<usize>) -> LoggerHandle {
+  init_logger_with(capacity, core, ConsoleBatchSink::new)
+}
+
+/// Like [`init_logger`], but the backend writes through the sink built by `make_sink`.
+/// The factory runs on the consumer thread, so the sink itself does not need to be `Send`.
+/// `core` pins the backend thread; `None` or a core that does not exist leaves it unpinned.
+pub fn init_logger_with<S, F>(capacity: usize, core: Option<usize>, make_sink: F) -> LoggerHandle
+where
+  S: Sink,
+  F: FnOnce() -> S + Send + 'static,
+{
+  init_logger_with_idle(capacity, core, IdleStrategy::default(), make_sink)
+}
 
-  std::thread::spawn(move || {
-    loop {
-      tscns::calibrate();
-      // println!("calibrate");
-      std::thread::sleep(Duration::from_nanos(tscns::CALIBRATE_INTERVAL_NANOS as u64));
+/// Like [`init_logger_with`], with timestamps rendered from `time` (see `Sink::set_clock`), e.g.
+/// `TimeSource::custom(|| sim.now_ns())` so a replay logs simulated time instead of wall clock.
+pub fn init_logger_with_time<S, F>(capacity: usize, core: Option<usize>, time: TimeSource, make_sink: F) -> LoggerHandle
+where
+  S: Sink,
+  F: FnOnce() -> S + Send + 'static,
+{
+  let clock = time.clock();
+  init_logger_with(capacity, core, move || {
+    let mut sink = make_sink();
+    if let Some(clock) = clock {
+      sink.set_clock(clock);
     }
+    sink
+  })
+}
+
+/// What the backend does when every staging queue is empty.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IdleStrategy {
+  /// `spins` empty rounds of `spin_loop`, then `park_timeout(park)` until data shows up again
+  SpinThenPark { spins: u32, park: Duration },
+  /// always `park_timeout`: lowest CPU, a message may wait up to the duration
+  Park(Duration),
+  /// `yield_now` between rounds
+  Yield,
+}
+
+impl Default for IdleStrategy {
+  /// Spin ~1024 rounds, then park for 100us (same as `run_log`).
+  fn default() -> Self {
+    IdleStrategy::SpinThenPark { spins: 1024, park: Duration::from_micros(100) }
+  }
+}
+
+impl IdleStrategy {
+  /// Still busy-spinning after `empty_rounds` rounds without data.
+  #[inline(always)]
+  fn spinning(&self, empty_rounds: u32) -> bool {
+    matches!(*self, IdleStrategy::SpinThenPark { spins, .. } if empty_rounds < spins)
+  }
+
+  #[inline(always)]
+  fn idle(&self, empty_rounds: u32) {
+    match *self {
+      IdleStrategy::SpinThenPark { spins, park } => {
+        if empty_rounds < spins {
+          std::hint::spin_loop();
+        } else {
+          std::thread::park_timeout(park);
+        }
+      }
+      IdleStrategy::Park(park) => std::thread::park_timeout(park),
+      IdleStrategy::Yield => std::thread::yield_now(),
+    }
+  }
+}
+
+static CLOCK_REPORTED: Once = Once::new();
+
+// 一个进程只要一个校准线程，多个 logger 共用：第一个 backend 启动它，最后一个 shutdown 停掉并 join
+static CALIBRATION: Mutex<Calibration> = Mutex::new(Calibration {
+  users: 0,
+  thread: None,
+});
+
+struct Calibration {
+  users: usize,
+  thread: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+}
+
+fn acquire_calibration() {
+  let mut calibration = CALIBRATION.lock().unwrap();
+  calibration.users += 1;
+  if calibration.thread.is_some() {
+    return;
+  }
+  let stop = Arc::new(AtomicBool::new(false));
+  let thread = {
+    let stop = stop.clone();
+    std::thread::spawn(move || {
+      while !stop.load(Ordering::Acquire) {
+        tscns::calibrate();
+        std::thread::park_timeout(Duration::from_nanos(tscns::CALIBRATE_INTERVAL_NANOS as u64));
+      }
+    })
+  };
+  calibration.thread = Some((stop, thread));
+}
+
+/// One backend fewer; the last one stops the calibration thread and waits for it.
+fn release_calibration() {
+  let mut calibration = CALIBRATION.lock().unwrap();
+  calibration.users = calibration.users.saturating_sub(1);
+  if calibration.users > 0 {
+    return;
+  }
+  if let Some((stop, thread)) = calibration.thread.take() {
+    stop.store(true, Ordering::Release);
+    thread.thread().unpark();
+    let _ = thread.join();
+  }
+}
+
+/// [`init_logger_with`] with an explicit backend [`IdleStrategy`]; `shutdown` unparks a parked backend.
+pub fn init_logger_with_idle<S, F>(capacity: usize, core: Option<usize>, idle: IdleStrategy, make_sink: F) -> LoggerHandle
+where
+  S: Sink,
+  F: FnOnce() -> S + Send + 'static,
+{
+  tscns::init(tscns::INIT_CALIBRATE_NANOS, tscns::CALIBRATE_INTERVAL_NANOS);
+  CLOCK_REPORTED.call_once(|| {
+    println!(
+      "log: {} at {:.4} GHz (calibrated against the system clock over {}ms)",
+      tscns::COUNTER, tscns::get_tsc_ghz(), tscns::INIT_CALIBRATE_NANOS / 1_000_000,
+    );
   });
+  acquire_calibration();
 
-  let queue = Arc::new(StagingBuffer::new());
-  {
-    let queue = queue.clone();
+  let (reg_tx, reg_rx) = crossbeam_channel::unbounded();
+  let stop = Arc::new(AtomicBool::new(false));
+  let counters = Arc::new(Counters::default());
+  let flush = Arc::new(FlushReq::default());
+  #[cfg(all(unix, feature = "signal_flush"))]
+  crate::signal_flush::add_backend();
+  let backend = {
+    let stop = stop.clone();
+    let counters = counters.clone();
+    let flush = flush.clone();
     std::thread::spawn(move || {
-      let res = core_affinity::set_for_current( core_affinity::CoreId { id: 7 });
-      if let Err(e) = run(1, queue) {
+      pin_backend(core);
+      #[cfg(all(unix, feature = "signal_flush"))]
+      crate::signal_flush::backend_started();
+      if let Err(e) = run(reg_rx, &stop, &flush, &counters, idle, make_sink()) {
         println!("Run log-backend error: {:?}", e);
       }
-    });
-  }
+      #[cfg(all(unix, feature = "signal_flush"))]
+      crate::signal_flush::backend_exited();
+    })
+  };
   LoggerHandle {
-    queue,
+    id: NEXT_LOGGER_ID.fetch_add(1, Ordering::Relaxed),
+    reg_tx,
+    queues: Arc::new(Mutex::new(Vec::new())),
+    counters,
+    stop,
+    flush,
+    backend: Arc::new(Mutex::new(Some(backend))),
+    capture: None,
+    tid_source: TidSource::Os,
+    queue_capacity: capacity,
   }
 }
 
-fn run(tid: usize, queue: Arc<StagingBuffer>) -> io::Result<()> {
-  let consumer = Consumer {
-    q: queue.as_ref(),
+impl LoggerHandle {
+  /// Logger for unit tests: no backend thread, records wait in the staging queues until
+  /// [`LoggerHandle::captured_lines`] drains them into a default [`CaptureSink`].
+  /// Each thread's queue holds 64KB, drain before logging more than that.
+  pub fn in_memory() -> LoggerHandle {
+    Self::in_memory_with(CaptureSink::new())
+  }
+
+  /// [`LoggerHandle::in_memory`] with a configured `sink`, e.g. `with_header` to leave out the timestamp.
+  pub fn in_memory_with(sink: CaptureSink) -> LoggerHandle {
+    tscns::init(tscns::INIT_CALIBRATE_NANOS, tscns::CALIBRATE_INTERVAL_NANOS);
+    let (reg_tx, reg_rx) = crossbeam_channel::unbounded();
+    LoggerHandle {
+      id: NEXT_LOGGER_ID.fetch_add(1, Ordering::Relaxed),
+      reg_tx,
+      queues: Arc::new(Mutex::new(Vec::new())),
+      counters: Arc::new(Counters::default()),
+      stop: Arc::new(AtomicBool::new(false)),
+      flush: Arc::new(FlushReq::default()),
+      backend: Arc::new(Mutex::new(None)),
+      capture: Some(Arc::new(Mutex::new(Backend::new(reg_rx, sink)))),
+      tid_source: TidSource::Os,
+      queue_capacity: StagingBuffer::CAPACITY_BYTES,
+    }
+  }
+}
+
+/// Pin the calling (backend) thread to `core`; warns instead of pinning to a core that is not there.
+pub(crate) fn pin_backend(core: Option<usize>) {
+  let Some(id) = core else {
+    return;
   };
-  let mut console_sink = ConsoleBatchSink::new();
+  // ids are the cores this process may run on, not necessarily 0..n
+  let available = core_affinity::get_core_ids().unwrap_or_default();
+  if !available.iter().any(|c| c.id == id) {
+    println!("log-backend: core {} not available ({} cores), not pinning", id, available.len());
+    return;
+  }
+  if !core_affinity::set_for_current(core_affinity::CoreId { id }) {
+    println!("log-backend: failed to pin to core {}", id);
+  }
+}
 
-  let mut no_data = 0;
-  let mut num_loop = 0usize;
-  loop {
-    no_data = 1;
-    while let Some((hdr, payload, total)) = consumer.front() {
+/// SIGTERM/SIGINT seen with the `signal_flush` feature: stop like `shutdown()` would.
+#[cfg(all(unix, feature = "signal_flush"))]
+#[inline(always)]
+fn signaled() -> bool {
+  crate::signal_flush::SIGNALED.load(Ordering::Acquire)
+}
+
+#[cfg(not(all(unix, feature = "signal_flush")))]
+#[inline(always)]
+fn signaled() -> bool {
+  false
+}
+
+struct QState {
+  queue: Arc<StagingBuffer>,
+  tid: u32,
+  last_dropped: u64,
+  // the producer thread has exited: drained completely this round, then retired
+  closed: bool,
+}
+
+impl QState {
+  #[inline(always)]
+  fn front_tsc(&self) -> Option<i64> {
+    let consumer = Consumer {
+      q: self.queue.as_ref(),
+    };
+    consumer.front().map(|(hdr, _, _)| unsafe { (*hdr).tsc })
+  }
+}
+
+/// What the backend thread owns: the registered queues and the sink. One `poll` is one drain round;
+/// `run` loops it on the backend thread, an in-memory logger runs it on the caller's.
+struct Backend<S: Sink> {
+  reg_rx: Receiver<RegMsg>,
+  qs: Vec<QState>,
+  // queue fronts by tsc, (tsc, index into qs); kept here to reuse the allocation
+  heap: BinaryHeap<Reverse<(i64, usize)>>,
+  // drops seen since the last summary line, reported once the backend catches up (idle) or stops
+  pending_dropped: u64,
+  sink: S,
+}
+
+impl<S: Sink> Backend<S> {
+  fn new(reg_rx: Receiver<RegMsg>, sink: S) -> Self {
+    Self {
+      reg_rx,
+      qs: Vec::with_capacity(64),
+      heap: BinaryHeap::with_capacity(64),
+      pending_dropped: 0,
+      sink,
+    }
+  }
+
+  /// Picks up new queues and drains them, merged by tsc like the heap in `run_log`: the record with
+  /// the smallest tsc across all queues goes first. Only records stamped before the round started
+  /// are taken, so a busy producer cannot keep one round going. `true` if any record was written.
+  fn poll(&mut self, counters: &Counters) -> io::Result<bool> {
+    let sink = &mut self.sink;
+    let qs = &mut self.qs;
+    while let Ok(msg) = self.reg_rx.try_recv() {
+      if let Some(name) = &msg.name {
+        sink.on_thread_name(msg.tid as usize, name)?;
+      }
+      // a rename of a queue we already have
+      if qs.iter().any(|st| Arc::ptr_eq(&st.queue, &msg.queue)) {
+        continue;
+      }
+      qs.push(QState {
+        queue: msg.queue,
+        tid: msg.tid,
+        last_dropped: 0,
+        closed: false,
+      });
+    }
+
+    for st in qs.iter_mut() {
+      // only our Arc left: the producer thread has exited, this drain is the last one
+      st.closed = Arc::strong_count(&st.queue) == 1;
+    }
+    // pairs with the Release decrement of the producer's Arc
+    fence(Ordering::Acquire);
+    let cutoff = tscns::read_tsc();
+    let limit = |st: &QState| if st.closed { i64::MAX } else { cutoff };
+
+    let heap = &mut self.heap;
+    heap.clear();
+    for (i, st) in qs.iter().enumerate() {
+      match st.front_tsc() {
+        Some(tsc) if tsc <= limit(st) => heap.push(Reverse((tsc, i))),
+        _ => {}
+      }
+    }
+
+    let ns_per_tsc = tscns::get_ns_per_tsc();
+    let mut had_data = false;
+    while let Some(Reverse((_, i))) = heap.pop() {
+      let st = &qs[i];
+      let consumer = Consumer {
+        q: st.queue.as_ref(),
+      };
+      let Some((hdr, payload, total)) = consumer.front() else {
+        continue;
+      };
       unsafe {
         let log_header = &*hdr;
-        let log_payload = &*slice_from_raw_parts(payload, total as usize);
-        console_sink.on_record(tid, log_header, log_payload).unwrap();
+        let waited = tscns::read_tsc().saturating_sub(log_header.tsc).max(0);
+        counters.drain_latency.record((waited as f64 * ns_per_tsc) as u64);
+        // `total` counts the header too
+        let log_payload = &*slice_from_raw_parts(payload, total as usize - MSG_HEADER_SIZE);
+        // 单条坏记录（未知 meta id / LogFn 出错或 panic）只跳过并计数，backend 继续跑
+        let ok = log_header.meta_id != 0
+          && matches!(
+            panic::catch_unwind(AssertUnwindSafe(|| sink.on_record(st.tid as usize, log_header, log_payload))),
+            Ok(Ok(()))
+          );
+        if !ok {
+          counters.record_errors.fetch_add(1, Ordering::Relaxed);
+        }
       }
       consumer.pop();
-      no_data = 0;
+      had_data = true;
+      match st.front_tsc() {
+        Some(tsc) if tsc <= limit(st) => heap.push(Reverse((tsc, i))),
+        _ => {}
+      }
     }
-    num_loop += no_data;
 
-    if num_loop >= 1024 {
-      console_sink.on_idle(tscns::read_tsc()).unwrap();
+    let mut i = 0;
+    while i < qs.len() {
+      let st = &mut qs[i];
+      let dropped = st.queue.dropped_count();
+      self.pending_dropped += dropped.wrapping_sub(st.last_dropped);
+      st.last_dropped = dropped;
+
+      if st.closed {
+        counters.retired_dropped.fetch_add(dropped, Ordering::Relaxed);
+        counters.retired_pushed.fetch_add(st.queue.pushed_count(), Ordering::Relaxed);
+        counters.retired_peak.fetch_max(st.queue.high_water_mark(), Ordering::Relaxed);
+        qs.swap_remove(i);
+      } else {
+        i += 1;
+      }
     }
-    std::hint::spin_loop();
+    Ok(had_data)
   }
 
-  Ok(())
+  /// The drop summary line, if anything was dropped since the last one.
+  fn report_pending_dropped(&mut self) -> io::Result<()> {
+    if self.pending_dropped != 0 {
+      report_dropped(&mut self.sink, self.pending_dropped)?;
+      self.pending_dropped = 0;
+    }
+    Ok(())
+  }
+}
+
+fn run<S: Sink>(
+  reg_rx: Receiver<RegMsg>,
+  stop: &AtomicBool,
+  flush: &FlushReq,
+  counters: &Counters,
+  idle: IdleStrategy,
+  sink: S,
+) -> io::Result<()> {
+  let mut backend = Backend::new(reg_rx, sink);
+  // rounds in a row without any record
+  let mut empty_rounds = 0u32;
+  loop {
+    // read the flag before draining: everything published before shutdown() is seen by this drain
+    let stopping = stop.load(Ordering::Acquire) || signaled();
+    // same for a flush request: records logged before flush() are in this drain
+    let flush_req = flush.req.load(Ordering::Acquire);
+    let had_data = backend.poll(counters)?;
+    if stopping {
+      backend.report_pending_dropped()?;
+      let res = backend.sink.flush();
+      flush.ack.store(flush_req, Ordering::Release);
+      return res;
+    }
+    if flush_req != flush.ack.load(Ordering::Relaxed) {
+      backend.sink.flush()?;
+      flush.ack.store(flush_req, Ordering::Release);
+    }
+
+    if had_data {
+      empty_rounds = 0;
+      continue;
+    }
+    empty_rounds = empty_rounds.saturating_add(1);
+    // 只空转时每 1024 轮看一次，park/yield 的每轮都不便宜，直接看
+    if empty_rounds >= 1024 || !idle.spinning(empty_rounds) {
+      backend.report_pending_dropped()?;
+      // 和单条坏记录一样：计数，backend 继续跑
+      if backend.sink.on_idle(tscns::read_tsc()).is_err() {
+        counters.record_errors.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+    idle.idle(empty_rounds);
+  }
 }
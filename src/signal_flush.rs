@@ -0,0 +1,71 @@
+//! Opt-in (`signal_flush` feature): on SIGTERM/SIGINT every backend drains, flushes its sink and exits
+//! before the process dies, so the lines explaining the shutdown are not lost with the detached thread.
+//!
+//! The handler only touches atomics and async-signal-safe libc calls: it raises `SIGNALED`,
+//! waits (bounded) for the backends to report flushed, then re-raises the signal with the default action.
+
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::Once;
+
+/// Set by the handler, polled by every backend next to its own stop flag.
+pub(crate) static SIGNALED: AtomicBool = AtomicBool::new(false);
+/// Backends still running; each one decrements it after its final flush.
+static LIVE_BACKENDS: AtomicUsize = AtomicUsize::new(0);
+/// Signal being handled, a second one while waiting goes straight to the default action.
+static HANDLING: AtomicI32 = AtomicI32::new(0);
+
+/// Longest the handler waits for the backends: a backend parked with `IdleStrategy::Park(d)`
+/// only sees the flag after `d`, the handler cannot unpark it.
+const MAX_WAIT_MS: u32 = 1000;
+
+static INSTALL: Once = Once::new();
+
+/// Install the SIGTERM/SIGINT handler once per process and count one more backend to wait for;
+/// called before the backend is spawned so a signal right after init still waits for it.
+pub(crate) fn add_backend() {
+  LIVE_BACKENDS.fetch_add(1, Ordering::AcqRel);
+  INSTALL.call_once(|| unsafe {
+    let mut sa: libc::sigaction = std::mem::zeroed();
+    sa.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    libc::sigemptyset(&mut sa.sa_mask);
+    sa.sa_flags = libc::SA_RESTART;
+    for sig in [libc::SIGTERM, libc::SIGINT] {
+      if libc::sigaction(sig, &sa, std::ptr::null_mut()) != 0 {
+        println!("log-backend: failed to install handler for signal {}", sig);
+      }
+    }
+  });
+}
+
+/// Called on the backend thread before it runs: keeps the handler off it,
+/// the handler would otherwise wait on the very thread that has to flush.
+pub(crate) fn backend_started() {
+  unsafe {
+    let mut set: libc::sigset_t = std::mem::zeroed();
+    libc::sigemptyset(&mut set);
+    libc::sigaddset(&mut set, libc::SIGTERM);
+    libc::sigaddset(&mut set, libc::SIGINT);
+    libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+  }
+}
+
+/// The backend has flushed and is exiting, for whatever reason.
+pub(crate) fn backend_exited() {
+  LIVE_BACKENDS.fetch_sub(1, Ordering::AcqRel);
+}
+
+extern "C" fn on_signal(sig: libc::c_int) {
+  if HANDLING.swap(sig, Ordering::AcqRel) == 0 {
+    SIGNALED.store(true, Ordering::Release);
+    let nap = libc::timespec { tv_sec: 0, tv_nsec: 1_000_000 };
+    let mut waited = 0;
+    while LIVE_BACKENDS.load(Ordering::Acquire) > 0 && waited < MAX_WAIT_MS {
+      unsafe { libc::nanosleep(&nap, std::ptr::null_mut()) };
+      waited += 1;
+    }
+  }
+  unsafe {
+    libc::signal(sig, libc::SIG_DFL);
+    libc::raise(sig);
+  }
+}
@@ -0,0 +1,37 @@
+use std::io;
+use std::sync::Arc;
+use crate::tscns::Clock;
+
+pub use crate::spsc_var_queue_opt::MsgHeader;
+
+/// Backend output of the consumer thread.
+///
+/// `on_record` gets one message at a time (header + raw payload, still encoded),
+/// `on_idle` is called when the staging queues are empty so time-based flushes can still happen,
+/// `flush` is called once on shutdown after the last record.
+pub trait Sink {
+  fn on_record(&mut self, tid: usize, hdr: &MsgHeader, payload: &[u8]) -> io::Result<()>;
+
+  /// `true` if the sink can take an already rendered text line through `on_line`
+  /// instead of formatting the record itself (see `TeeSink`).
+  fn accepts_lines(&self) -> bool {
+    false
+  }
+
+  /// A record rendered elsewhere, `line` ends with `\n`. Only called when `accepts_lines` is `true`.
+  fn on_line(&mut self, _hdr: &MsgHeader, _line: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "sink does not take rendered lines"))
+  }
+
+  /// `tid` was named with `LoggerHandle::set_thread_name`; its records from now on should show `name`.
+  fn on_thread_name(&mut self, _tid: usize, _name: &str) -> io::Result<()> {
+    Ok(())
+  }
+
+  /// Render timestamps from `clock` from now on (`TimeSource::Custom`). Sinks that keep the raw tsc ignore it.
+  fn set_clock(&mut self, _clock: Arc<dyn Clock + Send + Sync>) {}
+
+  fn on_idle(&mut self, now_cycles: i64) -> io::Result<()>;
+
+  fn flush(&mut self) -> io::Result<()>;
+}
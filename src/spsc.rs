@@ -55,9 +55,11 @@
 //! ```
 
 use std::fmt;
-use std::mem::ManuallyDrop;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::mem::{ManuallyDrop, MaybeUninit};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::Thread;
+use std::time::Duration;
 
 use crossbeam_utils::CachePadded;
 
@@ -80,6 +82,8 @@ pub fn ring_buffer<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
   let shared = Arc::new(Shared {
     tail: CachePadded::new(AtomicUsize::new(0)),
     head: CachePadded::new(AtomicUsize::new(0)),
+    parked: CachePadded::new(AtomicBool::new(false)),
+    consumer_thread: Mutex::new(None),
     buffer,
     mask,
   });
@@ -106,10 +110,34 @@ pub fn ring_buffer<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
 struct Shared<T> {
   tail: CachePadded<AtomicUsize>,
   head: CachePadded<AtomicUsize>,
+  /// Set by a consumer blocked in `pop_wait`, cleared by whoever wakes it.
+  parked: CachePadded<AtomicBool>,
+  consumer_thread: Mutex<Option<Thread>>,
   buffer: *mut T,
   mask: usize,
 }
 
+impl<T> Shared<T> {
+  /// Head is loaded before tail so the difference never underflows;
+  /// clamped because the producer may have refilled freed slots in between.
+  #[inline]
+  fn len(&self) -> usize {
+    let head = self.head.load(Ordering::Acquire);
+    let tail = self.tail.load(Ordering::Acquire);
+    tail.wrapping_sub(head).min(self.mask + 1)
+  }
+
+  #[cold]
+  #[inline(never)]
+  fn wake_consumer(&self) {
+    if self.parked.swap(false, Ordering::AcqRel) {
+      if let Some(t) = self.consumer_thread.lock().unwrap().as_ref() {
+        t.unpark();
+      }
+    }
+  }
+}
+
 unsafe impl<T: Send> Send for Shared<T> {}
 unsafe impl<T: Send> Sync for Shared<T> {}
 
@@ -174,6 +202,10 @@ impl<T> Producer<T> {
     self.shared.tail.store(new_tail, Ordering::Relaxed);
     self.local_tail = new_tail;
 
+    if self.shared.parked.load(Ordering::Relaxed) {
+      self.shared.wake_consumer();
+    }
+
     Ok(())
   }
 
@@ -197,6 +229,10 @@ impl<T> Producer<T> {
     self.shared.tail.store(new_tail, Ordering::Relaxed);
     self.local_tail = new_tail;
 
+    if self.shared.parked.load(Ordering::Relaxed) {
+      self.shared.wake_consumer();
+    }
+
     Ok(())
   }
 
@@ -206,6 +242,27 @@ impl<T> Producer<T> {
     self.mask + 1
   }
 
+  /// Returns the number of queued values.
+  ///
+  /// This is an instantaneous estimate: the other side may push or pop
+  /// concurrently, so the value can be stale as soon as it is returned.
+  #[inline]
+  pub fn len(&self) -> usize {
+    self.shared.len()
+  }
+
+  /// Returns `true` if no values are queued (estimate, see [`len`](Self::len)).
+  #[inline]
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Returns `true` if the queue is at capacity (estimate, see [`len`](Self::len)).
+  #[inline]
+  pub fn is_full(&self) -> bool {
+    self.len() == self.capacity()
+  }
+
   /// Returns `true` if the consumer has been dropped.
   #[inline]
   pub fn is_disconnected(&self) -> bool {
@@ -240,7 +297,7 @@ impl<T> Consumer<T> {
   ///
   /// Returns `None` if the queue is empty.
   #[inline]
-  pub fn pop(&mut self) -> Option<u64> {
+  pub fn pop(&mut self) -> Option<T> {
     let head = self.local_head;
 
     if head == self.cached_tail {
@@ -252,14 +309,187 @@ impl<T> Consumer<T> {
       }
     }
 
-    // let value = unsafe { self.buffer.add(head & self.mask).read() };
+    let value = unsafe { self.buffer.add(head & self.mask).read() };
     let new_head = head.wrapping_add(1);
     std::sync::atomic::fence(Ordering::Release);
 
     self.shared.head.store(new_head, Ordering::Relaxed);
     self.local_head = new_head;
 
-    Some(0)
+    Some(value)
+  }
+
+  /// Lazily pops the values queued right now: the tail is read once, values pushed while
+  /// iterating are left for the next call, so the loop always ends. Values the iterator is not
+  /// driven to stay queued.
+  ///
+  /// ```ignore
+  /// for entry in cons.drain() {
+  ///   handle(entry);
+  /// }
+  /// ```
+  #[inline]
+  pub fn drain(&mut self) -> Drain<'_, T> {
+    self.cached_tail = self.shared.tail.load(Ordering::Relaxed);
+    std::sync::atomic::fence(Ordering::Acquire);
+    let end = self.cached_tail;
+    Drain {
+      consumer: self,
+      end,
+    }
+  }
+
+  /// Pops a value, parking the thread while the queue is empty.
+  ///
+  /// The producer unparks the consumer on its next push. The wake-up check
+  /// on the producer side is a plain load without a fence, so a wake-up can
+  /// be missed in a narrow race; the park is bounded by `PARK_TIMEOUT` to
+  /// cover that. Returns `None` once the producer is gone and the queue is
+  /// drained.
+  pub fn pop_wait(&mut self) -> Option<T> {
+    const PARK_TIMEOUT: Duration = Duration::from_millis(1);
+    loop {
+      if let Some(v) = self.pop() {
+        return Some(v);
+      }
+      if self.is_disconnected() {
+        return self.pop();
+      }
+
+      {
+        // the Consumer may have moved to another thread since the last wait
+        let mut slot = self.shared.consumer_thread.lock().unwrap();
+        let me = std::thread::current();
+        if slot.as_ref().map(|t| t.id()) != Some(me.id()) {
+          *slot = Some(me);
+        }
+      }
+      self.shared.parked.store(true, Ordering::SeqCst);
+      // re-check after announcing, a push in between may not have seen `parked`
+      if let Some(v) = self.pop() {
+        self.shared.parked.store(false, Ordering::Relaxed);
+        return Some(v);
+      }
+      std::thread::park_timeout(PARK_TIMEOUT);
+      self.shared.parked.store(false, Ordering::Relaxed);
+    }
+  }
+
+  /// Returns a reference to the value at the head without consuming it.
+  ///
+  /// Avoids copying large values out of the ring: process the value in place,
+  /// then call [`advance`](Self::advance). The borrow checker keeps the
+  /// reference from outliving the next `advance`.
+  ///
+  /// ```
+  /// let (mut prod, mut cons) = hft_log_demo::spsc_queue::spsc_queue::<[u8; 256]>(4);
+  /// assert!(prod.push([7; 256]).is_ok());
+  ///
+  /// let mut out = Vec::new();
+  /// while let Some(e) = cons.peek() {
+  ///   out.extend_from_slice(&e[..2]);
+  ///   cons.advance();
+  /// }
+  /// assert_eq!(out, [7, 7]);
+  /// ```
+  #[inline]
+  pub fn peek(&self) -> Option<&T> {
+    let head = self.local_head;
+
+    if head == self.cached_tail {
+      // &self: can't refresh the cache here, advance() does it
+      let tail = self.shared.tail.load(Ordering::Relaxed);
+      std::sync::atomic::fence(Ordering::Acquire);
+
+      if head == tail {
+        return None;
+      }
+    }
+
+    Some(unsafe { &*self.buffer.add(head & self.mask) })
+  }
+
+  /// Consumes the value last returned by [`peek`](Self::peek).
+  ///
+  /// Must only be called after `peek` returned `Some`.
+  #[inline]
+  pub fn advance(&mut self) {
+    let head = self.local_head;
+
+    if head == self.cached_tail {
+      self.cached_tail = self.shared.tail.load(Ordering::Relaxed);
+      std::sync::atomic::fence(Ordering::Acquire);
+    }
+    debug_assert!(head != self.cached_tail, "advance() on an empty queue");
+
+    unsafe { self.buffer.add(head & self.mask).drop_in_place() };
+    let new_head = head.wrapping_add(1);
+    std::sync::atomic::fence(Ordering::Release);
+
+    self.shared.head.store(new_head, Ordering::Relaxed);
+    self.local_head = new_head;
+  }
+
+  /// Pops the head value through `f` without moving it out of its slot.
+  ///
+  /// `peek` + `advance` in one call: `f` sees the value in place, then it is dropped
+  /// and the slot freed. `f` gets a reference for any lifetime, so `R` cannot borrow from it.
+  /// Returns `None` without calling `f` if the queue is empty.
+  #[inline]
+  pub fn pop_with<R>(&mut self, f: impl FnOnce(&T) -> R) -> Option<R> {
+    let head = self.local_head;
+
+    if head == self.cached_tail {
+      self.cached_tail = self.shared.tail.load(Ordering::Relaxed);
+      std::sync::atomic::fence(Ordering::Acquire);
+
+      if head == self.cached_tail {
+        return None;
+      }
+    }
+
+    let slot = unsafe { self.buffer.add(head & self.mask) };
+    // a panicking `f` leaves the value queued
+    let r = f(unsafe { &*slot });
+    unsafe { slot.drop_in_place() };
+    let new_head = head.wrapping_add(1);
+    std::sync::atomic::fence(Ordering::Release);
+
+    self.shared.head.store(new_head, Ordering::Relaxed);
+    self.local_head = new_head;
+
+    Some(r)
+  }
+
+  /// Pops up to `out.len()` values into `out`, returning how many were written.
+  ///
+  /// The tail is refreshed at most once and the head is published with a
+  /// single release store, however many values are taken.
+  #[inline]
+  pub fn pop_bulk(&mut self, out: &mut [MaybeUninit<T>]) -> usize {
+    let head = self.local_head;
+
+    if self.cached_tail.wrapping_sub(head) < out.len() {
+      self.cached_tail = self.shared.tail.load(Ordering::Relaxed);
+      std::sync::atomic::fence(Ordering::Acquire);
+    }
+
+    let n = self.cached_tail.wrapping_sub(head).min(out.len());
+    if n == 0 {
+      return 0;
+    }
+
+    for (i, slot) in out[..n].iter_mut().enumerate() {
+      slot.write(unsafe { self.buffer.add(head.wrapping_add(i) & self.mask).read() });
+    }
+
+    let new_head = head.wrapping_add(n);
+    std::sync::atomic::fence(Ordering::Release);
+
+    self.shared.head.store(new_head, Ordering::Relaxed);
+    self.local_head = new_head;
+
+    n
   }
 
   /// Returns the capacity of the queue.
@@ -268,6 +498,27 @@ impl<T> Consumer<T> {
     self.mask + 1
   }
 
+  /// Returns the number of queued values.
+  ///
+  /// This is an instantaneous estimate: the other side may push or pop
+  /// concurrently, so the value can be stale as soon as it is returned.
+  #[inline]
+  pub fn len(&self) -> usize {
+    self.shared.len()
+  }
+
+  /// Returns `true` if no values are queued (estimate, see [`len`](Self::len)).
+  #[inline]
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Returns `true` if the queue is at capacity (estimate, see [`len`](Self::len)).
+  #[inline]
+  pub fn is_full(&self) -> bool {
+    self.len() == self.capacity()
+  }
+
   /// Returns `true` if the producer has been dropped.
   #[inline]
   pub fn is_disconnected(&self) -> bool {
@@ -275,6 +526,42 @@ impl<T> Consumer<T> {
   }
 }
 
+/// Pops until the queue is empty; a later push makes it yield again.
+impl<T> Iterator for Consumer<T> {
+  type Item = T;
+
+  #[inline]
+  fn next(&mut self) -> Option<T> {
+    self.pop()
+  }
+}
+
+/// Iterator returned by [`Consumer::drain`].
+pub struct Drain<'a, T> {
+  consumer: &'a mut Consumer<T>,
+  end: usize,
+}
+
+impl<T> Iterator for Drain<'_, T> {
+  type Item = T;
+
+  #[inline]
+  fn next(&mut self) -> Option<T> {
+    if self.consumer.local_head == self.end {
+      return None;
+    }
+    self.consumer.pop()
+  }
+
+  #[inline]
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let n = self.end.wrapping_sub(self.consumer.local_head);
+    (n, Some(n))
+  }
+}
+
+impl<T> ExactSizeIterator for Drain<'_, T> {}
+
 impl<T> fmt::Debug for Consumer<T> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     f.debug_struct("Consumer")
@@ -282,3 +569,207 @@ impl<T> fmt::Debug for Consumer<T> {
       .finish_non_exhaustive()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn taken(out: &[MaybeUninit<u64>], n: usize) -> Vec<u64> {
+    out[..n].iter().map(|v| unsafe { v.assume_init() }).collect()
+  }
+
+  #[test]
+  fn pop_bulk_takes_what_is_there() {
+    let (mut prod, mut cons) = ring_buffer::<u64>(8);
+    let mut out = [MaybeUninit::uninit(); 8];
+    assert_eq!(cons.pop_bulk(&mut out), 0);
+
+    for v in 0..3 {
+      prod.push(v).unwrap();
+    }
+    let n = cons.pop_bulk(&mut out);
+    assert_eq!(taken(&out, n), [0, 1, 2]);
+
+    // out 比队列里的少：只取 out.len() 个，剩下的留给下一次
+    for v in 3..8 {
+      prod.push(v).unwrap();
+    }
+    let mut small = [MaybeUninit::uninit(); 2];
+    let n = cons.pop_bulk(&mut small);
+    assert_eq!(taken(&small, n), [3, 4]);
+    let n = cons.pop_bulk(&mut out);
+    assert_eq!(taken(&out, n), [5, 6, 7]);
+  }
+
+  #[test]
+  fn pop_bulk_across_the_wrap() {
+    let (mut prod, mut cons) = ring_buffer::<u64>(4);
+    let mut out = [MaybeUninit::uninit(); 4];
+    for round in 0..10u64 {
+      // 每轮错开一个位置，让读区间跨过 buffer 末尾
+      for v in 0..3 {
+        prod.push(round * 10 + v).unwrap();
+      }
+      let n = cons.pop_bulk(&mut out);
+      assert_eq!(taken(&out, n), [round * 10, round * 10 + 1, round * 10 + 2]);
+    }
+    for v in 0..4 {
+      prod.push(v).unwrap();
+    }
+    assert!(prod.push(4).is_err());
+    let n = cons.pop_bulk(&mut out);
+    assert_eq!(taken(&out, n), [0, 1, 2, 3]);
+  }
+
+  #[test]
+  fn len_tracks_pushes_and_pops() {
+    let (mut prod, mut cons) = ring_buffer::<u64>(8);
+    assert!(prod.is_empty() && cons.is_empty());
+
+    const K: usize = 5;
+    for v in 0..K as u64 {
+      prod.push(v).unwrap();
+    }
+    assert_eq!(prod.len(), K);
+    assert_eq!(cons.len(), K);
+    assert!(!prod.is_full() && !cons.is_empty());
+
+    for v in K as u64..8 {
+      prod.push(v).unwrap();
+    }
+    assert!(prod.is_full() && cons.is_full());
+    assert_eq!(cons.len(), 8);
+
+    cons.pop().unwrap();
+    assert_eq!(prod.len(), 7);
+    assert!(!prod.is_full());
+  }
+
+  #[test]
+  fn peek_leaves_the_value_until_advance() {
+    let (mut prod, mut cons) = ring_buffer::<u64>(4);
+    assert!(cons.peek().is_none());
+    prod.push(1).unwrap();
+    prod.push(2).unwrap();
+
+    assert_eq!(cons.peek(), Some(&1));
+    assert_eq!(cons.peek(), Some(&1));
+    assert_eq!(prod.len(), 2);
+    cons.advance();
+    assert_eq!(prod.len(), 1);
+    assert_eq!(cons.peek(), Some(&2));
+    cons.advance();
+    assert!(cons.peek().is_none());
+
+    // 跨过环尾之后 peek 仍然指向正确的 slot
+    for i in 10..16 {
+      prod.push(i).unwrap();
+      assert_eq!(cons.peek(), Some(&i));
+      cons.advance();
+    }
+    assert!(cons.is_empty());
+  }
+
+  #[test]
+  fn advance_drops_the_value_in_place() {
+    let tracker = std::rc::Rc::new(());
+    let (mut prod, mut cons) = ring_buffer::<std::rc::Rc<()>>(2);
+    prod.push(tracker.clone()).unwrap();
+    assert_eq!(std::rc::Rc::strong_count(&tracker), 2);
+    assert!(cons.peek().is_some());
+    cons.advance();
+    assert_eq!(std::rc::Rc::strong_count(&tracker), 1);
+  }
+
+  #[test]
+  fn pop_wait_wakes_on_push() {
+    use std::time::Instant;
+
+    let (mut prod, mut cons) = ring_buffer::<Instant>(4);
+    let waiter = std::thread::spawn(move || {
+      let sent = cons.pop_wait().unwrap();
+      let latency = sent.elapsed();
+      // 生产者 drop 之后 pop_wait 返回 None，而不是一直睡
+      assert!(cons.pop_wait().is_none());
+      latency
+    });
+
+    // 让消费者先进入 park
+    std::thread::sleep(Duration::from_millis(50));
+    prod.push(Instant::now()).unwrap();
+    drop(prod);
+
+    let latency = waiter.join().unwrap();
+    assert!(latency < Duration::from_millis(100), "woke after {:?}", latency);
+  }
+
+  #[test]
+  fn iterator_yields_pushes_in_order_across_the_wrap() {
+    let (mut prod, mut cons) = ring_buffer::<u64>(8);
+    for round in 0..3u64 {
+      for v in 0..6 {
+        prod.push(round * 10 + v).unwrap();
+      }
+      let got: Vec<u64> = cons.by_ref().collect();
+      assert_eq!(got, (0..6).map(|v| round * 10 + v).collect::<Vec<_>>());
+    }
+    assert_eq!(cons.next(), None);
+    // 空了以后再 push，迭代器还能接着出值
+    prod.push(99).unwrap();
+    assert_eq!(cons.next(), Some(99));
+  }
+
+  #[test]
+  fn drain_stops_at_the_tail_it_started_with() {
+    let (mut prod, mut cons) = ring_buffer::<u64>(16);
+    for v in 0..5 {
+      prod.push(v).unwrap();
+    }
+    let mut drain = cons.drain();
+    assert_eq!(drain.len(), 5);
+    assert_eq!(drain.next(), Some(0));
+    // drain 开始之后 push 的值留给下一次
+    prod.push(100).unwrap();
+    assert_eq!(drain.collect::<Vec<_>>(), [1, 2, 3, 4]);
+    assert_eq!(cons.drain().collect::<Vec<_>>(), [100]);
+    assert_eq!(cons.drain().len(), 0);
+
+    // 没取完的 drain 不会丢值
+    for v in 0..3 {
+      prod.push(v).unwrap();
+    }
+    assert_eq!(cons.drain().take(1).collect::<Vec<_>>(), [0]);
+    assert_eq!(cons.drain().collect::<Vec<_>>(), [1, 2]);
+  }
+
+  #[test]
+  fn pop_with_reads_in_place_and_advances_once() {
+    use std::rc::Rc;
+
+    struct Entry {
+      seq: u64,
+      _drops: Rc<()>,
+    }
+
+    let drops = Rc::new(());
+    let (mut prod, mut cons) = ring_buffer::<Entry>(4);
+    assert_eq!(cons.pop_with(|_| unreachable!("empty queue")), None::<()>);
+
+    for seq in 0..3 {
+      assert!(prod.push(Entry { seq, _drops: drops.clone() }).is_ok());
+    }
+    assert_eq!(Rc::strong_count(&drops), 4);
+
+    assert_eq!(cons.pop_with(|e| e.seq), Some(0));
+    // head 只前进一格，值在槽里被 drop 掉一次
+    assert_eq!(cons.local_head, 1);
+    assert_eq!(cons.shared.head.load(Ordering::Relaxed), 1);
+    assert_eq!(cons.len(), 2);
+    assert_eq!(Rc::strong_count(&drops), 3);
+
+    assert_eq!(cons.pop().map(|e| e.seq), Some(1));
+    assert_eq!(cons.pop_with(|e| e.seq * 10), Some(20));
+    assert_eq!(cons.pop_with(|e| e.seq), None);
+    assert_eq!(Rc::strong_count(&drops), 1);
+  }
+}
@@ -1,8 +1,11 @@
 use core::cell::UnsafeCell;
 use core::mem::{align_of, size_of};
-use core::ptr;
-use core::sync::atomic::{AtomicU32, Ordering, compiler_fence};
+use core::ptr::{self, NonNull};
+use std::alloc::{self, Layout};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering, compiler_fence};
+use crossbeam_utils::CachePadded;
 
+/// Default block size: one x86 cache line.
 pub const BLOCK_SIZE: usize = 64;
 
 #[repr(C)]
@@ -12,15 +15,31 @@ pub struct MsgHeader {
   pub size: u32,
   pub level: u32,
   pub tsc: i64,
-  pub log_func: u64,
+  /// id of the call site's `metadata::Metadata`
+  pub meta_id: u64,
 }
 pub const MSG_HEADER_SIZE: usize = size_of::<MsgHeader>();
 
+/// A message's first block starts with its `MsgHeader`, the rest is payload.
+/// align(64) keeps blocks on cache lines; the ring is allocated `BLK_SIZE`-aligned for 128-byte blocks.
 #[repr(C, align(64))]
 #[derive(Copy, Clone)]
-struct Block {
-  header: MsgHeader,
-  bytes: [u8; BLOCK_SIZE - MSG_HEADER_SIZE],
+struct Block<const BLK_SIZE: usize>([u8; BLK_SIZE]);
+
+impl<const BLK_SIZE: usize> Block<BLK_SIZE> {
+  #[inline(always)]
+  fn header(blk: *mut Self) -> *mut MsgHeader {
+    blk as *mut MsgHeader
+  }
+}
+
+/// Why `try_alloc` returned no slot; both count in `dropped_count`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AllocError {
+  /// not enough free blocks right now
+  Full,
+  /// header + payload is more than the whole ring, never fits
+  TooLarge,
 }
 
 #[inline(always)]
@@ -29,106 +48,299 @@ const fn is_pow2(x: usize) -> bool { x != 0 && (x & (x - 1)) == 0 }
 #[inline(always)]
 fn div_ceil(a: usize, b: usize) -> usize { (a + b - 1) / b }
 
-pub struct SpscVarQueueOpt<const BLK_CNT: usize> {
-  blk: UnsafeCell<[Block; BLK_CNT]>,
+/// `BLK_CNT` for a ring of at least `bytes` in blocks of `blk_size`, rounded up to a power of two:
+/// `SpscVarQueueOpt<{ blocks_for(100_000, BLOCK_SIZE) }>` is a 2048-block (128KB) ring.
+pub const fn blocks_for(bytes: usize, blk_size: usize) -> usize {
+  let blocks = if bytes == 0 { 1 } else { bytes.div_ceil(blk_size) };
+  blocks.next_power_of_two()
+}
+
+/// `BLK_CNT`: blocks in the ring of `new` / `from_buffer`, a power of two (checked at compile time,
+/// see [`blocks_for`]); `with_capacity` sizes the ring at runtime instead.
+/// With 1 block every message is header + at most `BLK_SIZE - MSG_HEADER_SIZE` payload bytes.
+/// `BLK_SIZE`: bytes per block, a power of two >= 64; 128 for M-series cache lines
+/// or mostly large payloads, at the cost of more padding for small ones.
+///
+/// A bad parameter is a compile error naming it, not a panic at `new`:
+/// ```compile_fail
+/// // BLK_CNT must be a power of two, round it up with `blocks_for`
+/// let _ = hft_log_demo::SpscVarQueueOpt::<1000>::new();
+/// ```
+/// ```compile_fail
+/// // BLK_SIZE must be a power of two
+/// let _ = hft_log_demo::SpscVarQueueOpt::<8, 96>::new();
+/// ```
+/// ```
+/// use hft_log_demo::{blocks_for, SpscVarQueueOpt};
+/// let _ = SpscVarQueueOpt::<{ blocks_for(1000 * 64, 64) }>::new();
+/// ```
+#[repr(C, align(128))]
+pub struct SpscVarQueueOpt<const BLK_CNT: usize, const BLK_SIZE: usize = BLOCK_SIZE> {
+  // blk_cnt blocks, ours (freed on drop) unless the ring was built with `from_buffer`
+  blk: NonNull<Block<BLK_SIZE>>,
+  // power of two, BLK_CNT unless built `with_capacity`
+  blk_cnt: u32,
+  owned: bool,
+
+  // producer and consumer indices on separate cache lines, like head/tail in `spsc.rs`
+  prod: CachePadded<ProducerSide>,
+
+  // consumer-owned (producer reads)
+  read_idx: CachePadded<AtomicU32>,
+}
 
+/// Everything the producer writes, on its own cache line(s).
+struct ProducerSide {
   // producer-owned (consumer reads)
   writing_idx: AtomicU32,
   written_idx: AtomicU32,
 
-  // consumer-owned (producer reads)
-  read_idx: AtomicU32,
-
   // producer cache
   read_idx_cache: UnsafeCell<u32>,
-}
 
-unsafe impl<const BLK_CNT: usize> Sync for SpscVarQueueOpt<BLK_CNT> {}
+  // messages rejected by try_alloc because the ring was full
+  dropped: AtomicU64,
+
+  // messages committed
+  pushed: AtomicU64,
 
-impl<const BLK_CNT: usize> SpscVarQueueOpt<BLK_CNT> {
+  // max blocks in use seen by try_alloc
+  high_water_mark: AtomicU32,
+}
+
+unsafe impl<const BLK_CNT: usize, const BLK_SIZE: usize> Send for SpscVarQueueOpt<BLK_CNT, BLK_SIZE> {}
+unsafe impl<const BLK_CNT: usize, const BLK_SIZE: usize> Sync for SpscVarQueueOpt<BLK_CNT, BLK_SIZE> {}
+
+impl<const BLK_CNT: usize, const BLK_SIZE: usize> SpscVarQueueOpt<BLK_CNT, BLK_SIZE> {
+  // 编译期检查，错误信息里会带上出问题的 SpscVarQueueOpt<BLK_CNT, BLK_SIZE>
+  const PARAMS_OK: () = {
+    assert!(is_pow2(BLK_CNT), "SpscVarQueueOpt: BLK_CNT must be a power of two, round it up with `blocks_for`");
+    // indices are free-running u32 compared by wrapping difference
+    assert!(BLK_CNT <= 1 << 30, "SpscVarQueueOpt: BLK_CNT must be at most 2^30");
+    assert!(is_pow2(BLK_SIZE), "SpscVarQueueOpt: BLK_SIZE must be a power of two");
+    assert!(size_of::<Block<BLK_SIZE>>() == BLK_SIZE, "SpscVarQueueOpt: BLK_SIZE must be at least 64");
+    assert!(BLK_SIZE.is_multiple_of(align_of::<MsgHeader>()));
+    assert!(MSG_HEADER_SIZE <= BLK_SIZE);
+  };
+
+  /// Ring of `BLK_CNT` blocks on the global allocator, zeroed.
   pub fn new() -> Self {
-    assert!(is_pow2(BLK_CNT), "BLK_CNT must be power of two");
-    assert!(BLOCK_SIZE % align_of::<MsgHeader>() == 0);
-    assert!(MSG_HEADER_SIZE <= BLOCK_SIZE);
+    Self::alloc(BLK_CNT)
+  }
 
-    let zero_block = Block {
-      header: MsgHeader { size: 0, level: 0, tsc: 0, log_func: 0 },
-      bytes: [0u8; BLOCK_SIZE - MSG_HEADER_SIZE],
+  /// Ring of at least `bytes` (rounded up to a power of two number of blocks) on the global allocator,
+  /// for queues sized at runtime; `BLK_CNT` is ignored. Panics if that is more than 2^30 blocks.
+  pub fn with_capacity(bytes: usize) -> Self {
+    let blk_cnt = blocks_for(bytes, BLK_SIZE);
+    assert!(blk_cnt <= 1 << 30, "SpscVarQueueOpt::with_capacity: more than 2^30 blocks");
+    Self::alloc(blk_cnt)
+  }
+
+  fn alloc(blk_cnt: usize) -> Self {
+    let () = Self::PARAMS_OK;
+
+    let layout = Self::layout(blk_cnt);
+    // straight to the heap: `[Block; BLK_CNT]` by value would pass through the stack
+    let blk = unsafe { alloc::alloc_zeroed(layout) } as *mut Block<BLK_SIZE>;
+    let Some(blk) = NonNull::new(blk) else {
+      alloc::handle_alloc_error(layout);
     };
+    Self::with_blocks(blk, blk_cnt, true)
+  }
+
+  /// Ring over `len` bytes at `buf` instead of its own allocation, e.g. a huge-page `mmap` bound to
+  /// the NUMA node of the backend, or memory the caller pre-faulted. The bytes do not need to be zeroed
+  /// and are never freed by the queue.
+  ///
+  /// Panics if `buf` is not `BLK_SIZE`-aligned or `len < CAPACITY_BYTES`.
+  ///
+  /// # Safety
+  /// `buf..buf + len` is valid for reads and writes, and used by nothing but this queue, until it is dropped.
+  pub unsafe fn from_buffer(buf: NonNull<u8>, len: usize) -> Self {
+    let () = Self::PARAMS_OK;
+
+    assert!((buf.as_ptr() as usize).is_multiple_of(BLK_SIZE), "SpscVarQueueOpt::from_buffer: buffer must be BLK_SIZE-aligned");
+    assert!(len >= Self::CAPACITY_BYTES, "SpscVarQueueOpt::from_buffer: buffer smaller than CAPACITY_BYTES");
+    Self::with_blocks(buf.cast(), BLK_CNT, false)
+  }
+
+  #[inline]
+  fn layout(blk_cnt: usize) -> Layout {
+    Layout::from_size_align(blk_cnt * BLK_SIZE, BLK_SIZE).unwrap()
+  }
 
+  fn with_blocks(blk: NonNull<Block<BLK_SIZE>>, blk_cnt: usize, owned: bool) -> Self {
     Self {
-      blk: UnsafeCell::new([zero_block; BLK_CNT]),
-      writing_idx: AtomicU32::new(0),
-      written_idx: AtomicU32::new(0),
-      read_idx: AtomicU32::new(0),
-      read_idx_cache: UnsafeCell::new(0),
+      blk,
+      blk_cnt: blk_cnt as u32,
+      owned,
+      prod: CachePadded::new(ProducerSide {
+        writing_idx: AtomicU32::new(0),
+        written_idx: AtomicU32::new(0),
+        read_idx_cache: UnsafeCell::new(0),
+        dropped: AtomicU64::new(0),
+        pushed: AtomicU64::new(0),
+        high_water_mark: AtomicU32::new(0),
+      }),
+      read_idx: CachePadded::new(AtomicU32::new(0)),
     }
   }
 
+  /// Bytes in the ring of `new`: the largest header + payload a single message can have there.
+  pub const CAPACITY_BYTES: usize = BLK_CNT * BLK_SIZE;
+
+  /// Bytes in this ring, `CAPACITY_BYTES` unless it was built `with_capacity`.
+  #[inline]
+  pub fn capacity_bytes(&self) -> usize {
+    self.blk_cnt as usize * BLK_SIZE
+  }
+
   #[inline(always)]
-  fn mask() -> u32 { (BLK_CNT as u32) - 1 }
+  fn mask(&self) -> u32 { self.blk_cnt - 1 }
 
   #[inline(always)]
-  fn blk_ptr(&self) -> *mut Block {
-    unsafe { (*self.blk.get()).as_mut_ptr() }
+  fn blk_ptr(&self) -> *mut Block<BLK_SIZE> {
+    self.blk.as_ptr()
+  }
+
+  /// Number of messages dropped so far because the queue was full.
+  #[inline]
+  pub fn dropped_count(&self) -> u64 {
+    self.prod.dropped.load(Ordering::Relaxed)
+  }
+
+  /// Number of messages committed so far, published or about to be (`commit_deferred`).
+  #[inline]
+  pub fn pushed_count(&self) -> u64 {
+    self.prod.pushed.load(Ordering::Relaxed)
   }
 
-  pub fn split(&self) -> (Producer<'_, BLK_CNT>, Consumer<'_, BLK_CNT>) {
+  /// Blocks currently reserved or published and not yet popped, rewind padding included.
+  /// Instantaneous estimate under concurrency.
+  #[inline]
+  pub fn used_blocks(&self) -> u32 {
+    let r = self.read_idx.load(Ordering::Acquire);
+    let w = self.prod.writing_idx.load(Ordering::Acquire);
+    w.wrapping_sub(r).min(self.blk_cnt)
+  }
+
+  #[inline]
+  pub fn free_blocks(&self) -> u32 {
+    self.blk_cnt - self.used_blocks()
+  }
+
+  /// Largest `used_blocks` observed right after an allocation.
+  /// Computed against the producer's cached read index, so it can overstate the true peak slightly.
+  #[inline]
+  pub fn high_water_mark(&self) -> u32 {
+    self.prod.high_water_mark.load(Ordering::Relaxed)
+  }
+
+  pub fn split(&self) -> (Producer<'_, BLK_CNT, BLK_SIZE>, Consumer<'_, BLK_CNT, BLK_SIZE>) {
     (Producer { q: self }, Consumer { q: self })
   }
 }
 
+impl<const BLK_CNT: usize, const BLK_SIZE: usize> Default for SpscVarQueueOpt<BLK_CNT, BLK_SIZE> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<const BLK_CNT: usize, const BLK_SIZE: usize> Drop for SpscVarQueueOpt<BLK_CNT, BLK_SIZE> {
+  fn drop(&mut self) {
+    if self.owned {
+      unsafe { alloc::dealloc(self.blk.as_ptr() as *mut u8, Self::layout(self.blk_cnt as usize)) };
+    }
+  }
+}
+
 /// Producer handle (single thread)
-pub struct Producer<'a, const BLK_CNT: usize> { pub q: &'a SpscVarQueueOpt<BLK_CNT> }
+pub struct Producer<'a, const BLK_CNT: usize, const BLK_SIZE: usize = BLOCK_SIZE> { pub q: &'a SpscVarQueueOpt<BLK_CNT, BLK_SIZE> }
 
 /// Consumer handle (single thread)
-pub struct Consumer<'a, const BLK_CNT: usize> { pub q: &'a SpscVarQueueOpt<BLK_CNT> }
+pub struct Consumer<'a, const BLK_CNT: usize, const BLK_SIZE: usize = BLOCK_SIZE> { pub q: &'a SpscVarQueueOpt<BLK_CNT, BLK_SIZE> }
+
+impl<'a, const BLK_CNT: usize, const BLK_SIZE: usize> Producer<'a, BLK_CNT, BLK_SIZE> {
+  #[inline]
+  pub fn used_blocks(&self) -> u32 { self.q.used_blocks() }
+
+  #[inline]
+  pub fn free_blocks(&self) -> u32 { self.q.free_blocks() }
+
+  #[inline]
+  pub fn high_water_mark(&self) -> u32 { self.q.high_water_mark() }
 
-impl<'a, const BLK_CNT: usize> Producer<'a, BLK_CNT> {
   /// Allocate payload_len bytes (excluding header).
   /// Returns (hdr_ptr, payload_ptr, payload_cap_bytes, total_bytes, blk_sz)
+  /// or why it cannot: `Full` may succeed later, `TooLarge` never does
   ///
-  /// payload_cap_bytes == blk_sz*BLOCK_SIZE - MSG_HEADER_SIZE  (enough to write payload_len)
+  /// payload_cap_bytes == blk_sz*BLK_SIZE - MSG_HEADER_SIZE  (enough to write payload_len)
   #[inline(always)]
   pub fn try_alloc(&self, payload_len: usize)
-                   -> Option<(*mut MsgHeader, *mut u8, usize, u32, u32)>
+                   -> Result<(*mut MsgHeader, *mut u8, usize, u32, u32), AllocError>
+  {
+    let r = self.try_reserve(payload_len);
+    if r.is_err() {
+      self.q.prod.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+    r
+  }
+
+  /// `try_alloc` without counting a failure as dropped, for callers that retry.
+  #[inline(always)]
+  pub fn try_reserve(&self, payload_len: usize)
+                   -> Result<(*mut MsgHeader, *mut u8, usize, u32, u32), AllocError>
   {
-    let total_bytes = payload_len.checked_add(MSG_HEADER_SIZE)?;
-    let blk_sz = div_ceil(total_bytes, BLOCK_SIZE) as u32;
+    let total_bytes = match payload_len.checked_add(MSG_HEADER_SIZE) {
+      // blk_sz > blk_cnt: min_read_idx would be past any read index, this can never fit
+      Some(total) if total <= self.q.capacity_bytes() => total,
+      _ => return Err(AllocError::TooLarge),
+    };
+    let blk_sz = div_ceil(total_bytes, BLK_SIZE) as u32;
 
-    let mut write_idx = self.q.writing_idx.load(Ordering::Relaxed);
+    let blk_cnt = self.q.blk_cnt;
+    let mut write_idx = self.q.prod.writing_idx.load(Ordering::Relaxed);
 
     // blocks remaining to ring end
-    let pad = (BLK_CNT as u32) - (write_idx & SpscVarQueueOpt::<BLK_CNT>::mask());
-    let rewind = blk_sz > pad;
+    let pad = blk_cnt - (write_idx & self.q.mask());
+    let mut rewind = blk_sz > pad;
+    if rewind && blk_sz + pad > blk_cnt {
+      // 消息 + 填充比整个 ring 还大：从 ring 头开始的消息会盖住 rewind marker 本身，
+      // 先单独发布 marker，consumer 跳过它之后消息才放得下
+      if !self.has_room(write_idx.wrapping_add(1).wrapping_sub(blk_cnt)) {
+        return Err(AllocError::Full);
+      }
+      let cur = unsafe { self.q.blk_ptr().add((write_idx & self.q.mask()) as usize) };
+      unsafe { ptr::write_volatile(&mut (*Block::header(cur)).size, 0) };
+      write_idx = write_idx.wrapping_add(pad);
+      self.q.prod.writing_idx.store(write_idx, Ordering::Relaxed);
+      // also publishes anything `commit_deferred` left pending, all of it is complete
+      self.q.prod.written_idx.store(write_idx, Ordering::Release);
+      rewind = false;
+    }
     let needed = blk_sz + if rewind { pad } else { 0 };
 
-    // need read_idx <= write_idx + needed - BLK_CNT
-    let min_read_idx = write_idx.wrapping_add(needed).wrapping_sub(BLK_CNT as u32);
-
-    let ric = unsafe { &mut *self.q.read_idx_cache.get() };
-    if (*ric as i32) < (min_read_idx as i32) {
-      let fresh = self.q.read_idx.load(Ordering::Acquire);
-      *ric = fresh;
-      if (fresh as i32) < (min_read_idx as i32) {
-        return None;
-      }
+    // need read_idx <= write_idx + needed - blk_cnt
+    let min_read_idx = write_idx.wrapping_add(needed).wrapping_sub(blk_cnt);
+    if !self.has_room(min_read_idx) {
+      return Err(AllocError::Full);
     }
 
     let blk = self.q.blk_ptr();
 
     if rewind {
       // write rewind marker at current block
-      let cur = unsafe { blk.add((write_idx & SpscVarQueueOpt::<BLK_CNT>::mask()) as usize) };
-      unsafe { ptr::write_volatile(&mut (*cur).header.size, 0) };
+      let cur = unsafe { blk.add((write_idx & self.q.mask()) as usize) };
+      unsafe { ptr::write_volatile(&mut (*Block::header(cur)).size, 0) };
       compiler_fence(Ordering::Release);
 
       write_idx = write_idx.wrapping_add(pad);
-      self.q.writing_idx.store(write_idx, Ordering::Relaxed);
+      self.q.prod.writing_idx.store(write_idx, Ordering::Relaxed);
     }
 
-    let cur = unsafe { blk.add((write_idx & SpscVarQueueOpt::<BLK_CNT>::mask()) as usize) };
-    let hdr_ptr = unsafe { &mut (*cur).header as *mut MsgHeader };
+    let cur = unsafe { blk.add((write_idx & self.q.mask()) as usize) };
+    let hdr_ptr = Block::header(cur);
 
     // contiguous region start pointer at header (first block)
     let base_ptr = hdr_ptr as *mut u8;
@@ -136,10 +348,30 @@ impl<'a, const BLK_CNT: usize> Producer<'a, BLK_CNT> {
 
     // reserve blocks (not published)
     let new_write = write_idx.wrapping_add(blk_sz);
-    self.q.writing_idx.store(new_write, Ordering::Relaxed);
+    self.q.prod.writing_idx.store(new_write, Ordering::Relaxed);
 
-    let payload_cap = (blk_sz as usize) * BLOCK_SIZE - MSG_HEADER_SIZE;
-    Some((hdr_ptr, payload_ptr, payload_cap, total_bytes as u32, blk_sz))
+    // single writer: plain load/store is enough
+    let used = new_write.wrapping_sub(unsafe { *self.q.prod.read_idx_cache.get() });
+    if used > self.q.prod.high_water_mark.load(Ordering::Relaxed) {
+      self.q.prod.high_water_mark.store(used, Ordering::Relaxed);
+    }
+
+    let payload_cap = (blk_sz as usize) * BLK_SIZE - MSG_HEADER_SIZE;
+    Ok((hdr_ptr, payload_ptr, payload_cap, total_bytes as u32, blk_sz))
+  }
+
+  /// The consumer is at or past `min_read_idx`, checked against the cached read index first.
+  /// Indices are free-running u32, compared by their wrapping difference.
+  #[inline(always)]
+  fn has_room(&self, min_read_idx: u32) -> bool {
+    let ric = unsafe { &mut *self.q.prod.read_idx_cache.get() };
+    if (ric.wrapping_sub(min_read_idx) as i32) < 0 {
+      *ric = self.q.read_idx.load(Ordering::Acquire);
+      if (ric.wrapping_sub(min_read_idx) as i32) < 0 {
+        return false;
+      }
+    }
+    true
   }
 
   /// Publish after writing header fields (except size) + payload.
@@ -148,35 +380,68 @@ impl<'a, const BLK_CNT: usize> Producer<'a, BLK_CNT> {
     // publish size last
     ptr::write_volatile(&mut (*hdr).size, total_bytes_including_header);
     compiler_fence(Ordering::Release);
+    self.count_pushed();
 
-    let w = self.q.writing_idx.load(Ordering::Relaxed);
-    self.q.written_idx.store(w, Ordering::Release);
+    let w = self.q.prod.writing_idx.load(Ordering::Relaxed);
+    self.q.prod.written_idx.store(w, Ordering::Release);
+  }
+
+  /// `commit` without publishing: the message stays invisible to the consumer until
+  /// `flush_published`, so a burst of N messages costs one Release store instead of N.
+  /// Deferred messages still hold their blocks; a `try_alloc` that fails in between does not publish them.
+  #[inline(always)]
+  pub unsafe fn commit_deferred(&self, hdr: *mut MsgHeader, total_bytes_including_header: u32) {
+    ptr::write_volatile(&mut (*hdr).size, total_bytes_including_header);
+    self.count_pushed();
+  }
+
+  // single writer: a plain load + store, no locked RMW on the hot path
+  #[inline(always)]
+  fn count_pushed(&self) {
+    let n = self.q.prod.pushed.load(Ordering::Relaxed);
+    self.q.prod.pushed.store(n + 1, Ordering::Relaxed);
+  }
+
+  /// Publish everything committed so far (deferred or not) with a single `written_idx` store.
+  #[inline(always)]
+  pub fn flush_published(&self) {
+    let w = self.q.prod.writing_idx.load(Ordering::Relaxed);
+    self.q.prod.written_idx.store(w, Ordering::Release);
   }
 }
 
-impl<'a, const BLK_CNT: usize> Consumer<'a, BLK_CNT> {
+impl<'a, const BLK_CNT: usize, const BLK_SIZE: usize> Consumer<'a, BLK_CNT, BLK_SIZE> {
+  #[inline]
+  pub fn used_blocks(&self) -> u32 { self.q.used_blocks() }
+
+  #[inline]
+  pub fn free_blocks(&self) -> u32 { self.q.free_blocks() }
+
+  #[inline]
+  pub fn high_water_mark(&self) -> u32 { self.q.high_water_mark() }
+
   /// Peek front message. Returns (hdr_ptr, payload_ptr, total_bytes).
   #[inline(always)]
   pub fn front(&self) -> Option<(*const MsgHeader, *const u8, u32)> {
     let mut r = self.q.read_idx.load(Ordering::Relaxed);
-    let w = self.q.written_idx.load(Ordering::Acquire);
+    let w = self.q.prod.written_idx.load(Ordering::Acquire);
     if r == w { return None; }
 
     let blk = self.q.blk_ptr();
     loop {
-      let cur = unsafe { blk.add((r & SpscVarQueueOpt::<BLK_CNT>::mask()) as usize) };
-      let sz = unsafe { ptr::read_volatile(&(*cur).header.size) };
+      let cur = unsafe { blk.add((r & self.q.mask()) as usize) };
+      let sz = unsafe { ptr::read_volatile(&(*Block::header(cur)).size) };
 
       if sz == 0 {
         // rewind
-        let pad = (BLK_CNT as u32) - (r & SpscVarQueueOpt::<BLK_CNT>::mask());
+        let pad = self.q.blk_cnt - (r & self.q.mask());
         r = r.wrapping_add(pad);
         self.q.read_idx.store(r, Ordering::Relaxed);
         if r == w { return None; }
         continue;
       }
 
-      let hdr_ptr = unsafe { &(*cur).header as *const MsgHeader };
+      let hdr_ptr = Block::header(cur) as *const MsgHeader;
       let base_ptr = hdr_ptr as *const u8;
       let payload_ptr = unsafe { base_ptr.add(MSG_HEADER_SIZE) };
       return Some((hdr_ptr, payload_ptr, sz));
@@ -188,13 +453,332 @@ impl<'a, const BLK_CNT: usize> Consumer<'a, BLK_CNT> {
     let r = self.q.read_idx.load(Ordering::Relaxed);
 
     let blk = self.q.blk_ptr();
-    let cur = unsafe { blk.add((r & SpscVarQueueOpt::<BLK_CNT>::mask()) as usize) };
-    let sz = unsafe { ptr::read_volatile(&(*cur).header.size) };
+    let cur = unsafe { blk.add((r & self.q.mask()) as usize) };
+    let sz = unsafe { ptr::read_volatile(&(*Block::header(cur)).size) };
     debug_assert!(sz != 0);
 
-    let blk_sz = div_ceil(sz as usize, BLOCK_SIZE) as u32;
+    let blk_sz = div_ceil(sz as usize, BLK_SIZE) as u32;
     let new_r = r.wrapping_add(blk_sz);
 
     self.q.read_idx.store(new_r, Ordering::Release);
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn full_queue_counts_every_rejected_alloc() {
+    let q = SpscVarQueueOpt::<4>::new();
+    let (prod, _cons) = q.split();
+
+    let mut pushed = 0;
+    while let Ok((hdr, _, _, total, _)) = prod.try_alloc(8) {
+      unsafe { prod.commit(hdr, total) };
+      pushed += 1;
+    }
+    assert!(pushed > 0);
+    assert_eq!(q.dropped_count(), 1);
+
+    for _ in 0..9 {
+      assert_eq!(prod.try_alloc(8).err(), Some(AllocError::Full));
+    }
+    assert_eq!(q.dropped_count(), 10);
+  }
+
+  #[test]
+  fn larger_than_the_ring_is_too_large_even_when_empty() {
+    let q = SpscVarQueueOpt::<4>::new();
+    let (prod, _cons) = q.split();
+    assert_eq!(prod.try_alloc(4 * BLOCK_SIZE).err(), Some(AllocError::TooLarge));
+    assert_eq!(prod.try_alloc(usize::MAX).err(), Some(AllocError::TooLarge));
+    assert_eq!(q.dropped_count(), 2);
+    // 刚好填满整个 ring 的还能分配
+    assert!(prod.try_alloc(4 * BLOCK_SIZE - MSG_HEADER_SIZE).is_ok());
+  }
+
+  #[test]
+  fn runtime_capacity_overrides_blk_cnt() {
+    // BLK_CNT 是 4，运行时要 1000 字节：向上取到 16 块
+    let q = SpscVarQueueOpt::<4>::with_capacity(1000);
+    assert_eq!(q.capacity_bytes(), 16 * BLOCK_SIZE);
+    assert_eq!(SpscVarQueueOpt::<4>::new().capacity_bytes(), SpscVarQueueOpt::<4>::CAPACITY_BYTES);
+    let (prod, cons) = q.split();
+    assert_eq!(prod.try_alloc(16 * BLOCK_SIZE).err(), Some(AllocError::TooLarge));
+
+    // 跑几圈，回绕按 16 块算
+    for seq in 0..50u8 {
+      let len = if seq % 4 == 0 { 3 * BLOCK_SIZE } else { 8 };
+      let (hdr, payload, _, total, _) = prod.try_alloc(len).unwrap();
+      unsafe {
+        ptr::write_bytes(payload, seq, len);
+        prod.commit(hdr, total);
+      }
+      let (_, payload, _) = cons.front().unwrap();
+      assert!(unsafe { std::slice::from_raw_parts(payload, len) }.iter().all(|&b| b == seq));
+      cons.pop();
+    }
+    assert_eq!(q.free_blocks(), 16);
+  }
+
+  #[test]
+  fn deferred_burst_shows_up_after_one_flush() {
+    let q = SpscVarQueueOpt::<64>::new();
+    let (prod, cons) = q.split();
+
+    for seq in 0..8u64 {
+      let (hdr, payload, _, total, _) = prod.try_alloc(8).unwrap();
+      unsafe {
+        ptr::copy_nonoverlapping(seq.to_le_bytes().as_ptr(), payload, 8);
+        prod.commit_deferred(hdr, total);
+      }
+    }
+    assert!(cons.front().is_none());
+
+    prod.flush_published();
+    for seq in 0..8u64 {
+      let (_, payload, _) = cons.front().unwrap();
+      let got = unsafe { std::slice::from_raw_parts(payload, 8) };
+      assert_eq!(got, seq.to_le_bytes());
+      cons.pop();
+    }
+    assert!(cons.front().is_none());
+  }
+
+  // 先用 1 块的消息把写位置推到 ring 尾部前 `lead` 块，再写一条 2-4 块的消息逼它回绕
+  #[test]
+  fn multi_block_messages_survive_the_rewind() {
+    const N: usize = 16;
+    for blocks in 2..=4usize {
+      for lead in 0..N {
+        let q = SpscVarQueueOpt::<N>::new();
+        let (prod, cons) = q.split();
+        for _ in 0..N - lead {
+          let (hdr, _, _, total, _) = prod.try_alloc(8).unwrap();
+          unsafe { prod.commit(hdr, total) };
+          cons.pop();
+        }
+
+        for round in 0..3u8 {
+          let len = blocks * BLOCK_SIZE - MSG_HEADER_SIZE - round as usize;
+          let bytes: Vec<u8> = (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(round)).collect();
+          let (hdr, payload, cap, total, blk_sz) = prod.try_alloc(len).unwrap();
+          assert_eq!(blk_sz as usize, blocks);
+          assert!(cap >= len);
+          unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), payload, len);
+            prod.commit(hdr, total);
+          }
+
+          let (hdr, payload, _) = cons.front().unwrap();
+          assert_eq!(unsafe { (*hdr).size } as usize, len + MSG_HEADER_SIZE);
+          let got = unsafe { std::slice::from_raw_parts(payload, len) };
+          assert!(got == &bytes[..], "blocks {blocks} lead {lead} round {round}");
+          cons.pop();
+          assert!(cons.front().is_none());
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn message_bigger_than_ring_minus_padding_still_fits_after_rewind() {
+    const N: usize = 8;
+    let q = SpscVarQueueOpt::<N>::new();
+    let (prod, cons) = q.split();
+    for _ in 0..5 {
+      let (hdr, _, _, total, _) = prod.try_alloc(8).unwrap();
+      unsafe { prod.commit(hdr, total) };
+      cons.pop();
+    }
+    // 剩 3 块填充，7 块的消息只能从 ring 头开始，会盖住 marker：第一次只发布 marker
+    let len = 7 * BLOCK_SIZE - MSG_HEADER_SIZE;
+    assert_eq!(prod.try_alloc(len).err(), Some(AllocError::Full));
+    // consumer 跳过 marker 之后就放得下了
+    assert!(cons.front().is_none());
+    let (hdr, payload, _, total, _) = prod.try_alloc(len).unwrap();
+    unsafe {
+      ptr::write_bytes(payload, 0xab, len);
+      prod.commit(hdr, total);
+    }
+    let (_, payload, _) = cons.front().unwrap();
+    assert!(unsafe { std::slice::from_raw_parts(payload, len) }.iter().all(|&b| b == 0xab));
+    cons.pop();
+    assert!(cons.front().is_none());
+  }
+
+  #[test]
+  fn round_trips_with_128_byte_blocks() {
+    let q = SpscVarQueueOpt::<8, 128>::new();
+    let (prod, cons) = q.split();
+    assert_eq!(SpscVarQueueOpt::<8, 128>::CAPACITY_BYTES, 1024);
+
+    // 8 -> 1 块，200 -> 2 块；跑几圈让它回绕
+    for seq in 0..40u8 {
+      let len = if seq % 3 == 0 { 200 } else { 8 };
+      let (hdr, payload, cap, total, blk_sz) = prod.try_alloc(len).unwrap();
+      assert_eq!(blk_sz, if len == 200 { 2 } else { 1 });
+      assert_eq!(cap, blk_sz as usize * 128 - MSG_HEADER_SIZE);
+      assert_eq!(payload as usize % 8, 0);
+      unsafe {
+        ptr::write_bytes(payload, seq, len);
+        prod.commit(hdr, total);
+      }
+
+      let (hdr, payload, _) = cons.front().unwrap();
+      assert_eq!(hdr as usize % 128, 0);
+      assert_eq!(unsafe { (*hdr).size } as usize, len + MSG_HEADER_SIZE);
+      assert!(unsafe { std::slice::from_raw_parts(payload, len) }.iter().all(|&b| b == seq));
+      cons.pop();
+    }
+    assert!(cons.front().is_none());
+  }
+
+  #[test]
+  fn blocks_for_rounds_up_to_a_power_of_two() {
+    assert_eq!(blocks_for(0, BLOCK_SIZE), 1);
+    assert_eq!(blocks_for(1, BLOCK_SIZE), 1);
+    assert_eq!(blocks_for(BLOCK_SIZE, BLOCK_SIZE), 1);
+    assert_eq!(blocks_for(BLOCK_SIZE + 1, BLOCK_SIZE), 2);
+    assert_eq!(blocks_for(1000 * BLOCK_SIZE, BLOCK_SIZE), 1024);
+    assert_eq!(blocks_for(64 * 1024, 128), 512);
+  }
+
+  // 只有一块：每条消息独占整个 ring，消费掉之后才能写下一条
+  #[test]
+  fn single_block_ring_holds_one_message_at_a_time() {
+    let q = SpscVarQueueOpt::<1>::new();
+    let (prod, cons) = q.split();
+    let max = BLOCK_SIZE - MSG_HEADER_SIZE;
+    assert_eq!(prod.try_alloc(max + 1).err(), Some(AllocError::TooLarge));
+
+    for round in 0..4u8 {
+      let len = if round % 2 == 0 { max } else { 0 };
+      let (hdr, payload, cap, total, blk_sz) = prod.try_alloc(len).unwrap();
+      assert_eq!((cap, blk_sz), (max, 1));
+      unsafe {
+        ptr::write_bytes(payload, round, len);
+        prod.commit(hdr, total);
+      }
+      assert_eq!(prod.try_alloc(0).err(), Some(AllocError::Full));
+
+      let (hdr, payload, _) = cons.front().unwrap();
+      assert_eq!(unsafe { (*hdr).size } as usize, len + MSG_HEADER_SIZE);
+      assert!(unsafe { std::slice::from_raw_parts(payload, len) }.iter().all(|&b| b == round));
+      cons.pop();
+      assert!(cons.front().is_none());
+    }
+  }
+
+  #[test]
+  fn used_blocks_match_reserved_blocks() {
+    let q = SpscVarQueueOpt::<16>::new();
+    let (prod, cons) = q.split();
+
+    let mut used = 0;
+    // header 24 字节：8 -> 1 块，100 -> 2 块，200 -> 4 块
+    for (payload_len, blocks) in [(8, 1), (100, 2), (200, 4), (40, 1)] {
+      let (hdr, _, _, total, blk_sz) = prod.try_alloc(payload_len).unwrap();
+      assert_eq!(blk_sz, blocks);
+      unsafe { prod.commit(hdr, total) };
+      used += blocks;
+      assert_eq!(prod.used_blocks(), used);
+      assert_eq!(cons.free_blocks(), 16 - used);
+    }
+    assert_eq!(q.high_water_mark(), 8);
+
+    cons.pop();
+    cons.pop();
+    assert_eq!(cons.used_blocks(), 5);
+    // 峰值不会回落
+    assert_eq!(q.high_water_mark(), 8);
+  }
+
+  #[test]
+  fn producer_and_consumer_indices_do_not_share_a_line() {
+    let q = SpscVarQueueOpt::<16>::new();
+    let base = &q as *const _ as usize;
+    let prod = ptr::addr_of!(q.prod) as usize - base;
+    let read = ptr::addr_of!(q.read_idx) as usize - base;
+    let written = ptr::addr_of!(q.prod.written_idx) as usize - base;
+    assert_eq!(read % 64, 0);
+    assert_eq!(prod % 64, 0);
+    assert!(read.abs_diff(written) >= 64, "read_idx at {read}, written_idx at {written}");
+  }
+
+  // 两个线程一推一取，默认不跑；对比 CachePadded 前后时手动计时：
+  // cargo test --release --lib two_thread_throughput -- --ignored
+  #[test]
+  #[ignore]
+  fn two_thread_throughput() {
+    const ROUNDS: u64 = 200_000;
+    let q = SpscVarQueueOpt::<1024>::new();
+    let (prod, cons) = q.split();
+    std::thread::scope(|s| {
+      s.spawn(|| {
+        for seq in 0..ROUNDS {
+          loop {
+            if let Ok((hdr, payload, _, total, _)) = prod.try_alloc(8) {
+              unsafe {
+                ptr::copy_nonoverlapping(seq.to_le_bytes().as_ptr(), payload, 8);
+                prod.commit(hdr, total);
+              }
+              break;
+            }
+            std::hint::spin_loop();
+          }
+        }
+      });
+      for seq in 0..ROUNDS {
+        let payload = loop {
+          if let Some((_, payload, _)) = cons.front() {
+            break payload;
+          }
+          std::hint::spin_loop();
+        };
+        assert_eq!(unsafe { std::slice::from_raw_parts(payload, 8) }, seq.to_le_bytes());
+        cons.pop();
+      }
+    });
+  }
+
+  #[test]
+  fn ring_over_a_caller_page_aligned_buffer() {
+    type Q = SpscVarQueueOpt<16>;
+    let layout = Layout::from_size_align(Q::CAPACITY_BYTES, 4096).unwrap();
+    let buf = NonNull::new(unsafe { alloc::alloc(layout) }).unwrap();
+    // 不要求调用方清零
+    unsafe { ptr::write_bytes(buf.as_ptr(), 0xcd, Q::CAPACITY_BYTES) };
+    let range = buf.as_ptr() as usize..buf.as_ptr() as usize + Q::CAPACITY_BYTES;
+
+    {
+      let q = unsafe { Q::from_buffer(buf, Q::CAPACITY_BYTES) };
+      let (prod, cons) = q.split();
+      // 跑几圈，覆盖回绕
+      for seq in 0..40u64 {
+        let (hdr, payload, _, total, _) = prod.try_alloc(8).unwrap();
+        assert!(range.contains(&(hdr as usize)));
+        unsafe {
+          ptr::copy_nonoverlapping(seq.to_le_bytes().as_ptr(), payload, 8);
+          prod.commit(hdr, total);
+        }
+        let (_, payload, _) = cons.front().unwrap();
+        assert_eq!(unsafe { std::slice::from_raw_parts(payload, 8) }, seq.to_le_bytes());
+        cons.pop();
+      }
+      assert!(cons.front().is_none());
+    }
+    // 队列 drop 不会释放调用方的内存
+    unsafe { alloc::dealloc(buf.as_ptr(), layout) };
+  }
+
+  #[test]
+  #[should_panic(expected = "buffer must be BLK_SIZE-aligned")]
+  fn from_buffer_rejects_a_misaligned_buffer() {
+    let mut buf = vec![0u64; SpscVarQueueOpt::<4>::CAPACITY_BYTES / 8 + 8];
+    let addr = buf.as_mut_ptr() as usize;
+    let misaligned = (addr + BLOCK_SIZE - addr % BLOCK_SIZE + 8) as *mut u8;
+    let _ = unsafe { SpscVarQueueOpt::<4>::from_buffer(NonNull::new(misaligned).unwrap(), SpscVarQueueOpt::<4>::CAPACITY_BYTES) };
+  }
+}
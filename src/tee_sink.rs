@@ -0,0 +1,132 @@
+use std::io;
+use std::sync::Arc;
+use crate::format::{HeaderConfig, LineFormat, LineFormatter, Precision, TimeFormat};
+use crate::sink::Sink;
+use crate::spsc_var_queue_opt::MsgHeader;
+use crate::tscns::Clock;
+
+/// -------- Fan-out sink --------
+/// Forwards every record to all `sinks`, e.g. console + file. The text line is rendered once here
+/// and handed to each sink that `accepts_lines`; the others (e.g. `BinarySink`) get the raw record.
+/// Line layout options therefore come from the `TeeSink`, not from the sinks it wraps.
+pub struct TeeSink {
+  sinks: Vec<Box<dyn Sink>>,
+  line: LineFormatter,
+  // 至少有一个 sink 要文本行时才渲染
+  render: bool,
+}
+
+impl TeeSink {
+  /// Plain (no ANSI colors) `LineFormat::Text` lines with microsecond timestamps; control characters
+  /// in `&str` args are escaped, like `FileBatchSink` does, since the lines usually end up in a file.
+  pub fn new(sinks: Vec<Box<dyn Sink>>) -> Self {
+    let render = sinks.iter().any(|s| s.accepts_lines());
+    let mut line = LineFormatter::new();
+    line.set_escape_strings(true);
+    line.set_color(false);
+    Self {
+      sinks,
+      line,
+      render,
+    }
+  }
+
+  pub fn with_precision(mut self, precision: Precision) -> Self {
+    self.line.set_precision(precision);
+    self
+  }
+
+  /// Colors end up in every text sink, including files.
+  pub fn with_color(mut self, color: bool) -> Self {
+    self.line.set_color(color);
+    self
+  }
+
+  pub fn with_format(mut self, line_format: LineFormat) -> Self {
+    self.line.set_line_format(line_format);
+    self
+  }
+
+  pub fn with_header(mut self, header: HeaderConfig) -> Self {
+    self.line.set_header(header);
+    self
+  }
+
+  pub fn with_time_format(mut self, time_format: TimeFormat) -> Self {
+    self.line.set_time_format(time_format);
+    self
+  }
+
+  pub fn with_strip_module_prefix(mut self, prefix: &str) -> Self {
+    self.line.set_module_prefix(prefix);
+    self
+  }
+
+  pub fn with_level_names(mut self, names: &[&str]) -> Self {
+    self.line.set_level_names(names);
+    self
+  }
+
+  /// Escape control characters in the `&str` args of the lines rendered for line-accepting sinks (on by default).
+  pub fn with_escape_strings(mut self, on: bool) -> Self {
+    self.line.set_escape_strings(on);
+    self
+  }
+}
+
+/// Run `f` on every sink even if some fail; the first error is returned.
+#[inline(always)]
+fn for_each<F>(sinks: &mut [Box<dyn Sink>], mut f: F) -> io::Result<()>
+where
+  F: FnMut(&mut dyn Sink) -> io::Result<()>,
+{
+  let mut first_err = None;
+  for sink in sinks.iter_mut() {
+    if let Err(e) = f(sink.as_mut()) {
+      first_err.get_or_insert(e);
+    }
+  }
+  match first_err {
+    Some(e) => Err(e),
+    None => Ok(()),
+  }
+}
+
+impl Sink for TeeSink {
+  #[inline(always)]
+  fn on_record(&mut self, tid: usize, log_meta: &MsgHeader, log_payload: &[u8]) -> io::Result<()> {
+    let line: &[u8] = if self.render {
+      self.line.format(tid, log_meta, log_payload)?
+    } else {
+      &[]
+    };
+    for_each(&mut self.sinks, |sink| {
+      if sink.accepts_lines() {
+        sink.on_line(log_meta, line)
+      } else {
+        sink.on_record(tid, log_meta, log_payload)
+      }
+    })
+  }
+
+  fn on_thread_name(&mut self, tid: usize, name: &str) -> io::Result<()> {
+    self.line.set_thread_name(tid, name);
+    for_each(&mut self.sinks, |sink| sink.on_thread_name(tid, name))
+  }
+
+  fn set_clock(&mut self, clock: Arc<dyn Clock + Send + Sync>) {
+    self.line.set_clock(clock.clone());
+    for sink in self.sinks.iter_mut() {
+      sink.set_clock(clock.clone());
+    }
+  }
+
+  #[inline(always)]
+  fn on_idle(&mut self, now_cycles: i64) -> io::Result<()> {
+    for_each(&mut self.sinks, |sink| sink.on_idle(now_cycles))
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    for_each(&mut self.sinks, |sink| sink.flush())
+  }
+}
@@ -1,5 +1,5 @@
-use std::ptr::{addr_of, addr_of_mut};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Once};
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
 use std::time::SystemTime;
 
 /// [`NS_PER_SEC`]  The number of nanoseconds in each second is equal to one billion nanoseconds.
@@ -11,7 +11,7 @@ pub const INIT_CALIBRATE_NANOS: i64 = 300000000;
 /// [`CALIBRATE_INTERVAL_NANOS`] The default clock calibration period is 3 seconds.
 pub const CALIBRATE_INTERVAL_NANOS: i64 = NS_PER_SEC;
 
-/// [`PARAM_SEQ`] Global optimistic lock, used to detect whether global parameters have changed or whether global state (such as BASE_NS, BASE_TSC, NS_PER_TSC) has been modified by other threads during the calculation process.
+/// [`PARAM_SEQ`] Optimistic lock, used to detect whether global parameters have changed or whether global state (such as BASE_NS, BASE_TSC, NS_PER_TSC) has been modified by other threads during the calculation process.
 #[repr(align(64))]
 struct Sequence(AtomicUsize);
 
@@ -28,35 +28,191 @@ impl Sequence {
   }
 }
 
-static mut PARAM_SEQ: Sequence = const { Sequence::new() };
+/// `f64` stored through its bit pattern, so it can live in a plain (non-`mut`) static.
+struct AtomicF64(AtomicU64);
 
-/// [`NS_PER_TSC`] Indicates the number of nanoseconds per clock cycle.
-static mut NS_PER_TSC: f64 = 0.0;
+impl AtomicF64 {
+  const fn zero() -> Self {
+    // 0.0f64 has an all-zero bit pattern
+    Self(AtomicU64::new(0))
+  }
 
-/// [`BASE_TSC`] Benchmark TSC timestamp, used to calculate relative time.
-static mut BASE_TSC: i64 = 0;
+  #[inline(always)]
+  fn load(&self, order: Ordering) -> f64 {
+    f64::from_bits(self.0.load(order))
+  }
 
-/// [`BASE_NS`] Benchmark nanosecond error, used to reduce the error between TSC timestamp and nanosecond timestamp conversion.
-static mut BASE_NS: i64 = 0;
+  #[inline(always)]
+  fn store(&self, val: f64, order: Ordering) {
+    self.0.store(val.to_bits(), order);
+  }
+}
 
-/// [`CALIBATE_INTERVAL_NS`] Calibrate Clock Cycle
-static mut CALIBATE_INTERVAL_NS: i64 = 0;
+/// One calibration domain: tsc -> ns mapping plus its seqlock.
+/// The free functions of this module work on a process-wide default instance, see [`default_clock`].
+pub struct TscClock {
+  /// [`PARAM_SEQ`] optimistic lock guarding `base_tsc`, `base_ns` and `ns_per_tsc`.
+  param_seq: Sequence,
 
-/// [`BASE_NS_ERR`] Benchmark nanosecond error, used to reduce the error between TSC timestamp and nanosecond timestamp conversion.
-static mut BASE_NS_ERR: i64 = 0;
+  /// [`NS_PER_TSC`] Indicates the number of nanoseconds per clock cycle.
+  ns_per_tsc: AtomicF64,
 
-/// [`NEXT_CALIBRATE_TSC`]  The TSC timestamp for the next clock calibration is used to determine whether clock calibration is necessary.
-static mut NEXT_CALIBRATE_TSC: i64 = 0;
+  /// [`BASE_TSC`] Benchmark TSC timestamp, used to calculate relative time.
+  base_tsc: AtomicI64,
 
-/// # Examples
-/// ```
-/// tscns::init(tscns::INIT_CALIBRATE_NANOS, tscns::CALIBRATE_INTERVAL_NANOS);
-/// ```
-pub fn init(init_calibrate_ns: i64, calibrate_interval_ns: i64) {
-  unsafe {
-    addr_of_mut!(CALIBATE_INTERVAL_NS).write_volatile(calibrate_interval_ns);
+  /// [`BASE_NS`] Benchmark nanosecond error, used to reduce the error between TSC timestamp and nanosecond timestamp conversion.
+  base_ns: AtomicI64,
+
+  /// [`CALIBATE_INTERVAL_NS`] Calibrate Clock Cycle
+  calibate_interval_ns: AtomicI64,
+
+  /// [`BASE_NS_ERR`] Benchmark nanosecond error, used to reduce the error between TSC timestamp and nanosecond timestamp conversion.
+  base_ns_err: AtomicI64,
+
+  /// [`NEXT_CALIBRATE_TSC`]  The TSC timestamp for the next clock calibration is used to determine whether clock calibration is necessary.
+  next_calibrate_tsc: AtomicI64,
+
+  /// `ns_err` measured by the most recent calibration.
+  last_ns_err: AtomicI64,
+
+  /// optional `CalibrationHook` stored as a raw fn address, 0 means none
+  on_calibrate: AtomicUsize,
+}
+
+/// Called after every calibration with `(ns_err, new_ns_per_tsc)`.
+pub type CalibrationHook = fn(i64, f64);
+
+static DEFAULT_CLOCK: TscClock = TscClock::new();
+
+/// The clock behind [`init`], [`calibrate`], [`tsc2ns`], ...
+#[inline(always)]
+pub fn default_clock() -> &'static TscClock {
+  &DEFAULT_CLOCK
+}
+
+/// Wall-clock time as the sinks see it. Production goes through a [`TscClock`] (the default one unless a
+/// sink is given another); tests inject a [`ManualClock`] to get byte-exact timestamps.
+pub trait Clock {
+  /// Now, epoch ns.
+  fn now_ns(&self) -> i64;
+  /// Epoch ns of a record stamped with `tsc` (`MsgHeader::tsc`).
+  fn tsc_to_ns(&self, tsc: i64) -> i64;
+}
+
+impl Clock for TscClock {
+  #[inline(always)]
+  fn now_ns(&self) -> i64 {
+    self.read_nanos()
+  }
+
+  #[inline(always)]
+  fn tsc_to_ns(&self, tsc: i64) -> i64 {
+    self.tsc2ns(tsc)
+  }
+}
+
+/// A clock that only moves when told to. Records are stamped with its value when the sink renders them,
+/// whatever their tsc: set it, log, then drain (`LoggerHandle::captured_lines`) before moving it again.
+pub struct ManualClock {
+  ns: AtomicI64,
+}
+
+impl ManualClock {
+  pub const fn new(ns: i64) -> Self {
+    Self { ns: AtomicI64::new(ns) }
+  }
+
+  pub fn set_ns(&self, ns: i64) {
+    self.ns.store(ns, Ordering::Relaxed);
+  }
+
+  pub fn advance_ns(&self, delta: i64) {
+    self.ns.fetch_add(delta, Ordering::Relaxed);
+  }
+}
+
+impl Clock for ManualClock {
+  fn now_ns(&self) -> i64 {
+    self.ns.load(Ordering::Relaxed)
+  }
+
+  fn tsc_to_ns(&self, _tsc: i64) -> i64 {
+    self.now_ns()
+  }
+}
+
+/// Where the text sinks take record timestamps from, see `run_log2::init_logger_with_time`.
+/// Records are still stamped with the tsc on the hot path, only the conversion for display changes.
+#[derive(Clone, Default)]
+pub enum TimeSource {
+  /// tsc converted by the calibrated default [`TscClock`]
+  #[default]
+  Realtime,
+  /// epoch ns from the user, called once per record when the sink renders it (e.g. simulated time in a
+  /// backtest); the record's own tsc is ignored
+  Custom(Arc<dyn Fn() -> i64 + Send + Sync>),
+}
+
+impl TimeSource {
+  pub fn custom(f: impl Fn() -> i64 + Send + Sync + 'static) -> Self {
+    TimeSource::Custom(Arc::new(f))
+  }
+
+  /// The clock to hand to `Sink::set_clock`, `None` keeps the sinks on the default one.
+  pub fn clock(&self) -> Option<Arc<dyn Clock + Send + Sync>> {
+    match self {
+      TimeSource::Realtime => None,
+      TimeSource::Custom(f) => Some(Arc::new(FnClock(f.clone()))),
+    }
+  }
+}
+
+struct FnClock(Arc<dyn Fn() -> i64 + Send + Sync>);
+
+impl Clock for FnClock {
+  fn now_ns(&self) -> i64 {
+    (self.0)()
+  }
+
+  fn tsc_to_ns(&self, _tsc: i64) -> i64 {
+    (self.0)()
+  }
+}
+
+impl Default for TscClock {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl TscClock {
+  /// Uncalibrated clock, call [`TscClock::init`] before converting anything.
+  pub const fn new() -> Self {
+    Self {
+      param_seq: Sequence::new(),
+      ns_per_tsc: AtomicF64::zero(),
+      base_tsc: AtomicI64::new(0),
+      base_ns: AtomicI64::new(0),
+      calibate_interval_ns: AtomicI64::new(0),
+      base_ns_err: AtomicI64::new(0),
+      next_calibrate_tsc: AtomicI64::new(0),
+      last_ns_err: AtomicI64::new(0),
+      on_calibrate: AtomicUsize::new(0),
+    }
+  }
+
+  /// Clock with a known mapping `ns = base_ns + (tsc - base_tsc) * ns_per_tsc`, no sampling involved.
+  pub fn with_params(base_tsc: i64, base_ns: i64, ns_per_tsc: f64, calibrate_interval_ns: i64) -> Self {
+    let clock = Self::new();
+    clock.calibate_interval_ns.store(calibrate_interval_ns, Ordering::Relaxed);
+    clock.save_param(base_tsc, base_ns, base_ns, ns_per_tsc);
+    clock
+  }
+
+  pub fn init(&self, init_calibrate_ns: i64, calibrate_interval_ns: i64) {
+    self.calibate_interval_ns.store(calibrate_interval_ns, Ordering::Relaxed);
     let (base_tsc, base_ns) = sync_time();
-    let expire_ns = i64::unchecked_add(base_ns, init_calibrate_ns);
+    let expire_ns = base_ns.wrapping_add(init_calibrate_ns);
     while read_sys_nanos() < expire_ns {
       // Spin wait until the current system time exceeds the end time of the calibration period.
       std::thread::yield_now();
@@ -66,9 +222,126 @@ pub fn init(init_calibrate_ns: i64, calibrate_interval_ns: i64) {
     // Calculate the number of nanoseconds for each clock cycle initially,
     // dividing the difference between two nanosecond timestamps by the difference between two TSC timestamps
     // can more accurately represent the number of nanoseconds per tick of the TSC.
-    let init_ns_per_tsc = i64::unchecked_sub(delayed_ns, base_ns) as f64 / i64::unchecked_sub(delayed_tsc, base_tsc) as f64;
-    save_param(base_tsc, base_ns, base_ns, init_ns_per_tsc);
+    let init_ns_per_tsc = delayed_ns.wrapping_sub(base_ns) as f64 / delayed_tsc.wrapping_sub(base_tsc) as f64;
+    self.save_param(base_tsc, base_ns, base_ns, init_ns_per_tsc);
+  }
+
+  #[inline(always)]
+  pub fn read_nanos(&self) -> i64 {
+    self.tsc2ns(read_tsc())
+  }
+
+  pub fn calibrate(&self) {
+    if read_tsc() < self.next_calibrate_tsc.load(Ordering::Relaxed) {
+      // The current time should be beyond the next calibration time.
+      return;
+    }
+    let (tsc, ns) = sync_time();
+    self.calibrate_at(tsc, ns);
+  }
+
+  /// Calibrate against an explicit `(tsc, system ns)` sample.
+  pub fn calibrate_at(&self, tsc: i64, ns: i64) {
+    let calculated_ns = self.tsc2ns(tsc);
+    // Calculate the error in converting the current TSC timestamp to a nanosecond timestamp.
+    // If `ns_err` is a negative value, it indicates that the time converted by TSC is "slower" than the actual system time.
+    // When `ns_err` is a negative value, it will cause NS_PER_TSC to increase. This means that we need to increase the number of
+    // nanoseconds corresponding to each TSC cycle to catch up with the actual system time.
+    let calibrate_interval_ns = self.calibate_interval_ns.load(Ordering::Relaxed);
+    let base_ns_err = self.base_ns_err.load(Ordering::Relaxed);
+    let ns_err = calculated_ns.wrapping_sub(ns);
+    let expected_err_at_next_calibration = ns_err
+      + ns_err.wrapping_sub(base_ns_err) * calibrate_interval_ns
+      / ns.wrapping_sub(self.base_ns.load(Ordering::Relaxed)).wrapping_add(base_ns_err);
+
+    let new_ns_per_tsc = self.ns_per_tsc.load(Ordering::Relaxed)
+      * (1.0 - (expected_err_at_next_calibration as f64) / calibrate_interval_ns as f64); // Calculate the number of nanoseconds for each new clock cycle.
+    self.save_param(tsc, calculated_ns, ns, new_ns_per_tsc);
+
+    self.last_ns_err.store(ns_err, Ordering::Relaxed);
+    let hook = self.on_calibrate.load(Ordering::Acquire);
+    if hook != 0 {
+      let hook = unsafe { std::mem::transmute::<usize, CalibrationHook>(hook) };
+      hook(ns_err, new_ns_per_tsc);
+    }
+  }
+
+  /// Difference between the tsc-derived time and the system time at the last calibration, in ns.
+  /// Positive means the tsc clock was running ahead.
+  pub fn last_calibration_error_ns(&self) -> i64 {
+    self.last_ns_err.load(Ordering::Relaxed)
   }
+
+  /// Install (or remove with `None`) a callback run on the calibrating thread after each calibration.
+  pub fn set_calibration_hook(&self, hook: Option<CalibrationHook>) {
+    self.on_calibrate.store(hook.map_or(0, |f| f as usize), Ordering::Release);
+  }
+
+  pub fn get_tsc_ghz(&self) -> f64 {
+    1.0 / self.ns_per_tsc.load(Ordering::Relaxed)
+  }
+
+  pub fn get_ns_per_tsc(&self) -> f64 {
+    self.ns_per_tsc.load(Ordering::Relaxed)
+  }
+
+  /// Convert a nanosecond interval into tsc cycles at the current calibration.
+  pub fn ns2cycles(&self, ns: i64) -> i64 {
+    (ns as f64 / self.get_ns_per_tsc()) as i64
+  }
+
+  /// Convert tsc timestamp to nanosecond timestamp
+  pub fn tsc2ns(&self, tsc: i64) -> i64 {
+    loop {
+      let before_seq = self.param_seq.read(Ordering::Acquire) & !1;
+      std::sync::atomic::fence(Ordering::AcqRel);
+      // Calculate the TSC interval from the baseline time to the current time point and convert it into nanoseconds.
+      // Add the initial baseline nanoseconds to the interval nanoseconds to obtain the current nanoseconds.
+      // BASE_NS + ((tsc - BASE_TSC) as f64 * NS_PER_TSC) as i64
+      let diff_tsc = tsc.wrapping_sub(self.base_tsc.load(Ordering::Relaxed)) as f64;
+      let diff_ns = (diff_tsc * self.ns_per_tsc.load(Ordering::Relaxed)) as i64;
+      let ns = self.base_ns.load(Ordering::Relaxed).wrapping_add(diff_ns);
+      std::sync::atomic::fence(Ordering::AcqRel);
+      let after_seq = self.param_seq.read(Ordering::Acquire);
+      if before_seq == after_seq {
+        return ns;
+      }
+    }
+  }
+
+  /// Update the calibration parameters under the seqlock
+  fn save_param(&self, base_tsc: i64, base_ns: i64, sys_ns: i64, new_ns_per_tsc: f64) {
+    self.base_ns_err.store(base_ns.wrapping_sub(sys_ns), Ordering::Relaxed); // Compute benchmark nanosecond error.
+
+    // base_tsc + ((CALIBATE_INTERVAL_NS - 1000) as f64 / new_ns_per_tsc) as i64;
+    self.next_calibrate_tsc.store(
+      base_tsc.wrapping_add((self.calibate_interval_ns.load(Ordering::Relaxed).wrapping_sub(1000) as f64 / new_ns_per_tsc) as i64),
+      Ordering::Relaxed,
+    );
+
+    let seq = self.param_seq.read(Ordering::Relaxed);
+    self.param_seq.write(seq.wrapping_add(1), Ordering::Release);
+
+    std::sync::atomic::fence(Ordering::AcqRel); // Atomic barrier separation ensures that all read and write operations executed before the atomic barrier are completed.
+    self.base_tsc.store(base_tsc, Ordering::Relaxed);
+    self.base_ns.store(base_ns, Ordering::Relaxed);
+    self.ns_per_tsc.store(new_ns_per_tsc, Ordering::Relaxed);
+    std::sync::atomic::fence(Ordering::AcqRel);
+    self.param_seq.write(seq.wrapping_add(2), Ordering::Release);
+  }
+}
+
+static DEFAULT_INIT: Once = Once::new();
+
+/// Calibrate the default clock, once per process: later calls (every `init_logger*` makes one) are
+/// no-ops, so readers never see the base re-stamped mid-run. A call racing the first one waits for it.
+///
+/// # Examples
+/// ```
+/// tscns::init(tscns::INIT_CALIBRATE_NANOS, tscns::CALIBRATE_INTERVAL_NANOS);
+/// ```
+pub fn init(init_calibrate_ns: i64, calibrate_interval_ns: i64) {
+  DEFAULT_INIT.call_once(|| DEFAULT_CLOCK.init(init_calibrate_ns, calibrate_interval_ns));
 }
 
 /// # Examples
@@ -80,7 +353,7 @@ pub fn init(init_calibrate_ns: i64, calibrate_interval_ns: i64) {
 /// ```
 #[inline(always)]
 pub fn read_nanos() -> i64 {
-  tsc2ns(read_tsc())
+  DEFAULT_CLOCK.read_nanos()
 }
 
 /// # Examples
@@ -96,32 +369,7 @@ pub fn read_nanos() -> i64 {
 /// });
 /// ```
 pub fn calibrate() {
-  if read_tsc() < (unsafe { addr_of!(NEXT_CALIBRATE_TSC).read_volatile() }) {
-    // The current time should be beyond the next calibration time.
-    return;
-  }
-  let (tsc, ns) = sync_time();
-  let calculated_ns = tsc2ns(tsc);
-  // Calculate the error in converting the current TSC timestamp to a nanosecond timestamp.
-  // If `ns_err` is a negative value, it indicates that the time converted by TSC is "slower" than the actual system time.
-  // When `ns_err` is a negative value, it will cause NS_PER_TSC to increase. This means that we need to increase the number of
-  // nanoseconds corresponding to each TSC cycle to catch up with the actual system time.
-  unsafe {
-    let ns_err = i64::unchecked_sub(calculated_ns, ns);
-    let expected_err_at_next_calibration = ns_err
-      + (i64::unchecked_sub(ns_err, addr_of!(BASE_NS_ERR).read_volatile()))
-      * addr_of!(CALIBATE_INTERVAL_NS).read_volatile()
-      / (i64::unchecked_add(
-      i64::unchecked_sub(ns, addr_of!(BASE_NS).read_volatile()),
-      addr_of!(BASE_NS_ERR).read_volatile(),
-    ));
-
-    let new_ns_per_tsc = addr_of!(NS_PER_TSC).read_volatile()
-      * (1.0
-      - (expected_err_at_next_calibration as f64)
-      / addr_of!(CALIBATE_INTERVAL_NS).read_volatile() as f64); // Calculate the number of nanoseconds for each new clock cycle.
-    save_param(tsc, calculated_ns, ns, new_ns_per_tsc);
-  }
+  DEFAULT_CLOCK.calibrate()
 }
 
 /// Used to obtain the current CPU frequency in GHz units.
@@ -133,34 +381,30 @@ pub fn calibrate() {
 /// println!("cpu {}GHz", ghz);
 /// ```
 pub fn get_tsc_ghz() -> f64 {
-  1.0 / unsafe { addr_of!(NS_PER_TSC).read_volatile() }
+  DEFAULT_CLOCK.get_tsc_ghz()
 }
 
 pub fn get_ns_per_tsc() -> f64 {
-  unsafe { addr_of!(NS_PER_TSC).read_volatile() }
+  DEFAULT_CLOCK.get_ns_per_tsc()
+}
+
+pub fn last_calibration_error_ns() -> i64 {
+  DEFAULT_CLOCK.last_calibration_error_ns()
+}
+
+pub fn set_calibration_hook(hook: Option<CalibrationHook>) {
+  DEFAULT_CLOCK.set_calibration_hook(hook)
+}
+
+/// Convert a nanosecond interval into tsc cycles at the current calibration.
+pub fn ns2cycles(ns: i64) -> i64 {
+  DEFAULT_CLOCK.ns2cycles(ns)
 }
 
 /// Convert tsc timestamp to nanosecond timestamp
+#[inline(always)]
 pub fn tsc2ns(tsc: i64) -> i64 {
-  loop {
-    let before_seq = unsafe { (&*addr_of_mut!(PARAM_SEQ)).read(Ordering::Acquire) & !1 };
-    std::sync::atomic::fence(Ordering::AcqRel);
-    // Calculate the TSC interval from the baseline time to the current time point and convert it into nanoseconds.
-    // Add the initial baseline nanoseconds to the interval nanoseconds to obtain the current nanoseconds.
-    let ns = unsafe {
-      // BASE_NS + ((tsc - BASE_TSC) as f64 * NS_PER_TSC) as i64
-      let diff_tsc = i64::unchecked_sub(tsc, addr_of!(BASE_TSC).read_volatile()) as f64;
-      // the rust bug Cannot MulUnchecked non-integer type f64
-      // let diff_ns = unchecked_mul(diff_tsc, NS_PER_TSC) as i64;
-      let diff_ns = (diff_tsc * addr_of!(NS_PER_TSC).read_volatile()) as i64;
-      i64::unchecked_add(addr_of!(BASE_NS).read_volatile(), diff_ns)
-    };
-    std::sync::atomic::fence(Ordering::AcqRel);
-    let after_seq = unsafe { (&*addr_of_mut!(PARAM_SEQ)).read(Ordering::Acquire) };
-    if before_seq == after_seq {
-      return ns;
-    }
-  }
+  DEFAULT_CLOCK.tsc2ns(tsc)
 }
 
 /// Get the current system nanosecond timestamp.
@@ -173,32 +417,8 @@ fn read_sys_nanos() -> i64 {
   }
 }
 
-/// Update static global variables inside the module
-fn save_param(base_tsc: i64, base_ns: i64, sys_ns: i64, new_ns_per_tsc: f64) {
-  unsafe {
-    addr_of_mut!(BASE_NS).write_volatile(i64::unchecked_sub(base_ns, sys_ns)); // Compute benchmark nanosecond error.
-
-    // base_tsc + ((CALIBATE_INTERVAL_NS - 1000) as f64 / new_ns_per_tsc) as i64;
-    addr_of_mut!(NEXT_CALIBRATE_TSC).write_volatile(i64::unchecked_add(
-      base_tsc,
-      (i64::unchecked_sub(addr_of!(CALIBATE_INTERVAL_NS).read_volatile(), 1000) as f64
-        / new_ns_per_tsc) as i64,
-    ));
-
-    let seq = (&*addr_of!(PARAM_SEQ)).read(Ordering::Relaxed);
-    (&mut *addr_of_mut!(PARAM_SEQ)).write(usize::unchecked_add(seq, 1), Ordering::Release);
-
-    std::sync::atomic::fence(Ordering::AcqRel); // Atomic barrier separation ensures that all read and write operations executed before the atomic barrier are completed.
-    addr_of_mut!(BASE_TSC).write_volatile(base_tsc);
-    addr_of_mut!(BASE_NS).write_volatile(base_ns);
-    addr_of_mut!(NS_PER_TSC).write_volatile(new_ns_per_tsc);
-    std::sync::atomic::fence(Ordering::AcqRel);
-    (&mut *addr_of_mut!(PARAM_SEQ)).write(usize::unchecked_add(seq, 2), Ordering::Release);
-  }
-}
-
 /// Internal function to synchronize the tsc and system time
-fn sync_time() -> (i64, i64) {
+pub(crate) fn sync_time() -> (i64, i64) {
   const N: usize = if cfg!(windows) { 15 } else { 3 };
 
   let mut tsc: [i64; N + 1] = [0; N + 1];
@@ -211,29 +431,24 @@ fn sync_time() -> (i64, i64) {
     tsc[i] = read_tsc();
   }
 
-  let j: usize;
-  // If it is a Windows system, continuous identical timestamps in the sample data will be removed here to reduce errors.
-  #[cfg(windows)]
-  {
-    j = 1;
-    for i in 2..=N {
-      if ns[i] == ns[i - 1] {
-        continue;
-      }
-      tsc[j - 1] = tsc[i - 1];
-      ns[j] = ns[i];
-      j += 1;
-    }
-    j -= 1;
-  }
-  #[cfg(not(windows))]
-  {
-    j = N + 1;
-  }
+  // Windows system time only ticks every ~100ns-1ms, so most samples repeat the previous value
+  best_sample(&tsc, &ns, cfg!(windows))
+}
 
-  let mut best = 1;
-  for i in 2..j {
-    if tsc[i] - tsc[i - 1] < tsc[best] - tsc[best - 1] {
+/// `ns[i]` (i >= 1) was read between `tsc[i - 1]` and `tsc[i]`. Pick the sample with the tightest
+/// tsc bracket and return `(bracket midpoint, ns)`.
+///
+/// With `skip_repeats`, a sample whose `ns` equals the previous one is stale (the system clock has not
+/// ticked since) and is skipped; only the first read after each tick is a candidate.
+fn best_sample(tsc: &[i64], ns: &[i64], skip_repeats: bool) -> (i64, i64) {
+  let n = ns.len() - 1;
+  let candidate = |i: usize| !skip_repeats || i == 1 || ns[i] != ns[i - 1];
+  let mut best = 0;
+  for i in 1..=n {
+    if !candidate(i) {
+      continue;
+    }
+    if best == 0 || tsc[i] - tsc[i - 1] < tsc[best] - tsc[best - 1] {
       best = i;
     }
   }
@@ -242,7 +457,19 @@ fn sync_time() -> (i64, i64) {
   (tsc_out, ns_out)
 }
 
-/// Read tsc count, support x86_64 and aarch64 architecture cpu
+/// Counter behind [`read_tsc`] on this target. Only `rdtsc` ticks at (about) the cpu's nominal frequency,
+/// `cntvct_el0` / `rdtime` run at a fixed timer rate and `system clock` is ns, so "GHz" means counter GHz.
+pub const COUNTER: &str = if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
+  "rdtsc"
+} else if cfg!(target_arch = "aarch64") {
+  "cntvct_el0"
+} else if cfg!(target_arch = "riscv64") {
+  "rdtime"
+} else {
+  "system clock"
+};
+
+/// Read tsc count, support x86/x86_64, aarch64 and riscv64 cpus; other targets use the system clock in ns
 #[inline(always)]
 pub fn read_tsc() -> i64 {
   #[cfg(target_arch = "x86_64")]
@@ -263,15 +490,76 @@ pub fn read_tsc() -> i64 {
     tsc
   }
 
+  // `time` CSR: a constant-rate 64-bit counter (rdtime is the pseudo-instruction csrr _, time),
+  // never wraps in practice, so it fits i64 like the other counters
   #[cfg(target_arch = "riscv64")]
   {
-    let tsc: i64;
+    let tsc: u64;
     unsafe {
-      asm!("rdtime {}", out(reg) tsc);
+      core::arch::asm!("rdtime {}", out(reg) tsc, options(nomem, nostack));
     }
-    tsc
+    tsc as i64
   }
 
-  #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+  #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64")))]
   read_sys_nanos()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn tsc2ns_with_known_params_is_exact() {
+    // 4GHz: 0.25ns per tick
+    let clock = TscClock::with_params(1_000, 5_000_000_000, 0.25, NS_PER_SEC);
+    assert_eq!(clock.tsc2ns(1_000), 5_000_000_000);
+    assert_eq!(clock.tsc2ns(5_000), 5_000_001_000);
+    assert_eq!(clock.tsc2ns(0), 4_999_999_750);
+    assert_eq!(clock.tsc2ns(4_000_001_000), 6_000_000_000);
+    assert_eq!(clock.ns2cycles(1_000), 4_000);
+    assert_eq!(clock.get_tsc_ghz(), 4.0);
+  }
+
+  #[test]
+  fn calibrating_with_an_exact_sample_keeps_the_rate() {
+    let clock = TscClock::with_params(0, 1_000_000_000, 0.5, NS_PER_SEC);
+    clock.calibrate_at(2_000_000_000, 2_000_000_000);
+    assert_eq!(clock.get_ns_per_tsc(), 0.5);
+    assert_eq!(clock.tsc2ns(4_000_000_000), 3_000_000_000);
+  }
+
+  #[test]
+  fn independent_clocks_do_not_share_state() {
+    let a = TscClock::with_params(0, 0, 1.0, NS_PER_SEC);
+    let b = TscClock::with_params(0, 0, 2.0, NS_PER_SEC);
+    assert_eq!(a.tsc2ns(10), 10);
+    assert_eq!(b.tsc2ns(10), 20);
+  }
+
+  #[test]
+  fn calibration_reports_the_measured_error() {
+    let clock = TscClock::with_params(0, 0, 1.0, NS_PER_SEC);
+    // tsc 换算出来的是 1_000_100，系统时间是 1_000_000：快了 100ns
+    clock.calibrate_at(1_000_100, 1_000_000);
+    assert_eq!(clock.last_calibration_error_ns(), 100);
+    assert!(clock.get_ns_per_tsc() < 1.0);
+  }
+
+  #[test]
+  fn best_sample_skips_repeated_system_time() {
+    // ns[i] 读在 tsc[i - 1] 和 tsc[i] 之间，ns[0] 不用
+    let tsc = [0, 10, 12, 40, 45];
+    let ns = [0, 100, 100, 200, 200];
+
+    // 不去重时最窄的是 i = 2，但它的 ns 是上一次 tick 的旧值
+    assert_eq!(best_sample(&tsc, &ns, false), (11, 100));
+    assert_eq!(best_sample(&tsc, &ns, true), (5, 100));
+
+    // 全部重复时只剩第一个样本
+    assert_eq!(best_sample(&[0, 8, 9, 10], &[0, 7, 7, 7], true), (4, 7));
+    // 没有重复时两种方式一致
+    let (tsc, ns) = ([0, 6, 9, 20], [0, 1, 2, 3]);
+    assert_eq!(best_sample(&tsc, &ns, true), best_sample(&tsc, &ns, false));
+  }
+}
@@ -0,0 +1,198 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::format::{HeaderConfig, LineFormat, LineFormatter, Precision, TimeFormat};
+use crate::sink::Sink;
+use crate::spsc_var_queue_opt::MsgHeader;
+use crate::tscns::{self, Clock};
+
+/// 1500 (ethernet MTU) - 20 (IPv4) - 8 (UDP)
+pub const DEFAULT_MAX_DATAGRAM: usize = 1472;
+
+/// -------- UDP sink --------
+/// Batches rendered lines like `ConsoleBatchSink` and ships them to a collector as datagrams of at
+/// most `max_datagram` bytes, split at line boundaries. A single line longer than that is cut into
+/// `max_datagram` pieces, only the last one ending with `\n`.
+/// UDP is fire-and-forget: failed `send_to` calls are counted (see `send_errors`), never returned.
+pub struct UdpSink {
+  socket: UdpSocket,
+  addr: SocketAddr,
+  max_datagram: usize,
+  send_errors: Arc<AtomicU64>,
+
+  // 批量 buffer
+  batch: Vec<u8>,
+  line: LineFormatter,
+
+  // flush 策略
+  flush_bytes: usize,
+  flush_interval_cycles: i64,
+  last_flush_cycles: i64,
+}
+
+impl UdpSink {
+  /// 64KB batch flushed at least every 500us, `DEFAULT_MAX_DATAGRAM` bytes per datagram.
+  pub fn new(addr: SocketAddr) -> io::Result<Self> {
+    Self::with_config(addr, 64 * 1024, Duration::from_micros(500))
+  }
+
+  /// Binds an ephemeral local port of the same address family as `addr`.
+  /// Same as `ConsoleBatchSink::with_config`, `flush_interval` is converted with the current tsc calibration.
+  pub fn with_config(addr: SocketAddr, flush_bytes: usize, flush_interval: Duration) -> io::Result<Self> {
+    let local: SocketAddr = if addr.is_ipv4() { "0.0.0.0:0".parse().unwrap() } else { "[::]:0".parse().unwrap() };
+    let socket = UdpSocket::bind(local)?;
+    let mut line = LineFormatter::new();
+    line.set_color(false);
+
+    Ok(Self {
+      socket,
+      addr,
+      max_datagram: DEFAULT_MAX_DATAGRAM,
+      send_errors: Arc::new(AtomicU64::new(0)),
+
+      batch: Vec::with_capacity(flush_bytes),
+      line,
+
+      flush_bytes,
+      flush_interval_cycles: tscns::ns2cycles(flush_interval.as_nanos() as i64),
+      last_flush_cycles: tscns::read_tsc(),
+    })
+  }
+
+  /// Upper bound of each datagram's payload, clamped to at least 1.
+  pub fn with_max_datagram(mut self, max_datagram: usize) -> Self {
+    self.max_datagram = max_datagram.max(1);
+    self
+  }
+
+  /// Sub-second digits in the header, `Precision::Micros` by default.
+  pub fn with_precision(mut self, precision: Precision) -> Self {
+    self.line.set_precision(precision);
+    self
+  }
+
+  pub fn with_format(mut self, line_format: LineFormat) -> Self {
+    self.line.set_line_format(line_format);
+    self
+  }
+
+  pub fn with_header(mut self, header: HeaderConfig) -> Self {
+    self.line.set_header(header);
+    self
+  }
+
+  pub fn with_time_format(mut self, time_format: TimeFormat) -> Self {
+    self.line.set_time_format(time_format);
+    self
+  }
+
+  pub fn with_strip_module_prefix(mut self, prefix: &str) -> Self {
+    self.line.set_module_prefix(prefix);
+    self
+  }
+
+  pub fn with_level_names(mut self, names: &[&str]) -> Self {
+    self.line.set_level_names(names);
+    self
+  }
+
+  /// Escape `\n`, `\r`, `\t` and ESC inside `&str` args, off by default.
+  pub fn with_escape_strings(mut self, on: bool) -> Self {
+    self.line.set_escape_strings(on);
+    self
+  }
+
+  /// Number of failed `send_to` calls; keep a clone before handing the sink to the backend.
+  pub fn send_errors(&self) -> Arc<AtomicU64> {
+    self.send_errors.clone()
+  }
+
+  #[inline(always)]
+  fn should_flush(&self, now_cycles: i64) -> bool {
+    self.batch.len() >= self.flush_bytes || now_cycles.wrapping_sub(self.last_flush_cycles) >= self.flush_interval_cycles
+  }
+
+  #[inline(always)]
+  fn flush_now(&mut self) -> io::Result<()> {
+    let mut start = 0;
+    let len = self.batch.len();
+    while start < len {
+      let mut end = (start + self.max_datagram).min(len);
+      if end < len {
+        // 在最后一个完整行处切开；单行超长就只能硬切
+        if let Some(pos) = self.batch[start..end].iter().rposition(|&b| b == b'\n') {
+          end = start + pos + 1;
+        }
+      }
+      if self.socket.send_to(&self.batch[start..end], self.addr).is_err() {
+        self.send_errors.fetch_add(1, Ordering::Relaxed);
+      }
+      start = end;
+    }
+    self.batch.clear();
+    self.last_flush_cycles = tscns::read_tsc();
+    Ok(())
+  }
+}
+
+impl Sink for UdpSink {
+  #[inline(always)]
+  fn on_record(&mut self, tid: usize, log_meta: &MsgHeader, log_payload: &[u8]) -> io::Result<()> {
+    let line = self.line.format(tid, log_meta, log_payload)?;
+    self.batch.extend_from_slice(line);
+
+    if self.should_flush(log_meta.tsc) {
+      self.flush_now()?;
+    }
+    Ok(())
+  }
+
+  #[inline(always)]
+  fn accepts_lines(&self) -> bool {
+    true
+  }
+
+  #[inline(always)]
+  fn on_line(&mut self, log_meta: &MsgHeader, line: &[u8]) -> io::Result<()> {
+    self.batch.extend_from_slice(line);
+
+    if self.should_flush(log_meta.tsc) {
+      self.flush_now()?;
+    }
+    Ok(())
+  }
+
+  #[inline]
+  fn on_thread_name(&mut self, tid: usize, name: &str) -> io::Result<()> {
+    self.line.set_thread_name(tid, name);
+    Ok(())
+  }
+
+  #[inline]
+  fn set_clock(&mut self, clock: Arc<dyn Clock + Send + Sync>) {
+    self.line.set_clock(clock);
+  }
+
+  #[inline(always)]
+  fn on_idle(&mut self, now_cycles: i64) -> io::Result<()> {
+    if !self.batch.is_empty()
+      && now_cycles.wrapping_sub(self.last_flush_cycles) >= self.flush_interval_cycles
+    {
+      self.flush_now()?;
+    }
+    Ok(())
+  }
+
+  #[inline]
+  fn flush(&mut self) -> io::Result<()> {
+    self.flush_now()
+  }
+}
+
+impl Drop for UdpSink {
+  fn drop(&mut self) {
+    let _ = self.flush_now();
+  }
+}
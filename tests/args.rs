@@ -0,0 +1,280 @@
+mod common;
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
+
+use hft_log_demo::args2::{args2, decode_args, encode_to_vec, hex, hex_n, ptr, IntoArg, Tsc, STR_MAX_LEN};
+use hft_log_demo::tscns::read_tsc;
+use hft_log_demo::hft_info;
+
+use common::{capture_logger, wait_for};
+
+#[test]
+fn each_arity_round_trips_through_publish_args() {
+  let (logger, out) = capture_logger();
+
+  assert!(hft_info!(logger, "one {}", 1u64));
+  assert!(hft_info!(logger, "two {} {}", 2u64, u32::MAX));
+  assert!(hft_info!(logger, "three {} {} {:<5}!", 3u32, u64::MAX, 255u64));
+  assert!(hft_info!(logger, "four {:>4}|{:<3}|{}|{:08}", 4u64, 44u32, 0u64, 12345u32));
+
+  let got = wait_for(&out, 4);
+  let msgs: Vec<&str> = got.iter().map(|r| r.msg()).collect();
+  assert_eq!(msgs, [
+    format!("one {}", 1u64),
+    format!("two {} {}", 2u64, u32::MAX),
+    format!("three {} {} {:<5}!", 3u32, u64::MAX, 255u64),
+    format!("four {:>4}|{:<3}|{}|{:08}", 4u64, 44u32, 0u64, 12345u32),
+  ]);
+}
+
+#[test]
+fn message_without_args() {
+  let (logger, out) = capture_logger();
+  assert!(hft_info!(logger, "heartbeat"));
+  assert!(hft_info!(logger, "braces {{}} stay literal"));
+
+  let got = wait_for(&out, 2);
+  assert_eq!(got[0].msg(), "heartbeat");
+  assert!(got[0].payload.is_empty());
+  assert_eq!(got[1].msg(), "braces {} stay literal");
+}
+
+#[test]
+fn str_args_are_copied_inline() {
+  let (logger, out) = capture_logger();
+
+  let symbol = String::from("BERAUSDT");
+  assert!(hft_info!(logger, "sym={}", symbol.as_str()));
+  assert!(hft_info!(logger, "{} {} {}", 1u64, "mid", 2u64));
+  drop(symbol);
+
+  let got = wait_for(&out, 2);
+  assert_eq!(got[0].msg(), "sym=BERAUSDT");
+  assert_eq!(got[1].msg(), "1 mid 2");
+}
+
+#[test]
+fn long_str_is_cut_on_a_char_boundary() {
+  let (logger, out) = capture_logger();
+
+  // 每个 € 3 字节，前面垫一个字节，STR_MAX_LEN 的上限落在字符中间
+  let long = format!("x{}", "€".repeat(100));
+  assert!(hft_info!(logger, "{}|{}", long.as_str(), 9u64));
+
+  let got = wait_for(&out, 1);
+  let (s, tail) = got[0].msg().split_once('|').unwrap();
+  assert_eq!(s, &long[..STR_MAX_LEN - 2]);
+  assert_eq!(tail, "9");
+}
+
+#[test]
+fn str_args_take_their_length_padded_to_8() {
+  let (logger, out) = capture_logger();
+
+  let sym = "BTCUSDT-PERP-".repeat(10);
+  assert!(hft_info!(logger, "{} {} {}", "a", sym.as_str(), 5u64));
+
+  let got = wait_for(&out, 1);
+  assert_eq!(got[0].msg(), format!("a {} 5", sym));
+  // 8 字节 tag，"a" 2+1 -> 8，130 字节 2+130 -> 136，u64 8
+  assert_eq!(got[0].payload.len(), 8 + 8 + 136 + 8);
+}
+
+#[test]
+fn owned_string_survives_being_dropped() {
+  let (logger, out) = capture_logger();
+
+  let mut order_id = String::from("ord-000042");
+  assert!(hft_info!(logger, "id={} by_ref={}", order_id.clone(), &order_id));
+  // 调用返回后原字符串随便改、随便 drop，backend 看到的是快照
+  order_id.clear();
+  order_id.push_str("XXXXXXXXXX");
+  drop(order_id);
+  std::thread::sleep(std::time::Duration::from_millis(20));
+
+  let got = wait_for(&out, 1);
+  assert_eq!(got[0].msg(), "id=ord-000042 by_ref=ord-000042");
+}
+
+#[test]
+fn integer_types_at_their_limits() {
+  let (logger, out) = capture_logger();
+
+  assert!(hft_info!(logger, "i8 {} {}", i8::MIN, i8::MAX));
+  assert!(hft_info!(logger, "i16 {} {}", i16::MIN, i16::MAX));
+  assert!(hft_info!(logger, "i32 {} {}", i32::MIN, i32::MAX));
+  assert!(hft_info!(logger, "i64 {} {}", i64::MIN, i64::MAX));
+  assert!(hft_info!(logger, "u8 {} {}", u8::MIN, u8::MAX));
+  assert!(hft_info!(logger, "u16 {} {}", u16::MIN, u16::MAX));
+  assert!(hft_info!(logger, "u32 {} {}", u32::MIN, u32::MAX));
+  assert!(hft_info!(logger, "u64 {} {}", u64::MIN, u64::MAX));
+
+  let got = wait_for(&out, 8);
+  let msgs: Vec<&str> = got.iter().map(|r| r.msg()).collect();
+  assert_eq!(msgs, [
+    format!("i8 {} {}", i8::MIN, i8::MAX),
+    format!("i16 {} {}", i16::MIN, i16::MAX),
+    format!("i32 {} {}", i32::MIN, i32::MAX),
+    format!("i64 {} {}", i64::MIN, i64::MAX),
+    format!("u8 {} {}", u8::MIN, u8::MAX),
+    format!("u16 {} {}", u16::MIN, u16::MAX),
+    format!("u32 {} {}", u32::MIN, u32::MAX),
+    format!("u64 {} {}", u64::MIN, u64::MAX),
+  ]);
+}
+
+#[test]
+fn bool_char_and_floats() {
+  let (logger, out) = capture_logger();
+
+  assert!(hft_info!(logger, "{} {} {}", true, 'λ', 3.5f32));
+  assert!(hft_info!(logger, "{:>6}|{:^3}|{:.3}", false, 'x', -0.25f64));
+
+  let got = wait_for(&out, 2);
+  assert_eq!(got[0].msg(), format!("{} {} {}", true, 'λ', 3.5f32));
+  assert_eq!(got[1].msg(), format!("{:>6}|{:^3}|{:.3}", false, 'x', -0.25f64));
+}
+
+#[test]
+fn ten_arg_order_event() {
+  let (logger, out) = capture_logger();
+
+  assert!(hft_info!(logger, "{} {} px={:.2} qty={} ts={} {} seq={} lat={}ns maker={} {}",
+    "BTCUSDT", 'B', 65000.5f64, 3u32, 1_700_000_000_123u64, "BINANCE", 42u64, -17i64, true, 10u8));
+
+  let got = wait_for(&out, 1);
+  assert_eq!(got[0].msg(), "BTCUSDT B px=65000.50 qty=3 ts=1700000000123 BINANCE seq=42 lat=-17ns maker=true 10");
+}
+
+#[test]
+fn positional_and_named_holes() {
+  let (logger, out) = capture_logger();
+
+  assert!(hft_info!(logger, "{1} {0} {1}", 1u64, 2u64));
+  assert!(hft_info!(logger, "px={px:.3} qty={qty:>8}|", px = 1.5f64, qty = 7u32));
+  assert!(hft_info!(logger, "{side} {sym}", sym = "ETHUSDT", side = 'S'));
+
+  let got = wait_for(&out, 3);
+  assert_eq!(got[0].msg(), "2 1 2");
+  assert_eq!(got[1].msg(), "px=1.500 qty=       7|");
+  assert_eq!(got[2].msg(), "S ETHUSDT");
+}
+
+#[test]
+fn hex_slice_truncated_at_its_cap() {
+  let (logger, out) = capture_logger();
+
+  let pkt: Vec<u8> = (0xe0..0xf4).collect();
+  assert_eq!(pkt.len(), 20);
+  assert!(hft_info!(logger, "pkt [{}]", hex_n(&pkt, 16)));
+  assert!(hft_info!(logger, "id [{}] {}", hex(&[0xde, 0xad, 0xbe, 0xef]), 1u64));
+
+  let got = wait_for(&out, 2);
+  assert_eq!(got[0].msg(), "pkt [e0 e1 e2 e3 e4 e5 e6 e7 e8 e9 ea eb ec ed ee ef…(4 more)]");
+  assert_eq!(got[1].msg(), "id [de ad be ef] 1");
+}
+
+#[test]
+fn trailing_fields_keep_their_order() {
+  let (logger, out) = capture_logger();
+
+  assert!(hft_info!(logger, "sent"; order_id = 5u64, venue = "NYSE"));
+  assert!(hft_info!(logger, "sent {} @ {:.1}", 3u32, 101.25f64; venue = "ARCA", maker = true, seq = 9u64));
+
+  let got = wait_for(&out, 2);
+  assert_eq!(got[0].msg(), "sent order_id=5 venue=NYSE");
+  assert_eq!(got[1].msg(), "sent 3 @ 101.2 venue=ARCA maker=true seq=9");
+}
+
+#[test]
+fn tsc_wrapper_renders_the_loggers_time() {
+  let (logger, out) = capture_logger();
+  let nic_tsc = hft_log_demo::tscns::read_tsc();
+  assert!(hft_info!(logger, "recv_ts={}", Tsc(nic_tsc)));
+  let got = wait_for(&out, 1);
+
+  // "recv_ts=MM-DD HH:MM:SS.mmm.uuu.nnn"，按同一个时钟把当天的纳秒数算回来
+  let time = got[0].msg().strip_prefix("recv_ts=").unwrap();
+  assert_eq!(time.len(), 26, "{time}");
+  let num = |r: std::ops::Range<usize>| time[r].parse::<i64>().unwrap();
+  let day_ns = ((num(6..8) * 60 + num(9..11)) * 60 + num(12..14)) * 1_000_000_000
+    + num(15..18) * 1_000_000
+    + num(19..22) * 1_000
+    + num(23..26);
+  // backend 可能在两次换算之间重新校准过，允许 1us 误差
+  let want = logger.tsc_to_ns(nic_tsc).rem_euclid(86_400 * 1_000_000_000);
+  assert!((day_ns - want).abs() < 1_000, "{time} vs {want}");
+}
+
+#[derive(Hash)]
+struct Book {
+  bids: Vec<(u64, u64)>,
+  asks: Vec<(u64, u64)>,
+  name: String,
+}
+
+#[test]
+fn ptr_logs_address_and_hash() {
+  let (logger, out) = capture_logger();
+  let book = Book {
+    bids: vec![(100, 5), (99, 7)],
+    asks: vec![(101, 3)],
+    name: "BTC-USDT".to_string(),
+  };
+  assert!(hft_info!(logger, "book {}", ptr(&book)));
+  let got = wait_for(&out, 1);
+
+  let mut hasher = DefaultHasher::new();
+  book.hash(&mut hasher);
+  let want = format!("book obj@{:#x}#{:016x}", &book as *const Book as usize, hasher.finish());
+  assert_eq!(got[0].msg(), want);
+}
+
+#[test]
+fn truncated_payload_decodes_as_error_markers() {
+  let args = args2(7u64, "BTCUSDT");
+  let bytes = &encode_to_vec(&args)[..];
+  let [a, b] = decode_args::<2>(bytes);
+  assert_eq!(format!("{a} {b}"), "7 BTCUSDT");
+
+  // 8 字节 tag + 第一个参数，字符串被截掉一半
+  let [a, b] = decode_args::<2>(&bytes[..24]);
+  assert_eq!(format!("{a} {b}"), "7 <decode error>");
+
+  // 连 tag 都不全
+  let [a, b] = decode_args::<2>(&bytes[..1]);
+  assert_eq!(format!("{a} {b}"), "<decode error> <decode error>");
+
+  // 被写坏的 tag 当成一个超长 UserPod，同样不能越界
+  let mut bad = bytes.to_vec();
+  bad[0] = 0xff;
+  let [a, b] = decode_args::<2>(&bad);
+  assert_eq!(format!("{a} {b}"), "<decode error> <decode error>");
+}
+
+// into_arg 记下开始转换的 tsc，然后故意慢 5ms
+static CONVERT_STARTED: AtomicI64 = AtomicI64::new(0);
+
+struct SlowArg(u64);
+
+impl IntoArg for SlowArg {
+  type D = <u64 as IntoArg>::D;
+
+  fn into_arg(self) -> Self::D {
+    CONVERT_STARTED.store(read_tsc(), Ordering::Relaxed);
+    std::thread::sleep(Duration::from_millis(5));
+    self.0.into_arg()
+  }
+}
+
+#[test]
+fn tsc_is_stamped_before_the_args_are_converted() {
+  let (logger, out) = capture_logger();
+  assert!(hft_info!(logger, "slow {}", SlowArg(7)));
+  let got = wait_for(&out, 1);
+  assert_eq!(got[0].msg(), "slow 7");
+  let started = CONVERT_STARTED.load(Ordering::Relaxed);
+  assert!(got[0].tsc <= started, "record tsc {} after conversion started at {}", got[0].tsc, started);
+}
@@ -0,0 +1,206 @@
+mod common;
+
+use std::io;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use hft_log_demo::hft_info;
+use hft_log_demo::log::{Level, SourceLocation};
+use hft_log_demo::metadata::{CallSite, Metadata};
+use hft_log_demo::my_bytes_mut::MyBytesMut;
+use hft_log_demo::run_log2::PublishResult;
+use hft_log_demo::sink::MsgHeader;
+use hft_log_demo::StagingBuffer;
+
+use common::{gated_logger, wait_for, wait_matching};
+
+#[test]
+fn dropped_count_matches_rejected_publishes() {
+  let (logger, cap) = gated_logger();
+  cap.hold.store(true, Ordering::Release);
+  assert_eq!(logger.dropped_count(), 0);
+
+  let mut published = 0u64;
+  while hft_info!(logger, "fill {} {}", published, 0u64) {
+    published += 1;
+  }
+  assert!(published > 0);
+
+  let mut rejected = 1u64;
+  for i in 0..100u64 {
+    assert!(!hft_info!(logger, "drop {} {}", i, 0u64));
+    rejected += 1;
+  }
+  assert_eq!(logger.dropped_count(), rejected);
+
+  cap.hold.store(false, Ordering::Release);
+  // backend 追上之后另外补一条汇总行
+  let got = wait_for(&cap.out, published as usize + 1);
+  let fills: Vec<&str> = got.iter().map(|r| r.msg()).filter(|m| m.starts_with("fill")).collect();
+  assert_eq!(fills.len(), published as usize);
+  assert_eq!(fills[published as usize - 1], format!("fill {} 0", published - 1));
+  let summary = format!("dropped {} log messages in last interval", rejected);
+  assert!(got.iter().any(|r| r.msg() == summary));
+}
+
+fn summaries(cap: &common::Capture) -> Vec<String> {
+  let out = cap.out.lock().unwrap();
+  out.iter().map(|r| r.msg().to_string()).filter(|m| m.starts_with("dropped ")).collect()
+}
+
+#[test]
+fn one_summary_line_per_idle_cycle() {
+  let (logger, cap) = gated_logger();
+  for (cycle, extra) in [(1usize, 3u64), (2, 40)] {
+    cap.hold.store(true, Ordering::Release);
+    let mut published = 0usize;
+    while hft_info!(logger, "fill {} {}", cycle as u64, published as u64) {
+      published += 1;
+    }
+    for i in 0..extra {
+      assert!(!hft_info!(logger, "drop {} {}", cycle as u64, i));
+    }
+    cap.hold.store(false, Ordering::Release);
+
+    // 等 backend 追上：这一轮的行都到了，再多等一会儿让它进入 idle
+    wait_matching(&cap.out, published, |r| r.msg().starts_with(&format!("fill {} ", cycle)));
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while summaries(&cap).len() < cycle {
+      assert!(Instant::now() < deadline, "no summary for cycle {cycle}");
+      std::thread::sleep(Duration::from_millis(1));
+    }
+    std::thread::sleep(Duration::from_millis(50));
+    let got = summaries(&cap);
+    assert_eq!(got.len(), cycle, "{got:?}");
+    assert_eq!(got[cycle - 1], format!("dropped {} log messages in last interval", extra + 1));
+  }
+  assert_eq!(logger.dropped_count(), 3 + 1 + 40 + 1);
+}
+
+fn noop_shim(_out: &mut MyBytesMut, _bytes: &[u8], _loc: Option<&mut SourceLocation>) -> io::Result<()> {
+  Ok(())
+}
+
+static BIG_SITE: CallSite = CallSite::new(Metadata {
+  fmt: "big",
+  level: Level::Info,
+  loc: SourceLocation::__new(module_path!(), file!(), line!(), column!()),
+  num_args: 0,
+  arg_names: &[],
+  field_names: &[],
+  log_fn: noop_shim,
+});
+
+#[test]
+fn publish_result_tells_full_from_oversized() {
+  let (logger, cap) = gated_logger();
+  assert_eq!(logger.publish_args(&BIG_SITE, &[7u64; 4]), PublishResult::Ok);
+  wait_for(&cap.out, 1);
+
+  // 比整个 staging 队列（1024 块）还大，什么时候都放不下
+  let huge = [0u8; 128 * 1024];
+  assert_eq!(logger.publish_args(&BIG_SITE, &huge[..]), PublishResult::PayloadTooLarge);
+  assert_eq!(logger.dropped_count(), 1);
+
+  cap.hold.store(true, Ordering::Release);
+  let mut result = PublishResult::Ok;
+  while result == PublishResult::Ok {
+    result = logger.publish_args(&BIG_SITE, &[7u64; 4]);
+  }
+  assert_eq!(result, PublishResult::QueueFull);
+  assert_eq!(logger.dropped_count(), 2);
+  cap.hold.store(false, Ordering::Release);
+}
+
+#[test]
+fn blocking_publish_waits_for_the_backend_to_drain() {
+  let (logger, cap) = gated_logger();
+  cap.hold.store(true, Ordering::Release);
+  let mut published = 0usize;
+  while logger.publish_args(&BIG_SITE, &[7u64; 4]).is_ok() {
+    published += 1;
+  }
+  let dropped = logger.dropped_count();
+  // 满了，短超时的阻塞发布也失败，并算作一次丢弃
+  assert_eq!(logger.publish_args_blocking(&BIG_SITE, &[7u64; 4], Duration::from_millis(5)), PublishResult::QueueFull);
+  assert_eq!(logger.dropped_count(), dropped + 1);
+
+  let release = std::thread::spawn({
+    let hold = cap.hold.clone();
+    move || {
+      std::thread::sleep(Duration::from_millis(50));
+      hold.store(false, Ordering::Release);
+    }
+  });
+  assert_eq!(logger.publish_args_blocking(&BIG_SITE, &[8u64; 4], Duration::from_secs(10)), PublishResult::Ok);
+  assert_eq!(logger.dropped_count(), dropped + 1);
+  release.join().unwrap();
+
+  let got = wait_matching(&cap.out, published + 1, |r| r.meta_id == BIG_SITE.id());
+  assert_eq!(got[published].payload, bytemuck::bytes_of(&[8u64; 4]));
+}
+
+#[test]
+fn payload_past_the_ring_fails_fast_even_when_blocking() {
+  let (logger, _cap) = gated_logger();
+  // 整个 ring 是 64KB，多 8 个字节就永远放不下
+  let just_over = [0u8; StagingBuffer::CAPACITY_BYTES - size_of::<MsgHeader>() + 8];
+  let start = Instant::now();
+  assert_eq!(
+    logger.publish_args_blocking(&BIG_SITE, &just_over[..], Duration::from_secs(10)),
+    PublishResult::PayloadTooLarge
+  );
+  assert!(start.elapsed() < Duration::from_secs(1));
+  assert_eq!(logger.dropped_count(), 1);
+}
+
+#[test]
+fn stats_count_pushes_and_occupancy() {
+  const N: u64 = 20;
+  let (logger, cap) = gated_logger();
+  cap.hold.store(true, Ordering::Release);
+  for i in 0..N {
+    assert!(hft_info!(logger, "stat {} {}", i, 0u64));
+  }
+
+  let stats = logger.stats();
+  assert_eq!(stats.queue_capacity, StagingBuffer::CAPACITY_BYTES);
+  assert_eq!(stats.queues, 1);
+  assert_eq!(stats.pushed, N);
+  assert_eq!(stats.dropped, 0);
+  assert!(stats.occupancy > 0);
+  assert!(stats.peak_occupancy >= stats.occupancy);
+
+  cap.hold.store(false, Ordering::Release);
+  wait_for(&cap.out, N as usize);
+  // 最后一条交给 sink 之后才 pop，占用要稍后才归零
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while logger.stats().occupancy != 0 {
+    assert!(Instant::now() < deadline, "queue never drained: {:?}", logger.stats());
+    std::thread::sleep(Duration::from_millis(1));
+  }
+  let stats = logger.stats();
+  assert_eq!(stats.pushed, N);
+  assert!(stats.peak_occupancy > 0);
+}
+
+#[test]
+fn drain_latency_counts_every_record() {
+  const N: u64 = 500;
+  let (logger, cap) = gated_logger();
+  assert_eq!(logger.drain_latency_percentiles().count, 0);
+  // 先堵住 backend，让这一批在队列里多待一会儿
+  cap.hold.store(true, Ordering::Release);
+  for i in 0..N {
+    assert!(hft_info!(logger, "lat {} {}", i, 0u64));
+  }
+  std::thread::sleep(Duration::from_millis(2));
+  cap.hold.store(false, Ordering::Release);
+  wait_for(&cap.out, N as usize);
+
+  let lat = logger.drain_latency_percentiles();
+  assert_eq!(lat.count, N, "{lat:?}");
+  assert!(lat.p50 <= lat.p90 && lat.p90 <= lat.p99 && lat.p99 <= lat.p999 && lat.p999 <= lat.max, "{lat:?}");
+  // 至少被堵的那 2ms
+  assert!(lat.max >= 1 << 20, "{lat:?}");
+}
@@ -0,0 +1,131 @@
+use std::fmt;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use bytemuck::{Pod, Zeroable};
+use hft_log_demo::args2::{register_user_pod, UserPod};
+use hft_log_demo::binary_sink::{BinaryDecoder, BinarySink};
+use hft_log_demo::file_sink::FileBatchSink;
+use hft_log_demo::hft_info;
+use hft_log_demo::run_log2::init_logger_with;
+use hft_log_demo::sink::{MsgHeader, Sink};
+
+/// 同一条记录同时写文本和二进制，用来对比解码结果
+struct TeeSink {
+  text: FileBatchSink,
+  bin: BinarySink,
+}
+
+impl Sink for TeeSink {
+  fn on_record(&mut self, tid: usize, hdr: &MsgHeader, payload: &[u8]) -> io::Result<()> {
+    self.text.on_record(tid, hdr, payload)?;
+    self.bin.on_record(tid, hdr, payload)
+  }
+
+  fn on_idle(&mut self, now_cycles: i64) -> io::Result<()> {
+    self.text.on_idle(now_cycles)?;
+    self.bin.on_idle(now_cycles)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.text.flush()?;
+    self.bin.flush()
+  }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+  let path = std::env::temp_dir().join(format!("hft_{}_{}.log", name, std::process::id()));
+  let _ = std::fs::remove_file(&path);
+  path
+}
+
+#[test]
+fn decoded_binary_matches_text_output() {
+  let text_path = temp_path("bin_text");
+  let bin_path = temp_path("bin_raw");
+
+  let logger = {
+    let (text_path, bin_path) = (text_path.clone(), bin_path.clone());
+    init_logger_with(1 << 16, None, move || TeeSink {
+      text: FileBatchSink::new(text_path).unwrap(),
+      bin: BinarySink::new(bin_path).unwrap(),
+    })
+  };
+  assert!(hft_info!(logger, "sym={} px={:.2} qty={:>6}", "BTCUSDT", 65000.125f64, 3u32));
+  assert!(hft_info!(logger, "{} {} {:+} {:08.3} {:<4}|", -17i64, true, 5i32, 1.23456f64, 'x'));
+  assert!(hft_info!(logger, "one {}", u64::MAX));
+  assert!(hft_info!(logger, "px={px} qty={qty:>4}", px = 1.5f64, qty = 7u64));
+  assert!(hft_info!(logger, "heartbeat"));
+  logger.shutdown();
+
+  let text = std::fs::read_to_string(&text_path).unwrap();
+  let mut decoder = BinaryDecoder::new(BufReader::new(std::fs::File::open(&bin_path).unwrap())).unwrap();
+  let mut decoded = String::new();
+  while let Some(line) = decoder.next_line().unwrap() {
+    decoded.push_str(std::str::from_utf8(line).unwrap());
+  }
+
+  assert_eq!(text.lines().count(), 5);
+  assert_eq!(decoded, text);
+  std::fs::remove_file(&text_path).unwrap();
+  std::fs::remove_file(&bin_path).unwrap();
+}
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct Quote {
+  bid: u64,
+  ask: u64,
+}
+
+impl fmt::Display for Quote {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}", self.bid, self.ask)
+  }
+}
+
+impl UserPod for Quote {}
+
+const POD_CHILD_ENV: &str = "HFT_BINARY_POD_CHILD";
+
+fn decode_all(path: &Path) -> String {
+  let mut decoder = BinaryDecoder::new(BufReader::new(std::fs::File::open(path).unwrap())).unwrap();
+  let mut decoded = String::new();
+  while let Some(line) = decoder.next_line().unwrap() {
+    decoded.push_str(std::str::from_utf8(line).unwrap());
+  }
+  decoded
+}
+
+// 子进程：上一个 "session"，记下一条带 UserPod 的二进制日志就退出
+#[test]
+fn child_writes_a_user_pod() {
+  let Ok(path) = std::env::var(POD_CHILD_ENV) else {
+    return;
+  };
+  let logger = init_logger_with(1 << 16, None, move || BinarySink::new(path).unwrap());
+  assert!(hft_info!(logger, "quote {}", Quote { bid: 100, ask: 101 }));
+  logger.shutdown();
+}
+
+#[test]
+fn user_pod_decodes_in_another_process_through_the_registry() {
+  let bin_path = temp_path("bin_pod");
+  let status = Command::new(std::env::current_exe().unwrap())
+    .args(["--exact", "child_writes_a_user_pod", "--test-threads=1"])
+    .env(POD_CHILD_ENV, &bin_path)
+    .status()
+    .unwrap();
+  assert!(status.success(), "{status:?}");
+
+  // 这个进程从没记过 Quote，解码器不认识它的 id，只能给出原始字节
+  let raw = decode_all(&bin_path);
+  assert!(raw.trim_end().ends_with("quote pod[64 00 00 00 00 00 00 00 65 00 00 00 00 00 00 00]"), "{raw}");
+
+  // 按类型注册以后，同一个文件就能用 Display 解出来
+  register_user_pod::<Quote>();
+  let decoded = decode_all(&bin_path);
+  assert!(decoded.trim_end().ends_with("quote 100/101"), "{decoded}");
+  std::fs::remove_file(&bin_path).unwrap();
+}
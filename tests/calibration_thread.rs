@@ -0,0 +1,29 @@
+// 单独一个测试进程，线程数才不会被并行跑的别的测试干扰
+#![cfg(target_os = "linux")]
+
+use hft_log_demo::capture_sink::CaptureSink;
+use hft_log_demo::hft_info;
+use hft_log_demo::run_log2::init_logger_with;
+
+fn thread_count() -> usize {
+  let status = std::fs::read_to_string("/proc/self/status").unwrap();
+  let line = status.lines().find(|l| l.starts_with("Threads:")).unwrap();
+  line["Threads:".len()..].trim().parse().unwrap()
+}
+
+#[test]
+fn repeated_init_and_shutdown_leaves_no_threads_behind() {
+  let baseline = thread_count();
+  for round in 0..5u64 {
+    let a = init_logger_with(1 << 16, None, CaptureSink::new);
+    let b = init_logger_with(1 << 16, None, CaptureSink::new);
+    assert!(hft_info!(a, "round {}", round));
+    // 两个 backend + 共用的一个校准线程
+    assert_eq!(thread_count(), baseline + 3);
+    a.shutdown();
+    // 还有 logger 在跑，校准线程留着
+    assert_eq!(thread_count(), baseline + 2);
+    b.shutdown();
+    assert_eq!(thread_count(), baseline, "round {round}");
+  }
+}
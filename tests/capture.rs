@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+use hft_log_demo::capture_sink::CaptureSink;
+use hft_log_demo::file_sink::FileBatchSink;
+use hft_log_demo::format::{HeaderConfig, HeaderField};
+use hft_log_demo::run_log2::{init_logger_with, init_logger_with_time, LoggerHandle};
+use hft_log_demo::tscns::{ManualClock, TimeSource};
+use hft_log_demo::{hft_fatal, hft_info, hft_warn};
+
+#[test]
+fn in_memory_captures_lines_across_levels() {
+  let logger = LoggerHandle::in_memory();
+  assert!(hft_info!(logger, "order {} sent", 42u64));
+  assert!(hft_warn!(logger, "order {} rejected: {}", 42u64, "no margin"));
+
+  // 没有 backend 线程，captured_lines 在当前线程上把队列排空
+  let lines = logger.captured_lines();
+  assert_eq!(lines.len(), 2, "{lines:?}");
+  assert!(lines[0].contains(" info  ") && lines[0].ends_with("] order 42 sent"), "{}", lines[0]);
+  assert!(lines[1].contains(" warn  ") && lines[1].ends_with("] order 42 rejected: no margin"), "{}", lines[1]);
+
+  // 再取一次是累计的结果
+  assert!(hft_info!(logger, "order {} filled", 43u64));
+  assert_eq!(logger.captured_lines().len(), 3);
+}
+
+#[test]
+fn in_memory_with_a_fixed_header() {
+  let sink = CaptureSink::new().with_header(HeaderConfig::new(&[HeaderField::Level]));
+  let logger = LoggerHandle::in_memory_with(sink);
+  assert!(hft_warn!(logger, "px {} qty {}", 101u64, 5u64));
+  assert!(hft_info!(logger, "px {} qty {}", 102u64, 6u64));
+  assert_eq!(logger.captured_lines(), ["[warn ] px 101 qty 5", "[info ] px 102 qty 6"]);
+}
+
+#[test]
+fn lines_from_other_threads_are_captured_too() {
+  let logger = LoggerHandle::in_memory();
+  std::thread::scope(|s| {
+    s.spawn(|| assert!(hft_info!(logger, "from {}", "worker")));
+  });
+  let lines = logger.captured_lines();
+  assert_eq!(lines.len(), 1);
+  assert!(lines[0].ends_with("] from worker"), "{}", lines[0]);
+}
+
+#[test]
+fn pinned_clock_gives_byte_exact_headers() {
+  // 2026-01-16 09:33:36.123456789 UTC
+  let clock = Arc::new(ManualClock::new(1_768_556_016_123_456_789));
+  let sink = CaptureSink::new()
+    .with_clock(clock.clone())
+    .with_header(HeaderConfig::new(&[HeaderField::Time, HeaderField::Level]));
+  let logger = LoggerHandle::in_memory_with(sink);
+
+  assert!(hft_info!(logger, "px {}", 101u64));
+  assert_eq!(logger.captured_lines(), ["[01-16 09:33:36.123.456 info ] px 101"]);
+
+  clock.advance_ns(86_400 * 1_000_000_000 + 1_000);
+  assert!(hft_warn!(logger, "px {}", 102u64));
+  assert_eq!(logger.captured_lines()[1], "[01-17 09:33:36.123.457 warn ] px 102");
+
+  let default = CaptureSink::new().with_clock(Arc::new(ManualClock::new(1_768_556_016_123_456_789)));
+  let logger = LoggerHandle::in_memory_with(default);
+  assert!(hft_info!(logger, "px {}", 103u64));
+  let line = &logger.captured_lines()[0];
+  assert!(line.starts_with("[01-16 09:33:36.123.456 T="), "{line}");
+}
+
+#[test]
+fn custom_level_names_show_up() {
+  let sink = CaptureSink::new()
+    .with_level_names(&["TRC", "DBG", "INF", "WRN", "ERR", "FTL"])
+    .with_header(HeaderConfig::new(&[HeaderField::Level]));
+  let logger = LoggerHandle::in_memory_with(sink);
+  assert!(hft_info!(logger, "px {}", 1u64));
+  assert!(hft_warn!(logger, "px {}", 2u64));
+  assert!(hft_fatal!(logger, "px {}", 3u64));
+  assert_eq!(logger.captured_lines(), ["[INF] px 1", "[WRN] px 2", "[FTL] px 3"]);
+}
+
+#[test]
+#[should_panic(expected = "level names must cover all 6 levels")]
+fn level_names_must_cover_every_level() {
+  let _ = CaptureSink::new().with_level_names(&["T", "D", "I", "W", "E"]);
+}
+
+#[test]
+fn custom_time_source_drives_the_rendered_times() {
+  // 回放用的模拟时钟：每渲染一条走 1 秒
+  let sim = Arc::new(AtomicI64::new(1_768_556_016_123_456_789));
+  let time = TimeSource::custom({
+    let sim = sim.clone();
+    move || sim.fetch_add(1_000_000_000, Ordering::Relaxed)
+  });
+  let sink = CaptureSink::new().with_header(HeaderConfig::new(&[HeaderField::Time]));
+  let lines = sink.lines();
+  let logger = init_logger_with_time(1 << 16, None, time, move || sink);
+
+  for i in 0..3u64 {
+    assert!(hft_info!(logger, "tick {}", i));
+  }
+  assert!(logger.flush());
+  assert_eq!(
+    *lines.lock().unwrap(),
+    ["[01-16 09:33:36.123.456] tick 0", "[01-16 09:33:37.123.456] tick 1", "[01-16 09:33:38.123.456] tick 2"]
+  );
+}
+
+#[test]
+fn control_characters_in_str_args_are_escaped() {
+  let sink = CaptureSink::new()
+    .with_header(HeaderConfig::new(&[HeaderField::Level]))
+    .with_escape_strings(true);
+  let logger = LoggerHandle::in_memory_with(sink);
+  assert!(hft_info!(logger, "user {}|{:>12}|", "a\nb\x1b[31m", "x\ty"));
+  assert_eq!(logger.captured_lines(), [r"[info ] user a\nb\x1b[31m|        x\ty|"]);
+
+  // 默认不转义，原样输出
+  let logger = LoggerHandle::in_memory_with(CaptureSink::new().with_header(HeaderConfig::new(&[])));
+  assert!(hft_info!(logger, "user {}", "a\nb"));
+  assert_eq!(logger.captured_lines().concat(), "user a\nb");
+}
+
+#[test]
+fn file_sink_keeps_one_record_on_one_line() {
+  let path = std::env::temp_dir().join(format!("hft_escape_{}.log", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+  let logger = {
+    let path = path.clone();
+    init_logger_with(1 << 16, None, move || FileBatchSink::new(path).unwrap())
+  };
+  assert!(hft_info!(logger, "user {}", "a\nb\x1b[31m"));
+  logger.shutdown();
+
+  let text = std::fs::read_to_string(&path).unwrap();
+  assert_eq!(text.lines().count(), 1, "{text}");
+  assert!(text.ends_with("user a\\nb\\x1b[31m\n"), "{text}");
+  std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,135 @@
+#![allow(dead_code)]
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hft_log_demo::metadata;
+use hft_log_demo::my_bytes_mut::MyBytesMut;
+use hft_log_demo::run_log2::{init_logger_with, LoggerHandle};
+use hft_log_demo::sink::{MsgHeader, Sink};
+
+#[derive(Clone, Debug)]
+pub struct Record {
+  pub tid: usize,
+  pub level: u32,
+  pub tsc: i64,
+  pub meta_id: u32,
+  pub payload: Vec<u8>,
+  /// shim 渲染出来的 `module::file#line] msg`
+  pub line: String,
+}
+
+impl Record {
+  /// 去掉 location 之后的消息正文
+  pub fn msg(&self) -> &str {
+    match self.line.find("] ") {
+      Some(i) => &self.line[i + 2..],
+      None => &self.line,
+    }
+  }
+}
+
+pub type Captured = Arc<Mutex<Vec<Record>>>;
+
+/// 测试用的内存 sink：记下 header、原始 payload 和渲染结果
+pub struct VecSink {
+  pub out: Captured,
+  /// 为 true 时 on_record 卡住不返回，用来把 staging 队列堵满
+  pub hold: Arc<AtomicBool>,
+  pub flushed: Arc<AtomicBool>,
+}
+
+impl Sink for VecSink {
+  fn on_record(&mut self, tid: usize, hdr: &MsgHeader, payload: &[u8]) -> io::Result<()> {
+    while self.hold.load(Ordering::Acquire) {
+      std::thread::sleep(Duration::from_micros(100));
+    }
+    let meta = metadata::get(hdr.meta_id as u32).expect("unregistered meta_id");
+    let mut line = MyBytesMut::with_capacity(512);
+    (meta.log_fn)(&mut line, payload, None)?;
+    self.out.lock().unwrap().push(Record {
+      tid,
+      level: hdr.level,
+      tsc: hdr.tsc,
+      meta_id: hdr.meta_id as u32,
+      payload: payload.to_vec(),
+      line: String::from_utf8_lossy(line.result()).into_owned(),
+    });
+    Ok(())
+  }
+
+  fn on_idle(&mut self, _now_cycles: i64) -> io::Result<()> {
+    Ok(())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.flushed.store(true, Ordering::Release);
+    Ok(())
+  }
+}
+
+pub struct Capture {
+  pub out: Captured,
+  pub hold: Arc<AtomicBool>,
+  pub flushed: Arc<AtomicBool>,
+}
+
+impl Capture {
+  pub fn new() -> Self {
+    Capture {
+      out: Arc::default(),
+      hold: Arc::default(),
+      flushed: Arc::default(),
+    }
+  }
+
+  pub fn sink(&self) -> VecSink {
+    VecSink {
+      out: self.out.clone(),
+      hold: self.hold.clone(),
+      flushed: self.flushed.clone(),
+    }
+  }
+}
+
+pub fn capture_logger() -> (LoggerHandle, Captured) {
+  let (logger, cap) = gated_logger();
+  (logger, cap.out)
+}
+
+/// 和 capture_logger 一样，但能通过 `cap.hold` 让 backend 停在 on_record 里
+pub fn gated_logger() -> (LoggerHandle, Capture) {
+  let cap = Capture::new();
+  let sink = cap.sink();
+  let logger = init_logger_with(1 << 16, None, move || sink);
+  (logger, cap)
+}
+
+pub fn wait_for(out: &Captured, n: usize) -> Vec<Record> {
+  let deadline = Instant::now() + Duration::from_secs(10);
+  loop {
+    {
+      let got = out.lock().unwrap();
+      if got.len() >= n {
+        return got.clone();
+      }
+    }
+    assert!(Instant::now() < deadline, "backend did not deliver {} records", n);
+    std::thread::sleep(Duration::from_millis(1));
+  }
+}
+
+/// Like [`wait_for`], counting only the records `keep` accepts (e.g. skipping the drop reports).
+pub fn wait_matching(out: &Captured, n: usize, keep: impl Fn(&Record) -> bool) -> Vec<Record> {
+  let deadline = Instant::now() + Duration::from_secs(10);
+  loop {
+    let got: Vec<Record> = out.lock().unwrap().iter().filter(|r| keep(r)).cloned().collect();
+    if got.len() >= n {
+      return got;
+    }
+    assert!(Instant::now() < deadline, "backend did not deliver {} records", n);
+    std::thread::sleep(Duration::from_millis(1));
+  }
+}
@@ -0,0 +1,6 @@
+// 编译期检查：超大的 UserPod / 超长的参数列表必须在编译时报错
+#[test]
+fn oversized_args_are_rejected() {
+  let t = trybuild::TestCases::new();
+  t.compile_fail("tests/ui/*.rs");
+}
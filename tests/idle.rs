@@ -0,0 +1,40 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use hft_log_demo::hft_info;
+use hft_log_demo::run_log2::{init_logger_with_idle, IdleStrategy};
+
+use common::{wait_for, Capture};
+
+/// CPU time of the whole process; this binary has a single test, so it is the backend plus the test thread.
+fn process_cpu_time() -> Duration {
+  let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+  unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut ts) };
+  Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+#[test]
+fn parked_backend_stays_cheap_and_still_drains() {
+  const PARK: Duration = Duration::from_millis(5);
+  let cap = Capture::new();
+  let sink = cap.sink();
+  let logger = init_logger_with_idle(1 << 16, None, IdleStrategy::Park(PARK), move || sink);
+
+  // 空闲 300ms：park 的 backend 几乎不占 CPU（一直 spin 的话接近 300ms）
+  std::thread::sleep(Duration::from_millis(20));
+  let cpu0 = process_cpu_time();
+  std::thread::sleep(Duration::from_millis(300));
+  let idle_cpu = process_cpu_time() - cpu0;
+  assert!(idle_cpu < Duration::from_millis(30), "idle backend used {:?}", idle_cpu);
+
+  // 还在 park 里的 backend 最多睡一个间隔就能看到新消息
+  for i in 0..5u64 {
+    let sent = Instant::now();
+    assert!(hft_info!(logger, "wake {} {}", i, 0u64));
+    wait_for(&cap.out, i as usize + 1);
+    assert!(sent.elapsed() < PARK * 10, "drained after {:?}", sent.elapsed());
+    std::thread::sleep(Duration::from_millis(7));
+  }
+  logger.shutdown();
+}
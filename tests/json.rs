@@ -0,0 +1,35 @@
+use hft_log_demo::file_sink::FileBatchSink;
+use hft_log_demo::format::LineFormat;
+use hft_log_demo::hft_info;
+use hft_log_demo::run_log2::init_logger_with;
+
+#[test]
+fn json_lines_parse_and_carry_every_field() {
+  let path = std::env::temp_dir().join(format!("hft_json_{}.log", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+
+  let logger = {
+    let path = path.clone();
+    init_logger_with(1 << 16, None, move || FileBatchSink::new(path).unwrap().with_format(LineFormat::Json))
+  };
+  let line = line!() + 1;
+  assert!(hft_info!(logger, "sym={} px={}", "BTC\"USDT\\\n", 65000u64));
+  assert!(hft_info!(logger, "ctrl {} {}", "\t\x01", 1u64));
+  logger.shutdown();
+
+  let text = std::fs::read_to_string(&path).unwrap();
+  let lines: Vec<serde_json::Value> = text.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+  assert_eq!(lines.len(), 2);
+
+  let v = &lines[0];
+  assert!(v["ts"].as_i64().is_some());
+  assert!(v["tid"].as_u64().is_some());
+  assert_eq!(v["level"], "info");
+  assert_eq!(v["module"], "json");
+  assert_eq!(v["file"], file!());
+  assert_eq!(v["line"], line);
+  assert_eq!(v["column"], 11);
+  assert_eq!(v["msg"], "sym=BTC\"USDT\\\n px=65000");
+  assert_eq!(lines[1]["msg"], "ctrl \t\x01 1");
+  std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use std::cell::Cell;
+use std::sync::Mutex;
+
+use hft_log_demo::hft_info;
+use hft_log_demo::log::{
+  clear_module_level, clear_module_levels, enabled, enabled_for, max_level, set_max_level, set_module_level, Level,
+};
+use hft_log_demo::run_log2::LoggerHandle;
+
+// 级别是进程级全局变量，改级别的测试串行跑
+static LEVEL_LOCK: Mutex<()> = Mutex::new(());
+
+fn handle() -> LoggerHandle {
+  common::capture_logger().0
+}
+
+#[test]
+fn max_level_flips_at_runtime() {
+  let _g = LEVEL_LOCK.lock().unwrap();
+  set_max_level(Level::Warn);
+  assert_eq!(max_level(), Level::Warn);
+  assert!(!enabled(Level::Trace));
+  assert!(!enabled(Level::Info));
+  assert!(enabled(Level::Warn));
+  assert!(enabled(Level::Error));
+
+  set_max_level(Level::Trace);
+  assert_eq!(max_level(), Level::Trace);
+  assert!(enabled(Level::Trace));
+}
+
+#[test]
+fn below_threshold_never_reaches_the_queue() {
+  let _g = LEVEL_LOCK.lock().unwrap();
+  let logger = handle();
+  let evaluated = Cell::new(0u32);
+  let arg = || {
+    evaluated.set(evaluated.get() + 1);
+    7u64
+  };
+
+  set_max_level(Level::Warn);
+  for _ in 0..100 {
+    assert!(!hft_info!(logger, "filtered {} {}", arg(), 1u64));
+  }
+  assert_eq!(evaluated.get(), 0);
+
+  set_max_level(Level::Info);
+  assert!(hft_info!(logger, "kept {} {}", arg(), 1u64));
+  assert_eq!(evaluated.get(), 1);
+  set_max_level(Level::Trace);
+}
+
+#[test]
+fn module_rule_covers_nested_modules_only() {
+  let _g = LEVEL_LOCK.lock().unwrap();
+  set_max_level(Level::Info);
+  set_module_level("app::hotloop", Level::Error);
+
+  assert!(!enabled_for(Level::Warn, "app::hotloop"));
+  assert!(!enabled_for(Level::Warn, "app::hotloop::inner::deeper"));
+  assert!(enabled_for(Level::Error, "app::hotloop::inner"));
+  // 只按 :: 边界匹配，前缀相同的兄弟模块不受影响
+  assert!(enabled_for(Level::Warn, "app::hotloopy"));
+  // 没有规则命中时回落到全局级别
+  assert!(enabled_for(Level::Info, "app::other"));
+  assert!(!enabled_for(Level::Debug, "app::other"));
+  set_max_level(Level::Trace);
+}
+
+#[test]
+fn most_specific_overlapping_prefix_wins() {
+  let _g = LEVEL_LOCK.lock().unwrap();
+  set_max_level(Level::Info);
+  set_module_level("svc", Level::Warn);
+  set_module_level("svc::md::binance", Level::Trace);
+  set_module_level("svc::md", Level::Error);
+
+  assert!(!enabled_for(Level::Info, "svc::oms"));
+  assert!(enabled_for(Level::Warn, "svc::oms"));
+  assert!(!enabled_for(Level::Warn, "svc::md::okx"));
+  assert!(enabled_for(Level::Trace, "svc::md::binance::book"));
+
+  // 同一个前缀再设一次是覆盖，不是追加
+  set_module_level("svc::md", Level::Debug);
+  assert!(enabled_for(Level::Debug, "svc::md::okx"));
+  set_max_level(Level::Trace);
+}
+
+#[test]
+fn cleared_overrides_fall_back_to_the_global_level() {
+  let _g = LEVEL_LOCK.lock().unwrap();
+  set_max_level(Level::Info);
+  set_module_level("gone", Level::Error);
+  set_module_level("gone::inner", Level::Trace);
+  assert!(!enabled_for(Level::Warn, "gone"));
+
+  assert!(clear_module_level("gone"));
+  assert!(!clear_module_level("gone"));
+  assert!(enabled_for(Level::Warn, "gone"));
+  assert!(!enabled_for(Level::Debug, "gone"));
+  assert!(enabled_for(Level::Trace, "gone::inner"));
+
+  clear_module_levels();
+  assert!(!enabled_for(Level::Debug, "gone::inner"));
+  assert!(enabled_for(Level::Info, "gone::inner"));
+  set_max_level(Level::Trace);
+}
+
+mod hotpath {
+  use super::*;
+
+  #[test]
+  fn macros_gate_on_their_own_module_path() {
+    let _g = LEVEL_LOCK.lock().unwrap();
+    let logger = handle();
+    set_module_level("level_filter::hotpath", Level::Warn);
+    assert!(!hft_info!(logger, "quiet {} {}", 1u64, 2u64));
+    set_module_level("level_filter::hotpath", Level::Trace);
+    assert!(hft_info!(logger, "loud {} {}", 1u64, 2u64));
+  }
+
+  #[test]
+  fn site_cache_sees_later_changes() {
+    let _g = LEVEL_LOCK.lock().unwrap();
+    let logger = handle();
+    set_max_level(Level::Info);
+    let log = || hft_info!(logger, "cached {} {}", 1u64, 2u64);
+    set_module_level("level_filter::hotpath", Level::Warn);
+    assert!(!log());
+    assert!(!log());
+    clear_module_level("level_filter::hotpath");
+    assert!(log());
+    set_module_level("level_filter::hotpath", Level::Error);
+    assert!(!log());
+    clear_module_levels();
+    assert!(log());
+    set_max_level(Level::Trace);
+  }
+}
@@ -0,0 +1,196 @@
+mod common;
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use hft_log_demo::{hft_fatal, hft_info};
+use hft_log_demo::file_sink::FileBatchSink;
+use hft_log_demo::log::{Level, SourceLocation};
+use hft_log_demo::metadata;
+use hft_log_demo::my_bytes_mut::MyBytesMut;
+use hft_log_demo::run_log;
+use hft_log_demo::run_log2::init_logger_with;
+use hft_log_demo::sink::{MsgHeader, Sink};
+
+use common::{capture_logger, gated_logger, wait_for, Capture};
+
+#[test]
+fn shutdown_drains_flushes_and_joins() {
+  let (logger, cap) = gated_logger();
+  // backend 卡在第一条上，剩下的都还在队列里
+  cap.hold.store(true, Ordering::Release);
+
+  const N: u64 = 500;
+  for i in 0..N {
+    assert!(hft_info!(logger, "line {} of {}", i, N));
+  }
+  cap.hold.store(false, Ordering::Release);
+  logger.shutdown();
+
+  // shutdown 返回时 backend 已经 join，不需要再等
+  let got = cap.out.lock().unwrap();
+  assert_eq!(got.len(), N as usize);
+  for (i, r) in got.iter().enumerate() {
+    assert_eq!(r.msg(), format!("line {} of {}", i, N));
+  }
+  assert!(cap.flushed.load(Ordering::Acquire));
+}
+
+#[test]
+fn run_log_shutdown_joins_the_backend() {
+  let logger = run_log::init_logger(1024);
+  logger.shutdown();
+}
+
+#[test]
+fn unpinned_or_missing_core_still_logs() {
+  for core in [None, Some(usize::MAX)] {
+    let cap = Capture::new();
+    let sink = cap.sink();
+    let logger = init_logger_with(1 << 16, core, move || sink);
+    assert!(hft_info!(logger, "core {} {}", 1u64, 2u64));
+    assert_eq!(wait_for(&cap.out, 1)[0].msg(), "core 1 2");
+    logger.shutdown();
+  }
+
+  run_log::init_logger_on(1024, None).shutdown();
+}
+
+fn bad_shim(_out: &mut MyBytesMut, _bytes: &[u8], _loc: Option<&mut SourceLocation>) -> io::Result<()> {
+  panic!("corrupted record");
+}
+
+#[test]
+fn panicking_log_fn_is_skipped_and_counted() {
+  let (logger, out) = capture_logger();
+  let site = metadata::dynamic_site(Level::Info, "lifecycle", "bad.rs", 1, "bad", 0, bad_shim);
+  assert!(hft_info!(logger, "before {}", 1u64));
+  assert!(logger.log(site, &[]));
+  assert!(logger.log(site, &[]));
+  assert!(hft_info!(logger, "after {}", 2u64));
+
+  let got = wait_for(&out, 2);
+  assert_eq!(got[0].msg(), "before 1");
+  assert_eq!(got[1].msg(), "after 2");
+  assert_eq!(logger.record_errors(), 2);
+}
+
+// on_idle 每次都失败的 sink，只数收到的记录
+struct IdleFails(Arc<AtomicU64>);
+
+impl Sink for IdleFails {
+  fn on_record(&mut self, _tid: usize, _hdr: &MsgHeader, _payload: &[u8]) -> io::Result<()> {
+    self.0.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+  }
+
+  fn on_idle(&mut self, _now_cycles: i64) -> io::Result<()> {
+    Err(io::Error::other("idle flush failed"))
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+#[test]
+fn failing_on_idle_is_counted_and_the_backend_keeps_running() {
+  let seen = Arc::new(AtomicU64::new(0));
+  let logger = init_logger_with(1 << 16, None, {
+    let seen = seen.clone();
+    move || IdleFails(seen)
+  });
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while logger.record_errors() == 0 {
+    assert!(Instant::now() < deadline, "on_idle never ran");
+    std::thread::sleep(Duration::from_millis(1));
+  }
+  assert!(hft_info!(logger, "after {}", 1u64));
+  while seen.load(Ordering::Relaxed) == 0 {
+    assert!(Instant::now() < deadline, "backend stopped after the on_idle error");
+    std::thread::sleep(Duration::from_millis(1));
+  }
+  logger.shutdown();
+}
+
+#[test]
+fn fatal_flushes_before_returning() {
+  let (logger, cap) = gated_logger();
+  assert!(hft_info!(logger, "before {}", 1u64));
+  assert!(!cap.flushed.load(Ordering::Acquire));
+  assert!(hft_fatal!(logger, "halting: {}", "risk breach"));
+  // hft_fatal! 返回时 backend 已经写完并 flush 过
+  assert!(cap.flushed.load(Ordering::Acquire));
+  let got = cap.out.lock().unwrap().clone();
+  assert_eq!(got.len(), 2);
+  assert_eq!(got[1].msg(), "halting: risk breach");
+  assert_eq!(got[1].level, Level::Fatal as u32);
+}
+
+#[test]
+fn fatal_evaluates_the_logger_once() {
+  let (logger, cap) = gated_logger();
+  let mut evals = 0;
+  assert!(hft_fatal!({ evals += 1; &logger }, "halting: {}", "once"));
+  assert_eq!(evals, 1);
+  assert!(cap.flushed.load(Ordering::Acquire));
+  assert_eq!(cap.out.lock().unwrap().last().unwrap().msg(), "halting: once");
+}
+
+#[test]
+fn fatal_waits_for_room_on_a_full_queue() {
+  let (logger, cap) = gated_logger();
+  cap.hold.store(true, Ordering::Release);
+  let mut published = 0u64;
+  while hft_info!(logger, "fill {} {}", published, 0u64) {
+    published += 1;
+  }
+  let release = std::thread::spawn({
+    let hold = cap.hold.clone();
+    move || {
+      std::thread::sleep(Duration::from_millis(50));
+      hold.store(false, Ordering::Release);
+    }
+  });
+  assert!(hft_fatal!(logger, "halting: {}", "queue full"));
+  release.join().unwrap();
+
+  // 填满时被拒的那条之后会有一行汇总，不算
+  let got = cap.out.lock().unwrap().clone();
+  let last = got.iter().rev().find(|r| !r.msg().starts_with("dropped ")).unwrap();
+  assert_eq!(last.msg(), "halting: queue full");
+  assert_eq!(got.iter().filter(|r| r.msg().starts_with("fill ")).count(), published as usize);
+}
+
+const ABORT_CHILD_ENV: &str = "HFT_ABORT_ON_FATAL_CHILD";
+
+// 子进程：打开 abort_on_fatal 后打一条 fatal，进程应该在写完之后 abort
+#[test]
+fn child_aborts_on_fatal() {
+  let Ok(path) = std::env::var(ABORT_CHILD_ENV) else {
+    return;
+  };
+  let logger = init_logger_with(1 << 16, None, move || FileBatchSink::new(&path).unwrap());
+  hft_log_demo::log::set_abort_on_fatal(true);
+  hft_fatal!(logger, "halting: {}", "abort");
+  unreachable!("hft_fatal! should have aborted");
+}
+
+#[test]
+fn abort_on_fatal_aborts_after_the_line_is_written() {
+  use std::os::unix::process::ExitStatusExt;
+
+  let path = std::env::temp_dir().join(format!("hft_fatal_abort_{}.log", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+  let status = std::process::Command::new(std::env::current_exe().unwrap())
+    .args(["--exact", "child_aborts_on_fatal", "--test-threads=1"])
+    .env(ABORT_CHILD_ENV, &path)
+    .status()
+    .unwrap();
+  assert_eq!(status.signal(), Some(libc::SIGABRT), "{status:?}");
+  let text = std::fs::read_to_string(&path).unwrap();
+  assert!(text.trim_end().ends_with("halting: abort"), "{text}");
+  std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use hft_log_demo::hft_info;
+
+use common::{capture_logger, wait_for};
+
+/// "[location.rs#12:5] msg" -> (12, 5)
+fn line_col(line: &str) -> (u32, u32) {
+  let loc = &line[line.find('#').unwrap() + 1..line.find("] ").unwrap()];
+  let (l, c) = loc.split_once(':').unwrap();
+  (l.parse().unwrap(), c.parse().unwrap())
+}
+
+#[test]
+fn calls_on_one_line_get_distinct_columns() {
+  let (logger, out) = capture_logger();
+
+  let line = line!() + 1;
+  let (a, b) = (hft_info!(logger, "a {}", 1u64), hft_info!(logger, "b {}", 2u64));
+  assert!(a && b);
+
+  let got = wait_for(&out, 2);
+  let (la, ca) = line_col(&got[0].line);
+  let (lb, cb) = line_col(&got[1].line);
+  assert_eq!((la, lb), (line, line));
+  assert_eq!(ca, 17);
+  assert!(cb > ca, "{} vs {}", got[0].line, got[1].line);
+  assert_eq!(got[0].msg(), "a 1");
+}
@@ -0,0 +1,201 @@
+mod common;
+
+use std::io;
+
+use hft_log_demo::hft_info;
+use hft_log_demo::args2;
+use hft_log_demo::capture_sink::CaptureSink;
+use hft_log_demo::format::HeaderConfig;
+use hft_log_demo::log::{Level, SourceLocation, MAX_PAYLOAD_LEN};
+use hft_log_demo::metadata::{self, CallSite, Metadata};
+use hft_log_demo::my_bytes_mut::MyBytesMut;
+use hft_log_demo::run_log2::LoggerHandle;
+
+use common::{capture_logger, wait_for};
+
+fn px_shim(out: &mut MyBytesMut, _bytes: &[u8], _loc: Option<&mut SourceLocation>) -> io::Result<()> {
+  out.extend_from_slice(b"px");
+  Ok(())
+}
+
+const fn px_meta(line: u32) -> Metadata {
+  Metadata {
+    fmt: "px={}",
+    level: Level::Info,
+    loc: SourceLocation::__new("metadata", "tests/metadata.rs", line, 1),
+    num_args: 1,
+    arg_names: &[],
+    field_names: &[],
+    log_fn: px_shim,
+  }
+}
+
+static SITE_A: CallSite = CallSite::new(px_meta(7));
+static SITE_B: CallSite = CallSite::new(px_meta(7));
+static SITE_C: CallSite = CallSite::new(px_meta(8));
+// 同一位置、同一 fmt，但多了一个 field：不能共用 id，否则会用错 shim
+static SITE_D: CallSite = CallSite::new(Metadata {
+  fmt: "px={}",
+  level: Level::Info,
+  loc: SourceLocation::__new("metadata", "tests/metadata.rs", 7, 1),
+  num_args: 2,
+  arg_names: &[],
+  field_names: &["venue"],
+  log_fn: px_shim,
+});
+
+#[test]
+fn identical_sites_share_one_id() {
+  let a = SITE_A.id();
+  assert_eq!(SITE_B.id(), a);
+  assert_ne!(SITE_C.id(), a);
+  assert_ne!(SITE_D.id(), a);
+  // 第二次取直接读 static 里缓存的 id
+  assert_eq!(SITE_A.id(), a);
+
+  let meta = metadata::get(a).unwrap();
+  assert_eq!(meta.fmt, "px={}");
+  assert!(metadata::get(0).is_none());
+  assert!(metadata::get(u32::MAX).is_none());
+}
+
+fn log_fill(logger: &hft_log_demo::run_log2::LoggerHandle, px: u64) -> bool {
+  hft_info!(logger, "fill px={} qty={}", px, 3u64)
+}
+
+#[test]
+fn one_site_one_id_across_calls() {
+  let (logger, out) = capture_logger();
+  assert!(log_fill(&logger, 100));
+  assert!(log_fill(&logger, 101));
+  assert!(hft_info!(logger, "other {} {}", 1u64, 2u64));
+
+  let got = wait_for(&out, 3);
+  assert_eq!(got[0].meta_id, got[1].meta_id);
+  assert_ne!(got[0].meta_id, got[2].meta_id);
+  assert_eq!(got[0].msg(), "fill px=100 qty=3");
+  assert_eq!(got[1].msg(), "fill px=101 qty=3");
+  assert_eq!(got[2].msg(), "other 1 2");
+
+  let meta = metadata::get(got[0].meta_id).unwrap();
+  assert_eq!(meta.fmt, "fill px={} qty={}");
+  assert_eq!(meta.level, Level::Info);
+}
+
+// 桥接层自己的 shim：只写消息，location 由 sink 从 site 里取
+fn bridge_shim(out: &mut MyBytesMut, bytes: &[u8], _loc: Option<&mut SourceLocation>) -> io::Result<()> {
+  use std::io::Write;
+  let [px, qty] = args2::decode_args::<2>(bytes);
+  write!(out, "fill px={} qty={}", px, qty)
+}
+
+#[test]
+fn runtime_site_matches_the_macro_path() {
+  let (logger, out) = capture_logger();
+  assert!(log_fill(&logger, 100));
+
+  let site = metadata::dynamic_site(Level::Info, "bridge::feed", "feed.py", 42, "fill px={} qty={}", 2, bridge_shim);
+  assert!(std::ptr::eq(site, metadata::dynamic_site(Level::Info, "bridge::feed", "feed.py", 42, "fill px={} qty={}", 2, bridge_shim)));
+  let args = args2::args2(100u64, 3u64);
+  assert!(logger.log(site, &args2::encode_to_vec(&args)));
+  assert!(!logger.log(site, &[0u8; MAX_PAYLOAD_LEN + 1]));
+
+  let got = wait_for(&out, 2);
+  assert_eq!(got[1].msg(), got[0].msg());
+  assert_eq!(got[1].payload, got[0].payload);
+  assert_eq!(got[1].meta_id, site.id());
+  let meta = metadata::get(site.id()).unwrap();
+  let mut loc = MyBytesMut::with_capacity(64);
+  meta.loc.write_to(&mut loc);
+  assert!(String::from_utf8_lossy(loc.result()).starts_with("bridge::feed::feed.py#42"));
+}
+
+#[test]
+fn mismatched_fmt_and_args_are_detected() {
+  // fmt 只有一个 {}，却声明并写入了两个参数
+  let site = metadata::dynamic_site(Level::Info, "bridge::feed", "feed.py", 77, "fill px={}", 2, bridge_shim);
+  let args = args2::args2(100u64, 3u64);
+  let payload = &args2::encode_to_vec(&args);
+  let err = metadata::get(site.id()).unwrap().check_payload(payload).unwrap_err();
+  assert!(err.to_string().contains("fmt takes 1 args, site declares 2"), "{err}");
+
+  let logger = LoggerHandle::in_memory_with(CaptureSink::new().with_header(HeaderConfig::new(&[])));
+  let good = metadata::dynamic_site(Level::Info, "bridge::feed", "feed.py", 78, "fill px={} qty={}", 2, bridge_shim);
+  assert!(logger.log(good, payload));
+  let meta = metadata::get(good.id()).unwrap();
+  assert!(meta.check_payload(payload).is_ok());
+  // 同一个 site 换一组 tag（泛型函数里的 site 各实例共用）也不算错
+  let other = args2::args2(100u64, "3");
+  assert!(meta.check_payload(&args2::encode_to_vec(&other)).is_ok());
+  let mut long = payload.clone();
+  long.extend_from_slice(&[0; 8]);
+  assert!(meta.check_payload(&long).is_err());
+
+  // 对不上 fmt 的记录照样渲染，不 panic 也不丢
+  assert!(logger.log(site, payload));
+  assert!(logger.log(good, &args2::encode_to_vec(&other)));
+  assert_eq!(logger.captured_lines(), ["fill px=100 qty=3", "fill px=100 qty=3", "fill px=100 qty=3"]);
+  assert_eq!(logger.record_errors(), 0);
+}
+
+#[test]
+fn fmt_arg_count_follows_rust_format_rules() {
+  let cases: &[(&str, u8)] = &[
+    ("{0} {0}", 1),
+    ("{1} {0}", 2),
+    ("{:.*}", 2),
+    ("{:>1$}", 2),
+    ("{:.prec$} {{}} }}", 1),
+    ("{px} {qty:>8}", 0),
+    ("{} {:08.3} {:#x}", 3),
+  ];
+  for (line, &(fmt, num_args)) in (200..).zip(cases) {
+    let site = metadata::dynamic_site(Level::Info, "bridge::fmt", "fmt.py", line, fmt, num_args, bridge_shim);
+    let payload = match num_args {
+      0 => Vec::new(),
+      1 => args2::encode_to_vec(&args2::args1(1u64)),
+      2 => args2::encode_to_vec(&args2::args2(1u64, 2u64)),
+      _ => args2::encode_to_vec(&args2::args3(1u64, 2u64, 3u64)),
+    };
+    let meta = metadata::get(site.id()).unwrap();
+    assert!(meta.check_payload(&payload).is_ok(), "{fmt}: {:?}", meta.check_payload(&payload));
+  }
+}
+
+#[test]
+fn runtime_strings_are_interned_once() {
+  let a = metadata::intern(&String::from("intern px={}"));
+  let b = metadata::intern(&format!("intern {}={{}}", "px"));
+  assert_eq!(a, "intern px={}");
+  assert!(std::ptr::eq(a, b));
+
+  // 不同行的两个 runtime site 共用一份 fmt
+  let s1 = metadata::dynamic_site(Level::Info, &String::from("bridge::intern"), "intern.py", 1, &String::from("fill px={} qty={}"), 2, bridge_shim);
+  let s2 = metadata::dynamic_site(Level::Info, &String::from("bridge::intern"), "intern.py", 2, &String::from("fill px={} qty={}"), 2, bridge_shim);
+  assert!(!std::ptr::eq(s1, s2));
+  let (m1, m2) = (metadata::get(s1.id()).unwrap(), metadata::get(s2.id()).unwrap());
+  assert!(std::ptr::eq(m1.fmt, m2.fmt));
+
+  // 输出不受影响
+  let (logger, out) = capture_logger();
+  let args = args2::args2(100u64, 3u64);
+  assert!(logger.log(s1, &args2::encode_to_vec(&args)));
+  assert!(logger.log(s2, &args2::encode_to_vec(&args)));
+  let got = wait_for(&out, 2);
+  assert_eq!(got[0].msg(), "fill px=100 qty=3");
+  assert_eq!(got[1].msg(), "fill px=100 qty=3");
+  assert_ne!(got[0].meta_id, got[1].meta_id);
+}
+
+#[test]
+fn macro_sites_with_one_literal_share_the_fmt() {
+  let (logger, out) = capture_logger();
+  assert!(hft_info!(logger, "shared fmt px={}", 1u64));
+  assert!(hft_info!(logger, "shared fmt px={}", 2u64));
+
+  // 两个 call site，两个 id，fmt 是同一份字面量（rustc 合并），不用再 intern
+  let got = wait_for(&out, 2);
+  assert_ne!(got[0].meta_id, got[1].meta_id);
+  let (m1, m2) = (metadata::get(got[0].meta_id).unwrap(), metadata::get(got[1].meta_id).unwrap());
+  assert!(std::ptr::eq(m1.fmt.as_ptr(), m2.fmt.as_ptr()));
+}
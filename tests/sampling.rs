@@ -0,0 +1,61 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use hft_log_demo::{hft_info_every, hft_info_every_ms};
+
+use common::{capture_logger, wait_for};
+
+#[test]
+fn every_nth_hit_is_logged() {
+  let (logger, out) = capture_logger();
+
+  let mut emitted = 0;
+  for i in 0..1000u64 {
+    if hft_info_every!(logger, 100, "tick {}", i) {
+      emitted += 1;
+    }
+  }
+  assert_eq!(emitted, 10);
+
+  let got = wait_for(&out, 10);
+  let msgs: Vec<String> = got.iter().map(|r| r.msg().to_string()).collect();
+  let want: Vec<String> = (0..10)
+    .map(|k| if k == 0 { "tick 0".to_string() } else { format!("tick {} skipped=99", k * 100) })
+    .collect();
+  assert_eq!(msgs, want);
+}
+
+#[test]
+fn every_zero_logs_every_hit() {
+  let (logger, out) = capture_logger();
+
+  let n = 0u32;
+  for i in 0..3u64 {
+    assert!(hft_info_every!(logger, n, "tick {}", i));
+  }
+  let msgs: Vec<String> = wait_for(&out, 3).iter().map(|r| r.msg().to_string()).collect();
+  assert_eq!(msgs, ["tick 0", "tick 1", "tick 2"]);
+}
+
+#[test]
+fn at_most_once_per_window() {
+  let (logger, out) = capture_logger();
+
+  let start = Instant::now();
+  let mut emitted = 0;
+  let mut hits = 0u64;
+  while start.elapsed() < Duration::from_millis(100) {
+    if hft_info_every_ms!(logger, 40, "hit {}", hits) {
+      emitted += 1;
+    }
+    hits += 1;
+  }
+  // 0ms、40ms、80ms 各一次；慢机器上可能少一次
+  assert!((2..=3).contains(&emitted), "{} emissions over {} hits", emitted, hits);
+  let got = wait_for(&out, emitted);
+  assert_eq!(got[0].msg(), "hit 0");
+  // 第二行带上窗口内被跳过的次数：hit 0 之后、hit h 之前的 h-1 次
+  let (hit, skipped) = got[1].msg().strip_prefix("hit ").unwrap().split_once(" skipped=").unwrap();
+  assert_eq!(skipped.parse::<u64>().unwrap() + 1, hit.parse::<u64>().unwrap());
+}
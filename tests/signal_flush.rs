@@ -0,0 +1,45 @@
+// cargo test --features signal_flush --test signal_flush
+#![cfg(all(unix, feature = "signal_flush"))]
+
+use std::process::Command;
+use std::os::unix::process::ExitStatusExt;
+
+use hft_log_demo::file_sink::FileBatchSink;
+use hft_log_demo::hft_info;
+use hft_log_demo::run_log2::init_logger_with;
+
+const CHILD_ENV: &str = "HFT_SIGNAL_FLUSH_CHILD";
+const N: u64 = 200;
+
+// 子进程：写一批日志后给自己发 SIGTERM，handler 要等 backend 写完才让进程退出
+#[test]
+fn child_logs_then_raises_sigterm() {
+  let Ok(path) = std::env::var(CHILD_ENV) else {
+    return;
+  };
+  let logger = init_logger_with(1 << 16, None, move || FileBatchSink::new(&path).unwrap());
+  for i in 0..N {
+    assert!(hft_info!(logger, "line {} of {}", i, N));
+  }
+  unsafe { libc::raise(libc::SIGTERM) };
+  unreachable!("SIGTERM should have killed the process");
+}
+
+#[test]
+fn sigterm_flushes_buffered_lines_before_exit() {
+  let path = std::env::temp_dir().join(format!("hft_sigterm_{}.log", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+
+  let status = Command::new(std::env::current_exe().unwrap())
+    .args(["--exact", "child_logs_then_raises_sigterm", "--test-threads=1"])
+    .env(CHILD_ENV, &path)
+    .status()
+    .unwrap();
+  assert_eq!(status.signal(), Some(libc::SIGTERM), "{status:?}");
+
+  let text = std::fs::read_to_string(&path).unwrap();
+  let lines: Vec<&str> = text.lines().collect();
+  assert_eq!(lines.len(), N as usize, "{text}");
+  assert!(lines[N as usize - 1].ends_with(&format!("line {} of {}", N - 1, N)));
+  std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use hft_log_demo::args2::{args2, encode_to_vec};
+use hft_log_demo::file_sink::FileBatchSink;
+use hft_log_demo::hft_info;
+use hft_log_demo::run_log2::init_logger_with;
+use hft_log_demo::sink::{MsgHeader, Sink};
+use hft_log_demo::tee_sink::TeeSink;
+
+use common::{capture_logger, wait_for};
+
+#[test]
+fn custom_sink_gets_exact_payload_bytes() {
+  let (logger, out) = capture_logger();
+
+  assert!(hft_info!(logger, "px={} qty={}", 101u64, 7u64));
+  let got = wait_for(&out, 1);
+
+  let expected = args2(101u64, 7u64);
+  assert_eq!(got[0].level, 2);
+  assert_eq!(got[0].payload, encode_to_vec(&expected));
+  assert_eq!(got[0].msg(), "px=101 qty=7");
+}
+
+/// 只收渲染好的文本行
+struct LinesSink {
+  out: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Sink for LinesSink {
+  fn on_record(&mut self, _tid: usize, _hdr: &MsgHeader, _payload: &[u8]) -> io::Result<()> {
+    unreachable!("TeeSink hands rendered lines to sinks that accept them")
+  }
+
+  fn on_idle(&mut self, _now_cycles: i64) -> io::Result<()> {
+    Ok(())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+
+  fn accepts_lines(&self) -> bool {
+    true
+  }
+
+  fn on_line(&mut self, _hdr: &MsgHeader, line: &[u8]) -> io::Result<()> {
+    self.out.lock().unwrap().extend_from_slice(line);
+    Ok(())
+  }
+}
+
+#[test]
+fn tee_hands_identical_bytes_to_every_sink() {
+  let path = std::env::temp_dir().join(format!("hft_tee_{}.log", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+  let mem = Arc::new(Mutex::new(Vec::new()));
+
+  let logger = {
+    let (path, mem) = (path.clone(), mem.clone());
+    init_logger_with(1 << 16, None, move || {
+      TeeSink::new(vec![
+        Box::new(LinesSink { out: mem }),
+        Box::new(FileBatchSink::new(path).unwrap()),
+      ])
+    })
+  };
+  for i in 0..50u64 {
+    assert!(hft_info!(logger, "tee {} {:.3}", i, i as f64 / 7.0));
+  }
+  logger.shutdown();
+
+  let file = std::fs::read(&path).unwrap();
+  let mem = mem.lock().unwrap();
+  assert_eq!(String::from_utf8_lossy(&file).lines().count(), 50);
+  assert_eq!(*mem, file);
+  std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn tee_escapes_str_args_by_default() {
+  let path = std::env::temp_dir().join(format!("hft_tee_escape_{}.log", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+  let logger = {
+    let path = path.clone();
+    init_logger_with(1 << 16, None, move || TeeSink::new(vec![Box::new(FileBatchSink::new(path).unwrap())]))
+  };
+  assert!(hft_info!(logger, "user {}", "a\nb\x1b[31m"));
+  logger.shutdown();
+
+  let text = std::fs::read_to_string(&path).unwrap();
+  assert_eq!(text.lines().count(), 1, "{text}");
+  assert!(text.ends_with("user a\\nb\\x1b[31m\n"), "{text}");
+  std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use hft_log_demo::log::{static_enabled, Level, STATIC_MAX_LEVEL};
+
+#[test]
+fn static_floor_follows_features() {
+  assert!(static_enabled(Level::Error));
+  assert_eq!(static_enabled(Level::Debug), !cfg!(any(
+    feature = "max_level_info",
+    feature = "max_level_warn",
+    feature = "max_level_error"
+  )));
+  assert!(static_enabled(STATIC_MAX_LEVEL));
+}
+
+// cargo test --features max_level_info --test static_level
+#[cfg(feature = "max_level_info")]
+#[test]
+fn compiled_out_debug_never_evaluates_its_args() {
+  use std::cell::Cell;
+  use hft_log_demo::{hft_debug, hft_error, hft_log};
+
+  let (logger, _out) = common::capture_logger();
+  let evaluated = Cell::new(0u32);
+  let arg = || {
+    evaluated.set(evaluated.get() + 1);
+    7u64
+  };
+
+  assert!(!hft_debug!(logger, "px {}", arg()));
+  assert!(!hft_log!(logger, Level::Trace, "px {} {}", arg(), 1u64));
+  assert_eq!(evaluated.get(), 0);
+
+  assert!(hft_error!(logger, "px {}", arg()));
+  assert_eq!(evaluated.get(), 1);
+}
@@ -0,0 +1,41 @@
+use hft_log_demo::file_sink::FileBatchSink;
+use hft_log_demo::hft_info;
+use hft_log_demo::run_log2::init_logger_with;
+
+#[test]
+fn named_threads_show_their_names() {
+  let path = std::env::temp_dir().join(format!("hft_names_{}.log", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+  let logger = {
+    let path = path.clone();
+    init_logger_with(1 << 16, None, move || FileBatchSink::new(path).unwrap())
+  };
+
+  let md = {
+    let logger = logger.clone();
+    std::thread::spawn(move || {
+      logger.set_thread_name("market-data");
+      assert!(hft_info!(logger, "book {} {}", 1u64, 2u64));
+    })
+  };
+  let oms = {
+    let logger = logger.clone();
+    std::thread::spawn(move || {
+      // 先打一条再改名也行
+      assert!(hft_info!(logger, "ack {} {}", 3u64, 4u64));
+      logger.set_thread_name("oms");
+      assert!(hft_info!(logger, "fill {} {}", 5u64, 6u64));
+    })
+  };
+  md.join().unwrap();
+  oms.join().unwrap();
+  assert!(hft_info!(logger, "main {} {}", 7u64, 8u64));
+  logger.shutdown();
+
+  let text = std::fs::read_to_string(&path).unwrap();
+  let line = |msg: &str| text.lines().find(|l| l.ends_with(msg)).unwrap_or_else(|| panic!("{msg} missing:\n{text}"));
+  assert!(line("book 1 2").contains(" market-data "), "{text}");
+  assert!(line("fill 5 6").contains(" oms "), "{text}");
+  assert!(line("main 7 8").contains(" T="), "{text}");
+  std::fs::remove_file(&path).unwrap();
+}
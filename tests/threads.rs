@@ -0,0 +1,197 @@
+mod common;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use hft_log_demo::hft_info;
+use hft_log_demo::log::get_tid;
+use hft_log_demo::run_log2::{init_logger_with, TidSource};
+
+use common::{capture_logger, wait_for, wait_matching, Capture};
+
+#[test]
+fn four_threads_all_lines_arrive() {
+  const N: u64 = 2000;
+  let (logger, out) = capture_logger();
+
+  let workers: Vec<_> = (0..4u64).map(|t| {
+    let logger = logger.clone();
+    std::thread::spawn(move || {
+      for i in 0..N {
+        // 队列满了就等 backend 腾地方，不丢
+        while !hft_info!(logger, "t={} i={}", t, i) {
+          std::thread::yield_now();
+        }
+      }
+    })
+  }).collect();
+  for w in workers {
+    w.join().unwrap();
+  }
+
+  // 被拒的 publish 会多出 "dropped N log messages ..." 行，只看业务行
+  let got = wait_matching(&out, 4 * N as usize, |r| r.msg().starts_with("t="));
+  assert_eq!(got.len(), 4 * N as usize);
+
+  // 每个线程自己的队列：同一线程的行保持顺序，且 tid 一致
+  let mut next: HashMap<u64, u64> = HashMap::new();
+  let mut tid_of: HashMap<u64, usize> = HashMap::new();
+  for r in &got {
+    let (t, i) = r.msg().split_once(' ').unwrap();
+    let t: u64 = t[2..].parse().unwrap();
+    let i: u64 = i[2..].parse().unwrap();
+    let expect = next.entry(t).or_insert(0);
+    assert_eq!(i, *expect);
+    *expect += 1;
+    assert_eq!(*tid_of.entry(t).or_insert(r.tid), r.tid);
+  }
+  assert!(next.values().all(|&n| n == N));
+  let mut tids: Vec<_> = tid_of.values().collect();
+  tids.sort();
+  tids.dedup();
+  assert_eq!(tids.len(), 4);
+}
+
+#[test]
+fn alternating_loggers_keep_one_queue_each() {
+  let (a, out_a) = capture_logger();
+  let (b, out_b) = capture_logger();
+  for i in 0..100u64 {
+    assert!(hft_info!(a, "a {} {}", i, 0u64));
+    assert!(hft_info!(b, "b {} {}", i, 0u64));
+  }
+  // 来回切换不会换掉这个线程在另一个 logger 上的队列
+  assert_eq!(a.queue_count(), 1);
+  assert_eq!(b.queue_count(), 1);
+
+  for (out, name) in [(&out_a, "a"), (&out_b, "b")] {
+    let got = wait_for(out, 100);
+    let msgs: Vec<&str> = got.iter().map(|r| r.msg()).collect();
+    let want: Vec<String> = (0..100).map(|i| format!("{name} {i} 0")).collect();
+    assert_eq!(msgs, want);
+    assert!(got.iter().all(|r| r.tid == got[0].tid));
+  }
+}
+
+#[test]
+fn exited_thread_queue_is_drained_then_retired() {
+  let (logger, out) = capture_logger();
+
+  let worker = {
+    let logger = logger.clone();
+    std::thread::spawn(move || {
+      for i in 0..10u64 {
+        assert!(hft_info!(logger, "short-lived {} {}", i, 0u64));
+      }
+      assert_eq!(logger.queue_count(), 1);
+    })
+  };
+  worker.join().unwrap();
+
+  let got = wait_for(&out, 10);
+  assert_eq!(got.last().unwrap().msg(), "short-lived 9 0");
+
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while logger.queue_count() != 0 {
+    assert!(Instant::now() < deadline, "backend never retired the queue");
+    std::thread::sleep(Duration::from_millis(1));
+  }
+  assert_eq!(out.lock().unwrap().len(), 10);
+}
+
+#[test]
+fn tid_is_the_kernel_thread_id() {
+  let (logger, out) = capture_logger();
+
+  let worker = {
+    let logger = logger.clone();
+    std::thread::spawn(move || {
+      assert!(hft_info!(logger, "from {} {}", 1u64, 0u64));
+      get_tid()
+    })
+  };
+  let worker_tid = worker.join().unwrap();
+  assert!(hft_info!(logger, "from {} {}", 0u64, 0u64));
+
+  let got = wait_for(&out, 2);
+  assert_eq!(got[0].tid, worker_tid as usize);
+  assert_eq!(got[1].tid, get_tid() as usize);
+  assert_ne!(worker_tid, get_tid());
+}
+
+#[test]
+fn counter_tids_when_asked() {
+  let (logger, out) = capture_logger();
+  let logger = logger.with_tid_source(TidSource::Counter);
+
+  assert!(hft_info!(logger, "counted {} {}", 1u64, 0u64));
+  let got = wait_for(&out, 1);
+  assert_ne!(got[0].tid, get_tid() as usize);
+  assert!(got[0].tid < 1000);
+}
+
+#[test]
+fn two_staging_queues_merge_by_tsc() {
+  const N: u64 = 100;
+  let cap = Capture::new();
+  let sink = cap.sink();
+  let logger = init_logger_with(1 << 16, None, move || sink);
+
+  // 先让 backend 卡在一条记录上，两个线程的日志全部进队列后再一起 drain
+  cap.hold.store(true, Ordering::Release);
+  assert!(hft_info!(logger, "gate {}", 0u64));
+  std::thread::sleep(Duration::from_millis(50));
+
+  // 两个线程轮流写，tsc 交错：a0 < b0 < a1 < b1 ...
+  let turn = Arc::new(AtomicU64::new(0));
+  let workers: Vec<_> = ["a", "b"].into_iter().enumerate().map(|(me, name)| {
+    let (logger, turn) = (logger.clone(), turn.clone());
+    std::thread::spawn(move || {
+      for i in 0..N {
+        while turn.load(Ordering::Acquire) != 2 * i + me as u64 {
+          std::hint::spin_loop();
+        }
+        assert!(hft_info!(logger, "{} {}", name, i));
+        turn.fetch_add(1, Ordering::Release);
+      }
+    })
+  }).collect();
+  for w in workers {
+    w.join().unwrap();
+  }
+  cap.hold.store(false, Ordering::Release);
+
+  let got = wait_for(&cap.out, 1 + 2 * N as usize);
+  assert_eq!(got[0].msg(), "gate 0");
+  assert!(got.windows(2).all(|w| w[0].tsc <= w[1].tsc));
+  let expected: Vec<String> = (0..N).flat_map(|i| [format!("a {i}"), format!("b {i}")]).collect();
+  let msgs: Vec<&str> = got[1..].iter().map(|r| r.msg()).collect();
+  assert_eq!(msgs, expected);
+}
+
+#[test]
+fn threads_register_with_their_own_capacity() {
+  let (logger, out) = capture_logger();
+  assert_eq!(logger.thread_queue_capacity(), None);
+
+  // 行情线程要深队列，控制线程浅一点；没指定的用 init_logger 的 64KB
+  let spawn = |logger: hft_log_demo::run_log2::LoggerHandle, name: &'static str| {
+    std::thread::spawn(move || {
+      assert!(hft_info!(logger, "{} up", name));
+      logger.thread_queue_capacity().unwrap()
+    })
+  };
+  let md = spawn(logger.clone().with_capacity(1 << 20), "md");
+  let ctl = spawn(logger.clone().with_capacity(4000), "ctl");
+  let default = spawn(logger.clone(), "default");
+  assert_eq!(md.join().unwrap(), 1 << 20);
+  // 向上取到 2 的幂个块
+  assert_eq!(ctl.join().unwrap(), 4096);
+  assert_eq!(default.join().unwrap(), 64 * 1024);
+  assert_eq!(logger.stats().queue_capacity, 64 * 1024);
+  assert_eq!(logger.clone().with_capacity(4000).stats().queue_capacity, 4096);
+
+  assert_eq!(wait_matching(&out, 3, |r| r.msg().ends_with(" up")).len(), 3);
+}
@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Once};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hft_log_demo::tscns;
+
+// 校准间隔调短，让 stress 期间真的发生多次 calibrate
+const CALIBRATE_NS: i64 = 10_000_000;
+
+fn init() {
+  static INIT: Once = Once::new();
+  INIT.call_once(|| tscns::init(50_000_000, CALIBRATE_NS));
+}
+
+fn sys_nanos() -> i64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as i64
+}
+
+#[test]
+fn readers_stay_monotonic_while_calibrating() {
+  init();
+  let stop = Arc::new(AtomicBool::new(false));
+  let readers: Vec<_> = (0..4)
+    .map(|_| {
+      let stop = stop.clone();
+      std::thread::spawn(move || {
+        let mut last = tscns::read_nanos();
+        let mut worst_backstep = 0i64;
+        let mut reads = 0u64;
+        while !stop.load(Ordering::Relaxed) {
+          let now = tscns::read_nanos();
+          worst_backstep = worst_backstep.max(last - now);
+          last = now;
+          reads += 1;
+        }
+        (worst_backstep, reads)
+      })
+    })
+    .collect();
+
+  for _ in 0..30 {
+    tscns::calibrate();
+    std::thread::sleep(Duration::from_nanos(CALIBRATE_NS as u64));
+  }
+  stop.store(true, Ordering::Relaxed);
+
+  for r in readers {
+    let (worst_backstep, reads) = r.join().unwrap();
+    assert!(reads > 0);
+    // 校准只会把斜率微调，倒退最多几十微秒
+    assert!(worst_backstep < 100_000, "clock stepped back {}ns", worst_backstep);
+  }
+  assert!((tscns::read_nanos() - sys_nanos()).abs() < 5_000_000);
+}
+
+#[test]
+fn calibration_error_stays_bounded() {
+  static CALLS: AtomicUsize = AtomicUsize::new(0);
+  fn hook(ns_err: i64, ns_per_tsc: f64) {
+    assert!(ns_per_tsc > 0.0);
+    assert!(ns_err.abs() < 1_000_000, "ns_err {}", ns_err);
+    CALLS.fetch_add(1, Ordering::Relaxed);
+  }
+
+  let clock = tscns::TscClock::new();
+  clock.set_calibration_hook(Some(hook));
+  clock.init(50_000_000, CALIBRATE_NS);
+  for _ in 0..3 {
+    std::thread::sleep(Duration::from_nanos(CALIBRATE_NS as u64));
+    clock.calibrate();
+    assert!(clock.last_calibration_error_ns().abs() < 1_000_000);
+  }
+  assert_eq!(CALLS.load(Ordering::Relaxed), 3);
+}
+
+// riscv64 / aarch64 的分支由 CI 的 cross-check 编译；这里在本机验证计数器是正的、往前走的
+#[test]
+fn raw_counter_is_positive_and_advances() {
+  let t0 = tscns::read_tsc();
+  std::thread::sleep(Duration::from_millis(5));
+  let t1 = tscns::read_tsc();
+  assert!(t0 > 0);
+  assert!(t1 > t0, "{t0} -> {t1}");
+}
@@ -0,0 +1,21 @@
+// 单独一个测试进程：tests/tscns.rs 里别的测试会并发 calibrate，这里要参数完全不动
+use hft_log_demo::tscns;
+
+#[test]
+fn second_init_is_a_no_op() {
+  tscns::init(20_000_000, 1_000_000_000);
+  let ns_per_tsc = tscns::get_ns_per_tsc();
+  let tsc = tscns::read_tsc();
+  let ns = tscns::tsc2ns(tsc);
+
+  // 再来几次，包括不同的参数和并发调用，都不能重新打基准
+  std::thread::scope(|s| {
+    for _ in 0..4 {
+      s.spawn(|| tscns::init(1_000_000, 1_000));
+    }
+  });
+  tscns::init(20_000_000, 1_000_000_000);
+
+  assert_eq!(tscns::get_ns_per_tsc().to_bits(), ns_per_tsc.to_bits());
+  assert_eq!(tscns::tsc2ns(tsc), ns);
+}
@@ -0,0 +1,40 @@
+use std::net::UdpSocket;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use hft_log_demo::hft_info;
+use hft_log_demo::run_log2::init_logger_with;
+use hft_log_demo::udp_sink::UdpSink;
+
+#[test]
+fn datagrams_split_at_lines_and_reassemble() {
+  let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+  collector.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+  let addr = collector.local_addr().unwrap();
+
+  let (errors_tx, errors_rx) = std::sync::mpsc::channel();
+  let logger = init_logger_with(1 << 16, None, move || {
+    let sink = UdpSink::new(addr).unwrap().with_max_datagram(200);
+    errors_tx.send(sink.send_errors()).unwrap();
+    sink
+  });
+  const N: u64 = 30;
+  for i in 0..N {
+    assert!(hft_info!(logger, "udp line {} of {}", i, N));
+  }
+  logger.shutdown();
+
+  let mut text = String::new();
+  let mut buf = [0u8; 2048];
+  while text.lines().count() < N as usize {
+    let n = collector.recv(&mut buf).unwrap();
+    // 不超过上限，且每个 datagram 都是整行
+    assert!(n <= 200, "{} byte datagram", n);
+    assert_eq!(buf[n - 1], b'\n');
+    text.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+  }
+  for (i, line) in text.lines().enumerate() {
+    assert!(line.ends_with(&format!("] udp line {} of {}", i, N)), "{line}");
+  }
+  assert_eq!(errors_rx.recv().unwrap().load(Ordering::Relaxed), 0);
+}
@@ -0,0 +1,26 @@
+use std::fmt;
+
+use bytemuck::{Pod, Zeroable};
+use hft_log_demo::args2::{Arg, UserPod, UserPodSnap};
+
+// 256 字节：加上 tag 和 decode_fn 就放不进一条记录
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct Huge {
+  data: [u64; 32],
+}
+
+impl fmt::Display for Huge {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.data[0])
+  }
+}
+
+impl UserPod for Huge {}
+
+// trybuild 只跑 cargo check，泛型函数里的 const 断言要到 codegen 才触发，这里直接求值
+const TAG: u8 = <UserPodSnap<Huge> as Arg>::ARG_TAG;
+
+fn main() {
+  println!("{TAG}");
+}
@@ -0,0 +1,27 @@
+use std::fmt;
+
+use bytemuck::{Pod, Zeroable};
+use hft_log_demo::args2::{Arg, UserPod, UserPodSnap};
+
+// 12 字节：UserPodSnap 末尾会有 4 字节 padding
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct Level2 {
+  px: u32,
+  qty: u32,
+  side: u32,
+}
+
+impl fmt::Display for Level2 {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}@{}", self.qty, self.px)
+  }
+}
+
+impl UserPod for Level2 {}
+
+const TAG: u8 = <UserPodSnap<Level2> as Arg>::ARG_TAG;
+
+fn main() {
+  println!("{TAG}");
+}
@@ -0,0 +1,23 @@
+use std::fmt;
+
+use bytemuck::{Pod, Zeroable};
+use hft_log_demo::args2::{Arg, UserPod, UserPodSnap};
+
+// 0 字节：tag 会是 8，和 ArgPtr 撞上
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct Empty {}
+
+impl fmt::Display for Empty {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("empty")
+  }
+}
+
+impl UserPod for Empty {}
+
+const TAG: u8 = <UserPodSnap<Empty> as Arg>::ARG_TAG;
+
+fn main() {
+  println!("{TAG}");
+}